@@ -0,0 +1,186 @@
+// core.rs
+
+/* Core Path Benchmarks */
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use opentune::alignment::InputAlignment;
+use opentune::automation::{AutomationLane, CurveShape};
+use opentune::dsp::simd::{self, SimdLevel};
+use opentune::dspapi::{Command, StatState};
+use opentune::dspengine::{AudioNode, DspEngine, EngineConfig};
+use opentune::graph::AudioGraph;
+use opentune::mrbr::MagicRingBuffer;
+
+const BLOCK_SIZES: [usize; 4] = [64, 128, 512, 1024];
+
+/// Minimal node so the rack benchmarks measure scheduling cost, not DSP.
+struct Passthrough {
+    id: u32,
+    gain: f32,
+}
+
+impl AudioNode for Passthrough {
+    fn process(&mut self, buffer: &mut [f32]) {
+        for s in buffer.iter_mut() {
+            *s *= self.gain;
+        }
+    }
+
+    fn set_param(&mut self, _param_id: u32, payload: &[u8]) {
+        if let Ok(bytes) = payload.try_into() {
+            self.gain = f32::from_le_bytes(bytes);
+        }
+    }
+
+    fn get_id(&self) -> u32 { self.id }
+
+    fn get_name(&self) -> &str { "Passthrough" }
+}
+
+fn rack(count: usize) -> Vec<Box<dyn AudioNode>> {
+    (0..count).map(|i| Box::new(Passthrough { id: i as u32, gain: 1.0 }) as Box<dyn AudioNode>).collect()
+}
+
+fn ring_buffer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ring_buffer");
+    let buffer = MagicRingBuffer::new(8192).expect("MagicRingBuffer Initialization Failed");
+
+    for &block in &BLOCK_SIZES {
+        let input = vec![0.25f32; block];
+        group.throughput(Throughput::Elements(block as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(block), &block, |b, &block| {
+            b.iter(|| {
+                let slice = buffer.write_slice(block).unwrap();
+                slice.copy_from_slice(&input);
+                buffer.commit_write(block);
+                let read = buffer.read_slice();
+                black_box(read[block - 1]);
+                buffer.consume(block);
+            });
+        });
+    }
+    group.finish();
+}
+
+/// A prepared graph running `count` nodes in series, as the engine's graph would.
+fn graph(count: usize, block: usize) -> AudioGraph {
+    let mut graph = AudioGraph::new(count, 4 * count, 2, block);
+    graph.prepare(48000, block);
+    for node in rack(count) {
+        graph.append_node(node).expect("Graph Full");
+    }
+    graph
+}
+
+/// An engine with `count` nodes, pumped through `DspEngine::process_block`.
+fn engine(count: usize, block: usize) -> DspEngine {
+    let config = EngineConfig { max_nodes: count, ..EngineConfig::new(48000, block) };
+    let engine = DspEngine::with_config(1, "bench", config);
+    for node in rack(count) {
+        engine.graph.lock().unwrap().append_node(node).expect("Graph Full");
+    }
+    engine
+}
+
+fn rack_scheduling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rack_scheduling");
+    let mut buffer = vec![0.5f32; 256 * 2];
+
+    for &count in &[1usize, 8, 32, 128] {
+        let mut graph = graph(count, 256);
+        group.bench_with_input(BenchmarkId::new("graph", count), &count, |b, _| {
+            b.iter(|| graph.process(black_box(&mut buffer), &[], &[], &[], 0, None, None, None, None));
+        });
+
+        let mut engine = engine(count, 256);
+        group.bench_with_input(BenchmarkId::new("engine", count), &count, |b, _| {
+            b.iter(|| engine.process_block(black_box(&mut buffer)).unwrap());
+        });
+    }
+    group.finish();
+}
+
+fn parameter_dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parameter_dispatch");
+    let payload = 0.5f32.to_le_bytes();
+
+    for &count in &[8usize, 128] {
+        let mut graph = graph(count, 256);
+        group.bench_with_input(BenchmarkId::new("graph", count), &count, |b, &count| {
+            b.iter(|| {
+                for i in 0..32u32 {
+                    let _ = graph.set_param(i % count as u32, 0, black_box(&payload));
+                }
+            });
+        });
+
+        // Queues 32 Set Parameter commands and renders one block, so the audio thread's opcode 2 path applies them.
+        let mut engine = engine(count, 64);
+        let handle = engine.handle();
+        let mut buffer = vec![0.0f32; 64 * 2];
+        group.bench_with_input(BenchmarkId::new("engine", count), &count, |b, &count| {
+            b.iter(|| {
+                for i in 0..32u32 {
+                    handle.send(Command::new(2, "Set Parameter", payload.to_vec(), i % count as u32, 0, 0, StatState::ACTIVE));
+                }
+                engine.process_block(black_box(&mut buffer)).unwrap();
+            });
+        });
+    }
+    group.finish();
+}
+
+fn internal_nodes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("internal_nodes");
+
+    for &block in &BLOCK_SIZES {
+        let mut buffer = vec![0.5f32; block * 2];
+        group.throughput(Throughput::Elements(block as u64));
+
+        let mut align = InputAlignment::new(1, 2);
+        align.set_delay(0, 12.37);
+        align.set_polarity(1, true);
+        group.bench_with_input(BenchmarkId::new("input_alignment", block), &block, |b, _| {
+            b.iter(|| align.process(black_box(&mut buffer)));
+        });
+
+        let mut lane = AutomationLane::new(1, 0);
+        lane.add_point(0, 0.0, CurveShape::Exponential(3.0));
+        lane.add_point(1 << 20, 1.0, CurveShape::Linear);
+        let mut values = vec![0.0f32; block];
+        let mut position = 0u64;
+        group.bench_with_input(BenchmarkId::new("automation_lane", block), &block, |b, &block| {
+            b.iter(|| {
+                lane.render_block(position, black_box(&mut values));
+                position = (position + block as u64) % (1 << 20);
+            });
+        });
+    }
+    group.finish();
+}
+
+fn buffer_utilities(c: &mut Criterion) {
+    let mut group = c.benchmark_group("buffer_utilities");
+    let best = simd::level();
+
+    for &block in &BLOCK_SIZES {
+        let source = vec![0.25f32; block * 2];
+        let mut mix = vec![0.5f32; block * 2];
+        group.throughput(Throughput::Elements(block as u64 * 2));
+        for level in [SimdLevel::Scalar, best] {
+            simd::set_level(level).expect("Level Unsupported");
+            group.bench_with_input(BenchmarkId::new(format!("mix_gain_{:?}", level), block), &block, |b, _| {
+                b.iter(|| simd::mix_gain(black_box(&mut mix), black_box(&source), 0.5));
+            });
+            group.bench_with_input(BenchmarkId::new(format!("peak_{:?}", level), block), &block, |b, _| {
+                b.iter(|| simd::peak(black_box(&source)));
+            });
+        }
+    }
+    simd::set_level(best).expect("Level Unsupported");
+    group.finish();
+}
+
+criterion_group!(benches, ring_buffer, rack_scheduling, parameter_dispatch, internal_nodes, buffer_utilities);
+criterion_main!(benches);
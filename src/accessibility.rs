@@ -0,0 +1,168 @@
+// accessibility.rs
+
+/* Screen-Reader Value Descriptions */
+
+#![allow(warnings)]
+
+use std::collections::HashMap;
+
+use crate::dspapi::{NodeId, ParamId};
+use crate::strings::tr;
+use crate::taps::MeterReading;
+
+/// How a value should be spoken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueUnit {
+    Decibels,
+    /// A 0.0..1.0 value read as a percentage.
+    Percent,
+    Hertz,
+    Milliseconds,
+    Samples,
+    /// >= 0.5 is on.
+    Toggle,
+    Plain,
+}
+
+fn number(value: f32, decimals: usize) -> String {
+    let text = format!("{:.*}", decimals, value.abs());
+    if value < 0.0 && text.chars().any(|c| c.is_ascii_digit() && c != '0') {
+        tr("a11y.minus", "minus {0}", &[&text])
+    } else {
+        text
+    }
+}
+
+/// Verbal form of a value, e.g. "minus 6.5 decibels" or "2.4 kilohertz". Localizable under `a11y.*`.
+pub fn describe_value(value: f32, unit: ValueUnit) -> String {
+    match unit {
+        ValueUnit::Decibels => tr("a11y.db", "{0} decibels", &[&number(value, 1)]),
+        ValueUnit::Percent => tr("a11y.percent", "{0} percent", &[&number(value * 100.0, 0)]),
+        ValueUnit::Hertz if value.abs() >= 1000.0 => tr("a11y.khz", "{0} kilohertz", &[&number(value / 1000.0, 1)]),
+        ValueUnit::Hertz => tr("a11y.hz", "{0} hertz", &[&number(value, 0)]),
+        ValueUnit::Milliseconds => tr("a11y.ms", "{0} milliseconds", &[&number(value, 1)]),
+        ValueUnit::Samples => tr("a11y.samples", "{0} samples", &[&number(value, 0)]),
+        ValueUnit::Toggle if value >= 0.5 => tr("a11y.on", "on", &[]),
+        ValueUnit::Toggle => tr("a11y.off", "off", &[]),
+        ValueUnit::Plain => number(value, 2),
+    }
+}
+
+/// Coarse loudness band of a meter, so level changes can be announced in words rather than numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MeterBand {
+    Silent,
+    VeryQuiet,
+    Quiet,
+    Moderate,
+    Loud,
+    Clipping,
+}
+
+impl MeterBand {
+    pub fn from_db(level_db: f32) -> Self {
+        match level_db {
+            l if l >= 0.0 => MeterBand::Clipping,
+            l if l >= -6.0 => MeterBand::Loud,
+            l if l >= -20.0 => MeterBand::Moderate,
+            l if l >= -40.0 => MeterBand::Quiet,
+            l if l >= -90.0 => MeterBand::VeryQuiet,
+            _ => MeterBand::Silent,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            MeterBand::Silent => tr("a11y.meter.silent", "silent", &[]),
+            MeterBand::VeryQuiet => tr("a11y.meter.very_quiet", "very quiet", &[]),
+            MeterBand::Quiet => tr("a11y.meter.quiet", "quiet", &[]),
+            MeterBand::Moderate => tr("a11y.meter.moderate", "moderate", &[]),
+            MeterBand::Loud => tr("a11y.meter.loud", "loud", &[]),
+            MeterBand::Clipping => tr("a11y.meter.clipping", "clipping", &[]),
+        }
+    }
+}
+
+/// Something worth announcing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessibleEvent {
+    pub description: String,
+    /// Clipping and similar warnings; screen readers should interrupt for these.
+    pub urgent: bool,
+}
+
+struct WatchedParam {
+    label: String,
+    unit: ValueUnit,
+    threshold: f32,
+    announced: Option<f32>,
+}
+
+/// Turns streams of raw values into announcements, only when a value moved by at least its threshold
+/// (or a meter changed band), so screen readers aren't flooded while a knob turns.
+pub struct ChangeAnnouncer {
+    params: HashMap<(NodeId, ParamId), WatchedParam>,
+    meters: HashMap<usize, (String, MeterBand)>,
+}
+
+impl ChangeAnnouncer {
+    pub fn new() -> Self {
+        Self {
+            params: HashMap::new(),
+            meters: HashMap::new(),
+        }
+    }
+
+    /// Announces changes of a parameter larger than `threshold` (in the parameter's own units).
+    pub fn watch_param(&mut self, node_id: NodeId, param_id: ParamId, label: &str, unit: ValueUnit, threshold: f32) {
+        let param = WatchedParam { label: label.to_string(), unit, threshold: threshold.abs(), announced: None };
+        self.params.insert((node_id, param_id), param);
+    }
+
+    /// Announces band changes of a metering tap (see `taps`).
+    pub fn watch_meter(&mut self, tap: usize, label: &str) {
+        self.meters.insert(tap, (label.to_string(), MeterBand::Silent));
+    }
+
+    pub fn unwatch_param(&mut self, node_id: NodeId, param_id: ParamId) {
+        self.params.remove(&(node_id, param_id));
+    }
+
+    pub fn unwatch_meter(&mut self, tap: usize) {
+        self.meters.remove(&tap);
+    }
+
+    /// Full description of a watched parameter, for when the user focuses its control.
+    pub fn describe_param(&self, node_id: NodeId, param_id: ParamId, value: f32) -> Option<String> {
+        let param = self.params.get(&(node_id, param_id))?;
+        Some(tr("a11y.param", "{0}, {1}", &[&param.label, &describe_value(value, param.unit)]))
+    }
+
+    pub fn update_param(&mut self, node_id: NodeId, param_id: ParamId, value: f32) -> Option<AccessibleEvent> {
+        let param = self.params.get_mut(&(node_id, param_id))?;
+        if param.announced.is_some_and(|last| (value - last).abs() < param.threshold) {
+            return None;
+        }
+        param.announced = Some(value);
+        Some(AccessibleEvent {
+            description: tr("a11y.param", "{0}, {1}", &[&param.label, &describe_value(value, param.unit)]),
+            urgent: false,
+        })
+    }
+
+    /// Feeds the latest tap readings and returns announcements for meters that changed band.
+    pub fn update_meters(&mut self, readings: &[MeterReading]) -> Vec<AccessibleEvent> {
+        let mut events = Vec::new();
+        for reading in readings {
+            let Some((label, band)) = self.meters.get_mut(&reading.tap) else { continue };
+            let next = MeterBand::from_db(20.0 * reading.peak.max(1.0e-9).log10());
+            if next == *band { continue; }
+            *band = next;
+            events.push(AccessibleEvent {
+                description: tr("a11y.meter", "{0} {1}", &[label, &next.describe()]),
+                urgent: next == MeterBand::Clipping,
+            });
+        }
+        events
+    }
+}
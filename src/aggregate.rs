@@ -0,0 +1,276 @@
+// aggregate.rs
+
+/* Aggregate Output Devices */
+
+#![allow(warnings)]
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use crate::dspapi::NodeId;
+use crate::layout::{ChannelLayout, ChannelMap};
+use crate::mrbr::MagicRingBuffer as Buffer;
+use crate::resample::Resampler;
+
+/// Extra output devices one engine can play to besides its main output.
+pub const MAX_AGGREGATE_OUTPUTS: usize = 8;
+/// Samples in each feed ring: a few blocks are all a reader keeps, the rest absorbs a device that stalls.
+pub const FEED_RING_CAPACITY: usize = 1 << 18;
+/// Largest clock correction applied. Real interfaces differ by well under 200 ppm.
+pub const MAX_DRIFT_PPM: f64 = 1000.0;
+/// Correction per unit of relative fill error, in ppm. A steady drift of d ppm settles the fill d / 2000
+/// away from the target, which keeps the correction inaudible and the added latency bounded.
+const DRIFT_GAIN_PPM: f64 = 2000.0;
+/// Time constant of the fill level average the correction follows, in seconds.
+const FILL_SMOOTHING_SECONDS: f64 = 1.0;
+/// Engine blocks kept queued ahead of an extra device, covering the jitter between the two callbacks.
+const TARGET_BLOCKS: usize = 3;
+
+/// What an extra output device plays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputSource {
+    /// The rack output after the master section, as the main device hears it.
+    Master,
+    /// A node's output, typically a return bus (`bus::bus_id`). To hear a bus only on the extra device,
+    /// disconnect it from the graph output.
+    Node(NodeId),
+}
+
+impl OutputSource {
+    fn encode(self) -> u64 {
+        match self {
+            OutputSource::Master => 1,
+            OutputSource::Node(node) => (2 << 32) | node as u64,
+        }
+    }
+
+    fn decode(bits: u64) -> Option<Self> {
+        match bits >> 32 {
+            0 if bits == 1 => Some(OutputSource::Master),
+            2 => Some(OutputSource::Node(bits as u32)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct FeedSlot {
+    source: AtomicU64,
+    /// Allocated the first time the slot is claimed and kept, so the audio thread never sees it go away.
+    ring: OnceLock<Buffer>,
+}
+
+/// Fixed set of feeds the audio thread copies sources into, one per extra output. Claiming, releasing and
+/// rerouting are lock-free.
+pub struct OutputFeeds {
+    slots: Vec<FeedSlot>,
+    active: AtomicU32,
+}
+
+impl OutputFeeds {
+    pub fn new() -> Self {
+        Self {
+            slots: (0..MAX_AGGREGATE_OUTPUTS).map(|_| FeedSlot::default()).collect(),
+            active: AtomicU32::new(0),
+        }
+    }
+
+    /// Takes a free feed for `source`. Returns its index, or an error if every feed is in use.
+    pub fn claim(&self, source: OutputSource) -> Result<usize, String> {
+        for (index, slot) in self.slots.iter().enumerate() {
+            if slot.source.load(Ordering::Acquire) != 0 { continue; }
+            if slot.ring.get().is_none() {
+                let ring = Buffer::new(FEED_RING_CAPACITY).map_err(|e| format!("Failed to allocate output feed: {}", e))?;
+                let _ = slot.ring.set(ring);
+            }
+            if slot.source.compare_exchange(0, source.encode(), Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+                self.active.fetch_add(1, Ordering::Relaxed);
+                return Ok(index);
+            }
+        }
+        Err(format!("All {} aggregate outputs are in use", MAX_AGGREGATE_OUTPUTS))
+    }
+
+    pub fn release(&self, feed: usize) {
+        if let Some(slot) = self.slots.get(feed) {
+            if slot.source.swap(0, Ordering::AcqRel) != 0 {
+                self.active.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Reroutes a claimed feed; the reader hears the new source from the next engine block.
+    pub fn set_source(&self, feed: usize, source: OutputSource) -> bool {
+        let Some(slot) = self.slots.get(feed) else { return false };
+        let current = slot.source.load(Ordering::Acquire);
+        current != 0 && slot.source.compare_exchange(current, source.encode(), Ordering::AcqRel, Ordering::Relaxed).is_ok()
+    }
+
+    pub fn source(&self, feed: usize) -> Option<OutputSource> {
+        OutputSource::decode(self.slots.get(feed)?.source.load(Ordering::Acquire))
+    }
+
+    pub fn ring(&self, feed: usize) -> Option<&Buffer> {
+        self.slots.get(feed)?.ring.get()
+    }
+
+    /// Cheap check so the audio thread can skip feeding entirely when no extra outputs are open.
+    pub fn is_empty(&self) -> bool {
+        self.active.load(Ordering::Relaxed) == 0
+    }
+
+    /// Copies `audio` (interleaved, engine layout) into every feed listening to `source`. Called from the audio
+    /// thread; a feed whose reader fell a whole ring behind drops the block.
+    pub fn feed(&self, source: OutputSource, audio: &[f32]) {
+        let bits = source.encode();
+        for slot in self.slots.iter().filter(|slot| slot.source.load(Ordering::Relaxed) == bits) {
+            let Some(ring) = slot.ring.get() else { continue };
+            if let Some(space) = ring.write_slice(audio.len()) {
+                space.copy_from_slice(audio);
+                ring.commit_write(audio.len());
+            }
+        }
+    }
+}
+
+/// How an extra output keeps up with the engine, published by its reader.
+#[derive(Default)]
+pub struct DriftStats {
+    /// Current clock correction in ppm, as f32 bits; positive when the engine's clock runs fast
+    /// relative to the device's.
+    drift_ppm: AtomicU32,
+    underruns: AtomicU64,
+}
+
+impl DriftStats {
+    pub fn drift_ppm(&self) -> f32 {
+        f32::from_bits(self.drift_ppm.load(Ordering::Relaxed))
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+}
+
+/// Plays one feed on a device whose clock is independent of the engine's: resamples from the engine rate to the
+/// device rate, trimming the ratio so the frames queued in the feed stay near a target. Runs in the extra
+/// device's callback and never allocates.
+pub struct DriftReader {
+    feeds: Arc<OutputFeeds>,
+    feed: usize,
+    channels: usize,
+    device_channels: usize,
+    device_rate: u32,
+    map: Option<ChannelMap>,
+    resampler: Resampler,
+    /// Resampled frames in the engine layout before mapping to the device's channels.
+    scratch: Vec<f32>,
+    max_push_frames: usize,
+    target: f64,
+    fill: f64,
+    /// Whether enough is queued to play; cleared by an underrun so the queue builds up again.
+    primed: bool,
+    /// Skips what piled up in the feed before the device started.
+    flush: bool,
+    stats: Arc<DriftStats>,
+}
+
+impl DriftReader {
+    /// Reads feed `feed` of `layout` at `engine_rate` in blocks of `block` frames, for a device with
+    /// `device_channels` at `device_rate`.
+    pub fn new(feeds: Arc<OutputFeeds>, feed: usize, layout: ChannelLayout, engine_rate: u32, block: usize, device_channels: usize, device_rate: u32) -> Self {
+        let channels = layout.channels();
+        let device_layout = ChannelLayout::from_channels(device_channels);
+        let block = block.max(1);
+        let max_push_frames = block * 2;
+        DriftReader {
+            feeds,
+            feed,
+            channels,
+            device_channels,
+            device_rate: device_rate.max(1),
+            map: (device_layout.channels() != channels).then(|| ChannelMap::new(layout, device_layout)),
+            resampler: Resampler::new(channels, engine_rate, device_rate, max_push_frames),
+            scratch: vec![0.0; block * channels],
+            max_push_frames,
+            target: (block * TARGET_BLOCKS).max(engine_rate as usize / 100) as f64,
+            fill: 0.0,
+            primed: false,
+            flush: true,
+            stats: Arc::new(DriftStats::default()),
+        }
+    }
+
+    pub fn stats(&self) -> Arc<DriftStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Fills `output` (interleaved, device channels) from the feed, or with silence while it builds up.
+    pub fn render(&mut self, output: &mut [f32]) {
+        let Some(ring) = self.feeds.ring(self.feed) else {
+            output.fill(0.0);
+            return;
+        };
+        if self.flush {
+            let stale = ring.read_slice().len();
+            ring.consume(stale - stale % self.channels);
+            self.flush = false;
+        }
+
+        let queued = (ring.read_slice().len() / self.channels + self.resampler.pending_frames()) as f64;
+        if !self.primed {
+            if queued < self.target {
+                output.fill(0.0);
+                return;
+            }
+            self.primed = true;
+            self.fill = queued;
+        }
+        let frames = output.len() / self.device_channels;
+        let alpha = (frames as f64 / (self.device_rate as f64 * FILL_SMOOTHING_SECONDS)).min(1.0);
+        self.fill += alpha * (queued - self.fill);
+        let ppm = ((self.fill - self.target) / self.target * DRIFT_GAIN_PPM).clamp(-MAX_DRIFT_PPM, MAX_DRIFT_PPM);
+        self.resampler.set_drift(1.0 + ppm * 1e-6);
+        self.stats.drift_ppm.store((ppm as f32).to_bits(), Ordering::Relaxed);
+
+        let scratch_frames = self.scratch.len() / self.channels;
+        for piece in output.chunks_mut(scratch_frames * self.device_channels) {
+            let block = &mut self.scratch[..piece.len() / self.device_channels * self.channels];
+            let mut done = 0;
+            while done < block.len() {
+                done += self.resampler.pull(&mut block[done..]) * self.channels;
+                if done >= block.len() { break; }
+                let available = ring.read_slice();
+                let take = available.len().min(self.max_push_frames * self.channels);
+                let pushed = self.resampler.push(&available[..take - take % self.channels]);
+                ring.consume(pushed * self.channels);
+                if pushed == 0 {
+                    // The engine fell behind the device: play silence and queue up to the target again.
+                    block[done..].fill(0.0);
+                    if self.primed {
+                        self.primed = false;
+                        self.stats.underruns.fetch_add(1, Ordering::Relaxed);
+                    }
+                    break;
+                }
+            }
+            match self.map.as_ref() {
+                Some(map) => { map.apply(block, piece); }
+                None => piece.copy_from_slice(block),
+            }
+        }
+    }
+}
+
+/// An extra output as reported by `DspEngine::aggregate_outputs`.
+#[derive(Debug, Clone)]
+pub struct AggregateOutputInfo {
+    pub id: usize,
+    pub device: String,
+    pub source: OutputSource,
+    /// Rate the device runs at, while it is open.
+    pub sample_rate: Option<u32>,
+    /// Clock correction currently applied, see `DriftStats`.
+    pub drift_ppm: f32,
+    pub underruns: u64,
+}
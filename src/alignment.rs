@@ -0,0 +1,240 @@
+// alignment.rs
+
+/* Input Phase / Delay Alignment */
+
+#![allow(warnings)]
+
+use crate::dspengine::AudioNode;
+use crate::layout::ChannelLayout;
+use crate::rtsafety::RtSafety;
+
+/// Longest correction the delay lines can hold, in samples.
+pub const MAX_ALIGN_DELAY: usize = 4096;
+
+/// Per-channel polarity and fractional delay, for lining up several inputs that pick up the same source.
+/// Parameters: `channel * 2` = polarity (payload f32, >= 0.5 inverts), `channel * 2 + 1` = delay in samples (f32).
+/// The delays accept audio-rate modulation in samples, for vibrato/chorus/FM-style patches.
+pub struct InputAlignment {
+    id: u32,
+    channels: usize,
+    sample_rate: u32,
+    invert: Vec<bool>,
+    /// Delay in samples at the current sample rate.
+    delay: Vec<f32>,
+    /// The same delay in seconds, kept so the correction survives a sample-rate change.
+    delay_seconds: Vec<f32>,
+    lines: Vec<Vec<f32>>,
+    write_pos: usize,
+    delay_params: Vec<u32>,
+    /// Per-channel delay modulation for the next block, and how many frames of it are valid.
+    modulation: Vec<Vec<f32>>,
+    mod_frames: Vec<usize>,
+}
+
+impl InputAlignment {
+    pub fn new(id: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        InputAlignment {
+            id,
+            channels,
+            sample_rate: 44100,
+            invert: vec![false; channels],
+            delay: vec![0.0; channels],
+            delay_seconds: vec![0.0; channels],
+            // Power of two so the read/write positions wrap with a mask.
+            lines: vec![vec![0.0; MAX_ALIGN_DELAY.next_power_of_two() * 2]; channels],
+            write_pos: 0,
+            delay_params: (0..channels as u32).map(|ch| ch * 2 + 1).collect(),
+            modulation: vec![Vec::new(); channels],
+            mod_frames: vec![0; channels],
+        }
+    }
+
+    pub fn set_polarity(&mut self, channel: usize, invert: bool) {
+        if let Some(p) = self.invert.get_mut(channel) { *p = invert; }
+    }
+
+    /// Sets the delay in samples at the current sample rate.
+    pub fn set_delay(&mut self, channel: usize, samples: f32) {
+        self.set_delay_seconds(channel, samples.max(0.0) / self.sample_rate as f32);
+    }
+
+    /// Sets the delay in seconds. It is kept as requested; only the samples used at the current rate are limited
+    /// to what the delay lines hold, so a delay cut short at a high rate is whole again at a lower one.
+    pub fn set_delay_seconds(&mut self, channel: usize, seconds: f32) {
+        if channel >= self.channels { return; }
+        self.delay_seconds[channel] = seconds.max(0.0);
+        self.delay[channel] = (self.delay_seconds[channel] * self.sample_rate as f32).min((MAX_ALIGN_DELAY - 2) as f32);
+    }
+
+    /// Applies a suggestion from `auto_align` to `channel`.
+    pub fn apply(&mut self, channel: usize, alignment: &Alignment) {
+        self.set_polarity(channel, alignment.invert);
+        self.set_delay(channel, alignment.delay_samples);
+    }
+
+    /// 4-point cubic Hermite read `delay` samples behind the newest written sample.
+    fn read(line: &[f32], newest: usize, delay: f32) -> f32 {
+        let mask = line.len() - 1;
+        let whole = delay.floor() as usize;
+        let frac = delay - whole as f32;
+        let at = |offset: isize| line[(newest as isize - whole as isize - offset) as usize & mask];
+
+        // Under one sample there is no newer neighbour yet, so fall back to linear.
+        if whole == 0 {
+            return at(0) + frac * (at(1) - at(0));
+        }
+
+        let (xm1, x0, x1, x2) = (at(-1), at(0), at(1), at(2));
+        let c1 = 0.5 * (x1 - xm1);
+        let c2 = xm1 - 2.5 * x0 + 2.0 * x1 - 0.5 * x2;
+        let c3 = 0.5 * (x2 - xm1) + 1.5 * (x0 - x1);
+        ((c3 * frac + c2) * frac + c1) * frac + x0
+    }
+}
+
+impl AudioNode for InputAlignment {
+    fn prepare(&mut self, sample_rate: u32, max_block_size: usize) {
+        self.sample_rate = sample_rate.max(1);
+        for m in self.modulation.iter_mut() {
+            m.resize(max_block_size, 0.0);
+        }
+        for ch in 0..self.channels {
+            let seconds = self.delay_seconds[ch];
+            self.set_delay_seconds(ch, seconds);
+        }
+    }
+
+    /// Channels added by a wider layout start unaligned; settings of channels that remain are kept.
+    fn set_channel_layout(&mut self, layout: ChannelLayout) {
+        let channels = layout.channels();
+        self.channels = channels;
+        self.invert.resize(channels, false);
+        self.delay.resize(channels, 0.0);
+        self.delay_seconds.resize(channels, 0.0);
+        let line_len = self.lines[0].len();
+        self.lines.resize(channels, vec![0.0; line_len]);
+        self.delay_params = (0..channels as u32).map(|ch| ch * 2 + 1).collect();
+        let block = self.modulation[0].len();
+        self.modulation.resize(channels, vec![0.0; block]);
+        self.mod_frames.resize(channels, 0);
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        let mask = self.lines[0].len() - 1;
+        for (f, frame) in buffer.chunks_mut(self.channels).enumerate() {
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                let line = &mut self.lines[ch];
+                line[self.write_pos] = *sample;
+                let delay = if f < self.mod_frames[ch] {
+                    (self.delay[ch] + self.modulation[ch][f]).clamp(0.0, (MAX_ALIGN_DELAY - 2) as f32)
+                } else {
+                    self.delay[ch]
+                };
+                let delayed = if delay > 0.0 { Self::read(line, self.write_pos, delay) } else { *sample };
+                *sample = if self.invert[ch] { -delayed } else { delayed };
+            }
+            self.write_pos = (self.write_pos + 1) & mask;
+        }
+        self.mod_frames.fill(0);
+    }
+
+    fn set_param(&mut self, param_id: u32, payload: &[u8]) {
+        let Ok(bytes) = payload.try_into() else { return };
+        let value = f32::from_le_bytes(bytes);
+        let channel = (param_id / 2) as usize;
+        match param_id % 2 {
+            0 => self.set_polarity(channel, value >= 0.5),
+            _ => self.set_delay(channel, value),
+        }
+    }
+
+    fn get_id(&self) -> u32 { self.id }
+
+    fn get_name(&self) -> &str { "Input Alignment" }
+
+    fn rt_safety(&self) -> RtSafety { RtSafety::SAFE }
+
+    fn audio_rate_params(&self) -> &[u32] { &self.delay_params }
+
+    /// Polarity is a switch.
+    fn host_smoothing(&self, param_id: u32) -> bool { param_id % 2 == 1 }
+
+    fn set_param_modulation(&mut self, param_id: u32, modulation: &[f32]) {
+        let channel = (param_id / 2) as usize;
+        if param_id % 2 == 0 || channel >= self.channels { return; }
+        let frames = modulation.len().min(self.modulation[channel].len());
+        self.modulation[channel][..frames].copy_from_slice(&modulation[..frames]);
+        self.mod_frames[channel] = frames;
+    }
+
+    /// Empties the delay lines; the settings are kept.
+    fn reset(&mut self) {
+        self.lines.iter_mut().for_each(|line| line.fill(0.0));
+        self.mod_frames.fill(0);
+    }
+
+    fn param_name(&self, param_id: u32) -> Option<String> {
+        let channel = param_id as usize / 2;
+        if channel >= self.channels { return None; }
+        let kind = if param_id % 2 == 0 { "Polarity" } else { "Delay" };
+        Some(format!("Ch {} {}", channel + 1, kind))
+    }
+}
+
+/// Suggested correction for a target input relative to a reference.
+#[derive(Debug, Clone, Copy)]
+pub struct Alignment {
+    /// Delay to apply to the target (negative: the reference should be delayed instead).
+    pub delay_samples: f32,
+    pub invert: bool,
+    /// Normalized correlation at the chosen lag (0.0..1.0).
+    pub confidence: f32,
+}
+
+/// Cross-correlates two captures of the same source and suggests the polarity and sub-sample delay
+/// that best aligns `target` to `reference`. Searches lags in `-max_lag..=max_lag`.
+pub fn auto_align(reference: &[f32], target: &[f32], max_lag: usize) -> Option<Alignment> {
+    let n = reference.len().min(target.len());
+    if n == 0 { return None; }
+    let max_lag = max_lag.min(n - 1) as isize;
+
+    let correlate = |lag: isize| -> f32 {
+        // Positive lag: the target arrives early and has to be delayed by `lag`.
+        let mut sum = 0.0;
+        for i in 0..n as isize {
+            let j = i + lag;
+            if j >= 0 && (j as usize) < n {
+                sum += reference[j as usize] * target[i as usize];
+            }
+        }
+        sum
+    };
+
+    let mut best_lag = 0;
+    let mut best = 0.0f32;
+    for lag in -max_lag..=max_lag {
+        let c = correlate(lag);
+        if c.abs() > best.abs() {
+            best = c;
+            best_lag = lag;
+        }
+    }
+
+    // Parabolic interpolation around the peak for sub-sample resolution.
+    let mut fraction = 0.0;
+    if best_lag > -max_lag && best_lag < max_lag {
+        let (l, c, r) = (correlate(best_lag - 1).abs(), best.abs(), correlate(best_lag + 1).abs());
+        let denom = l - 2.0 * c + r;
+        if denom.abs() > f32::EPSILON {
+            fraction = (0.5 * (l - r) / denom).clamp(-0.5, 0.5);
+        }
+    }
+
+    let energy = (reference[..n].iter().map(|x| x * x).sum::<f32>() * target[..n].iter().map(|x| x * x).sum::<f32>()).sqrt();
+    Some(Alignment {
+        delay_samples: best_lag as f32 + fraction,
+        invert: best < 0.0,
+        confidence: if energy > 0.0 { (best.abs() / energy).min(1.0) } else { 0.0 },
+    })
+}
@@ -0,0 +1,133 @@
+// allowlist.rs
+
+/* Verified Plugin / Preset Allowlist */
+
+#![allow(warnings)]
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+pub static ALLOWLIST: Lazy<Arc<Mutex<Allowlist>>> = Lazy::new(|| {
+    Arc::new(Mutex::new(Allowlist::new()))
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ArtifactKind {
+    Plugin,
+    Preset,
+}
+
+/// An approved plugin bundle or preset file, pinned to its SHA-256.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AllowEntry {
+    pub kind: ArtifactKind,
+    /// Plugin name (bundle stem) or preset file name.
+    pub name: String,
+    /// Lowercase hex SHA-256 of the file, or of every file in a bundle directory (see `hash_path`).
+    pub sha256: String,
+}
+
+/// For locked-down installations: when enforced, only allowlisted plugins and presets whose contents
+/// still match their recorded hash can be loaded. Internal nodes are always allowed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Allowlist {
+    pub enforced: bool,
+    pub entries: Vec<AllowEntry>,
+    /// Hashes already computed, keyed by path and modification time, so repeated loads don't rehash bundles.
+    #[serde(skip)]
+    cache: HashMap<PathBuf, (SystemTime, String)>,
+}
+
+impl Allowlist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read allowlist {:?}: {}", path, e))?;
+        serde_json::from_str(&text).map_err(|e| format!("Invalid allowlist file {:?}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("Failed to write allowlist {:?}: {}", path, e))
+    }
+
+    /// Approves the artifact at `path` as it is now. Returns the recorded hash.
+    pub fn allow(&mut self, kind: ArtifactKind, name: &str, path: &Path) -> Result<String, String> {
+        let sha256 = self.hash_cached(path)?;
+        self.entries.retain(|e| !(e.kind == kind && e.name == name));
+        self.entries.push(AllowEntry { kind, name: name.to_string(), sha256: sha256.clone() });
+        Ok(sha256)
+    }
+
+    pub fn revoke(&mut self, kind: ArtifactKind, name: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| !(e.kind == kind && e.name == name));
+        self.entries.len() != before
+    }
+
+    pub fn entry(&self, kind: ArtifactKind, name: &str) -> Option<&AllowEntry> {
+        self.entries.iter().find(|e| e.kind == kind && e.name == name)
+    }
+
+    /// Checks that `path` may be loaded as `name`. Always succeeds when the allowlist isn't enforced.
+    pub fn verify(&mut self, kind: ArtifactKind, name: &str, path: &Path) -> Result<(), String> {
+        if !self.enforced { return Ok(()); }
+        let label = match kind {
+            ArtifactKind::Plugin => "Plugin",
+            ArtifactKind::Preset => "Preset",
+        };
+        let expected = self
+            .entry(kind, name)
+            .map(|e| e.sha256.clone())
+            .ok_or(format!("{} '{}' is not on the allowlist", label, name))?;
+        let actual = self.hash_cached(path)?;
+        if actual != expected {
+            return Err(format!(
+                "{} '{}' does not match its allowlisted hash (expected {}, found {}); it was modified or replaced",
+                label, name, expected, actual
+            ));
+        }
+        Ok(())
+    }
+
+    fn hash_cached(&mut self, path: &Path) -> Result<String, String> {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+        if let Some((at, hash)) = self.cache.get(path) {
+            if *at == modified { return Ok(hash.clone()); }
+        }
+        let hash = hash_path(path)?;
+        self.cache.insert(path.to_path_buf(), (modified, hash.clone()));
+        Ok(hash)
+    }
+}
+
+/// SHA-256 of a file, or of a bundle directory: every file's relative path and contents, in sorted path order.
+pub fn hash_path(path: &Path) -> Result<String, String> {
+    let mut hasher = Sha256::new();
+    if path.is_dir() {
+        let mut files: Vec<PathBuf> = WalkDir::new(path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect();
+        files.sort();
+        for file in files {
+            let relative = file.strip_prefix(path).unwrap_or(&file);
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.update(fs::read(&file).map_err(|e| format!("Failed to read {:?}: {}", file, e))?);
+        }
+    } else {
+        hasher.update(fs::read(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
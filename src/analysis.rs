@@ -0,0 +1,298 @@
+// analysis.rs
+
+/* Offline Node Analysis */
+
+#![allow(warnings)]
+
+use std::f64::consts::PI;
+use std::path::Path;
+
+use crate::dspapi::ParamId;
+use crate::dspengine::AudioNode;
+use crate::layout::ChannelLayout;
+use crate::pmanager::PMANAGER;
+use crate::rtsafety::RtSafety;
+use crate::wav;
+
+/// Magnitude and phase curve of a node, ready for plotting.
+#[derive(Debug, Clone)]
+pub struct FrequencyResponse {
+    pub sample_rate: u32,
+    pub freqs: Vec<f32>,
+    pub magnitude_db: Vec<f32>,
+    /// Phase in degrees with the node's reported latency removed, wrapped to -180..180.
+    pub phase_deg: Vec<f32>,
+}
+
+/// Log-spaced frequencies from `min_hz` to `max_hz`, inclusive.
+pub fn log_frequencies(points: usize, min_hz: f32, max_hz: f32) -> Vec<f32> {
+    let points = points.max(2);
+    let ratio = (max_hz / min_hz).ln();
+    (0..points).map(|i| min_hz * (ratio * i as f32 / (points - 1) as f32).exp()).collect()
+}
+
+/// Feeds an impulse through `node` (interleaved, same signal on every channel) and returns channel 0's impulse response.
+pub fn impulse_response(node: &mut dyn AudioNode, sample_rate: u32, channels: usize, block_size: usize, len: usize) -> Vec<f32> {
+    let channels = channels.max(1);
+    node.prepare(sample_rate, block_size);
+
+    let mut buffer = vec![0.0f32; len * channels];
+    buffer[..channels].fill(1.0);
+    for block in buffer.chunks_mut(block_size.max(1) * channels) {
+        node.process(block);
+    }
+    buffer.iter().step_by(channels).copied().collect()
+}
+
+/// Measures a node's magnitude and phase response offline from its impulse response.
+/// The node is prepared and processed, so pass a fresh instance rather than one playing in the rack.
+pub fn probe_response(node: &mut dyn AudioNode, sample_rate: u32, channels: usize, freqs: &[f32]) -> FrequencyResponse {
+    // Prepared first: a node's latency may depend on the rate and block size.
+    node.prepare(sample_rate, 512);
+    let latency = node.latency_samples();
+    // Long enough for the latency plus a reverb-ish tail at the lowest probe frequencies.
+    let len = (latency + sample_rate as usize / 2).next_power_of_two();
+    let ir = impulse_response(node, sample_rate, channels, 512, len);
+
+    let mut magnitude_db = Vec::with_capacity(freqs.len());
+    let mut phase_deg = Vec::with_capacity(freqs.len());
+    for &f in freqs {
+        let w = 2.0 * PI * f as f64 / sample_rate as f64;
+        let (mut re, mut im) = (0.0f64, 0.0f64);
+        for (n, &h) in ir.iter().enumerate() {
+            let angle = w * n as f64;
+            re += h as f64 * angle.cos();
+            im -= h as f64 * angle.sin();
+        }
+
+        let magnitude = (re * re + im * im).sqrt();
+        magnitude_db.push((20.0 * magnitude.max(1.0e-12).log10()) as f32);
+
+        // Remove the pure delay so only the node's own phase shift is shown.
+        let phase = im.atan2(re) + w * latency as f64;
+        let wrapped = (phase + PI).rem_euclid(2.0 * PI) - PI;
+        phase_deg.push(wrapped.to_degrees() as f32);
+    }
+
+    FrequencyResponse {
+        sample_rate,
+        freqs: freqs.to_vec(),
+        magnitude_db,
+        phase_deg,
+    }
+}
+
+/// Creates a fresh instance of a registered or discovered plugin, applies `params` and probes it,
+/// so frontends can show what an EQ or plugin is actually doing without touching the live rack.
+pub fn probe_plugin(name: &str, params: &[(ParamId, f32)], sample_rate: u32, points: usize) -> Result<FrequencyResponse, String> {
+    let mut node = PMANAGER
+        .lock()
+        .map_err(|_| "Plugin manager lock poisoned".to_string())?
+        .create_node(name)
+        .ok_or(format!("Unknown plugin '{}'", name))?;
+
+    for &(param_id, value) in params {
+        node.set_param(param_id, &value.to_le_bytes());
+    }
+
+    let freqs = log_frequencies(points, 20.0, (sample_rate as f32 / 2.0).min(20000.0));
+    Ok(probe_response(node.as_mut(), sample_rate, 2, &freqs))
+}
+
+/// Several nodes processed in series, so whole chains can be measured like a single node.
+pub struct NodeChain {
+    pub nodes: Vec<Box<dyn AudioNode>>,
+}
+
+impl NodeChain {
+    pub fn new(nodes: Vec<Box<dyn AudioNode>>) -> Self {
+        NodeChain { nodes }
+    }
+}
+
+impl AudioNode for NodeChain {
+    fn prepare(&mut self, sample_rate: u32, max_block_size: usize) {
+        for node in self.nodes.iter_mut() {
+            node.prepare(sample_rate, max_block_size);
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        for node in self.nodes.iter_mut() {
+            node.process(buffer);
+        }
+    }
+
+    fn set_channel_layout(&mut self, layout: ChannelLayout) {
+        for node in self.nodes.iter_mut() {
+            node.set_channel_layout(layout);
+        }
+    }
+
+    fn set_param(&mut self, _param_id: u32, _payload: &[u8]) {}
+
+    fn get_id(&self) -> u32 { 0 }
+
+    fn get_name(&self) -> &str { "Chain" }
+
+    fn latency_samples(&self) -> usize {
+        self.nodes.iter().map(|n| n.latency_samples()).sum()
+    }
+
+    fn rt_safety(&self) -> RtSafety {
+        self.nodes.iter().map(|n| n.rt_safety()).fold(RtSafety::SAFE, RtSafety::combine)
+    }
+
+    fn reset(&mut self) {
+        for node in self.nodes.iter_mut() {
+            node.reset();
+        }
+    }
+}
+
+/// Result of a THD+N measurement.
+#[derive(Debug, Clone)]
+pub struct DistortionReport {
+    /// Test frequency actually used (snapped so the analysis window holds whole cycles).
+    pub fundamental_hz: f32,
+    /// Output level of the fundamental in dBFS (peak).
+    pub fundamental_db: f32,
+    /// Level of harmonics 2, 3, ... relative to the fundamental, in dB. Harmonics above Nyquist are left out.
+    pub harmonics_db: Vec<f32>,
+    /// Harmonic distortion only, in percent.
+    pub thd_percent: f32,
+    /// Everything that isn't the fundamental (harmonics, noise, aliasing), in percent and dB.
+    pub thd_n_percent: f32,
+    pub thd_n_db: f32,
+}
+
+/// Drives `node` with a sine and reports its distortion products. Measure a whole rack by passing a `NodeChain`.
+pub fn measure_thd_n(node: &mut dyn AudioNode, sample_rate: u32, channels: usize, freq: f32, amplitude: f32, harmonics: usize) -> DistortionReport {
+    let channels = channels.max(1);
+    let sr = sample_rate as f64;
+
+    // A quarter second window with a whole number of cycles keeps every harmonic orthogonal, so no window function is needed.
+    let window = sample_rate as usize / 4;
+    let cycles = (freq as f64 * window as f64 / sr).round().max(1.0);
+    let f = cycles * sr / window as f64;
+
+    // Prepared before the latency is read, since it may depend on the rate and block size.
+    node.prepare(sample_rate, 512);
+    let settle = sample_rate as usize / 10 + node.latency_samples();
+    let total = settle + window;
+    let w = 2.0 * PI * f / sr;
+
+    let mut buffer: Vec<f32> = (0..total)
+        .flat_map(|i| std::iter::repeat_n(amplitude * (w * i as f64).sin() as f32, channels))
+        .collect();
+    for block in buffer.chunks_mut(512 * channels) {
+        node.process(block);
+    }
+    let out: Vec<f64> = buffer.iter().skip(settle * channels).step_by(channels).map(|&s| s as f64).collect();
+
+    // Amplitude of the component at `k` times the fundamental, by projection onto sin/cos.
+    let component = |k: f64| -> f64 {
+        let wk = w * k;
+        let (mut s, mut c) = (0.0, 0.0);
+        for (n, &x) in out.iter().enumerate() {
+            let phase = wk * (n + settle) as f64;
+            s += x * phase.sin();
+            c += x * phase.cos();
+        }
+        2.0 * (s * s + c * c).sqrt() / out.len() as f64
+    };
+
+    let mean = out.iter().sum::<f64>() / out.len() as f64;
+    let fundamental = component(1.0);
+    let total_power = out.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / out.len() as f64;
+    let fundamental_power = fundamental * fundamental / 2.0;
+    let residual_power = (total_power - fundamental_power).max(0.0);
+
+    let mut harmonics_db = Vec::new();
+    let mut harmonic_power = 0.0;
+    for k in 2..=harmonics.max(2) {
+        if f * k as f64 >= sr / 2.0 { break; }
+        let a = component(k as f64);
+        harmonic_power += a * a / 2.0;
+        harmonics_db.push((20.0 * (a / fundamental.max(1.0e-12)).max(1.0e-12).log10()) as f32);
+    }
+
+    let ratio = |power: f64| if fundamental_power > 0.0 { (power / fundamental_power).sqrt() } else { 0.0 };
+    let thd_n = ratio(residual_power);
+    DistortionReport {
+        fundamental_hz: f as f32,
+        fundamental_db: (20.0 * fundamental.max(1.0e-12).log10()) as f32,
+        harmonics_db,
+        thd_percent: (ratio(harmonic_power) * 100.0) as f32,
+        thd_n_percent: (thd_n * 100.0) as f32,
+        thd_n_db: (20.0 * thd_n.max(1.0e-12).log10()) as f32,
+    }
+}
+
+/// Result of rendering the same input through two chains and subtracting them.
+#[derive(Debug, Clone)]
+pub struct NullTestReport {
+    pub reference_rms_db: f32,
+    pub residual_rms_db: f32,
+    pub residual_peak_db: f32,
+    /// Residual relative to the reference; more negative means a deeper null.
+    pub null_depth_db: f32,
+    /// Samples `b` was shifted by to line up with `a`, from the difference in reported latency.
+    pub latency_offset: isize,
+}
+
+/// Null test: renders interleaved `input` through `a` and `b`, aligns them by their reported latency,
+/// subtracts and measures the residual. Optionally writes the residual as a float WAV.
+pub fn null_test(
+    a: &mut dyn AudioNode,
+    b: &mut dyn AudioNode,
+    input: &[f32],
+    channels: usize,
+    sample_rate: u32,
+    residual_wav: Option<&Path>,
+) -> Result<NullTestReport, String> {
+    let channels = channels.max(1);
+    let render = |node: &mut dyn AudioNode| {
+        node.prepare(sample_rate, 512);
+        let mut buffer = input.to_vec();
+        for block in buffer.chunks_mut(512 * channels) {
+            node.process(block);
+        }
+        buffer
+    };
+    let out_a = render(a);
+    let out_b = render(b);
+
+    let offset = b.latency_samples() as isize - a.latency_samples() as isize;
+    let frames = out_a.len() / channels;
+    let usable = frames.saturating_sub(offset.unsigned_abs());
+
+    let mut residual = Vec::with_capacity(usable * channels);
+    let mut reference_power = 0.0f64;
+    for frame in 0..usable {
+        let (fa, fb) = if offset >= 0 { (frame, frame + offset as usize) } else { (frame + offset.unsigned_abs(), frame) };
+        for ch in 0..channels {
+            let x = out_a[fa * channels + ch];
+            reference_power += (x as f64) * (x as f64);
+            residual.push(x - out_b[fb * channels + ch]);
+        }
+    }
+
+    let count = residual.len().max(1) as f64;
+    let residual_power = residual.iter().map(|&r| (r as f64) * (r as f64)).sum::<f64>() / count;
+    let reference_power = reference_power / count;
+    let peak = residual.iter().fold(0.0f32, |p, r| p.max(r.abs()));
+    let db = |x: f64| (10.0 * x.max(1.0e-24).log10()) as f32;
+
+    if let Some(path) = residual_wav {
+        wav::write_f32(path, &residual, channels as u16, sample_rate).map_err(|e| format!("Failed to write residual {:?}: {}", path, e))?;
+    }
+
+    Ok(NullTestReport {
+        reference_rms_db: db(reference_power),
+        residual_rms_db: db(residual_power),
+        residual_peak_db: 20.0 * peak.max(1.0e-12).log10(),
+        null_depth_db: db(residual_power) - db(reference_power),
+        latency_offset: offset,
+    })
+}
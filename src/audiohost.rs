@@ -0,0 +1,90 @@
+// audiohost.rs
+
+/* Audio Host Selection */
+
+#![allow(warnings)]
+
+use cpal::traits::{DeviceTrait, HostTrait};
+
+/// Name of cpal's ASIO host, the low-latency choice on Windows where WASAPI shared mode is too slow for live
+/// processing. Only present when cpal is built with its `asio` feature.
+pub const ASIO: &str = "ASIO";
+
+/// Name of cpal's WASAPI host, the Windows default. With the `wasapi` feature its output can be opened
+/// exclusively or in low-latency shared mode (see `wasapibackend`).
+pub const WASAPI: &str = "WASAPI";
+
+/// Name of the native JACK backend (see `jackbackend`), with the `jack` feature. Selecting it opens a JACK client
+/// with a port per engine channel instead of going through cpal; its only "device" is the server, also `JACK`.
+pub const JACK: &str = "JACK";
+
+/// Name of the native PipeWire backend (see `pipewirebackend`), with the `pipewire` feature. Selecting it adds the
+/// engine to the PipeWire graph as nodes of its own instead of going through ALSA's compatibility layer. Its only
+/// "device" is the server, also `PipeWire`; `EngineConfig::output_device` names the sink node to link to.
+pub const PIPEWIRE: &str = "PipeWire";
+
+/// Name of the null backend (see `nullbackend`): no hardware at all, a timer thread drives the engine, e.g. on
+/// servers and CI machines. Its only "device" is also `Null`.
+pub const NULL: &str = "Null";
+
+/// Whether host `name` is the native JACK backend.
+pub fn is_native_jack(name: Option<&str>) -> bool {
+    cfg!(feature = "jack") && name.is_some_and(|n| n.eq_ignore_ascii_case(JACK))
+}
+
+/// Whether host `name` is the native PipeWire backend.
+pub fn is_native_pipewire(name: Option<&str>) -> bool {
+    cfg!(feature = "pipewire") && name.is_some_and(|n| n.eq_ignore_ascii_case(PIPEWIRE))
+}
+
+/// Whether host `name` is the null backend.
+pub fn is_null(name: Option<&str>) -> bool {
+    name.is_some_and(|n| n.eq_ignore_ascii_case(NULL))
+}
+
+/// Whether host `name` is one of the backends opened natively rather than through cpal.
+pub fn is_native(name: Option<&str>) -> bool {
+    is_native_jack(name) || is_native_pipewire(name) || is_null(name)
+}
+
+/// Host a new engine opens its streams on: ASIO with the `asio` feature on Windows, otherwise the system
+/// default (`None`).
+pub fn preferred_host() -> Option<String> {
+    if cfg!(all(windows, feature = "asio")) && available_hosts().iter().any(|h| h == ASIO) {
+        Some(ASIO.to_string())
+    } else {
+        None
+    }
+}
+
+/// Names of the audio hosts (driver APIs: WASAPI, ASIO, ALSA, JACK, CoreAudio, ...) available on this system.
+pub fn available_hosts() -> Vec<String> {
+    let mut hosts: Vec<String> = cpal::available_hosts().iter().map(|id| id.name().to_string()).collect();
+    if cfg!(feature = "jack") && !hosts.iter().any(|h| is_native_jack(Some(h))) { hosts.push(JACK.to_string()); }
+    if cfg!(feature = "pipewire") { hosts.push(PIPEWIRE.to_string()); }
+    hosts.push(NULL.to_string());
+    hosts
+}
+
+/// Opens host `name` (matched ignoring case; `None` for the system default). The native JACK and PipeWire
+/// backends and the null backend aren't cpal hosts and are refused.
+pub fn host(name: Option<&str>) -> Result<cpal::Host, String> {
+    let Some(name) = name else { return Ok(cpal::default_host()) };
+    if is_native(Some(name)) { return Err(format!("{} is opened natively, not through cpal", name)); }
+    let id = cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or(format!("Audio host '{}' is not available", name))?;
+    cpal::host_from_id(id).map_err(|e| e.to_string())
+}
+
+/// Names of the output devices of host `name` (`None` for the system default); empty if it can't be opened.
+pub fn output_devices(name: Option<&str>) -> Vec<String> {
+    if is_native_jack(name) { return vec![JACK.to_string()]; }
+    if is_native_pipewire(name) { return vec![PIPEWIRE.to_string()]; }
+    if is_null(name) { return vec![NULL.to_string()]; }
+    host(name)
+        .ok()
+        .and_then(|host| host.output_devices().ok().map(|devices| devices.filter_map(|d| d.name().ok()).collect()))
+        .unwrap_or_default()
+}
@@ -0,0 +1,273 @@
+// audit.rs
+
+/* Command Audit Log and Replay */
+
+#![allow(warnings)]
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::dspapi::{Command, StatState, RESPONSE_QUEUE};
+use crate::graph::GRAPH_OUTPUT;
+use crate::session::{Connection, NodeState, Session};
+use crate::strip::HOST_PARAM_BYPASS;
+use crate::transfer;
+
+/// The active log, if recording. Every command accepted by `Command::send` that changes state is appended, with
+/// the engine it was sent to: one log covers every engine in the process.
+pub static AUDIT: Lazy<Arc<Mutex<Option<AuditLog>>>> = Lazy::new(|| {
+    Arc::new(Mutex::new(None))
+});
+
+/// Engine commands that change state: node, parameter and routing edits, transport and scene recalls.
+/// Chunk commands are left out; the reassembled command is logged when it is sent.
+pub fn is_state_changing(command: &Command) -> bool {
+    command.command_id < 100 && !transfer::is_transfer(command)
+}
+
+/// One line of the log (JSON).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    /// Wall clock time, milliseconds since the Unix epoch.
+    pub unix_ms: u64,
+    /// Milliseconds since recording started, from a monotonic clock; replay timing uses this.
+    pub offset_ms: u64,
+    /// Engine the command was sent to (`EngineHandle::engine_id`); 0 in logs recorded before it was logged.
+    #[serde(default)]
+    pub engine_id: u32,
+    pub command_id: u32,
+    pub description: String,
+    pub node_id: u32,
+    pub param_id: u32,
+    pub port_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl AuditEntry {
+    /// An entry for `command` to engine `engine_id` outside any log (no sequence number or times), e.g. to apply
+    /// it to a session with `replay_into_session` as it is sent.
+    pub fn unlogged(engine_id: u32, command: &Command) -> Self {
+        AuditEntry {
+            seq: 0,
+            unix_ms: 0,
+            offset_ms: 0,
+            engine_id,
+            command_id: command.command_id,
+            description: command.description_text().to_string(),
+            node_id: command.node_id,
+            param_id: command.param_id,
+            port_id: command.port_id,
+            payload: command.payload.clone(),
+        }
+    }
+
+    pub fn to_command(&self) -> Command {
+        Command::new(self.command_id, &self.description, self.payload.clone(), self.node_id, self.param_id, self.port_id, StatState::ACTIVE)
+    }
+}
+
+/// Append-only JSON lines file. Reopening a log continues its sequence numbers; entries are never rewritten.
+pub struct AuditLog {
+    path: PathBuf,
+    file: File,
+    next_seq: u64,
+    started: Instant,
+    /// The last write failed and was reported; the next failure isn't, until a write succeeds.
+    failing: bool,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let next_seq = match File::open(path) {
+            Ok(existing) => BufReader::new(existing).lines().count() as u64,
+            Err(_) => 0,
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open audit log {:?}: {}", path, e))?;
+        Ok(AuditLog { path: path.to_path_buf(), file, next_seq, started: Instant::now(), failing: false })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&mut self, engine_id: u32, command: &Command) -> Result<(), String> {
+        let entry = AuditEntry {
+            seq: self.next_seq,
+            unix_ms: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0),
+            offset_ms: self.started.elapsed().as_millis() as u64,
+            ..AuditEntry::unlogged(engine_id, command)
+        };
+        let line = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+        writeln!(self.file, "{}", line)
+            .and_then(|_| self.file.flush())
+            .map_err(|e| format!("Failed to write audit log {:?}: {}", self.path, e))?;
+        self.next_seq += 1;
+        Ok(())
+    }
+}
+
+/// Starts recording to `path`, replacing any active log.
+pub fn start(path: &Path) -> Result<(), String> {
+    let log = AuditLog::open(path)?;
+    if let Ok(mut audit) = AUDIT.lock() {
+        *audit = Some(log);
+    }
+    Ok(())
+}
+
+pub fn stop() {
+    if let Ok(mut audit) = AUDIT.lock() {
+        *audit = None;
+    }
+}
+
+pub fn is_recording() -> bool {
+    AUDIT.lock().map(|a| a.is_some()).unwrap_or(false)
+}
+
+/// Logs `command`, sent to engine `engine_id`, if recording and it changes state. Write errors don't stop the
+/// command; the first of a run is reported with an Audit Write Failed response.
+pub fn record(engine_id: u32, command: &Command) {
+    if !is_state_changing(command) { return; }
+    let Ok(mut audit) = AUDIT.lock() else { return };
+    let Some(log) = audit.as_mut() else { return };
+    match log.append(engine_id, command) {
+        Ok(()) => log.failing = false,
+        Err(_) if log.failing => {}
+        Err(e) => {
+            log.failing = true;
+            if let Ok(mut queue) = RESPONSE_QUEUE.lock() {
+                queue.push(Command::new(116, "Audit Write Failed", e.into_bytes(), 0, 0, 0, StatState::INACTIVE));
+            }
+        }
+    }
+}
+
+pub fn read_log(path: &Path) -> Result<Vec<AuditEntry>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read audit log {:?}: {}", path, e))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line).map_err(|e| format!("Invalid audit log {:?} line {}: {}", path, i + 1, e)))
+        .collect()
+}
+
+/// Whether `entry` was sent to `engine`; `None` matches every engine.
+fn sent_to(entry: &AuditEntry, engine: Option<u32>) -> bool {
+    engine.is_none_or(|id| entry.engine_id == id)
+}
+
+/// Sends the commands logged for `engine` (every engine for `None`) through `sink` with their original spacing
+/// divided by `speed` (0 or less sends them back to back). Returns how many `sink` accepted.
+pub fn replay(entries: &[AuditEntry], engine: Option<u32>, speed: f32, mut sink: impl FnMut(Command) -> bool) -> usize {
+    let start = Instant::now();
+    let first = entries.iter().find(|e| sent_to(e, engine)).map(|e| e.offset_ms).unwrap_or(0);
+    let mut accepted = 0;
+    for entry in entries.iter().filter(|e| sent_to(e, engine)) {
+        if speed > 0.0 {
+            let due = Duration::from_secs_f64(entry.offset_ms.saturating_sub(first) as f64 / 1000.0 / speed as f64);
+            if let Some(wait) = due.checked_sub(start.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+        if sink(entry.to_command()) {
+            accepted += 1;
+        }
+    }
+    accepted
+}
+
+/// Applies the node, parameter and routing edits logged for `engine` (every engine for `None`) to a session model,
+/// so a show's control actions can be reproduced offline. Transport and scene commands don't change the model and
+/// are skipped. Returns how many entries were applied.
+pub fn replay_into_session(session: &mut Session, entries: &[AuditEntry], engine: Option<u32>) -> usize {
+    let mut applied = 0;
+    for entry in entries.iter().filter(|e| sent_to(e, engine)) {
+        match entry.command_id {
+            0 => {
+                if session.node(entry.node_id).is_none() {
+                    session.nodes.push(NodeState {
+                        id: entry.node_id,
+                        plugin: entry.description.clone(),
+                        params: Default::default(),
+                        state: Vec::new(),
+                        sample_params: Vec::new(),
+                        assets: Default::default(),
+                    });
+                    applied += 1;
+                }
+            }
+            1 => {
+                let before = session.nodes.len();
+                session.nodes.retain(|n| n.id != entry.node_id);
+                session.connections.retain(|c| c.from_node != entry.node_id && c.to_node != entry.node_id);
+                if session.nodes.len() != before { applied += 1; }
+            }
+            2 => {
+                let value = <[u8; 4]>::try_from(entry.payload.as_slice()).map(f32::from_le_bytes);
+                if let (Some(node), Ok(value)) = (session.node_mut(entry.node_id), value) {
+                    node.params.insert(entry.param_id, value);
+                    applied += 1;
+                }
+            }
+            3 | 4 => {
+                // u32 destination node + u32 destination port, as the engine reads it.
+                let word = |at: usize| entry.payload.get(at..at + 4).and_then(|b| b.try_into().ok()).map(u32::from_le_bytes);
+                let (Some(to_node), Some(to_port)) = (word(0), word(4)) else { continue };
+                let connection = Connection { from_node: entry.node_id, from_port: entry.port_id, to_node, to_port };
+                let known = session.connections.contains(&connection);
+                if entry.command_id == 3 && !known {
+                    session.connections.push(connection);
+                    applied += 1;
+                } else if entry.command_id == 4 && known {
+                    session.connections.retain(|c| *c != connection);
+                    applied += 1;
+                }
+            }
+            5 => {
+                if let Some(node) = session.node_mut(entry.node_id) {
+                    node.plugin = entry.description.clone();
+                    node.params.clear();
+                    node.state.clear();
+                    node.sample_params.clear();
+                    node.assets.clear();
+                    applied += 1;
+                }
+            }
+            6 => {
+                session.nodes.clear();
+                session.connections.clear();
+                applied += 1;
+            }
+            7 => {
+                // Runs just before node `before`, or last for `GRAPH_OUTPUT`.
+                let Some(before) = entry.payload.get(..4).and_then(|b| b.try_into().ok()).map(u32::from_le_bytes) else { continue };
+                let Some(from) = session.nodes.iter().position(|n| n.id == entry.node_id) else { continue };
+                if before == entry.node_id || (before != GRAPH_OUTPUT && session.node(before).is_none()) { continue; }
+                let node = session.nodes.remove(from);
+                let to = session.nodes.iter().position(|n| n.id == before).unwrap_or(session.nodes.len());
+                session.nodes.insert(to, node);
+                applied += 1;
+            }
+            8 => {
+                if let Some(node) = session.node_mut(entry.node_id) {
+                    let bypass = entry.payload.first().is_some_and(|&b| b != 0);
+                    node.params.insert(HOST_PARAM_BYPASS, if bypass { 1.0 } else { 0.0 });
+                    applied += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    applied
+}
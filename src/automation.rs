@@ -0,0 +1,315 @@
+// automation.rs
+
+/* Parameter Automation Implementation */
+
+#![allow(warnings)]
+
+use serde::{Deserialize, Serialize};
+
+use crate::dspapi::{NodeId, ParamId};
+use crate::events::TransportInfo;
+
+/// Most parameter changes the automation delivers in one render; further ones wait for the next.
+pub const MAX_BLOCK_CHANGES: usize = 1024;
+/// Default spacing, in frames, of the changes sent while a curve moves.
+pub const DEFAULT_RESOLUTION: usize = 32;
+
+/// The shape of the segment leaving a breakpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CurveShape {
+    /// Straight line to the next breakpoint.
+    Linear,
+    /// Exponential bend. Positive curvature starts slow and ends fast, negative does the opposite.
+    Exponential(f32),
+    /// Cubic bezier with the two inner control values given as a fraction (0.0..1.0) of the segment's range.
+    Bezier { c1: f32, c2: f32 },
+    /// Holds the current value until the next breakpoint, then jumps.
+    Step,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Breakpoint {
+    /// Position in samples on the engine timeline.
+    pub position: u64,
+    pub value: f32,
+    pub shape: CurveShape,
+}
+
+/// A breakpoint curve bound to a single (node, parameter) pair.
+pub struct AutomationLane {
+    pub node_id: NodeId,
+    pub param_id: ParamId,
+    /// When set, every segment behaves as `CurveShape::Step` regardless of its own shape.
+    pub stepped: bool,
+    points: Vec<Breakpoint>,
+    // Index of the segment used by the last evaluation; playback is mostly monotonic so this avoids a search per block.
+    cursor: usize,
+}
+
+impl AutomationLane {
+    pub fn new(node_id: NodeId, param_id: ParamId) -> Self {
+        AutomationLane {
+            node_id,
+            param_id,
+            stepped: false,
+            points: Vec::new(),
+            cursor: 0,
+        }
+    }
+
+    /// Inserts a breakpoint, keeping the lane sorted. A point at an existing position replaces it.
+    pub fn add_point(&mut self, position: u64, value: f32, shape: CurveShape) {
+        let point = Breakpoint { position, value, shape };
+        match self.points.binary_search_by_key(&position, |p| p.position) {
+            Ok(i) => self.points[i] = point,
+            Err(i) => self.points.insert(i, point),
+        }
+        self.cursor = 0;
+    }
+
+    pub fn remove_point(&mut self, position: u64) -> Option<Breakpoint> {
+        let i = self.points.binary_search_by_key(&position, |p| p.position).ok()?;
+        self.cursor = 0;
+        Some(self.points.remove(i))
+    }
+
+    pub fn points(&self) -> &[Breakpoint] {
+        &self.points
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Evaluates the curve at a single sample position.
+    pub fn value_at(&mut self, position: u64) -> Option<f32> {
+        let first = self.points.first()?;
+        if position <= first.position { return Some(first.value); }
+        let last = self.points[self.points.len() - 1];
+        if position >= last.position { return Some(last.value); }
+
+        let i = self.seek(position);
+        let (a, b) = (self.points[i], self.points[i + 1]);
+        let t = (position - a.position) as f32 / (b.position - a.position) as f32;
+        Some(Self::shape_value(self.segment_shape(&a), a.value, b.value, t))
+    }
+
+    /// Fills `out` with one value per sample starting at `start`.
+    /// Linear and exponential segments are generated incrementally so the per-sample cost is a single add or multiply.
+    pub fn render_block(&mut self, start: u64, out: &mut [f32]) -> bool {
+        if self.points.is_empty() { return false; }
+
+        let mut pos = start;
+        let mut written = 0;
+        while written < out.len() {
+            let remaining = out.len() - written;
+            let first = self.points[0];
+            let last = self.points[self.points.len() - 1];
+
+            // Before the first or after the last breakpoint the value is flat.
+            if pos < first.position || pos >= last.position {
+                let flat = if pos < first.position { first.value } else { last.value };
+                let run = if pos < first.position {
+                    ((first.position - pos) as usize).min(remaining)
+                } else {
+                    remaining
+                };
+                out[written..written + run].fill(flat);
+                written += run;
+                pos += run as u64;
+                continue;
+            }
+
+            let i = self.seek(pos);
+            let (a, b) = (self.points[i], self.points[i + 1]);
+            let span = (b.position - a.position) as f32;
+            let run = ((b.position - pos) as usize).min(remaining);
+            let slice = &mut out[written..written + run];
+            let t0 = (pos - a.position) as f32 / span;
+            let dt = 1.0 / span;
+
+            match self.segment_shape(&a) {
+                CurveShape::Step => slice.fill(a.value),
+                CurveShape::Linear => {
+                    let delta = (b.value - a.value) * dt;
+                    let mut v = a.value + (b.value - a.value) * t0;
+                    for s in slice.iter_mut() {
+                        *s = v;
+                        v += delta;
+                    }
+                }
+                CurveShape::Exponential(k) if k.abs() > 1.0e-4 => {
+                    // v(t) = a + (b - a) * (e^(k*t) - 1) / (e^k - 1), with e^(k*t) advanced by a constant factor.
+                    let scale = (b.value - a.value) / (k.exp() - 1.0);
+                    let step = (k * dt).exp();
+                    let mut e = (k * t0).exp();
+                    for s in slice.iter_mut() {
+                        *s = a.value + scale * (e - 1.0);
+                        e *= step;
+                    }
+                }
+                shape => {
+                    let mut t = t0;
+                    for s in slice.iter_mut() {
+                        *s = Self::shape_value(shape, a.value, b.value, t);
+                        t += dt;
+                    }
+                }
+            }
+
+            written += run;
+            pos += run as u64;
+        }
+        true
+    }
+
+    fn segment_shape(&self, point: &Breakpoint) -> CurveShape {
+        if self.stepped { CurveShape::Step } else { point.shape }
+    }
+
+    /// Returns the index of the segment containing `position` (points[i].position <= position < points[i + 1].position).
+    fn seek(&mut self, position: u64) -> usize {
+        let n = self.points.len();
+        let c = self.cursor;
+        if c + 1 < n && self.points[c].position <= position {
+            if position < self.points[c + 1].position { return c; }
+            if c + 2 < n && position < self.points[c + 2].position {
+                self.cursor = c + 1;
+                return c + 1;
+            }
+        }
+        let i = match self.points.binary_search_by_key(&position, |p| p.position) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        self.cursor = i.min(n.saturating_sub(2));
+        self.cursor
+    }
+
+    fn shape_value(shape: CurveShape, a: f32, b: f32, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        let shaped = match shape {
+            CurveShape::Linear => t,
+            CurveShape::Step => 0.0,
+            CurveShape::Exponential(k) => {
+                if k.abs() <= 1.0e-4 { t } else { ((k * t).exp() - 1.0) / (k.exp() - 1.0) }
+            }
+            CurveShape::Bezier { c1, c2 } => {
+                let u = 1.0 - t;
+                3.0 * u * u * t * c1 + 3.0 * u * t * t * c2 + t * t * t
+            }
+        };
+        a + (b - a) * shaped
+    }
+}
+
+/// A parameter value taking effect at `frame` of the render it was produced for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParamChange {
+    pub node_id: NodeId,
+    pub param_id: ParamId,
+    pub frame: u32,
+    pub value: f32,
+}
+
+impl ParamChange {
+    /// The same change at another frame.
+    pub fn at_frame(self, frame: u32) -> Self {
+        ParamChange { frame, ..self }
+    }
+}
+
+/// The automation lanes of an engine, read on the audio thread every render. Lanes are edited from the control
+/// side; evaluating them never allocates once `prepare` has sized the scratch for the largest render.
+pub struct Automation {
+    lanes: Vec<AutomationLane>,
+    /// Last value sent for each lane, so unchanged values aren't sent again.
+    sent: Vec<Option<f32>>,
+    scratch: Vec<f32>,
+    /// Frames between the changes sent while a curve moves; lower is smoother and costs more events.
+    pub resolution: usize,
+}
+
+impl Automation {
+    pub fn new(max_frames: usize) -> Self {
+        Automation { lanes: Vec::new(), sent: Vec::new(), scratch: vec![0.0; max_frames], resolution: DEFAULT_RESOLUTION }
+    }
+
+    /// Sizes the scratch for renders of up to `max_frames`. Call from the control thread.
+    pub fn prepare(&mut self, max_frames: usize) {
+        self.scratch.resize(max_frames, 0.0);
+    }
+
+    pub fn lanes(&self) -> &[AutomationLane] {
+        &self.lanes
+    }
+
+    pub fn lane(&self, node_id: NodeId, param_id: ParamId) -> Option<&AutomationLane> {
+        self.lanes.iter().find(|l| l.node_id == node_id && l.param_id == param_id)
+    }
+
+    /// The lane of `param_id` on `node_id`, created empty if there is none. Edits take effect with the next render.
+    pub fn lane_mut(&mut self, node_id: NodeId, param_id: ParamId) -> &mut AutomationLane {
+        let index = match self.lanes.iter().position(|l| l.node_id == node_id && l.param_id == param_id) {
+            Some(index) => index,
+            None => {
+                self.lanes.push(AutomationLane::new(node_id, param_id));
+                self.sent.push(None);
+                self.lanes.len() - 1
+            }
+        };
+        self.sent[index] = None;
+        &mut self.lanes[index]
+    }
+
+    pub fn remove_lane(&mut self, node_id: NodeId, param_id: ParamId) -> Option<AutomationLane> {
+        let index = self.lanes.iter().position(|l| l.node_id == node_id && l.param_id == param_id)?;
+        self.sent.remove(index);
+        Some(self.lanes.remove(index))
+    }
+
+    /// Removes every lane of `node_id`, e.g. when the node is deleted.
+    pub fn remove_node(&mut self, node_id: NodeId) {
+        let mut index = 0;
+        while index < self.lanes.len() {
+            if self.lanes[index].node_id == node_id {
+                self.lanes.remove(index);
+                self.sent.remove(index);
+            } else {
+                index += 1;
+            }
+        }
+    }
+
+    /// Appends the changes for a render of `frames` starting at the transport's position, in time order. While
+    /// playing, moving curves produce a change every `resolution` frames; while stopped, lanes hold the value at
+    /// the current position. Only values that differ from the last one sent are added.
+    pub fn render(&mut self, transport: &TransportInfo, frames: usize, out: &mut Vec<ParamChange>) {
+        let frames = frames.min(self.scratch.len());
+        let start = out.len();
+        for (lane, sent) in self.lanes.iter_mut().zip(self.sent.iter_mut()) {
+            if !transport.playing {
+                let Some(value) = lane.value_at(transport.position) else { continue };
+                if *sent != Some(value) && insert_in_order(out, start, ParamChange { node_id: lane.node_id, param_id: lane.param_id, frame: 0, value }) {
+                    *sent = Some(value);
+                }
+                continue;
+            }
+            if !lane.render_block(transport.position, &mut self.scratch[..frames]) { continue; }
+            for frame in (0..frames).step_by(self.resolution.max(1)) {
+                let value = self.scratch[frame];
+                if *sent == Some(value) { continue; }
+                if !insert_in_order(out, start, ParamChange { node_id: lane.node_id, param_id: lane.param_id, frame: frame as u32, value }) { break; }
+                *sent = Some(value);
+            }
+        }
+    }
+}
+
+/// Inserts `change` into `out[start..]` after every change at or before its frame. Returns false if `out` is full.
+pub fn insert_in_order(out: &mut Vec<ParamChange>, start: usize, change: ParamChange) -> bool {
+    if out.len() == out.capacity() { return false; }
+    let index = out[start..].iter().rposition(|c| c.frame <= change.frame).map_or(start, |i| start + i + 1);
+    out.insert(index, change);
+    true
+}
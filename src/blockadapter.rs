@@ -0,0 +1,293 @@
+// blockadapter.rs
+
+/* Fixed Block Size Adapter */
+
+#![allow(warnings)]
+
+use crate::dspengine::AudioNode;
+use crate::events::{self, NodeEvent, TransportInfo};
+use crate::layout::ChannelLayout;
+use crate::midi::{self, MidiEvent, MidiWriter};
+use crate::planar::PlanarBuffer;
+use crate::rtsafety::RtSafety;
+
+/// Feeds the wrapped node in blocks of exactly `block_frames`, whatever size the engine calls it with.
+/// Input is accumulated and output is read back one block late, so the adapter adds `block_frames` of latency.
+/// Sidechain input and audio-rate modulation are accumulated alongside and reach the node with their block.
+/// The adapter works interleaved and deinterleaves the blocks for nodes that process planar.
+pub struct FixedBlockAdapter {
+    inner: Box<dyn AudioNode>,
+    block_frames: usize,
+    channels: usize,
+    input: Vec<f32>,
+    output: Vec<f32>,
+    pos: usize,
+    /// MIDI for the block being accumulated and beyond, frames relative to its start.
+    midi: Vec<MidiEvent>,
+    /// MIDI the node produced, frames relative to the current `process` call and possibly beyond it.
+    midi_out: Vec<MidiEvent>,
+    /// Frames in the last `process` call.
+    frames: usize,
+    /// Events for the block being accumulated and beyond, as `midi`, for nodes that handle events.
+    events: Vec<NodeEvent>,
+    /// Per sidechain port (from 1 up): input accumulated like `input`, followed by what arrived beyond the block.
+    /// Sized in `prepare`.
+    sidechains: Vec<Vec<f32>>,
+    /// Sidechain ports given input for the coming `process` call.
+    fed: Vec<bool>,
+    /// Per audio-rate parameter of the node: its id, values accumulated one per frame like `sidechains`, and
+    /// the frames filled. Sized in `prepare`.
+    modulation: Vec<(u32, Vec<f32>, usize)>,
+    /// A block deinterleaved, for nodes that process planar.
+    planar: PlanarBuffer,
+}
+
+impl FixedBlockAdapter {
+    pub fn new(inner: Box<dyn AudioNode>, block_frames: usize, channels: usize) -> Self {
+        let block_frames = block_frames.max(1);
+        let channels = channels.max(1);
+        FixedBlockAdapter {
+            inner,
+            block_frames,
+            channels,
+            input: vec![0.0; block_frames * channels],
+            // Starts as a block of silence: that is the added latency.
+            output: vec![0.0; block_frames * channels],
+            pos: 0,
+            midi: Vec::with_capacity(midi::MAX_BLOCK_EVENTS),
+            midi_out: Vec::with_capacity(midi::MAX_BLOCK_EVENTS),
+            frames: 0,
+            events: Vec::with_capacity(events::MAX_PENDING_EVENTS + midi::MAX_BLOCK_EVENTS),
+            sidechains: Vec::new(),
+            fed: Vec::new(),
+            modulation: Vec::new(),
+            planar: PlanarBuffer::new(channels, block_frames),
+        }
+    }
+
+    pub fn block_frames(&self) -> usize {
+        self.block_frames
+    }
+}
+
+impl AudioNode for FixedBlockAdapter {
+    fn prepare(&mut self, sample_rate: u32, max_block_size: usize) {
+        self.inner.prepare(sample_rate, self.block_frames);
+        // Room for a block being accumulated plus a whole call beyond it.
+        let frames = self.block_frames + max_block_size.max(1);
+        self.sidechains = (1..self.inner.input_ports()).map(|_| vec![0.0; frames * self.channels]).collect();
+        self.fed = vec![false; self.sidechains.len()];
+        self.modulation = self.inner.audio_rate_params().iter().map(|&id| (id, vec![0.0; frames], 0)).collect();
+        self.input.fill(0.0);
+        self.output.fill(0.0);
+        self.pos = 0;
+        self.midi.clear();
+        self.midi_out.clear();
+        self.events.clear();
+    }
+
+    /// Resizes the block buffers; the following `prepare` clears them.
+    fn set_channel_layout(&mut self, layout: ChannelLayout) {
+        self.channels = layout.channels();
+        self.input.resize(self.block_frames * self.channels, 0.0);
+        self.output.resize(self.block_frames * self.channels, 0.0);
+        self.planar.resize(self.channels, self.block_frames);
+        self.inner.set_channel_layout(layout);
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        let block_len = self.block_frames * self.channels;
+        // Ports without input this call are silent for its span.
+        for (sidechain, fed) in self.sidechains.iter_mut().zip(self.fed.iter_mut()) {
+            if !*fed {
+                let end = (self.pos + buffer.len()).min(sidechain.len());
+                sidechain[self.pos..end].fill(0.0);
+            }
+            *fed = false;
+        }
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let run = (block_len - self.pos).min(buffer.len() - offset);
+            let span = self.pos..self.pos + run;
+
+            self.input[span.clone()].copy_from_slice(&buffer[offset..offset + run]);
+            buffer[offset..offset + run].copy_from_slice(&self.output[span]);
+
+            self.pos += run;
+            offset += run;
+
+            if self.pos == block_len {
+                // What arrived beyond this block moves to the front for the next one.
+                let beyond = buffer.len() - offset;
+                for (index, sidechain) in self.sidechains.iter_mut().enumerate() {
+                    self.inner.set_sidechain_input(index as u32 + 1, &sidechain[..block_len]);
+                    let end = (block_len + beyond).min(sidechain.len());
+                    sidechain.copy_within(block_len..end, 0);
+                }
+                for (id, values, filled) in self.modulation.iter_mut() {
+                    if *filled == 0 { continue; }
+                    self.inner.set_param_modulation(*id, &values[..(*filled).min(self.block_frames)]);
+                    if *filled > self.block_frames { values.copy_within(self.block_frames..*filled, 0); }
+                    *filled = filled.saturating_sub(self.block_frames);
+                }
+                if self.inner.accepts_midi() {
+                    let due = self.midi.iter().take_while(|e| (e.frame as usize) < self.block_frames).count();
+                    self.inner.set_midi_input(&self.midi[..due]);
+                    self.midi.drain(..due);
+                    self.midi.iter_mut().for_each(|e| e.frame -= self.block_frames as u32);
+                }
+                if self.inner.handles_events() {
+                    let due = self.events.iter().take_while(|e| (e.frame as usize) < self.block_frames).count();
+                    self.inner.process_events(&mut self.input, &self.events[..due]);
+                    self.events.drain(..due);
+                    self.events.iter_mut().for_each(|e| *e = e.at_frame(e.frame - self.block_frames as u32));
+                } else if self.inner.is_planar() {
+                    self.planar.deinterleave(&self.input);
+                    self.inner.process_planar(&mut self.planar);
+                    self.planar.interleave(&mut self.input);
+                } else {
+                    self.inner.process(&mut self.input);
+                }
+                if self.inner.produces_midi() {
+                    // The block just processed is heard from `offset` on, so its MIDI is shifted to match.
+                    let start = self.midi_out.len();
+                    self.inner.take_midi_output(&mut MidiWriter::new(&mut self.midi_out));
+                    let shift = (offset / self.channels) as u32;
+                    self.midi_out[start..].iter_mut().for_each(|e| e.frame += shift);
+                }
+                std::mem::swap(&mut self.input, &mut self.output);
+                self.pos = 0;
+            }
+        }
+        self.frames = buffer.len() / self.channels;
+    }
+
+    fn set_param(&mut self, param_id: u32, payload: &[u8]) {
+        self.inner.set_param(param_id, payload);
+    }
+
+    fn get_id(&self) -> u32 { self.inner.get_id() }
+
+    fn get_name(&self) -> &str { self.inner.get_name() }
+
+    fn param_name(&self, param_id: u32) -> Option<String> { self.inner.param_name(param_id) }
+
+    fn rt_safety(&self) -> RtSafety { self.inner.rt_safety() }
+
+    fn audio_rate_params(&self) -> &[u32] { self.inner.audio_rate_params() }
+
+    /// Queues the values at their offset in the block being accumulated; they reach the node with that block.
+    fn set_param_modulation(&mut self, param_id: u32, modulation: &[f32]) {
+        let frame = self.pos / self.channels;
+        let Some((_, values, filled)) = self.modulation.iter_mut().find(|(id, _, _)| *id == param_id) else { return };
+        let len = modulation.len().min(values.len() - frame);
+        values[frame..frame + len].copy_from_slice(&modulation[..len]);
+        *filled = frame + len;
+    }
+
+    fn input_ports(&self) -> usize { self.inner.input_ports() }
+
+    fn output_ports(&self) -> usize { self.inner.output_ports() }
+
+    /// Queues the input at its offset in the block being accumulated, like the main input.
+    fn set_sidechain_input(&mut self, port: u32, input: &[f32]) {
+        let Some(index) = (port as usize).checked_sub(1).filter(|&i| i < self.sidechains.len()) else { return };
+        let sidechain = &mut self.sidechains[index];
+        let len = input.len().min(sidechain.len() - self.pos);
+        sidechain[self.pos..self.pos + len].copy_from_slice(&input[..len]);
+        self.fed[index] = true;
+    }
+
+    fn accepts_midi(&self) -> bool { self.inner.accepts_midi() }
+
+    /// Queues the events at their offset in the block being accumulated; they reach the node with that block.
+    fn set_midi_input(&mut self, events: &[MidiEvent]) {
+        let offset = (self.pos / self.channels) as u32;
+        for event in events {
+            if self.midi.len() == self.midi.capacity() { break; }
+            self.midi.push(event.at_frame(event.frame + offset));
+        }
+    }
+
+    fn produces_midi(&self) -> bool { self.inner.produces_midi() }
+
+    fn set_transport(&mut self, transport: &TransportInfo) {
+        self.inner.set_transport(transport);
+    }
+
+    fn handles_events(&self) -> bool { self.inner.handles_events() }
+
+    fn host_smoothing(&self, param_id: u32) -> bool { self.inner.host_smoothing(param_id) }
+
+    /// Queues the events like `set_midi_input` and processes `audio`; they reach the node with their block.
+    fn process_events(&mut self, audio: &mut [f32], events: &[NodeEvent]) {
+        let offset = (self.pos / self.channels) as u32;
+        for event in events.iter().take(self.events.capacity() - self.events.len()) {
+            self.events.push(event.at_frame(event.frame + offset));
+        }
+        self.process(audio);
+    }
+
+    /// Hands out the events due in the last `process` call and keeps the rest for the next one.
+    fn take_midi_output(&mut self, out: &mut MidiWriter) {
+        let frames = self.frames as u32;
+        for event in self.midi_out.iter().filter(|e| e.frame < frames) {
+            out.push(event.frame, event.bytes());
+        }
+        self.midi_out.retain(|e| e.frame >= frames);
+        self.midi_out.iter_mut().for_each(|e| e.frame -= frames);
+    }
+
+    fn latency_samples(&self) -> usize {
+        self.block_frames + self.inner.latency_samples()
+    }
+
+    /// Drops the block being accumulated, the one being played back, and the MIDI, events, sidechain input and
+    /// modulation waiting for either.
+    fn reset(&mut self) {
+        self.input.fill(0.0);
+        self.output.fill(0.0);
+        self.sidechains.iter_mut().for_each(|sidechain| sidechain.fill(0.0));
+        self.modulation.iter_mut().for_each(|(_, _, filled)| *filled = 0);
+        self.pos = 0;
+        self.midi.clear();
+        self.midi_out.clear();
+        self.events.clear();
+        self.inner.reset();
+    }
+}
+
+/// The pull-side counterpart for a whole render: hands out any number of samples while `source` is only ever
+/// asked for exactly `block_frames`. Frames left over from a block are kept for the next call, so this adds
+/// less than a block of latency, and none when callers already ask in whole blocks.
+pub struct BlockFifo {
+    block: Vec<f32>,
+    read: usize,
+}
+
+impl BlockFifo {
+    pub fn new(block_frames: usize, channels: usize) -> Self {
+        let len = block_frames.max(1) * channels.max(1);
+        BlockFifo { block: vec![0.0; len], read: len }
+    }
+
+    /// Samples rendered but not yet handed out.
+    pub fn buffered(&self) -> usize {
+        self.block.len() - self.read
+    }
+
+    /// Fills `output` completely, rendering blocks from `source` whenever the leftovers run out.
+    pub fn render(&mut self, output: &mut [f32], mut source: impl FnMut(&mut [f32])) {
+        let mut done = 0;
+        while done < output.len() {
+            if self.read == self.block.len() {
+                source(&mut self.block);
+                self.read = 0;
+            }
+            let run = (self.block.len() - self.read).min(output.len() - done);
+            output[done..done + run].copy_from_slice(&self.block[self.read..self.read + run]);
+            self.read += run;
+            done += run;
+        }
+    }
+}
@@ -0,0 +1,53 @@
+// bus.rs
+
+/* Aux Send/Return Buses */
+
+#![allow(warnings)]
+
+use crate::dspapi::NodeId;
+use crate::dspengine::AudioNode;
+use crate::rtsafety::RtSafety;
+
+/// Node type to pass to Add Node for a return bus. The command's `node_id` must be a `bus_id`.
+pub const RETURN_BUS: &str = "Return Bus";
+/// Node ids from here up, `MAX_BUSES` of them, are reserved for return buses.
+pub const BUS_ID_BASE: NodeId = 0xFFFF_0000;
+pub const MAX_BUSES: u32 = 64;
+
+/// Node id of return bus `index`.
+pub fn bus_id(index: u32) -> Option<NodeId> {
+    (index < MAX_BUSES).then(|| BUS_ID_BASE + index)
+}
+
+/// Index of the return bus with node id `id`.
+pub fn bus_index(id: NodeId) -> Option<u32> {
+    id.checked_sub(BUS_ID_BASE).filter(|&index| index < MAX_BUSES)
+}
+
+/// Summing point for sends (see `strip::HOST_PARAM_SEND_BASE`): the graph mixes every send into its input and
+/// it passes that on unchanged. It starts out routed to the graph output; effects such as a shared reverb are
+/// connected after it, and its host gain works as the return level.
+pub struct ReturnBus {
+    id: NodeId,
+}
+
+impl ReturnBus {
+    pub fn new(id: NodeId) -> Self {
+        ReturnBus { id }
+    }
+}
+
+impl AudioNode for ReturnBus {
+    fn process(&mut self, _buffer: &mut [f32]) {}
+
+    fn set_param(&mut self, _param_id: u32, _payload: &[u8]) {}
+
+    fn get_id(&self) -> u32 { self.id }
+
+    fn get_name(&self) -> &str { RETURN_BUS }
+
+    fn rt_safety(&self) -> RtSafety { RtSafety::SAFE }
+
+    /// Holds no audio: the sends are mixed by the graph, which clears its buffers itself.
+    fn reset(&mut self) {}
+}
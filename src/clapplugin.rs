@@ -0,0 +1,656 @@
+// clapplugin.rs
+
+/* OpenTune as a CLAP Plugin */
+
+#![allow(warnings)]
+
+use std::ffi::{c_char, c_void, CStr};
+use std::ptr;
+use std::slice;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+use once_cell::sync::Lazy;
+
+use crate::audit::{self, AuditEntry};
+use crate::dspapi::{Command, StatState};
+use crate::dspengine::{DspEngine, EngineConfig, EngineHandle, HostRenderer, Transport};
+use crate::midi::MidiEvent;
+use crate::plugtime::{CLAP_TIME_FACTOR, CLAP_TRANSPORT_HAS_SECONDS_TIMELINE, CLAP_TRANSPORT_HAS_TEMPO, CLAP_TRANSPORT_HAS_TIME_SIGNATURE, CLAP_TRANSPORT_IS_PLAYING};
+use crate::session::Session;
+
+// Built as a shared library exporting `clap_entry`:
+//   cargo rustc --lib --release --features clap-plugin --crate-type cdylib
+// then installed as OpenTune.clap (the .so/.dll renamed, or wrapped in a bundle on macOS).
+
+/// CLAP version the plugin was written against.
+const CLAP_VERSION: ClapVersion = ClapVersion { major: 1, minor: 2, revision: 0 };
+const CLAP_PLUGIN_FACTORY_ID: &CStr = c"clap.plugin-factory";
+const CLAP_EXT_AUDIO_PORTS: &CStr = c"clap.audio-ports";
+const CLAP_EXT_NOTE_PORTS: &CStr = c"clap.note-ports";
+const CLAP_EXT_LATENCY: &CStr = c"clap.latency";
+const CLAP_EXT_STATE: &CStr = c"clap.state";
+const CLAP_CORE_EVENT_SPACE_ID: u16 = 0;
+const CLAP_EVENT_NOTE_ON: u16 = 0;
+const CLAP_EVENT_NOTE_OFF: u16 = 1;
+const CLAP_EVENT_MIDI: u16 = 10;
+const CLAP_PROCESS_ERROR: i32 = 0;
+const CLAP_PROCESS_CONTINUE: i32 = 1;
+const CLAP_AUDIO_PORT_IS_MAIN: u32 = 1 << 0;
+const CLAP_NOTE_DIALECT_CLAP: u32 = 1 << 0;
+const CLAP_NOTE_DIALECT_MIDI: u32 = 1 << 1;
+const CLAP_NAME_SIZE: usize = 256;
+
+/// Id hosts store in projects; never change it.
+pub const PLUGIN_ID: &CStr = c"org.opentune.rack";
+/// Rate and block size an instance starts with, until the host activates it with its own.
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+const DEFAULT_BLOCK: usize = 512;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ClapVersion {
+    major: u32,
+    minor: u32,
+    revision: u32,
+}
+
+#[repr(C)]
+pub struct ClapPluginEntry {
+    clap_version: ClapVersion,
+    init: unsafe extern "C" fn(plugin_path: *const c_char) -> bool,
+    deinit: unsafe extern "C" fn(),
+    get_factory: unsafe extern "C" fn(factory_id: *const c_char) -> *const c_void,
+}
+
+#[repr(C)]
+struct ClapPluginFactory {
+    get_plugin_count: unsafe extern "C" fn(factory: *const ClapPluginFactory) -> u32,
+    get_plugin_descriptor: unsafe extern "C" fn(factory: *const ClapPluginFactory, index: u32) -> *const ClapPluginDescriptor,
+    create_plugin: unsafe extern "C" fn(factory: *const ClapPluginFactory, host: *const c_void, plugin_id: *const c_char) -> *const ClapPlugin,
+}
+
+#[repr(C)]
+struct ClapPluginDescriptor {
+    clap_version: ClapVersion,
+    id: *const c_char,
+    name: *const c_char,
+    vendor: *const c_char,
+    url: *const c_char,
+    manual_url: *const c_char,
+    support_url: *const c_char,
+    version: *const c_char,
+    description: *const c_char,
+    /// Null-terminated.
+    features: *const *const c_char,
+}
+
+#[repr(C)]
+struct ClapPlugin {
+    desc: *const ClapPluginDescriptor,
+    plugin_data: *mut c_void,
+    init: unsafe extern "C" fn(plugin: *const ClapPlugin) -> bool,
+    destroy: unsafe extern "C" fn(plugin: *const ClapPlugin),
+    activate: unsafe extern "C" fn(plugin: *const ClapPlugin, sample_rate: f64, min_frames: u32, max_frames: u32) -> bool,
+    deactivate: unsafe extern "C" fn(plugin: *const ClapPlugin),
+    start_processing: unsafe extern "C" fn(plugin: *const ClapPlugin) -> bool,
+    stop_processing: unsafe extern "C" fn(plugin: *const ClapPlugin),
+    reset: unsafe extern "C" fn(plugin: *const ClapPlugin),
+    process: unsafe extern "C" fn(plugin: *const ClapPlugin, process: *const ClapProcess) -> i32,
+    get_extension: unsafe extern "C" fn(plugin: *const ClapPlugin, id: *const c_char) -> *const c_void,
+    on_main_thread: unsafe extern "C" fn(plugin: *const ClapPlugin),
+}
+
+#[repr(C)]
+struct ClapProcess {
+    steady_time: i64,
+    frames_count: u32,
+    transport: *const ClapEventTransport,
+    audio_inputs: *const ClapAudioBuffer,
+    audio_outputs: *mut ClapAudioBuffer,
+    audio_inputs_count: u32,
+    audio_outputs_count: u32,
+    in_events: *const ClapInputEvents,
+    out_events: *const c_void,
+}
+
+#[repr(C)]
+struct ClapAudioBuffer {
+    data32: *mut *mut f32,
+    data64: *mut *mut f64,
+    channel_count: u32,
+    latency: u32,
+    constant_mask: u64,
+}
+
+#[repr(C)]
+struct ClapInputEvents {
+    ctx: *mut c_void,
+    size: unsafe extern "C" fn(list: *const ClapInputEvents) -> u32,
+    get: unsafe extern "C" fn(list: *const ClapInputEvents, index: u32) -> *const ClapEventHeader,
+}
+
+#[repr(C)]
+struct ClapEventHeader {
+    size: u32,
+    time: u32,
+    space_id: u16,
+    event_type: u16,
+    flags: u32,
+}
+
+#[repr(C)]
+struct ClapEventTransport {
+    header: ClapEventHeader,
+    flags: u32,
+    song_pos_beats: i64,
+    song_pos_seconds: i64,
+    tempo: f64,
+    tempo_inc: f64,
+    loop_start_beats: i64,
+    loop_end_beats: i64,
+    loop_start_seconds: i64,
+    loop_end_seconds: i64,
+    bar_start: i64,
+    bar_number: i32,
+    tsig_num: u16,
+    tsig_denom: u16,
+}
+
+#[repr(C)]
+struct ClapEventNote {
+    header: ClapEventHeader,
+    note_id: i32,
+    port_index: i16,
+    channel: i16,
+    key: i16,
+    velocity: f64,
+}
+
+#[repr(C)]
+struct ClapEventMidi {
+    header: ClapEventHeader,
+    port_index: u16,
+    data: [u8; 3],
+}
+
+#[repr(C)]
+struct ClapPluginAudioPorts {
+    count: unsafe extern "C" fn(plugin: *const ClapPlugin, is_input: bool) -> u32,
+    get: unsafe extern "C" fn(plugin: *const ClapPlugin, index: u32, is_input: bool, info: *mut ClapAudioPortInfo) -> bool,
+}
+
+#[repr(C)]
+struct ClapAudioPortInfo {
+    id: u32,
+    name: [c_char; CLAP_NAME_SIZE],
+    flags: u32,
+    channel_count: u32,
+    port_type: *const c_char,
+    in_place_pair: u32,
+}
+
+#[repr(C)]
+struct ClapPluginNotePorts {
+    count: unsafe extern "C" fn(plugin: *const ClapPlugin, is_input: bool) -> u32,
+    get: unsafe extern "C" fn(plugin: *const ClapPlugin, index: u32, is_input: bool, info: *mut ClapNotePortInfo) -> bool,
+}
+
+#[repr(C)]
+struct ClapNotePortInfo {
+    id: u32,
+    supported_dialects: u32,
+    preferred_dialect: u32,
+    name: [c_char; CLAP_NAME_SIZE],
+}
+
+#[repr(C)]
+struct ClapPluginLatency {
+    get: unsafe extern "C" fn(plugin: *const ClapPlugin) -> u32,
+}
+
+#[repr(C)]
+struct ClapPluginState {
+    save: unsafe extern "C" fn(plugin: *const ClapPlugin, stream: *const ClapOstream) -> bool,
+    load: unsafe extern "C" fn(plugin: *const ClapPlugin, stream: *const ClapIstream) -> bool,
+}
+
+#[repr(C)]
+struct ClapOstream {
+    ctx: *mut c_void,
+    /// Returns the bytes written, or a negative number on error.
+    write: unsafe extern "C" fn(stream: *const ClapOstream, buffer: *const c_void, size: u64) -> i64,
+}
+
+#[repr(C)]
+struct ClapIstream {
+    ctx: *mut c_void,
+    /// Returns the bytes read, 0 at the end of the stream, or a negative number on error.
+    read: unsafe extern "C" fn(stream: *const ClapIstream, buffer: *mut c_void, size: u64) -> i64,
+}
+
+/// Statics holding C pointers, which are only ever read.
+struct Shared<T>(T);
+unsafe impl<T> Sync for Shared<T> {}
+
+static FEATURES: Shared<[*const c_char; 4]> = Shared([c"audio-effect".as_ptr(), c"instrument".as_ptr(), c"mixing".as_ptr(), ptr::null()]);
+
+static DESCRIPTOR: Shared<ClapPluginDescriptor> = Shared(ClapPluginDescriptor {
+    clap_version: CLAP_VERSION,
+    id: PLUGIN_ID.as_ptr(),
+    name: c"OpenTune".as_ptr(),
+    vendor: c"OpenTune".as_ptr(),
+    url: c"".as_ptr(),
+    manual_url: c"".as_ptr(),
+    support_url: c"".as_ptr(),
+    version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+    description: c"The OpenTune rack: plugin chains, routing and the command API inside a DAW track".as_ptr(),
+    features: &FEATURES.0 as *const _ as *const *const c_char,
+});
+
+static FACTORY: ClapPluginFactory = ClapPluginFactory { get_plugin_count, get_plugin_descriptor, create_plugin };
+static AUDIO_PORTS: ClapPluginAudioPorts = ClapPluginAudioPorts { count: audio_ports_count, get: audio_ports_get };
+static NOTE_PORTS: ClapPluginNotePorts = ClapPluginNotePorts { count: note_ports_count, get: note_ports_get };
+static LATENCY: ClapPluginLatency = ClapPluginLatency { get: latency_get };
+static STATE: ClapPluginState = ClapPluginState { save: state_save, load: state_load };
+
+/// The symbol CLAP hosts look up in the library.
+#[unsafe(no_mangle)]
+pub static clap_entry: ClapPluginEntry = ClapPluginEntry { clap_version: CLAP_VERSION, init: entry_init, deinit: entry_deinit, get_factory: entry_get_factory };
+
+/// Engine ids of plugin instances, clear of the ids applications give their own engines.
+static NEXT_ENGINE_ID: AtomicU32 = AtomicU32::new(0x0C1A_0000);
+static INSTANCES: Lazy<Mutex<Vec<Rack>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// The plugin instances alive in this process, in creation order. Each instance runs its own engine, so the chain
+/// builder reaches a rack through `Rack::send` rather than `Command::send`.
+pub fn instances() -> Vec<Rack> {
+    INSTANCES.lock().map(|instances| instances.clone()).unwrap_or_default()
+}
+
+/// One plugin instance as the chain builder sees it: its engine and the session the host saves with the project.
+#[derive(Clone)]
+pub struct Rack {
+    pub handle: EngineHandle,
+    pub session: Arc<Mutex<Session>>,
+}
+
+impl Rack {
+    /// Sends `command` to the rack's engine and, once queued, applies it to the session (see
+    /// `audit::replay_into_session`). Commands sent through the handle directly aren't saved with the project.
+    pub fn send(&self, command: Command) -> bool {
+        let entry = AuditEntry::unlogged(self.handle.engine_id, &command);
+        if !self.handle.send(command) { return false; }
+        if let Ok(mut session) = self.session.lock() {
+            audit::replay_into_session(&mut session, &[entry], None);
+        }
+        true
+    }
+}
+
+/// One plugin instance. `clap` comes first and is what the host holds a pointer to.
+#[repr(C)]
+struct Instance {
+    clap: ClapPlugin,
+    engine: DspEngine,
+    /// The rack as the host stores it (see `clap.state`), kept up to date by `Rack::send`.
+    session: Arc<Mutex<Session>>,
+    transport: Arc<Transport>,
+    /// Present while the host has the plugin activated.
+    renderer: Option<HostRenderer>,
+    /// One host block, interleaved in the engine layout.
+    scratch: Vec<f32>,
+    midi: Vec<MidiEvent>,
+    channels: usize,
+}
+
+/// # Safety
+/// `plugin` must be one `create_plugin` returned and not yet destroyed.
+unsafe fn instance<'a>(plugin: *const ClapPlugin) -> &'a mut Instance {
+    unsafe { &mut *((*plugin).plugin_data as *mut Instance) }
+}
+
+unsafe extern "C" fn entry_init(_plugin_path: *const c_char) -> bool {
+    true
+}
+
+unsafe extern "C" fn entry_deinit() {}
+
+unsafe extern "C" fn entry_get_factory(factory_id: *const c_char) -> *const c_void {
+    if factory_id.is_null() || unsafe { CStr::from_ptr(factory_id) } != CLAP_PLUGIN_FACTORY_ID {
+        return ptr::null();
+    }
+    &FACTORY as *const ClapPluginFactory as *const c_void
+}
+
+unsafe extern "C" fn get_plugin_count(_factory: *const ClapPluginFactory) -> u32 {
+    1
+}
+
+unsafe extern "C" fn get_plugin_descriptor(_factory: *const ClapPluginFactory, index: u32) -> *const ClapPluginDescriptor {
+    if index == 0 { &DESCRIPTOR.0 } else { ptr::null() }
+}
+
+unsafe extern "C" fn create_plugin(_factory: *const ClapPluginFactory, _host: *const c_void, plugin_id: *const c_char) -> *const ClapPlugin {
+    if plugin_id.is_null() || unsafe { CStr::from_ptr(plugin_id) } != PLUGIN_ID {
+        return ptr::null();
+    }
+    let id = NEXT_ENGINE_ID.fetch_add(1, Ordering::Relaxed);
+    let engine = DspEngine::with_config(id, "CLAP plugin instance", EngineConfig::new(DEFAULT_SAMPLE_RATE, DEFAULT_BLOCK));
+    let instance = Box::into_raw(Box::new(Instance {
+        clap: ClapPlugin {
+            desc: &DESCRIPTOR.0,
+            plugin_data: ptr::null_mut(),
+            init: plugin_init,
+            destroy: plugin_destroy,
+            activate: plugin_activate,
+            deactivate: plugin_deactivate,
+            start_processing: plugin_start_processing,
+            stop_processing: plugin_stop_processing,
+            reset: plugin_reset,
+            process: plugin_process,
+            get_extension: plugin_get_extension,
+            on_main_thread: plugin_on_main_thread,
+        },
+        session: Arc::new(Mutex::new(Session::new("OpenTune", engine.sample_rate))),
+        transport: engine.handle().transport,
+        channels: engine.channel_layout().channels(),
+        midi: Vec::with_capacity(engine.config.midi_queue_capacity.max(1)),
+        engine,
+        renderer: None,
+        scratch: Vec::new(),
+    }));
+    unsafe {
+        (*instance).clap.plugin_data = instance as *mut c_void;
+        &(*instance).clap
+    }
+}
+
+unsafe extern "C" fn plugin_init(plugin: *const ClapPlugin) -> bool {
+    let instance = unsafe { instance(plugin) };
+    if let Ok(mut instances) = INSTANCES.lock() {
+        instances.push(Rack { handle: instance.engine.handle(), session: Arc::clone(&instance.session) });
+    }
+    true
+}
+
+unsafe extern "C" fn plugin_destroy(plugin: *const ClapPlugin) {
+    let instance = unsafe { Box::from_raw((*plugin).plugin_data as *mut Instance) };
+    if let Ok(mut instances) = INSTANCES.lock() {
+        instances.retain(|rack| rack.handle.engine_id != instance.engine.engine_id);
+    }
+    drop(instance);
+}
+
+unsafe extern "C" fn plugin_activate(plugin: *const ClapPlugin, sample_rate: f64, _min_frames: u32, max_frames: u32) -> bool {
+    let instance = unsafe { instance(plugin) };
+    instance.renderer = None;
+    let max_frames = (max_frames as usize).max(1);
+    let prepared = instance.engine.set_sample_rate(sample_rate.round() as u32).and_then(|()| instance.engine.set_buffer_size(max_frames));
+    match prepared.and_then(|()| instance.engine.host_renderer()) {
+        Ok(renderer) => {
+            // Automation positions are in samples at the session's rate.
+            if let Ok(mut session) = instance.session.lock() {
+                session.convert_sample_rate(instance.engine.sample_rate);
+                load_automation(&instance.engine, &session);
+            }
+            instance.renderer = Some(renderer);
+            instance.scratch = vec![0.0; max_frames * instance.channels];
+            true
+        }
+        Err(cause) => {
+            eprintln!("[CLAP] Activation failed: {}", cause);
+            false
+        }
+    }
+}
+
+unsafe extern "C" fn plugin_deactivate(plugin: *const ClapPlugin) {
+    unsafe { instance(plugin) }.renderer = None;
+}
+
+unsafe extern "C" fn plugin_start_processing(_plugin: *const ClapPlugin) -> bool {
+    true
+}
+
+unsafe extern "C" fn plugin_stop_processing(_plugin: *const ClapPlugin) {}
+
+unsafe extern "C" fn plugin_reset(plugin: *const ClapPlugin) {
+    // Stops hanging notes and clears the nodes' tails, as after a panic.
+    if let Some(renderer) = unsafe { instance(plugin) }.renderer.as_ref() {
+        renderer.panic();
+    }
+}
+
+unsafe extern "C" fn plugin_process(plugin: *const ClapPlugin, process: *const ClapProcess) -> i32 {
+    let instance = unsafe { instance(plugin) };
+    let (Some(renderer), Some(process)) = (instance.renderer.as_mut(), unsafe { process.as_ref() }) else { return CLAP_PROCESS_ERROR };
+    let channels = instance.channels;
+    let frames = process.frames_count as usize;
+    let Some(io) = instance.scratch.get_mut(..frames * channels) else { return CLAP_PROCESS_ERROR };
+
+    io.fill(0.0);
+    if process.audio_inputs_count > 0 {
+        if let Some(input) = unsafe { process.audio_inputs.as_ref() } {
+            for (channel, data) in unsafe { channel_pointers(input) }.iter().take(channels).enumerate() {
+                let Some(data) = (unsafe { data.as_ref() }) else { continue };
+                let samples = unsafe { slice::from_raw_parts(data, frames) };
+                for (frame, &sample) in samples.iter().enumerate() {
+                    io[frame * channels + channel] = sample;
+                }
+            }
+        }
+    }
+
+    instance.midi.clear();
+    if let Some(events) = unsafe { process.in_events.as_ref() } {
+        for index in 0..unsafe { (events.size)(events) } {
+            if instance.midi.len() == instance.midi.capacity() { break; }
+            if let Some(event) = unsafe { midi_event((events.get)(events, index)) } {
+                instance.midi.push(event);
+            }
+        }
+    }
+    if let Some(transport) = unsafe { process.transport.as_ref() } {
+        follow_transport(&instance.transport, transport, instance.engine.sample_rate);
+    }
+
+    renderer.process(io, &instance.midi);
+
+    if process.audio_outputs_count > 0 {
+        if let Some(output) = unsafe { process.audio_outputs.as_ref() } {
+            for (channel, &data) in unsafe { channel_pointers(output) }.iter().enumerate() {
+                if data.is_null() { continue; }
+                let samples = unsafe { slice::from_raw_parts_mut(data, frames) };
+                if channel < channels {
+                    for (frame, sample) in samples.iter_mut().enumerate() {
+                        *sample = io[frame * channels + channel];
+                    }
+                } else {
+                    samples.fill(0.0);
+                }
+            }
+        }
+    }
+    CLAP_PROCESS_CONTINUE
+}
+
+unsafe extern "C" fn plugin_get_extension(_plugin: *const ClapPlugin, id: *const c_char) -> *const c_void {
+    if id.is_null() { return ptr::null(); }
+    let id = unsafe { CStr::from_ptr(id) };
+    if id == CLAP_EXT_AUDIO_PORTS {
+        &AUDIO_PORTS as *const ClapPluginAudioPorts as *const c_void
+    } else if id == CLAP_EXT_NOTE_PORTS {
+        &NOTE_PORTS as *const ClapPluginNotePorts as *const c_void
+    } else if id == CLAP_EXT_LATENCY {
+        &LATENCY as *const ClapPluginLatency as *const c_void
+    } else if id == CLAP_EXT_STATE {
+        &STATE as *const ClapPluginState as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn plugin_on_main_thread(_plugin: *const ClapPlugin) {}
+
+/// One main port each way, in the engine's channel layout.
+unsafe extern "C" fn audio_ports_count(_plugin: *const ClapPlugin, _is_input: bool) -> u32 {
+    1
+}
+
+unsafe extern "C" fn audio_ports_get(plugin: *const ClapPlugin, index: u32, is_input: bool, info: *mut ClapAudioPortInfo) -> bool {
+    let (instance, Some(info)) = (unsafe { instance(plugin) }, unsafe { info.as_mut() }) else { return false };
+    if index != 0 { return false; }
+    info.id = 0;
+    write_name(&mut info.name, if is_input { "Input" } else { "Output" });
+    info.flags = CLAP_AUDIO_PORT_IS_MAIN;
+    info.channel_count = instance.channels as u32;
+    info.port_type = match instance.channels {
+        1 => c"mono".as_ptr(),
+        2 => c"stereo".as_ptr(),
+        _ => ptr::null(),
+    };
+    info.in_place_pair = 0;
+    true
+}
+
+/// One MIDI input feeding the nodes that accept MIDI; CLAP notes are converted to MIDI.
+unsafe extern "C" fn note_ports_count(_plugin: *const ClapPlugin, is_input: bool) -> u32 {
+    is_input as u32
+}
+
+unsafe extern "C" fn note_ports_get(_plugin: *const ClapPlugin, index: u32, is_input: bool, info: *mut ClapNotePortInfo) -> bool {
+    let Some(info) = (unsafe { info.as_mut() }) else { return false };
+    if index != 0 || !is_input { return false; }
+    info.id = 0;
+    info.supported_dialects = CLAP_NOTE_DIALECT_CLAP | CLAP_NOTE_DIALECT_MIDI;
+    info.preferred_dialect = CLAP_NOTE_DIALECT_MIDI;
+    write_name(&mut info.name, "MIDI In");
+    true
+}
+
+/// The rack's latency, so the host compensates for it. Queried by the host after activation.
+unsafe extern "C" fn latency_get(plugin: *const ClapPlugin) -> u32 {
+    unsafe { instance(plugin) }.engine.latency_samples() as u32
+}
+
+/// Writes the session as JSON, the same text `Session::save` stores.
+unsafe extern "C" fn state_save(plugin: *const ClapPlugin, stream: *const ClapOstream) -> bool {
+    let (instance, Some(stream)) = (unsafe { instance(plugin) }, unsafe { stream.as_ref() }) else { return false };
+    let Ok(Ok(text)) = instance.session.lock().map(|session| session.to_json()) else { return false };
+    let mut bytes = text.as_bytes();
+    while !bytes.is_empty() {
+        let written = unsafe { (stream.write)(stream, bytes.as_ptr() as *const c_void, bytes.len() as u64) };
+        if written <= 0 { return false; }
+        bytes = &bytes[(written as usize).min(bytes.len())..];
+    }
+    true
+}
+
+/// Reads a session `state_save` wrote, makes it the active one and rebuilds the rack from it. The engine swaps
+/// the rack at the start of the next processed block.
+unsafe extern "C" fn state_load(plugin: *const ClapPlugin, stream: *const ClapIstream) -> bool {
+    let (instance, Some(stream)) = (unsafe { instance(plugin) }, unsafe { stream.as_ref() }) else { return false };
+    let mut bytes = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let read = unsafe { (stream.read)(stream, chunk.as_mut_ptr() as *mut c_void, chunk.len() as u64) };
+        if read < 0 { return false; }
+        if read == 0 { break; }
+        bytes.extend_from_slice(&chunk[..(read as usize).min(chunk.len())]);
+    }
+    let Ok(mut session) = String::from_utf8(bytes).map_err(|e| e.to_string()).and_then(|text| Session::from_json(&text)) else { return false };
+    session.convert_sample_rate(instance.engine.sample_rate);
+    if !rebuild(&instance.engine, &session) { return false; }
+    load_automation(&instance.engine, &session);
+    session.activate();
+    match instance.session.lock() {
+        Ok(mut current) => *current = session,
+        Err(_) => return false,
+    }
+    true
+}
+
+/// Queues the commands that replace the engine's rack with the session's: its nodes, their parameters and the
+/// routing. False if the command queue filled up before all of them were queued.
+fn rebuild(engine: &DspEngine, session: &Session) -> bool {
+    let handle = engine.handle();
+    let mut commands = vec![Command::new(6, "Clear Rack", Vec::new(), 0, 0, 0, StatState::ACTIVE)];
+    for node in &session.nodes {
+        commands.push(Command::new(0, &node.plugin, Vec::new(), node.id, 0, 0, StatState::ACTIVE));
+        for (&param_id, value) in &node.params {
+            commands.push(Command::new(2, "Set Parameter", value.to_le_bytes().to_vec(), node.id, param_id, 0, StatState::ACTIVE));
+        }
+    }
+    for connection in &session.connections {
+        let mut payload = connection.to_node.to_le_bytes().to_vec();
+        payload.extend_from_slice(&connection.to_port.to_le_bytes());
+        commands.push(Command::new(3, "Connect", payload, connection.from_node, 0, connection.from_port, StatState::ACTIVE));
+    }
+    commands.into_iter().all(|command| handle.send(command))
+}
+
+/// Replaces the engine's automation lanes with the session's.
+fn load_automation(engine: &DspEngine, session: &Session) {
+    let Ok(mut automation) = engine.automation.lock() else { return };
+    while let Some((node_id, param_id)) = automation.lanes().first().map(|lane| (lane.node_id, lane.param_id)) {
+        automation.remove_lane(node_id, param_id);
+    }
+    for lane in &session.automation {
+        *automation.lane_mut(lane.node_id, lane.param_id) = lane.to_lane();
+    }
+}
+
+/// The channel pointers of a host buffer, empty when it has no 32-bit data.
+unsafe fn channel_pointers<'a>(buffer: &'a ClapAudioBuffer) -> &'a [*mut f32] {
+    if buffer.data32.is_null() { return &[]; }
+    unsafe { slice::from_raw_parts(buffer.data32, buffer.channel_count as usize) }
+}
+
+/// A CLAP note or MIDI event as a `MidiEvent` at its frame, or `None` for other events.
+unsafe fn midi_event(header: *const ClapEventHeader) -> Option<MidiEvent> {
+    let header = unsafe { header.as_ref() }?;
+    if header.space_id != CLAP_CORE_EVENT_SPACE_ID { return None; }
+    let mut event = match header.event_type {
+        CLAP_EVENT_MIDI => {
+            let midi = unsafe { &*(header as *const ClapEventHeader as *const ClapEventMidi) };
+            let len = match midi.data[0] & 0xF0 {
+                0xC0 | 0xD0 => 2,
+                _ => 3,
+            };
+            MidiEvent::new(0, midi.port_index, &midi.data[..len])?
+        }
+        CLAP_EVENT_NOTE_ON | CLAP_EVENT_NOTE_OFF => {
+            let note = unsafe { &*(header as *const ClapEventHeader as *const ClapEventNote) };
+            // Wildcard keys address every note, which MIDI has no message for.
+            if !(0..128).contains(&note.key) { return None; }
+            let status = if header.event_type == CLAP_EVENT_NOTE_ON { 0x90 } else { 0x80 };
+            let velocity = (note.velocity * 127.0).round().clamp(if status == 0x90 { 1.0 } else { 0.0 }, 127.0) as u8;
+            MidiEvent::new(0, note.port_index.max(0) as u16, &[status | note.channel.clamp(0, 15) as u8, note.key as u8, velocity])?
+        }
+        _ => return None,
+    };
+    event.frame = header.time;
+    Some(event)
+}
+
+/// Makes the engine transport follow the host's: play state, tempo, time signature and position.
+fn follow_transport(transport: &Transport, host: &ClapEventTransport, sample_rate: u32) {
+    if host.flags & CLAP_TRANSPORT_IS_PLAYING != 0 { transport.play(); } else { transport.stop(); }
+    if host.flags & CLAP_TRANSPORT_HAS_TEMPO != 0 {
+        let _ = transport.set_tempo(host.tempo as f32);
+    }
+    if host.flags & CLAP_TRANSPORT_HAS_TIME_SIGNATURE != 0 {
+        let _ = transport.set_time_signature(host.tsig_num, host.tsig_denom);
+    }
+    if host.flags & CLAP_TRANSPORT_HAS_SECONDS_TIMELINE != 0 {
+        let seconds = host.song_pos_seconds as f64 / CLAP_TIME_FACTOR;
+        transport.locate((seconds * sample_rate as f64).round().max(0.0) as u64);
+    }
+}
+
+fn write_name(target: &mut [c_char; CLAP_NAME_SIZE], name: &str) {
+    target.fill(0);
+    for (slot, &byte) in target.iter_mut().zip(name.as_bytes().iter().take(CLAP_NAME_SIZE - 1)) {
+        *slot = byte as c_char;
+    }
+}
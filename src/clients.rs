@@ -0,0 +1,223 @@
+// clients.rs
+
+/* Remote Client Sessions */
+
+#![allow(warnings)]
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use once_cell::sync::Lazy;
+
+use crate::dspapi::{Command, StatState};
+use crate::graph::GRAPH_CAPTURE;
+use crate::taps::{MeterReading, TapPoint};
+use crate::transfer::{self, Reassembler, TransferStatus};
+
+pub static CLIENTS: Lazy<Arc<Mutex<ClientHub>>> = Lazy::new(|| {
+    Arc::new(Mutex::new(ClientHub::new()))
+});
+
+pub type ClientId = u32;
+
+/// Meter frames (and transfer progress, node loads and latency) each client may have queued before the oldest
+/// are dropped.
+pub const DEFAULT_TELEMETRY_CAPACITY: usize = 256;
+
+/// Telemetry is superseded by the next frame, so it may be dropped when a client falls behind.
+/// Everything else changes state and is always delivered.
+pub fn is_telemetry(event: &Command) -> bool {
+    matches!(event.command_id, 107 | 109 | 112 | 115)
+}
+
+/// 109: Meter Frame (u32 tap + f32 peak + f32 rms + u64 timestamp), `node_id` is the tapped node
+/// and `port_id` is 0 for the rack input (`node_id` 0) or captured input (`graph::GRAPH_CAPTURE`), 1 for a node
+/// output, 2 + the port for a connection from it.
+pub fn meter_event(reading: &MeterReading) -> Command {
+    let (node_id, port_id) = match reading.point {
+        TapPoint::Input => (0, 0),
+        TapPoint::Capture => (GRAPH_CAPTURE, 0),
+        TapPoint::AfterNode(node) => (node, 1),
+        TapPoint::Connection { from, from_port } => (from, 2 + from_port),
+    };
+    let mut payload = Vec::with_capacity(20);
+    payload.extend_from_slice(&(reading.tap as u32).to_le_bytes());
+    payload.extend_from_slice(&reading.peak.to_le_bytes());
+    payload.extend_from_slice(&reading.rms.to_le_bytes());
+    payload.extend_from_slice(&reading.timestamp.to_le_bytes());
+    Command::new(109, "Meter Frame", payload, node_id, 0, port_id, StatState::ACTIVE)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientRole {
+    /// Full control: commands are forwarded to the engine.
+    Controller,
+    /// Read-only: receives everything a controller does, but its commands are rejected.
+    Observer,
+}
+
+pub struct Client {
+    pub id: ClientId,
+    pub name: String,
+    pub role: ClientRole,
+    /// Pending events in publish order.
+    outbox: VecDeque<Command>,
+    telemetry_queued: usize,
+    /// Telemetry frames dropped because the client didn't keep up.
+    pub dropped: u64,
+    /// Chunked transfers in flight; transfer ids are per client.
+    transfers: Reassembler,
+}
+
+/// Connected frontends and remote viewers. Engine responses and the commands accepted from any controller are
+/// fanned out to every client, so observers (a collaborator, an instructor) see the session change live.
+/// While clients are connected the hub should be the only consumer of `RESPONSE_QUEUE` (see `pump`).
+pub struct ClientHub {
+    clients: Vec<Client>,
+    next_id: ClientId,
+    telemetry_capacity: usize,
+}
+
+impl ClientHub {
+    pub fn new() -> Self {
+        ClientHub { clients: Vec::new(), next_id: 1, telemetry_capacity: DEFAULT_TELEMETRY_CAPACITY }
+    }
+
+    pub fn connect(&mut self, name: &str, role: ClientRole) -> ClientId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.clients.push(Client { id, name: name.to_string(), role, outbox: VecDeque::new(), telemetry_queued: 0, dropped: 0, transfers: Reassembler::new() });
+        id
+    }
+
+    pub fn disconnect(&mut self, id: ClientId) -> bool {
+        let before = self.clients.len();
+        self.clients.retain(|c| c.id != id);
+        self.clients.len() != before
+    }
+
+    pub fn client(&self, id: ClientId) -> Option<&Client> {
+        self.clients.iter().find(|c| c.id == id)
+    }
+
+    pub fn clients(&self) -> &[Client] {
+        &self.clients
+    }
+
+    /// Per-client telemetry bound. State events are not limited.
+    pub fn set_telemetry_capacity(&mut self, capacity: usize) {
+        self.telemetry_capacity = capacity.max(1);
+        for client in self.clients.iter_mut() {
+            client.trim_telemetry(self.telemetry_capacity);
+        }
+    }
+
+    pub fn pending(&self, id: ClientId) -> usize {
+        self.client(id).map(|c| c.outbox.len()).unwrap_or(0)
+    }
+
+    /// Promotes an observer to controller or the other way round.
+    pub fn set_role(&mut self, id: ClientId, role: ClientRole) -> Result<(), String> {
+        let client = self.clients.iter_mut().find(|c| c.id == id).ok_or(format!("Unknown client {}", id))?;
+        client.role = role;
+        Ok(())
+    }
+
+    /// Forwards a command from client `id` to the engine. Observers get a 106 (Command Rejected) response instead.
+    /// Chunked transfers (opcodes 30-32) are reassembled here, reported back with 107/108, and forwarded once complete.
+    pub fn submit(&mut self, id: ClientId, command: Command) -> Result<(), String> {
+        let role = self.client(id).map(|c| c.role).ok_or(format!("Unknown client {}", id))?;
+        if role == ClientRole::Observer {
+            let reason = format!("Client {} is an observer; '{}' was not applied", id, command.description_text());
+            self.deliver(id, Command::new(106, "Command Rejected", reason.as_bytes().to_vec(), command.node_id, command.param_id, command.port_id, StatState::ACTIVE));
+            return Err(reason);
+        }
+        if transfer::is_transfer(&command) {
+            let client = self.clients.iter_mut().find(|c| c.id == id).ok_or(format!("Unknown client {}", id))?;
+            return match client.transfers.accept(&command) {
+                Ok(TransferStatus::Progress { transfer_id, received, total }) => {
+                    self.deliver(id, transfer::progress_event(transfer_id, received, total));
+                    Ok(())
+                }
+                Ok(TransferStatus::Complete(command)) => self.forward(id, command),
+                Err(reason) => {
+                    self.deliver(id, transfer::failed_event(command.port_id, &reason));
+                    Err(reason)
+                }
+            };
+        }
+        self.forward(id, command)
+    }
+
+    fn forward(&mut self, id: ClientId, command: Command) -> Result<(), String> {
+        if !command.clone().send() {
+            return Err("Engine command queue is full".to_string());
+        }
+        let capacity = self.telemetry_capacity;
+        for client in self.clients.iter_mut().filter(|c| c.id != id) {
+            client.push(command.clone(), capacity);
+        }
+        Ok(())
+    }
+
+    /// Sends events to every client. A slow client loses its oldest telemetry, never state events.
+    pub fn publish(&mut self, events: &[Command]) {
+        let capacity = self.telemetry_capacity;
+        for client in self.clients.iter_mut() {
+            for event in events {
+                client.push(event.clone(), capacity);
+            }
+        }
+    }
+
+    /// Publishes tap readings as meter frames.
+    pub fn publish_meters(&mut self, readings: &[MeterReading]) {
+        let events: Vec<Command> = readings.iter().map(meter_event).collect();
+        self.publish(&events);
+    }
+
+    /// Moves pending engine responses to every client. Call regularly from the control thread.
+    pub fn pump(&mut self) {
+        let events = Command::receive_all();
+        if !events.is_empty() {
+            self.publish(&events);
+        }
+    }
+
+    /// Takes everything queued for client `id`.
+    pub fn receive(&mut self, id: ClientId) -> Vec<Command> {
+        self.clients
+            .iter_mut()
+            .find(|c| c.id == id)
+            .map(|c| {
+                c.telemetry_queued = 0;
+                c.outbox.drain(..).collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn deliver(&mut self, id: ClientId, event: Command) {
+        let capacity = self.telemetry_capacity;
+        if let Some(client) = self.clients.iter_mut().find(|c| c.id == id) {
+            client.push(event, capacity);
+        }
+    }
+}
+
+impl Client {
+    fn push(&mut self, event: Command, telemetry_capacity: usize) {
+        if is_telemetry(&event) {
+            self.telemetry_queued += 1;
+        }
+        self.outbox.push_back(event);
+        self.trim_telemetry(telemetry_capacity);
+    }
+
+    fn trim_telemetry(&mut self, capacity: usize) {
+        while self.telemetry_queued > capacity {
+            let Some(oldest) = self.outbox.iter().position(is_telemetry) else { break };
+            self.outbox.remove(oldest);
+            self.telemetry_queued -= 1;
+            self.dropped += 1;
+        }
+    }
+}
@@ -0,0 +1,90 @@
+// denormal.rs
+
+/* Denormal Protection */
+
+#![allow(warnings)]
+
+use std::marker::PhantomData;
+
+/// Level of the DC offset added to the graph input when `EngineConfig::denormal_dither` is on: about -360 dB,
+/// far below hearing but enough to keep decaying filter and reverb states out of the denormal range.
+pub const DENORMAL_DC: f32 = 1.0e-18;
+
+// MXCSR flush-to-zero (bit 15) and denormals-are-zero (bit 6).
+#[cfg(target_arch = "x86_64")]
+const FLUSH_BITS: u32 = 0x8040;
+// FPCR flush-to-zero (bit 24), which on AArch64 covers denormal inputs as well.
+#[cfg(target_arch = "aarch64")]
+const FLUSH_BITS: u32 = 1 << 24;
+
+#[cfg(target_arch = "x86_64")]
+fn read_control() -> Option<u32> {
+    let mut csr = 0u32;
+    unsafe { std::arch::asm!("stmxcsr [{}]", in(reg) &mut csr, options(nostack, preserves_flags)) };
+    Some(csr)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn write_control(csr: u32) {
+    unsafe { std::arch::asm!("ldmxcsr [{}]", in(reg) &csr, options(nostack, readonly, preserves_flags)) };
+}
+
+#[cfg(target_arch = "aarch64")]
+fn read_control() -> Option<u32> {
+    let fpcr: u64;
+    unsafe { std::arch::asm!("mrs {}, fpcr", out(reg) fpcr, options(nomem, nostack, preserves_flags)) };
+    Some(fpcr as u32)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn write_control(fpcr: u32) {
+    unsafe { std::arch::asm!("msr fpcr, {}", in(reg) fpcr as u64, options(nomem, nostack, preserves_flags)) };
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const FLUSH_BITS: u32 = 0;
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn read_control() -> Option<u32> {
+    None
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn write_control(_control: u32) {}
+
+/// Makes the calling thread flush denormal floats to zero (FTZ/DAZ), so filters and reverbs decaying towards
+/// silence don't hit the slow denormal path. Stays on for the thread's lifetime; for threads the engine doesn't
+/// own, use `FlushToZero` instead. Returns false on CPUs where this isn't supported.
+pub fn flush_denormals() -> bool {
+    let Some(control) = read_control() else { return false };
+    write_control(control | FLUSH_BITS);
+    true
+}
+
+/// Whether the calling thread currently flushes denormals to zero.
+pub fn flushing_denormals() -> bool {
+    read_control().is_some_and(|control| control & FLUSH_BITS == FLUSH_BITS)
+}
+
+/// Flushes denormals on the calling thread until dropped, then restores its previous floating-point mode.
+/// Tied to the thread it was created on.
+pub struct FlushToZero {
+    previous: Option<u32>,
+    _thread: PhantomData<*const ()>,
+}
+
+impl FlushToZero {
+    pub fn enable() -> Self {
+        let previous = read_control();
+        flush_denormals();
+        FlushToZero { previous, _thread: PhantomData }
+    }
+}
+
+impl Drop for FlushToZero {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous {
+            write_control(previous);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+// devices.rs
+
+/* Audio Device Hot-Plug Watcher */
+
+#![allow(warnings)]
+
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::audiohost;
+use crate::dspapi::{Command, StatState, RESPONSE_QUEUE};
+use crate::dspengine::{DspEngine, EngineState};
+use crate::threads::{self, ThreadRole};
+
+/// Watches the engine's output device. When it disappears (the stream reports the device gone, or it drops out
+/// of the device list) playback moves to the system default device and a 111 (Device Fallback) response is sent
+/// with the lost and new device names.
+pub struct DeviceWatcher {
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DeviceWatcher {
+    /// Starts watching, checking the device list every `interval`. `engine` is `&DSPENGINE` or an
+    /// `Arc<Mutex<DspEngine>>` shared with the embedder.
+    pub fn spawn<E: Deref<Target = Mutex<DspEngine>> + Send + 'static>(engine: E, interval: Duration) -> Result<Self, String> {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&shutdown);
+        let thread = thread::Builder::new()
+            .name("opentune-device-watch".into())
+            .spawn(move || {
+                let _thread = threads::register_current("opentune-device-watch", ThreadRole::Supervisor);
+                // A failed fallback is retried every interval but only reported once.
+                let mut failed: Option<String> = None;
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(interval);
+                    let (lost, name, host) = {
+                        let Ok(engine) = engine.lock() else { return };
+                        (engine.device_lost.load(Ordering::Acquire), engine.device_name().map(str::to_string), engine.audio_host().map(str::to_string))
+                    };
+                    let Some(name) = name else { continue };
+                    // Listing devices can be slow, so it happens without holding the engine.
+                    if !lost && audiohost::output_devices(host.as_deref()).contains(&name) { continue; }
+
+                    let Ok(mut engine) = engine.lock() else { return };
+                    if !engine.is_running() && !matches!(engine.state(), EngineState::Error { .. }) { continue; }
+                    let ok = Self::fall_back(&mut engine, &name, failed.as_deref() != Some(name.as_str()));
+                    failed = if ok { None } else { Some(name) };
+                }
+            })
+            .map_err(|e| format!("Failed to spawn device watcher: {}", e))?;
+
+        Ok(Self { shutdown, thread: Some(thread) })
+    }
+
+    fn fall_back(engine: &mut DspEngine, lost: &str, notify: bool) -> bool {
+        if notify { eprintln!("[Devices] Output device '{}' disconnected, falling back to the default device.", lost); }
+        let result = engine.fall_back_to_default();
+        let (stat, current) = match &result {
+            Ok(name) => (StatState::ACTIVE, name.clone()),
+            Err(e) => {
+                if notify { eprintln!("[Devices] Fallback failed: {}", e); }
+                (StatState::INACTIVE, String::new())
+            }
+        };
+        if !notify && result.is_err() { return false; }
+        let mut payload = lost.as_bytes().to_vec();
+        payload.push(0);
+        payload.extend_from_slice(current.as_bytes());
+        if let Ok(mut queue) = RESPONSE_QUEUE.lock() {
+            queue.push(Command::new(111, "Device Fallback", payload, engine.engine_id, 0, 0, stat));
+        }
+        result.is_ok()
+    }
+}
+
+impl Drop for DeviceWatcher {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,6 @@
+// dsp.rs
+
+/* DSP Building Blocks */
+
+pub mod simd;
+pub mod limiter;
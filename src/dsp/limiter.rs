@@ -0,0 +1,84 @@
+// limiter.rs
+
+/* Output Protection Limiter */
+
+#![allow(warnings)]
+
+/// Default ceiling of the output limiter, in dBFS.
+pub const DEFAULT_CEILING_DB: f32 = -0.3;
+/// Time the gain takes to recover most of the way (1 - 1/e) after the peak has passed.
+pub const DEFAULT_RELEASE_MS: f32 = 50.0;
+
+/// Brickwall peak limiter for the last stage before the device. Attack is instant and there is no lookahead,
+/// so it adds no latency and no sample ever leaves above the ceiling; the price is some distortion on the
+/// transients it catches, which for a protection stage is the point. Channels are linked so the image doesn't
+/// shift, and NaN or infinite samples are replaced with silence.
+#[derive(Debug, Clone)]
+pub struct OutputLimiter {
+    channels: usize,
+    ceiling: f32,
+    /// Per-frame factor the reduction decays by.
+    release: f32,
+    /// 1.0 - gain; kept instead of the gain so recovery doesn't stall just short of unity in f32.
+    reduction: f32,
+}
+
+impl OutputLimiter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let mut limiter = OutputLimiter { channels: channels.max(1), ceiling: 1.0, release: 0.0, reduction: 0.0 };
+        limiter.set_ceiling_db(DEFAULT_CEILING_DB);
+        limiter.set_release(sample_rate, DEFAULT_RELEASE_MS);
+        limiter
+    }
+
+    /// Sets the ceiling, clamped to at most 0 dBFS.
+    pub fn set_ceiling_db(&mut self, db: f32) {
+        self.ceiling = 10f32.powf(db.min(0.0) / 20.0);
+    }
+
+    pub fn ceiling_db(&self) -> f32 {
+        20.0 * self.ceiling.log10()
+    }
+
+    pub fn set_release(&mut self, sample_rate: u32, ms: f32) {
+        let frames = ms.max(0.0) * 0.001 * sample_rate.max(1) as f32;
+        self.release = if frames > 0.0 { (-1.0 / frames).exp() } else { 0.0 };
+    }
+
+    /// Gain reduction currently applied, in dB (0.0 when idle).
+    pub fn gain_reduction_db(&self) -> f32 {
+        -20.0 * (1.0 - self.reduction).log10()
+    }
+
+    pub fn reset(&mut self) {
+        self.reduction = 0.0;
+    }
+
+    /// Limits interleaved `io` in place. Returns whether anything was reduced or replaced.
+    pub fn process(&mut self, io: &mut [f32]) -> bool {
+        let mut engaged = false;
+        for frame in io.chunks_mut(self.channels) {
+            let mut peak = 0.0f32;
+            for sample in frame.iter_mut() {
+                if !sample.is_finite() {
+                    *sample = 0.0;
+                    engaged = true;
+                }
+                peak = peak.max(sample.abs());
+            }
+            let target = if peak > self.ceiling { 1.0 - self.ceiling / peak } else { 0.0 };
+            // Down at once, back up smoothly. Recovering towards the target never undershoots it, so the frame
+            // stays under the ceiling either way.
+            self.reduction = if target > self.reduction { target } else { target + (self.reduction - target) * self.release };
+            if self.reduction < 1.0e-6 { self.reduction = 0.0; }
+            if self.reduction > 0.0 {
+                engaged = true;
+                let gain = 1.0 - self.reduction;
+                for sample in frame.iter_mut() {
+                    *sample = (*sample * gain).clamp(-self.ceiling, self.ceiling);
+                }
+            }
+        }
+        engaged
+    }
+}
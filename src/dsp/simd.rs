@@ -0,0 +1,417 @@
+// simd.rs
+
+/* SIMD Buffer Utilities */
+
+#![allow(warnings)]
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Instruction set the buffer utilities run with. Picked once from what the CPU supports; results are the same
+/// at every level, bit for bit (NaN samples are skipped by `peak` everywhere).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimdLevel {
+    Scalar,
+    Sse,
+    Avx,
+    Neon,
+}
+
+const UNDETECTED: u8 = u8::MAX;
+static LEVEL: AtomicU8 = AtomicU8::new(UNDETECTED);
+
+impl SimdLevel {
+    fn from_code(code: u8) -> Self {
+        match code {
+            1 => SimdLevel::Sse,
+            2 => SimdLevel::Avx,
+            3 => SimdLevel::Neon,
+            _ => SimdLevel::Scalar,
+        }
+    }
+
+    fn is_supported(self) -> bool {
+        match self {
+            SimdLevel::Scalar => true,
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Sse => true,
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx => std::arch::is_x86_feature_detected!("avx"),
+            #[cfg(target_arch = "aarch64")]
+            SimdLevel::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+            _ => false,
+        }
+    }
+}
+
+/// The instruction set in use.
+pub fn level() -> SimdLevel {
+    match LEVEL.load(Ordering::Relaxed) {
+        UNDETECTED => {
+            let level = [SimdLevel::Avx, SimdLevel::Neon, SimdLevel::Sse].into_iter().find(|l| l.is_supported()).unwrap_or(SimdLevel::Scalar);
+            LEVEL.store(level as u8, Ordering::Relaxed);
+            level
+        }
+        code => SimdLevel::from_code(code),
+    }
+}
+
+/// Runs the utilities with `level` instead of the best one available, e.g. to rule SIMD out while debugging or
+/// to benchmark against scalar code. Refused if the CPU doesn't support it.
+pub fn set_level(level: SimdLevel) -> Result<(), &'static str> {
+    if !level.is_supported() { return Err("instruction set not supported by this CPU"); }
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Copies `src` into `dst`, as far as both reach. The platform's memcpy is already vectorized, so this is
+/// `copy_from_slice`; it's here so hot paths read alike.
+pub fn copy(dst: &mut [f32], src: &[f32]) {
+    let len = dst.len().min(src.len());
+    dst[..len].copy_from_slice(&src[..len]);
+}
+
+/// Scales `buffer` by `gain`.
+pub fn gain(buffer: &mut [f32], gain: f32) {
+    match level() {
+        // Safety: `level` only reports an instruction set the CPU has.
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Avx => unsafe { x86::gain_avx(buffer, gain) },
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Sse => unsafe { x86::gain_sse(buffer, gain) },
+        #[cfg(target_arch = "aarch64")]
+        SimdLevel::Neon => neon::gain(buffer, gain),
+        _ => scalar::gain(buffer, gain),
+    }
+}
+
+/// Adds `src` into `dst`, as far as both reach.
+pub fn mix(dst: &mut [f32], src: &[f32]) {
+    let len = dst.len().min(src.len());
+    let (dst, src) = (&mut dst[..len], &src[..len]);
+    match level() {
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Avx => unsafe { x86::mix_avx(dst, src) },
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Sse => unsafe { x86::mix_sse(dst, src) },
+        #[cfg(target_arch = "aarch64")]
+        SimdLevel::Neon => neon::mix(dst, src),
+        _ => scalar::mix(dst, src),
+    }
+}
+
+/// Adds `src` scaled by `gain` into `dst`, as far as both reach.
+pub fn mix_gain(dst: &mut [f32], src: &[f32], gain: f32) {
+    let len = dst.len().min(src.len());
+    let (dst, src) = (&mut dst[..len], &src[..len]);
+    match level() {
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Avx => unsafe { x86::mix_gain_avx(dst, src, gain) },
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Sse => unsafe { x86::mix_gain_sse(dst, src, gain) },
+        #[cfg(target_arch = "aarch64")]
+        SimdLevel::Neon => neon::mix_gain(dst, src, gain),
+        _ => scalar::mix_gain(dst, src, gain),
+    }
+}
+
+/// Largest absolute sample in `buffer`, 0.0 when empty.
+pub fn peak(buffer: &[f32]) -> f32 {
+    match level() {
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Avx => unsafe { x86::peak_avx(buffer) },
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Sse => unsafe { x86::peak_sse(buffer) },
+        #[cfg(target_arch = "aarch64")]
+        SimdLevel::Neon => neon::peak(buffer),
+        _ => scalar::peak(buffer),
+    }
+}
+
+/// Interleaves `channels` planes, which start `stride` samples apart in `planes`, into `out`, as many frames as
+/// `out` holds (at most `stride`).
+pub fn interleave(planes: &[f32], stride: usize, channels: usize, out: &mut [f32]) {
+    let frames = (out.len() / channels.max(1)).min(stride);
+    if channels == 2 && planes.len() >= stride + frames {
+        let (left, right) = (&planes[..frames], &planes[stride..stride + frames]);
+        let out = &mut out[..frames * 2];
+        return match level() {
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx | SimdLevel::Sse => unsafe { x86::interleave_stereo(left, right, out) },
+            #[cfg(target_arch = "aarch64")]
+            SimdLevel::Neon => neon::interleave_stereo(left, right, out),
+            _ => scalar::interleave_stereo(left, right, out),
+        };
+    }
+    scalar::interleave(planes, stride, channels, &mut out[..frames * channels]);
+}
+
+/// Splits interleaved `input` (`channels` wide) into planes starting `stride` samples apart in `planes`, as
+/// many frames as `input` holds (at most `stride`).
+pub fn deinterleave(input: &[f32], channels: usize, planes: &mut [f32], stride: usize) {
+    let frames = (input.len() / channels.max(1)).min(stride);
+    if channels == 2 && planes.len() >= stride + frames {
+        let input = &input[..frames * 2];
+        let (left, right) = planes.split_at_mut(stride);
+        let (left, right) = (&mut left[..frames], &mut right[..frames]);
+        return match level() {
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx | SimdLevel::Sse => unsafe { x86::deinterleave_stereo(input, left, right) },
+            #[cfg(target_arch = "aarch64")]
+            SimdLevel::Neon => neon::deinterleave_stereo(input, left, right),
+            _ => scalar::deinterleave_stereo(input, left, right),
+        };
+    }
+    scalar::deinterleave(&input[..frames * channels], channels, planes, stride);
+}
+
+/// Plain loops, for other CPUs and the samples left over after the vector ones.
+mod scalar {
+    pub fn gain(buffer: &mut [f32], gain: f32) {
+        buffer.iter_mut().for_each(|s| *s *= gain);
+    }
+
+    pub fn mix(dst: &mut [f32], src: &[f32]) {
+        dst.iter_mut().zip(src).for_each(|(d, s)| *d += s);
+    }
+
+    pub fn mix_gain(dst: &mut [f32], src: &[f32], gain: f32) {
+        dst.iter_mut().zip(src).for_each(|(d, s)| *d += s * gain);
+    }
+
+    pub fn peak(buffer: &[f32]) -> f32 {
+        buffer.iter().fold(0.0f32, |peak, s| peak.max(s.abs()))
+    }
+
+    pub fn interleave(planes: &[f32], stride: usize, channels: usize, out: &mut [f32]) {
+        for (f, frame) in out.chunks_exact_mut(channels).enumerate() {
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                *sample = planes[ch * stride + f];
+            }
+        }
+    }
+
+    pub fn deinterleave(input: &[f32], channels: usize, planes: &mut [f32], stride: usize) {
+        for (f, frame) in input.chunks_exact(channels).enumerate() {
+            for (ch, &sample) in frame.iter().enumerate() {
+                planes[ch * stride + f] = sample;
+            }
+        }
+    }
+
+    pub fn interleave_stereo(left: &[f32], right: &[f32], out: &mut [f32]) {
+        for ((frame, &l), &r) in out.chunks_exact_mut(2).zip(left).zip(right) {
+            frame[0] = l;
+            frame[1] = r;
+        }
+    }
+
+    pub fn deinterleave_stereo(input: &[f32], left: &mut [f32], right: &mut [f32]) {
+        for ((frame, l), r) in input.chunks_exact(2).zip(left.iter_mut()).zip(right.iter_mut()) {
+            *l = frame[0];
+            *r = frame[1];
+        }
+    }
+}
+
+/// SSE2 and AVX kernels. Slices passed in are equally long.
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::scalar;
+
+    #[target_feature(enable = "sse2")]
+    pub fn gain_sse(buffer: &mut [f32], gain: f32) {
+        let (body, tail) = buffer.as_chunks_mut::<4>();
+        let g = _mm_set1_ps(gain);
+        for chunk in body {
+            unsafe { _mm_storeu_ps(chunk.as_mut_ptr(), _mm_mul_ps(_mm_loadu_ps(chunk.as_ptr()), g)) };
+        }
+        scalar::gain(tail, gain);
+    }
+
+    #[target_feature(enable = "avx")]
+    pub fn gain_avx(buffer: &mut [f32], gain: f32) {
+        let (body, tail) = buffer.as_chunks_mut::<8>();
+        let g = _mm256_set1_ps(gain);
+        for chunk in body {
+            unsafe { _mm256_storeu_ps(chunk.as_mut_ptr(), _mm256_mul_ps(_mm256_loadu_ps(chunk.as_ptr()), g)) };
+        }
+        scalar::gain(tail, gain);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub fn mix_sse(dst: &mut [f32], src: &[f32]) {
+        let ((body, tail), (src_body, src_tail)) = (dst.as_chunks_mut::<4>(), src.as_chunks::<4>());
+        for (d, s) in body.iter_mut().zip(src_body) {
+            unsafe { _mm_storeu_ps(d.as_mut_ptr(), _mm_add_ps(_mm_loadu_ps(d.as_ptr()), _mm_loadu_ps(s.as_ptr()))) };
+        }
+        scalar::mix(tail, src_tail);
+    }
+
+    #[target_feature(enable = "avx")]
+    pub fn mix_avx(dst: &mut [f32], src: &[f32]) {
+        let ((body, tail), (src_body, src_tail)) = (dst.as_chunks_mut::<8>(), src.as_chunks::<8>());
+        for (d, s) in body.iter_mut().zip(src_body) {
+            unsafe { _mm256_storeu_ps(d.as_mut_ptr(), _mm256_add_ps(_mm256_loadu_ps(d.as_ptr()), _mm256_loadu_ps(s.as_ptr()))) };
+        }
+        scalar::mix(tail, src_tail);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub fn mix_gain_sse(dst: &mut [f32], src: &[f32], gain: f32) {
+        let ((body, tail), (src_body, src_tail)) = (dst.as_chunks_mut::<4>(), src.as_chunks::<4>());
+        let g = _mm_set1_ps(gain);
+        for (d, s) in body.iter_mut().zip(src_body) {
+            unsafe {
+                let scaled = _mm_mul_ps(_mm_loadu_ps(s.as_ptr()), g);
+                _mm_storeu_ps(d.as_mut_ptr(), _mm_add_ps(_mm_loadu_ps(d.as_ptr()), scaled));
+            }
+        }
+        scalar::mix_gain(tail, src_tail, gain);
+    }
+
+    #[target_feature(enable = "avx")]
+    pub fn mix_gain_avx(dst: &mut [f32], src: &[f32], gain: f32) {
+        let ((body, tail), (src_body, src_tail)) = (dst.as_chunks_mut::<8>(), src.as_chunks::<8>());
+        let g = _mm256_set1_ps(gain);
+        for (d, s) in body.iter_mut().zip(src_body) {
+            unsafe {
+                let scaled = _mm256_mul_ps(_mm256_loadu_ps(s.as_ptr()), g);
+                _mm256_storeu_ps(d.as_mut_ptr(), _mm256_add_ps(_mm256_loadu_ps(d.as_ptr()), scaled));
+            }
+        }
+        scalar::mix_gain(tail, src_tail, gain);
+    }
+
+    /// Clears the sign bits.
+    #[target_feature(enable = "sse2")]
+    fn abs_mask() -> __m128 {
+        _mm_castsi128_ps(_mm_set1_epi32(i32::MAX))
+    }
+
+    /// Largest of the four lanes.
+    #[target_feature(enable = "sse2")]
+    fn max_lane(v: __m128) -> f32 {
+        let v = _mm_max_ps(v, _mm_movehl_ps(v, v));
+        _mm_cvtss_f32(_mm_max_ss(v, _mm_shuffle_ps::<0b01>(v, v)))
+    }
+
+    // `_mm_max_ps` returns its second operand when either is NaN: the peak so far goes second, so NaN samples
+    // are skipped as `f32::max` skips them.
+    #[target_feature(enable = "sse2")]
+    pub fn peak_sse(buffer: &[f32]) -> f32 {
+        let (body, tail) = buffer.as_chunks::<4>();
+        let mask = abs_mask();
+        let mut peak = _mm_setzero_ps();
+        for chunk in body {
+            peak = _mm_max_ps(_mm_and_ps(unsafe { _mm_loadu_ps(chunk.as_ptr()) }, mask), peak);
+        }
+        max_lane(peak).max(scalar::peak(tail))
+    }
+
+    #[target_feature(enable = "avx")]
+    pub fn peak_avx(buffer: &[f32]) -> f32 {
+        let (body, tail) = buffer.as_chunks::<8>();
+        let mask = _mm256_castsi256_ps(_mm256_set1_epi32(i32::MAX));
+        let mut peak = _mm256_setzero_ps();
+        for chunk in body {
+            peak = _mm256_max_ps(_mm256_and_ps(unsafe { _mm256_loadu_ps(chunk.as_ptr()) }, mask), peak);
+        }
+        let halves = _mm_max_ps(_mm256_castps256_ps128(peak), _mm256_extractf128_ps::<1>(peak));
+        max_lane(halves).max(scalar::peak(tail))
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub fn interleave_stereo(left: &[f32], right: &[f32], out: &mut [f32]) {
+        let ((l_body, l_tail), (r_body, r_tail)) = (left.as_chunks::<4>(), right.as_chunks::<4>());
+        let (out_body, out_tail) = out.as_chunks_mut::<8>();
+        for ((l, r), o) in l_body.iter().zip(r_body).zip(out_body.iter_mut()) {
+            unsafe {
+                let (l, r) = (_mm_loadu_ps(l.as_ptr()), _mm_loadu_ps(r.as_ptr()));
+                _mm_storeu_ps(o.as_mut_ptr(), _mm_unpacklo_ps(l, r));
+                _mm_storeu_ps(o.as_mut_ptr().add(4), _mm_unpackhi_ps(l, r));
+            }
+        }
+        scalar::interleave_stereo(l_tail, r_tail, out_tail);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub fn deinterleave_stereo(input: &[f32], left: &mut [f32], right: &mut [f32]) {
+        let (in_body, in_tail) = input.as_chunks::<8>();
+        let ((l_body, l_tail), (r_body, r_tail)) = (left.as_chunks_mut::<4>(), right.as_chunks_mut::<4>());
+        for ((i, l), r) in in_body.iter().zip(l_body.iter_mut()).zip(r_body.iter_mut()) {
+            unsafe {
+                let (a, b) = (_mm_loadu_ps(i.as_ptr()), _mm_loadu_ps(i.as_ptr().add(4)));
+                _mm_storeu_ps(l.as_mut_ptr(), _mm_shuffle_ps::<0b10_00_10_00>(a, b));
+                _mm_storeu_ps(r.as_mut_ptr(), _mm_shuffle_ps::<0b11_01_11_01>(a, b));
+            }
+        }
+        scalar::deinterleave_stereo(in_tail, l_tail, r_tail);
+    }
+}
+
+/// NEON kernels (always present on AArch64). Slices passed in are equally long.
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use super::scalar;
+
+    pub fn gain(buffer: &mut [f32], gain: f32) {
+        let (body, tail) = buffer.as_chunks_mut::<4>();
+        for chunk in body {
+            unsafe { vst1q_f32(chunk.as_mut_ptr(), vmulq_n_f32(vld1q_f32(chunk.as_ptr()), gain)) };
+        }
+        scalar::gain(tail, gain);
+    }
+
+    pub fn mix(dst: &mut [f32], src: &[f32]) {
+        let ((body, tail), (src_body, src_tail)) = (dst.as_chunks_mut::<4>(), src.as_chunks::<4>());
+        for (d, s) in body.iter_mut().zip(src_body) {
+            unsafe { vst1q_f32(d.as_mut_ptr(), vaddq_f32(vld1q_f32(d.as_ptr()), vld1q_f32(s.as_ptr()))) };
+        }
+        scalar::mix(tail, src_tail);
+    }
+
+    pub fn mix_gain(dst: &mut [f32], src: &[f32], gain: f32) {
+        let ((body, tail), (src_body, src_tail)) = (dst.as_chunks_mut::<4>(), src.as_chunks::<4>());
+        for (d, s) in body.iter_mut().zip(src_body) {
+            // Multiply then add, not vfmaq: fused results would differ from the scalar path.
+            unsafe { vst1q_f32(d.as_mut_ptr(), vaddq_f32(vld1q_f32(d.as_ptr()), vmulq_n_f32(vld1q_f32(s.as_ptr()), gain))) };
+        }
+        scalar::mix_gain(tail, src_tail, gain);
+    }
+
+    pub fn peak(buffer: &[f32]) -> f32 {
+        let (body, tail) = buffer.as_chunks::<4>();
+        let mut peak = vdupq_n_f32(0.0);
+        // The `nm` forms return the number when one operand is NaN, as `f32::max` does.
+        for chunk in body {
+            peak = vmaxnmq_f32(peak, vabsq_f32(unsafe { vld1q_f32(chunk.as_ptr()) }));
+        }
+        vmaxnmvq_f32(peak).max(scalar::peak(tail))
+    }
+
+    pub fn interleave_stereo(left: &[f32], right: &[f32], out: &mut [f32]) {
+        let ((l_body, l_tail), (r_body, r_tail)) = (left.as_chunks::<4>(), right.as_chunks::<4>());
+        let (out_body, out_tail) = out.as_chunks_mut::<8>();
+        for ((l, r), o) in l_body.iter().zip(r_body).zip(out_body.iter_mut()) {
+            unsafe { vst2q_f32(o.as_mut_ptr(), float32x4x2_t(vld1q_f32(l.as_ptr()), vld1q_f32(r.as_ptr()))) };
+        }
+        scalar::interleave_stereo(l_tail, r_tail, out_tail);
+    }
+
+    pub fn deinterleave_stereo(input: &[f32], left: &mut [f32], right: &mut [f32]) {
+        let (in_body, in_tail) = input.as_chunks::<8>();
+        let ((l_body, l_tail), (r_body, r_tail)) = (left.as_chunks_mut::<4>(), right.as_chunks_mut::<4>());
+        for ((i, l), r) in in_body.iter().zip(l_body.iter_mut()).zip(r_body.iter_mut()) {
+            unsafe {
+                let pair = vld2q_f32(i.as_ptr());
+                vst1q_f32(l.as_mut_ptr(), pair.0);
+                vst1q_f32(r.as_mut_ptr(), pair.1);
+            }
+        }
+        scalar::deinterleave_stereo(in_tail, l_tail, r_tail);
+    }
+}
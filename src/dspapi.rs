@@ -4,9 +4,13 @@
 
 #![allow(warnings)]
 
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 use once_cell::sync::Lazy;
 
+use crate::clients::is_telemetry;
+use crate::intern::{self, NameId};
+
 pub const DSPAPI_VERSION: &str = "0.0.1";
 
 pub type NodeId = u32;
@@ -14,10 +18,87 @@ pub type ParamId = u32;
 pub type PortId = u32;
 
 // Global Response Queue: For the Engine to send telemetry/ACK back to GUI
-pub static RESPONSE_QUEUE: Lazy<Arc<Mutex<Vec<Command>>>> = Lazy::new(|| {
-    Arc::new(Mutex::new(Vec::new()))
+// Response opcodes start at 100: 100: Failover Engaged (INACTIVE with the cause if the backup isn't playing),
+// 101: Engine State (u8 state code + error cause),
+// 102: Pickup Engaged (f32 control value), 103: Plugin Scan Complete (u32 plugin count),
+// 104: Plugin Registry Changed (u32 added + u32 removed),
+// 105: Node Rejected (reason text: strict mode, full graph, unknown node, allowlist),
+// 106: Command Rejected (reason text, sent to the submitting client only),
+// 107: Transfer Progress (u64 received + u64 total), 108: Transfer Failed (reason text),
+// 109: Meter Frame (see `clients::meter_event`), 110: Routing Rejected (reason text; also for modulation commands),
+// 111: Device Fallback (lost device name, NUL, new device name; INACTIVE if no device could be opened),
+// 112: Node Load (see `usage::load_event`), 113: Xrun (see `xrun::xrun_event`), 114: Stream Recovery (see
+// `recovery::recovery_event`), 115: Latency (see `latency::latency_event`),
+// 116: Audit Write Failed (reason text; sent once until a write succeeds again),
+// 117: Scene Recalled (u32 scene; `node_id` is the engine id. The engine only tracks the active scene: the
+// application restores the scene's parameters, e.g. by sending their Set Parameter commands)
+pub static RESPONSE_QUEUE: Lazy<Arc<Mutex<ResponseQueue>>> = Lazy::new(|| {
+    Arc::new(Mutex::new(ResponseQueue::new()))
 });
 
+/// Telemetry responses `RESPONSE_QUEUE` holds before the oldest are dropped.
+pub const RESPONSE_TELEMETRY_CAPACITY: usize = 256;
+
+/// Responses not received yet, in the order they were sent. Telemetry (see `clients::is_telemetry`) is superseded
+/// by the next frame, so when nobody receives it only the newest `RESPONSE_TELEMETRY_CAPACITY` are kept; every
+/// other response is kept until received.
+pub struct ResponseQueue {
+    responses: VecDeque<Command>,
+    telemetry_queued: usize,
+    dropped: u64,
+}
+
+impl ResponseQueue {
+    /// Preallocated for a full load of telemetry and as many other responses, so pushes from the audio thread
+    /// don't normally allocate.
+    pub fn new() -> Self {
+        Self { responses: VecDeque::with_capacity(RESPONSE_TELEMETRY_CAPACITY * 2), telemetry_queued: 0, dropped: 0 }
+    }
+
+    pub fn push(&mut self, response: Command) {
+        if is_telemetry(&response) {
+            if self.telemetry_queued == RESPONSE_TELEMETRY_CAPACITY {
+                if let Some(oldest) = self.responses.iter().position(is_telemetry) {
+                    self.responses.remove(oldest);
+                    self.telemetry_queued -= 1;
+                    self.dropped += 1;
+                }
+            }
+            self.telemetry_queued += 1;
+        }
+        self.responses.push_back(response);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Command> {
+        self.responses.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.responses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.responses.is_empty()
+    }
+
+    /// Telemetry responses dropped so far because nobody received them in time.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Takes every queued response, oldest first.
+    pub fn drain(&mut self) -> impl Iterator<Item = Command> + '_ {
+        self.telemetry_queued = 0;
+        self.responses.drain(..)
+    }
+}
+
+impl Default for ResponseQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum StatState {
     ACTIVE,
@@ -27,10 +108,42 @@ pub enum StatState {
 
 /// The Universal Command structure.
 /// To support "anything", the command_id acts as an OpCode:
-/// 0: Add Node, 1: Remove Node, 2: Set Parameter, 3: Connect Routing
+/// 0: Add Node (optional u32 payload: fixed block size; appended to the main chain), 1: Remove Node (its inputs are
+/// reconnected to its outputs), 2: Set Parameter,
+/// 3: Connect Routing, 4: Disconnect Routing (from `node_id`:`port_id`, payload u32 destination node + u32 destination port;
+/// see `graph::GRAPH_INPUT` / `GRAPH_CAPTURE` / `GRAPH_OUTPUT`; destination ports from 1 up are sidechain inputs;
+/// `graph::MIDI_PORT` on both ends routes MIDI, with `GRAPH_MIDI_INPUT` / `GRAPH_MIDI_OUTPUT` for the hardware),
+/// 5: Replace Node (`node_id` is replaced by a node of the type in `description`, keeping its connections;
+/// payload as for Add Node), 6: Clear Rack, 7: Move Node (payload u32 node to run before, `GRAPH_OUTPUT` for the
+/// end of the chain), 8: Set Bypass (payload u8, nonzero bypasses with a short crossfade). Added, removed and replaced
+/// nodes are crossfaded in and out over the same few milliseconds, so editing the rack mid-playback doesn't click;
+/// a removed node stays in the graph until it has faded out. Removed nodes are dropped off the audio thread (see
+/// `reaper`). Parameter ids from `strip::HOST_PARAM_BASE` set the engine's per-node controls
+/// (bypass, mix, trim, gain, pan, oversampling, and sends to the return buses added with Add Node `bus::RETURN_BUS`).
+/// 10: Transport Play, 11: Transport Stop, 12: Transport Record, 13: Tempo Nudge (f32 BPM payload), 14: Locate (u64 frame
+/// payload), 15: Set Tempo (f32 BPM payload), 16: Set Time Signature (u16 beats per bar + u16 beat unit); see
+/// `dspengine::Transport`. Nodes get the transport every block, and as events if they handle events
+/// 20: Scene Recall (u32 scene payload; becomes the engine's active scene, see `dspengine::EngineHandle::active_scene`, and is
+/// answered with a 117 Scene Recalled response)
+/// 21: Set Mod Source (`node_id` is the source id; payload u8 kind: 0 LFO + u8 shape (sine, triangle, saw, square) +
+/// u8 tempo sync + f32 rate (Hz, or quarter notes per cycle when synced), 1 envelope follower + u32 node followed
+/// (`graph::GRAPH_INPUT` for the rack input) + f32 attack ms + f32 release ms, 2 macro knob), 22: Remove Mod Source,
+/// 23: Set Mod Route (`node_id`:`param_id` modulated; payload u32 source + f32 depth + u8 polarity (nonzero bipolar) +
+/// optional f32 base value), 24: Remove Mod Route (payload u32 source), 25: Set Macro (`node_id` the source, f32
+/// payload 0.0..1.0); see `modmatrix`. Set Parameter on a modulated parameter moves the value it is modulated around
+/// 26: Freeze Node (payload u64 start frame + u64 frames: the node's output is recorded while the transport plays
+/// through that range, then played back instead of running the node; see `graph::AudioGraph::freeze_node`),
+/// 27: Unfreeze Node
+/// 30: Transfer Begin, 31: Transfer Chunk, 32: Transfer End (chunked large payloads, see `transfer`)
+/// 40: Set Master Gain (f32 dB payload), 41: Master Mute (payload u8, nonzero mutes), 42: Master Dim (payload u8,
+/// nonzero dims by `master::DIM_DB`); applied to the rack output with a short ramp, see `master`
+/// 43: Panic (drops queued audio and MIDI, fades the output out over `dspengine::PANIC_FADE_MS` and resets every
+/// node, sending All Notes Off to those that take MIDI; see `graph::AudioGraph::panic`)
+#[derive(Clone)]
 pub struct Command {
     pub command_id: u32,
-    pub description: &'static str,
+    /// Interned name (see `intern`), e.g. the node type to create. Resolve with `description_text()`.
+    pub description: NameId,
     pub payload_size: usize,
     pub payload: Vec<u8>, // Can hold floats, strings, or serialized structs
     pub node_id: NodeId,
@@ -40,7 +153,13 @@ pub struct Command {
 }
 
 impl Command {
-    pub fn new(command_id: u32, description: &'static str, payload: Vec<u8>, node_id: NodeId, param_id: ParamId, port_id: PortId, stat: StatState) -> Self {
+    /// Builds a command, interning `description`. Call from the control side; the audio thread should use `with_name_id`.
+    pub fn new(command_id: u32, description: &str, payload: Vec<u8>, node_id: NodeId, param_id: ParamId, port_id: PortId, stat: StatState) -> Self {
+        Self::with_name_id(command_id, intern::intern(description), payload, node_id, param_id, port_id, stat)
+    }
+
+    /// Builds a command from an already interned name. Never allocates for an empty payload.
+    pub fn with_name_id(command_id: u32, description: NameId, payload: Vec<u8>, node_id: NodeId, param_id: ParamId, port_id: PortId, stat: StatState) -> Self {
         Command {
             command_id,
             description,
@@ -53,17 +172,25 @@ impl Command {
         }
     }
 
-    pub fn send(self) {
-        if let Ok(engine) = crate::dspengine::DSPENGINE.lock() {
-            if let Ok(mut queue) = engine.command_queue.lock() {
-                queue.push(self);
-            }
-        }
+    pub fn description_text(&self) -> std::sync::Arc<str> {
+        intern::resolve(self.description).unwrap_or_else(|| std::sync::Arc::from(""))
+    }
+
+    /// Queues the command for the default engine (`dspengine::DSPENGINE`); see `send_to` for other engines.
+    /// Returns false if the queue is at its configured capacity.
+    pub fn send(self) -> bool {
+        let Ok(handle) = crate::dspengine::DSPENGINE.lock().map(|engine| engine.handle()) else { return false };
+        handle.send(self)
+    }
+
+    /// Queues the command for the engine behind `engine`. Returns false if its queue is full.
+    pub fn send_to(self, engine: &crate::dspengine::EngineHandle) -> bool {
+        engine.send(self)
     }
 
     pub fn receive_all() -> Vec<Self> {
         if let Ok(mut queue) = RESPONSE_QUEUE.lock() {
-            return queue.drain(..).collect();
+            return queue.drain().collect();
         }
         vec![]
     }
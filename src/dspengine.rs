@@ -4,169 +4,2565 @@
 
 #![allow(warnings)]
 
+use cpal::SampleFormat;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use crossbeam::queue::ArrayQueue;
 use once_cell::sync::Lazy;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
+use crate::aggregate::{AggregateOutputInfo, DriftReader, DriftStats, OutputFeeds, OutputSource};
+use crate::audiohost;
+use crate::automation::{self, Automation, ParamChange};
+use crate::blockadapter::{BlockFifo, FixedBlockAdapter};
+use crate::bus;
+use crate::denormal::{self, FlushToZero};
+use crate::dsp::limiter::{self, OutputLimiter};
+use crate::dsp::simd;
 use crate::dspapi::*;
-use crate::pmanager::PMANAGER;
+use crate::events::{EventKind, NodeEvent, TransportInfo};
+use crate::freeze::FrozenAudio;
+use crate::graph::AudioGraph;
+use crate::latency::{LatencyMeter, LatencyPublisher, LatencyReport};
+use crate::layout::{ChannelLayout, ChannelMap};
+use crate::master::{MasterControls, MasterRamp};
+use crate::meters::LevelMeters;
+use crate::midi::{self, MidiEvent, MidiInputs, MidiOutputs, MidiWriter};
+use crate::modmatrix::{ModMatrix, ModRoute, ModSourceKind};
+use crate::nullbackend::{NullOptions, NullStream};
+use crate::planar::PlanarBuffer;
+use crate::smoothing;
+use crate::intern;
+#[cfg(feature = "jack")]
+use crate::jackbackend::{JackOptions, JackStream};
+#[cfg(feature = "link")]
+use crate::link::{LinkSession, LinkSync, LinkThread};
+#[cfg(feature = "pipewire")]
+use crate::pipewirebackend::{PipeWireOptions, PipeWireStream};
+#[cfg(all(windows, feature = "wasapi"))]
+use crate::wasapibackend::{WasapiLatency, WasapiMode, WasapiOptions, WasapiOutput, WasapiStream};
+use crate::taps::TapSet;
+use crate::threads::{self, ThreadHandle, ThreadRole};
+use crate::usage::{LoadPublisher, UsageMeter, UsageReport};
+use crate::wav;
+use crate::sharedgraph::{CheckedOutGraph, SharedGraph};
+use crate::xrun::{self, XrunCounters, XrunMonitor};
+use crate::pmanager::{PluginManager, PMANAGER};
+use crate::reaper::{Graveyard, NodeReaper};
+use crate::resample::Resampler;
+use crate::rtpsink::{RtpConfig, RtpSender, RtpStreamInfo};
 use crate::mrbr::MagicRingBuffer as Buffer;
+use crate::rtsafety::RtSafety;
+use crate::sampleformat::{self, FormatConverter, OutputSample};
 
 pub const DSPENGINE_VERSION: &str = "0.1.0";
 
 /// The Universal Audio Trait. 
 /// All internal nodes and external plugin wrappers (VST3, CLAP, LV2) must implement this.
 pub trait AudioNode: Send {
+    /// Called before the node first processes and whenever the engine's sample rate or block size changes.
+    /// Nodes must derive all rate-dependent coefficients here rather than assuming 44.1 kHz.
+    fn prepare(&mut self, sample_rate: u32, max_block_size: usize) {}
+    /// Processes one block of interleaved audio in place.
     fn process(&mut self, buffer: &mut [f32]);
+    /// Whether the engine should call `process_planar` instead of `process`. Plugin wrappers whose formats
+    /// deinterleave (VST3, CLAP, LV2) should return true to skip a conversion of their own. Such nodes still
+    /// implement `process` for callers that only have interleaved audio, e.g. through a `PlanarBuffer` they own.
+    fn is_planar(&self) -> bool { false }
+    /// Planar counterpart of `process`: one slice per channel, processed in place. The engine deinterleaves into
+    /// preallocated planes and interleaves the result back.
+    fn process_planar(&mut self, audio: &mut PlanarBuffer) {}
     fn set_param(&mut self, param_id: u32, payload: &[u8]);
     fn get_id(&self) -> u32;
     fn get_name(&self) -> &str;
+    /// English display name of a parameter, if the node describes its parameters. Localize with `strings::param_label`.
+    fn param_name(&self, param_id: u32) -> Option<String> { None }
+    /// Delay in samples the node adds to its signal, reported to delay compensation. Read every block, so it
+    /// may change, but each change shifts the compensated paths.
+    fn latency_samples(&self) -> usize { 0 }
+    /// Whether `process` is free of allocation and blocking. Nodes that don't override this are rejected in strict mode.
+    fn rt_safety(&self) -> RtSafety { RtSafety::UNKNOWN }
+    /// Parameters that accept audio-rate modulation through `set_param_modulation`.
+    fn audio_rate_params(&self) -> &[u32] { &[] }
+    /// Supplies one modulation value per frame for the next `process` call, added to the parameter's set value
+    /// in the parameter's own units. Must not allocate; values beyond the prepared block size are ignored.
+    fn set_param_modulation(&mut self, param_id: u32, modulation: &[f32]) {}
+    /// Called before `prepare` with the layout of the interleaved buffers `process` receives, and again whenever
+    /// the engine's layout changes. Nodes that keep per-channel state should size it for `layout.channels()`.
+    fn set_channel_layout(&mut self, layout: ChannelLayout) {}
+    /// Input ports the node can be connected to in the graph. Port 0 is the signal passed to `process`; ports
+    /// from 1 up are sidechains, delivered through `set_sidechain_input`.
+    fn input_ports(&self) -> usize { 1 }
+    /// Supplies what is connected to sidechain `port` (1 and up) for the next `process` call, interleaved like the
+    /// main buffer and aligned with it for latency. Only called for connected ports, every block they are
+    /// connected; a port that isn't called for is silent. Must not allocate, so copy into prepared storage.
+    fn set_sidechain_input(&mut self, port: u32, input: &[f32]) {}
+    /// Whether the node takes MIDI (instruments, MIDI-controlled effects). Only such nodes get `set_midi_input`.
+    fn accepts_midi(&self) -> bool { false }
+    /// MIDI for the next `process` call, in time order, with `MidiEvent::frame` the frame within the block.
+    /// Called every block, with no events when nothing arrived. Must not allocate.
+    fn set_midi_input(&mut self, events: &[MidiEvent]) {}
+    /// Whether the node generates MIDI (arpeggiators, sequencers, MIDI effects), read with `take_midi_output`.
+    fn produces_midi(&self) -> bool { false }
+    /// Called after each `process` on nodes that produce MIDI: write the block's events, in time order, with
+    /// frames within that block. Must not allocate.
+    fn take_midi_output(&mut self, out: &mut MidiWriter) {}
+    /// Output ports the node can be connected from. Port 0 is the signal `process` leaves in the buffer.
+    fn output_ports(&self) -> usize { 1 }
+    /// Transport state (see `Transport`) as of the start of the next block, for tempo-synced processing. Called
+    /// every block the node processes. Plugin wrappers pass it on as their format's time info (see `plugtime`).
+    fn set_transport(&mut self, transport: &TransportInfo) {}
+    /// Whether the node takes its MIDI, parameter changes and transport as events through `process_events`
+    /// (instrument plugins), instead of `set_midi_input`, `set_param` and `process`.
+    fn handles_events(&self) -> bool { false }
+    /// Whether the engine should spread changes of `param_id` over its smoothing time (see
+    /// `EngineConfig::param_smoothing_ms`). Return false for parameters the node smooths itself and for discrete
+    /// ones (modes, switches). Not consulted for nodes that handle events.
+    fn host_smoothing(&self, param_id: u32) -> bool { true }
+    /// Processes one block of interleaved audio in place along with the events falling in it, in time order with
+    /// `NodeEvent::frame` the frame within the block. Called instead of `process` (and `process_planar`) on nodes
+    /// that handle events; MIDI is included if the node accepts MIDI. Must not allocate. The default applies
+    /// parameter events at the start of the block and ignores the rest.
+    fn process_events(&mut self, audio: &mut [f32], events: &[NodeEvent]) {
+        for event in events {
+            if let EventKind::Param { id, value } = event.kind {
+                self.set_param(id, &value.to_le_bytes());
+            }
+        }
+        self.process(audio);
+    }
+    /// Drops voices, tails and anything buffered, as on the Panic command; the node should produce silence from
+    /// the next block until it gets new input. Must not allocate. Plugin wrappers pass it on as their format's
+    /// reset, on top of the All Notes Off MIDI nodes are sent.
+    fn reset(&mut self) {}
 }
 
-/// Thread-safety wrapper to allow the CPAL Stream to be sent between threads.
-struct SendStream(cpal::Stream);
+/// An open stream, with a thread-safety wrapper to allow the CPAL Stream to be sent between threads.
+enum SendStream {
+    Cpal(cpal::Stream),
+    #[cfg(feature = "jack")]
+    Jack(JackStream),
+    #[cfg(feature = "pipewire")]
+    PipeWire(PipeWireStream),
+    #[cfg(all(windows, feature = "wasapi"))]
+    Wasapi(WasapiStream),
+    Null(NullStream),
+}
 unsafe impl Send for SendStream {}
 
-/// Global handle to the active audio stream.
-static ACTIVE_STREAM: Mutex<Option<SendStream>> = Mutex::new(None);
+/// A further output device playing one of the engine's feeds, see `DspEngine::add_aggregate_output`.
+struct AggregateOutput {
+    feed: usize,
+    device: String,
+    /// Open while the engine runs.
+    stream: Option<SendStream>,
+    device_rate: u32,
+    stats: Option<Arc<DriftStats>>,
+}
 
-/// Global Singleton for the DSP Engine.
+/// Default engine, used by `Command::send` and the other conveniences that don't take an engine. Embedders
+/// running several engines (e.g. one per output device) construct their own and address them through `handle()`.
 pub static DSPENGINE: Lazy<Mutex<DspEngine>> = Lazy::new(|| {
     Mutex::new(DspEngine::new(1, "OpenTune Universal Host", 44100, 1024))
 });
 
+// Output fade states shared with the audio callback, used to switch devices without clicks.
+const FADE_NONE: u32 = 0;
+/// Ramp the next block down, then go silent.
+const FADE_OUT: u32 = 1;
+/// Output silence without consuming the ring buffer or running the graph.
+const FADE_SILENT: u32 = 2;
+/// Ramp the next block up from silence.
+const FADE_IN: u32 = 3;
+
+/// Time the Panic command fades the output to silence over.
+pub const PANIC_FADE_MS: f32 = 5.0;
+
+/// Active scene of an engine that hasn't recalled one yet.
+const NO_SCENE: u32 = u32::MAX;
+
+/// Engine lifecycle. Transitions are validated by `EngineState::can_transition` and broadcast on `RESPONSE_QUEUE`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineState {
+    Stopped,
+    Starting,
+    Running,
+    /// The stream is being torn down; no new audio will be produced.
+    Draining,
+    Error { cause: String },
+}
+
+impl EngineState {
+    /// Wire code used in the `Engine State` response payload.
+    pub fn code(&self) -> u8 {
+        match self {
+            EngineState::Stopped => 0,
+            EngineState::Starting => 1,
+            EngineState::Running => 2,
+            EngineState::Draining => 3,
+            EngineState::Error { .. } => 4,
+        }
+    }
+
+    pub fn can_transition(&self, next: &EngineState) -> bool {
+        use EngineState::*;
+        matches!(
+            (self, next),
+            (Stopped, Starting)
+                | (Starting, Running)
+                | (Running, Draining)
+                | (Draining, Stopped)
+                | (Error { .. }, Starting)
+                | (Error { .. }, Stopped)
+                | (Starting | Running | Draining, Error { .. })
+        )
+    }
+}
+
+/// Validates and applies a state change, then notifies the frontend.
+/// Free function so the stream's error callback can report failures without access to the engine.
+fn transition(state: &Mutex<EngineState>, engine_id: u32, next: EngineState) -> Result<(), String> {
+    let mut current = state.lock().map_err(|_| "Engine state lock poisoned".to_string())?;
+    if !current.can_transition(&next) {
+        return Err(format!("Invalid engine transition {:?} -> {:?}", *current, next));
+    }
+    *current = next.clone();
+    drop(current);
+
+    let mut payload = vec![next.code()];
+    let stat = match &next {
+        EngineState::Running => StatState::ACTIVE,
+        EngineState::Error { cause } => {
+            payload.extend_from_slice(cause.as_bytes());
+            StatState::INACTIVE
+        }
+        _ => StatState::INACTIVE,
+    };
+    if let Ok(mut queue) = RESPONSE_QUEUE.lock() {
+        queue.push(Command::new(101, "Engine State", payload, engine_id, 0, 0, stat));
+    }
+    Ok(())
+}
+
+/// Sizes of everything the engine preallocates, fixed up front so memory use is bounded.
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    pub sample_rate: u32,
+    pub buffer_size: usize,
+    /// Frames the graph processes at a time, however many the device asks for per callback; `None` uses
+    /// `buffer_size`. Gives plugins and automation a constant block even on drivers with variable callbacks.
+    pub block_size: Option<usize>,
+    /// Channel layout of the ring buffers and the graph. Devices with a different channel count are up/downmixed.
+    pub layout: ChannelLayout,
+    /// Playback ring buffer capacity in samples (all channels). Rounded up to a power of two.
+    pub ring_buffer_capacity: usize,
+    /// Open the default input device alongside the output. Captured audio is available from `capture_samples`
+    /// and to the graph through `graph::GRAPH_CAPTURE`.
+    pub capture_input: bool,
+    /// Capture ring buffer capacity in samples (interleaved in `layout`). Rounded up to a power of two.
+    pub capture_ring_capacity: usize,
+    /// Live effects mode: opens input with the output and feeds the captured input, scaled by the monitor gain,
+    /// into the graph input alongside pushed samples. Implies `capture_input`.
+    pub duplex: bool,
+    /// Initial monitor gain for duplex mode (linear).
+    pub monitor_gain: f32,
+    /// Audio host (driver API) the streams are opened on, see `audiohost::available_hosts`; `None` uses the
+    /// system default. Defaults to `audiohost::preferred_host`.
+    pub audio_host: Option<String>,
+    /// Output device name (see `output_devices`); `None` uses the host's default.
+    pub output_device: Option<String>,
+    /// Commands that can be pending for the audio thread; further sends are rejected.
+    pub command_queue_capacity: usize,
+    /// Graph node slots; adding a node to a full graph is rejected instead of reallocating on the audio thread.
+    pub max_nodes: usize,
+    /// Graph connection slots.
+    pub max_connections: usize,
+    /// Longest delay, in frames, the graph inserts to keep parallel paths of different latency aligned.
+    pub max_latency_compensation: usize,
+    /// Metering tap slots.
+    pub max_taps: usize,
+    /// MIDI events that can be waiting for the audio thread, and for the MIDI outputs; further events are dropped.
+    pub midi_queue_capacity: usize,
+    /// Refuse to insert nodes that aren't known to be real-time safe, for live rigs that must never glitch.
+    pub strict_rt: bool,
+    /// Time, in milliseconds, parameter changes are spread over before they reach the node, against zipper
+    /// noise; 0 applies them directly. Automation is already sample-accurate and is never smoothed.
+    pub param_smoothing_ms: f32,
+    /// Promote the audio callback thread to real-time scheduling (see `threads::promote_current`). Without the
+    /// needed permissions the engine runs at normal priority and says so on stderr.
+    pub realtime_priority: bool,
+    /// Add a tiny DC offset (`denormal::DENORMAL_DC`) to the graph input, for nodes whose feedback paths would
+    /// otherwise decay into denormals even with flush-to-zero on (e.g. ones computing in f64).
+    pub denormal_dither: bool,
+    /// Run the device output through a brickwall limiter (see `limiter::OutputLimiter`), so a misbehaving node
+    /// or a gain mistake can't reach the speakers above `output_ceiling_db` or as NaN. Switchable while running
+    /// with `set_output_protection`. Offline renders are never limited.
+    pub output_protection: bool,
+    /// Ceiling of the output protection limiter, in dBFS (at most 0.0).
+    pub output_ceiling_db: f32,
+    /// TPDF-dither the output when the device takes 16-bit samples (see `sampleformat::FormatConverter`).
+    pub output_dither: bool,
+    /// Client name, autoconnection and transport following of the native JACK backend, used when `audio_host`
+    /// is `audiohost::JACK`.
+    #[cfg(feature = "jack")]
+    pub jack: JackOptions,
+    /// Node name, quantum and autoconnection of the native PipeWire backend, used when `audio_host` is
+    /// `audiohost::PIPEWIRE`.
+    #[cfg(feature = "pipewire")]
+    pub pipewire: PipeWireOptions,
+    /// Exclusive or low-latency shared output and the period to ask for, on the WASAPI host (the Windows
+    /// default). Shared mode goes through cpal as on every other host.
+    #[cfg(all(windows, feature = "wasapi"))]
+    pub wasapi: WasapiOptions,
+    /// Pacing and length of the null backend, used when `audio_host` is `audiohost::NULL`.
+    pub null: NullOptions,
+}
+
+impl EngineConfig {
+    pub fn new(sample_rate: u32, buffer_size: usize) -> Self {
+        EngineConfig {
+            sample_rate,
+            buffer_size,
+            block_size: None,
+            layout: ChannelLayout::Stereo,
+            ring_buffer_capacity: buffer_size.next_power_of_two(),
+            capture_input: false,
+            capture_ring_capacity: (buffer_size * 2 * 4).next_power_of_two(),
+            duplex: false,
+            monitor_gain: 1.0,
+            audio_host: audiohost::preferred_host(),
+            output_device: None,
+            command_queue_capacity: 256,
+            max_nodes: 64,
+            max_connections: 256,
+            max_latency_compensation: crate::graph::DEFAULT_MAX_COMPENSATION,
+            max_taps: 32,
+            midi_queue_capacity: 1024,
+            strict_rt: false,
+            param_smoothing_ms: smoothing::DEFAULT_SMOOTHING_MS,
+            realtime_priority: true,
+            denormal_dither: false,
+            output_protection: true,
+            output_ceiling_db: limiter::DEFAULT_CEILING_DB,
+            output_dither: true,
+            #[cfg(feature = "jack")]
+            jack: JackOptions::default(),
+            #[cfg(feature = "pipewire")]
+            pipewire: PipeWireOptions::default(),
+            #[cfg(all(windows, feature = "wasapi"))]
+            wasapi: WasapiOptions::default(),
+            null: NullOptions::default(),
+        }
+    }
+}
+
+/// One preallocated region in the memory report.
+#[derive(Debug, Clone)]
+pub struct AllocationEntry {
+    pub name: &'static str,
+    pub bytes: usize,
+}
+
+/// Preallocation report produced at engine construction.
+#[derive(Debug, Clone)]
+pub struct MemoryReport {
+    pub entries: Vec<AllocationEntry>,
+}
+
+impl MemoryReport {
+    pub fn total_bytes(&self) -> usize {
+        self.entries.iter().map(|e| e.bytes).sum()
+    }
+}
+
+impl std::fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "  {:<28} {:>10} bytes", entry.name, entry.bytes)?;
+        }
+        write!(f, "  {:<28} {:>10} bytes", "Total", self.total_bytes())
+    }
+}
+
+/// The engine's transport: play state, timeline position, tempo and time signature. Changed by the transport
+/// commands (see `dspapi`) or directly from the control side; the audio thread advances the position while
+/// playing and hands the state to the graph every block. Lock-free, so reading it never blocks either side.
+pub struct Transport {
+    playing: AtomicBool,
+    recording: AtomicBool,
+    position: AtomicU64,
+    /// f32 bits.
+    tempo: AtomicU32,
+    /// Beats per bar in the high half, beat unit in the low half.
+    time_signature: AtomicU32,
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        let info = TransportInfo::default();
+        Transport {
+            playing: AtomicBool::new(info.playing),
+            recording: AtomicBool::new(info.recording),
+            position: AtomicU64::new(info.position),
+            tempo: AtomicU32::new(info.tempo.to_bits()),
+            time_signature: AtomicU32::new((info.beats_per_bar as u32) << 16 | info.beat_unit as u32),
+        }
+    }
+
+    pub fn info(&self) -> TransportInfo {
+        let time_signature = self.time_signature.load(Ordering::Relaxed);
+        TransportInfo {
+            playing: self.playing.load(Ordering::Relaxed),
+            recording: self.recording.load(Ordering::Relaxed),
+            tempo: f32::from_bits(self.tempo.load(Ordering::Relaxed)),
+            position: self.position.load(Ordering::Relaxed),
+            beats_per_bar: (time_signature >> 16) as u16,
+            beat_unit: time_signature as u16,
+        }
+    }
+
+    pub fn play(&self) {
+        self.playing.store(true, Ordering::Relaxed);
+    }
+
+    /// Stops playing and recording; the position stays where it is.
+    pub fn stop(&self) {
+        self.playing.store(false, Ordering::Relaxed);
+        self.recording.store(false, Ordering::Relaxed);
+    }
+
+    /// Starts recording, and playing if stopped.
+    pub fn record(&self) {
+        self.recording.store(true, Ordering::Relaxed);
+        self.playing.store(true, Ordering::Relaxed);
+    }
+
+    /// Moves the timeline to `frame`.
+    pub fn locate(&self, frame: u64) {
+        self.position.store(frame, Ordering::Relaxed);
+    }
+
+    /// Sets the tempo in BPM. Refused unless it is at least 1.
+    pub fn set_tempo(&self, bpm: f32) -> Result<(), &'static str> {
+        if !(bpm >= 1.0 && bpm.is_finite()) { return Err("tempo must be at least 1 BPM"); }
+        self.tempo.store(bpm.to_bits(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Changes the tempo by `delta` BPM, keeping it at 1 BPM or more.
+    pub fn nudge_tempo(&self, delta: f32) {
+        let tempo = f32::from_bits(self.tempo.load(Ordering::Relaxed));
+        let _ = self.set_tempo((tempo + delta).max(1.0));
+    }
+
+    /// Sets the time signature, e.g. 6/8 as (6, 8). Refused for a zero beat count or unit.
+    pub fn set_time_signature(&self, beats_per_bar: u16, beat_unit: u16) -> Result<(), &'static str> {
+        if beats_per_bar == 0 || beat_unit == 0 { return Err("time signature needs nonzero beats and unit"); }
+        self.time_signature.store((beats_per_bar as u32) << 16 | beat_unit as u32, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Moves the position on by a rendered block of `frames`, if playing.
+    fn advance(&self, frames: u64) {
+        if self.playing.load(Ordering::Relaxed) {
+            self.position.fetch_add(frames, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct DspEngine {
     pub engine_id: u32,
     pub description: &'static str,
-    pub is_running: bool,
+    state: Arc<Mutex<EngineState>>,
     pub sample_rate: u32,
     pub buffer_size: usize,
     pub buffer: Arc<Buffer>,
-    pub command_queue: Arc<Mutex<Vec<Command>>>,
-    /// The Rack: A dynamic list of loaded plugins and DSP nodes.
-    pub nodes: Arc<Mutex<Vec<Box<dyn AudioNode>>>>, 
+    /// Captured input for the application (recording, analysis). Read with `capture_samples`; when nobody reads,
+    /// new input is dropped once it is full.
+    pub capture: Arc<Buffer>,
+    /// Captured input on its way to the graph; consumed by the output callback.
+    live_input: Arc<Buffer>,
+    /// Duplex monitor gain as f32 bits, adjustable while running.
+    monitor_gain: Arc<AtomicU32>,
+    /// Whether the output protection limiter runs, adjustable while running.
+    output_protection: Arc<AtomicBool>,
+    /// Keeps rendering but sends silence to the hardware: a hot standby that takes over the output by clearing
+    /// it (see `failover`). Changes fade over one callback.
+    pub standby: Arc<AtomicBool>,
+    /// Commands for the audio thread; bounded to `config.command_queue_capacity` and lock-free on both ends.
+    pub command_queue: Arc<ArrayQueue<Command>>,
+    /// Loaded plugins and DSP nodes and the routing between them. Owned by the output stream while it runs;
+    /// read `graph.snapshot()` then and change it through commands.
+    pub graph: Arc<SharedGraph>,
+    /// Incremented once per audio callback; a watchdog can detect a stalled engine by sampling it.
+    pub heartbeat: Arc<AtomicU64>,
+    /// Frames played since the engine was created. Survives device switches.
+    pub position: Arc<AtomicU64>,
+    fade: Arc<AtomicU32>,
+    /// Raised by the Panic command, handled by the next rendered block.
+    panic: Arc<AtomicBool>,
+    /// Set by the stream error callbacks when the device went away (unplugged); see `devices::DeviceWatcher`.
+    pub device_lost: Arc<AtomicBool>,
+    /// Nodes removed by the audio thread, dropped by `reaper` on its own thread.
+    graveyard: Arc<Graveyard>,
+    /// Started with the first stream and kept for the engine's lifetime.
+    reaper: Option<NodeReaper>,
+    /// The open output stream; dropping it stops the callback.
+    stream: Option<SendStream>,
+    /// The open capture stream, when input is enabled.
+    input_stream: Option<SendStream>,
+    /// Name of the output device the stream is open on.
+    device_name: Option<String>,
+    /// Rate the output device actually runs at; differs from `sample_rate` when the engine resamples.
+    device_rate: u32,
+    /// Meters placed on the rack, read by the frontend.
+    pub taps: Arc<TapSet>,
+    /// Levels of the rack output and of every node, always measured.
+    pub meters: Arc<LevelMeters>,
+    /// Cumulative CPU time per node, for battery-aware frontends.
+    pub usage: Arc<UsageMeter>,
+    pub transport: Arc<Transport>,
+    /// Master volume, mute and dim of the rack output; changed through the master commands.
+    pub master: Arc<MasterControls>,
+    /// Scene last recalled with Scene Recall, `NO_SCENE` before the first.
+    scene: Arc<AtomicU32>,
+    /// Parameter automation lanes, played back sample-accurately against the transport.
+    pub automation: Arc<Mutex<Automation>>,
+    /// LFOs, envelope followers and macros routed to node parameters; changed through the modulation commands.
+    pub modulation: Arc<Mutex<ModMatrix>>,
+    /// Incoming MIDI for the audio thread, from the open inputs and `send_midi`.
+    midi_queue: Arc<ArrayQueue<MidiEvent>>,
+    midi_inputs: MidiInputs,
+    /// MIDI the graph routed to `graph::GRAPH_MIDI_OUTPUT`, timestamped for the output thread.
+    midi_out_queue: Arc<ArrayQueue<MidiEvent>>,
+    /// Started with the first opened output.
+    midi_outputs: Option<MidiOutputs>,
+    /// Publishes node loads while load reporting is on.
+    load_publisher: Option<LoadPublisher>,
+    /// Underruns, overruns and missed callback deadlines, reported as Xrun responses by `xrun_monitor`.
+    pub xruns: Arc<XrunCounters>,
+    /// Started with the first stream and kept for the engine's lifetime.
+    xrun_monitor: Option<XrunMonitor>,
+    /// Output latency as of the last callback, see `latency`.
+    pub latency: Arc<LatencyMeter>,
+    /// Publishes the output latency while latency reporting is on.
+    latency_publisher: Option<LatencyPublisher>,
+    /// What the audio thread sends to the extra output devices.
+    pub output_feeds: Arc<OutputFeeds>,
+    /// Extra output devices playing alongside the main one.
+    aggregate_outputs: Vec<AggregateOutput>,
+    /// Network stream of the master output and the feed it reads, see `start_rtp_stream`.
+    rtp_stream: Option<(usize, RtpSender)>,
+    /// Renderer `process_block` pumps, holding the graph until a stream or an offline render needs it.
+    pump: Option<HostRenderer>,
+    /// Keeps the transport in step with a Link session, see `set_link`.
+    #[cfg(feature = "link")]
+    link: Option<LinkThread>,
+    /// JACK connections made with `jack_connect`, as (own port short name, other port), restored whenever the
+    /// client is opened again.
+    #[cfg(feature = "jack")]
+    jack_connections: Vec<(String, String)>,
+    pub config: EngineConfig,
+}
+
+/// Cloneable reference to one engine's shared state: its command queue, graph and counters. Sending through a
+/// handle reaches exactly that engine, so several engines can run in one process.
+#[derive(Clone)]
+pub struct EngineHandle {
+    pub engine_id: u32,
+    command_queue: Arc<ArrayQueue<Command>>,
+    pub graph: Arc<SharedGraph>,
+    state: Arc<Mutex<EngineState>>,
+    pub heartbeat: Arc<AtomicU64>,
+    pub position: Arc<AtomicU64>,
+    pub taps: Arc<TapSet>,
+    pub meters: Arc<LevelMeters>,
+    pub usage: Arc<UsageMeter>,
+    pub transport: Arc<Transport>,
+    pub master: Arc<MasterControls>,
+    scene: Arc<AtomicU32>,
+    pub automation: Arc<Mutex<Automation>>,
+    pub modulation: Arc<Mutex<ModMatrix>>,
+    pub xruns: Arc<XrunCounters>,
+}
+
+impl EngineHandle {
+    /// Queues `command` for the engine. Returns false if the queue is at its configured capacity.
+    /// Accepted state-changing commands are written to the audit log when one is recording.
+    pub fn send(&self, command: Command) -> bool {
+        let audited = crate::audit::is_recording().then(|| command.clone());
+        let accepted = self.command_queue.push(command).is_ok();
+        if let (true, Some(command)) = (accepted, audited) {
+            crate::audit::record(self.engine_id, &command);
+        }
+        accepted
+    }
+
+    pub fn state(&self) -> EngineState {
+        self.state.lock().map(|s| s.clone()).unwrap_or(EngineState::Error { cause: "Engine state lock poisoned".into() })
+    }
+
+    /// Scene the engine last applied a Scene Recall for, if any.
+    pub fn active_scene(&self) -> Option<u32> {
+        active_scene(&self.scene)
+    }
 }
 
 impl DspEngine {
     pub fn new(engine_id: u32, description: &'static str, sample_rate: u32, buffer_size: usize) -> Self {
-        let buffer = Arc::new(Buffer::new(buffer_size).expect("MagicRingBuffer Initialization Failed"));
-        DspEngine {
+        Self::with_config(engine_id, description, EngineConfig::new(sample_rate, buffer_size))
+    }
+
+    pub fn with_config(engine_id: u32, description: &'static str, config: EngineConfig) -> Self {
+        let ring_capacity = config.ring_buffer_capacity.next_power_of_two();
+        let buffer = Arc::new(Buffer::new(ring_capacity).expect("MagicRingBuffer Initialization Failed"));
+        let capture_capacity = config.capture_ring_capacity.next_power_of_two();
+        let capture = Arc::new(Buffer::new(capture_capacity).expect("MagicRingBuffer Initialization Failed"));
+        let live_input = Arc::new(Buffer::new(capture_capacity).expect("MagicRingBuffer Initialization Failed"));
+        let midi_queue = Arc::new(ArrayQueue::new(config.midi_queue_capacity.max(1)));
+        let engine = DspEngine {
             engine_id,
             description,
-            is_running: false,
-            sample_rate,
-            buffer_size,
+            state: Arc::new(Mutex::new(EngineState::Stopped)),
+            sample_rate: config.sample_rate,
+            buffer_size: config.buffer_size,
             buffer,
-            command_queue: Arc::new(Mutex::new(Vec::new())),
-            nodes: Arc::new(Mutex::new(Vec::new())),
+            capture,
+            live_input,
+            monitor_gain: Arc::new(AtomicU32::new(config.monitor_gain.to_bits())),
+            output_protection: Arc::new(AtomicBool::new(config.output_protection)),
+            standby: Arc::new(AtomicBool::new(false)),
+            command_queue: Arc::new(ArrayQueue::new(config.command_queue_capacity.max(1))),
+            graph: Arc::new(SharedGraph::new(AudioGraph::new(config.max_nodes, config.max_connections, config.layout.channels(), config.block_size.unwrap_or(config.buffer_size)))),
+            heartbeat: Arc::new(AtomicU64::new(0)),
+            position: Arc::new(AtomicU64::new(0)),
+            fade: Arc::new(AtomicU32::new(FADE_NONE)),
+            panic: Arc::new(AtomicBool::new(false)),
+            device_lost: Arc::new(AtomicBool::new(false)),
+            graveyard: Arc::new(Graveyard::new(config.max_nodes.max(1) * 2)),
+            reaper: None,
+            stream: None,
+            input_stream: None,
+            device_name: None,
+            device_rate: config.sample_rate,
+            taps: Arc::new(TapSet::new(config.max_taps)),
+            meters: Arc::new(LevelMeters::new(config.max_nodes)),
+            usage: Arc::new(UsageMeter::new(config.max_nodes)),
+            transport: Arc::new(Transport::new()),
+            master: Arc::new(MasterControls::new()),
+            scene: Arc::new(AtomicU32::new(NO_SCENE)),
+            automation: Arc::new(Mutex::new(Automation::new(config.block_size.unwrap_or(config.buffer_size)))),
+            modulation: Arc::new(Mutex::new(ModMatrix::new(config.sample_rate))),
+            midi_queue: Arc::clone(&midi_queue),
+            midi_inputs: MidiInputs::new(midi_queue),
+            midi_out_queue: Arc::new(ArrayQueue::new(config.midi_queue_capacity.max(1))),
+            midi_outputs: None,
+            load_publisher: None,
+            xruns: Arc::new(XrunCounters::new()),
+            xrun_monitor: None,
+            latency: Arc::new(LatencyMeter::new(config.sample_rate)),
+            latency_publisher: None,
+            output_feeds: Arc::new(OutputFeeds::new()),
+            aggregate_outputs: Vec::new(),
+            rtp_stream: None,
+            pump: None,
+            #[cfg(feature = "link")]
+            link: None,
+            #[cfg(feature = "jack")]
+            jack_connections: Vec::new(),
+            config: EngineConfig { ring_buffer_capacity: ring_capacity, capture_ring_capacity: capture_capacity, ..config },
+        };
+        if let Ok(mut graph) = engine.graph.lock() {
+            graph.set_max_compensation(engine.config.max_latency_compensation);
+            graph.set_param_smoothing(engine.config.param_smoothing_ms);
+            graph.set_layout(engine.config.layout, engine.sample_rate);
+        }
+        engine
+    }
+
+    /// Lists every buffer the engine preallocated. Node-internal allocations are not included.
+    pub fn memory_report(&self) -> MemoryReport {
+        let command_capacity = self.command_queue.capacity();
+        let graph = self.graph.snapshot();
+        let (node_capacity, graph_bytes, compensation_bytes) = (graph.capacity, graph.allocated_bytes, graph.compensation_bytes);
+        MemoryReport {
+            entries: vec![
+                AllocationEntry { name: "Playback ring buffer", bytes: self.config.ring_buffer_capacity * std::mem::size_of::<f32>() },
+                AllocationEntry { name: "Capture ring buffers", bytes: 2 * self.config.capture_ring_capacity * std::mem::size_of::<f32>() },
+                AllocationEntry { name: "Command queue", bytes: command_capacity * std::mem::size_of::<Command>() },
+                AllocationEntry { name: "Graph slots and buffers", bytes: graph_bytes - compensation_bytes + node_capacity * std::mem::size_of::<Box<dyn AudioNode>>() },
+                AllocationEntry { name: "Latency compensation", bytes: compensation_bytes },
+                AllocationEntry { name: "Metering taps", bytes: self.taps.allocated_bytes() },
+                AllocationEntry { name: "Level meters", bytes: self.meters.allocated_bytes() },
+                AllocationEntry { name: "Usage counters", bytes: self.usage.allocated_bytes() },
+                AllocationEntry { name: "MIDI queue", bytes: 2 * self.midi_queue.capacity() * std::mem::size_of::<MidiEvent>() },
+                AllocationEntry { name: "MIDI output queue", bytes: self.midi_out_queue.capacity() * std::mem::size_of::<MidiEvent>() },
+                AllocationEntry { name: "Automation", bytes: automation::MAX_BLOCK_CHANGES * std::mem::size_of::<ParamChange>() },
+            ],
+        }
+    }
+
+    /// Publishes each node's average and peak load (Node Load responses, see `usage::load_event`) every
+    /// `interval`; `None` stops.
+    pub fn set_load_reporting(&mut self, interval: Option<Duration>) -> Result<(), String> {
+        self.load_publisher = None;
+        if let Some(interval) = interval {
+            self.load_publisher = Some(LoadPublisher::spawn(Arc::clone(&self.usage), interval)?);
+        }
+        Ok(())
+    }
+
+    /// Publishes the output latency (Latency responses, see `latency::latency_event`) every `interval` while the
+    /// engine runs; `None` stops.
+    pub fn set_latency_reporting(&mut self, interval: Option<Duration>) -> Result<(), String> {
+        self.latency_publisher = None;
+        if let Some(interval) = interval {
+            self.latency_publisher = Some(LatencyPublisher::spawn(Arc::clone(&self.latency), Arc::clone(&self.graph), self.engine_id, interval)?);
+        }
+        Ok(())
+    }
+
+    /// Syncs the transport's tempo and phase (and with `start_stop_sync` its play state) with `session` every
+    /// `interval`, phase-aligned to `quantum` beats and compensating the output latency (see `link::LinkSync`).
+    /// `None` leaves the session; call again after changing the sample rate.
+    #[cfg(feature = "link")]
+    pub fn set_link(&mut self, session: Option<Box<dyn LinkSession>>, quantum: f64, start_stop_sync: bool, interval: Duration) -> Result<(), String> {
+        self.link = None;
+        if let Some(session) = session {
+            let mut sync = LinkSync::new(session, Arc::clone(&self.transport), self.sample_rate, quantum);
+            sync.start_stop_sync = start_stop_sync;
+            sync.follow_latency(Arc::clone(&self.latency));
+            self.link = Some(sync.spawn(interval)?);
+        }
+        Ok(())
+    }
+
+    /// CPU time and estimated energy per node and for the whole session since the last `usage.reset()`.
+    pub fn usage_report(&self) -> UsageReport {
+        let graph = self.graph.snapshot();
+        self.usage.report(|id| graph.node(id).map(|n| n.name.clone()))
+    }
+
+    pub fn state(&self) -> EngineState {
+        self.state.lock().map(|s| s.clone()).unwrap_or(EngineState::Error { cause: "Engine state lock poisoned".into() })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state() == EngineState::Running
+    }
+
+    /// Scene the engine last applied a Scene Recall for, if any.
+    pub fn active_scene(&self) -> Option<u32> {
+        active_scene(&self.scene)
+    }
+
+    /// Handle for talking to this engine from other threads without going through its lock.
+    pub fn handle(&self) -> EngineHandle {
+        EngineHandle {
+            engine_id: self.engine_id,
+            command_queue: Arc::clone(&self.command_queue),
+            graph: Arc::clone(&self.graph),
+            state: Arc::clone(&self.state),
+            heartbeat: Arc::clone(&self.heartbeat),
+            position: Arc::clone(&self.position),
+            taps: Arc::clone(&self.taps),
+            meters: Arc::clone(&self.meters),
+            usage: Arc::clone(&self.usage),
+            transport: Arc::clone(&self.transport),
+            master: Arc::clone(&self.master),
+            scene: Arc::clone(&self.scene),
+            automation: Arc::clone(&self.automation),
+            modulation: Arc::clone(&self.modulation),
+            xruns: Arc::clone(&self.xruns),
         }
     }
 
     /// Initializes and starts the high-priority audio thread.
     pub fn start(&mut self) -> Result<(), String> {
-        if self.is_running { return Ok(()); }
+        self.start_with(FADE_NONE)
+    }
 
-        let host = cpal::default_host();
-        let device = host.default_output_device().ok_or("No output device found")?;
-        
+    /// Starts the streams with the output in fade state `fade`.
+    fn start_with(&mut self, fade: u32) -> Result<(), String> {
+        if self.is_running() { return Ok(()); }
+
+        transition(&self.state, self.engine_id, EngineState::Starting)?;
+        self.fade.store(fade, Ordering::Release);
+        match self.open_stream() {
+            Ok(()) => {
+                self.open_aggregate_outputs();
+                transition(&self.state, self.engine_id, EngineState::Running)?;
+                println!("[DspEngine] Audio Thread Started successfully.");
+                Ok(())
+            }
+            Err(cause) => {
+                let _ = transition(&self.state, self.engine_id, EngineState::Error { cause: cause.clone() });
+                Err(cause)
+            }
+        }
+    }
+
+    fn open_stream(&mut self) -> Result<(), String> {
+        self.pump = None;
+        if audiohost::is_null(self.config.audio_host.as_deref()) {
+            return self.open_null_stream();
+        }
+        #[cfg(feature = "jack")]
+        if audiohost::is_native_jack(self.config.audio_host.as_deref()) {
+            return self.open_jack_stream();
+        }
+        #[cfg(feature = "pipewire")]
+        if audiohost::is_native_pipewire(self.config.audio_host.as_deref()) {
+            return self.open_pipewire_stream();
+        }
+        let host = audiohost::host(self.config.audio_host.as_deref())?;
+        if self.config.capture_input || self.config.duplex {
+            self.open_input_stream(&host)?;
+        }
+        self.open_output_stream(&host)
+    }
+
+    /// Names of the output devices of the system default host (see `audiohost::output_devices` for others).
+    pub fn output_devices() -> Vec<String> {
+        audiohost::output_devices(None)
+    }
+
+    /// Names of the audio hosts the engine can open streams on.
+    pub fn audio_hosts() -> Vec<String> {
+        audiohost::available_hosts()
+    }
+
+    /// Audio host the streams are opened on; `None` is the system default.
+    pub fn audio_host(&self) -> Option<&str> {
+        self.config.audio_host.as_deref()
+    }
+
+    /// Moves the streams to another audio host (`None` for the system default), e.g. ASIO for low latency on
+    /// Windows, on that host's default device. The graph and queued audio are kept and the output fades over.
+    /// If the host can't be opened the engine goes back to the previous host and device.
+    pub fn set_audio_host(&mut self, name: Option<String>) -> Result<(), String> {
+        if !audiohost::is_native(name.as_deref()) { audiohost::host(name.as_deref())?; }
+        let running = self.is_running();
+        if running { self.fade_out_and_close(true); }
+        let previous = (std::mem::replace(&mut self.config.audio_host, name), self.config.output_device.take());
+        match self.reopen(running) {
+            Ok(()) => Ok(()),
+            Err(cause) => {
+                (self.config.audio_host, self.config.output_device) = previous;
+                // Leaves the engine in the error state if the previous host is gone too.
+                let _ = self.restart_streams(self.config.output_device.clone());
+                Err(cause)
+            }
+        }
+    }
+
+    /// Names of the MIDI inputs that can be opened.
+    pub fn midi_input_ports() -> Vec<String> {
+        midi::input_ports().unwrap_or_default()
+    }
+
+    /// Starts delivering MIDI from input `name` to the nodes that accept MIDI. Works whether or not the engine
+    /// is running.
+    pub fn open_midi_input(&mut self, name: &str) -> Result<(), String> {
+        self.midi_inputs.open(name)
+    }
+
+    /// Stops listening to MIDI input `name`. Returns false if it wasn't open.
+    pub fn close_midi_input(&mut self, name: &str) -> bool {
+        self.midi_inputs.close(name)
+    }
+
+    /// Names of the open MIDI inputs.
+    pub fn midi_inputs(&self) -> Vec<String> {
+        self.midi_inputs.open_inputs().map(str::to_string).collect()
+    }
+
+    /// Names of the MIDI outputs that can be opened.
+    pub fn midi_output_ports() -> Vec<String> {
+        midi::output_ports().unwrap_or_default()
+    }
+
+    /// Starts sending the MIDI routed to `graph::GRAPH_MIDI_OUTPUT` to output `name`.
+    pub fn open_midi_output(&mut self, name: &str) -> Result<(), String> {
+        if self.midi_outputs.is_none() {
+            self.midi_outputs = Some(MidiOutputs::spawn(Arc::clone(&self.midi_out_queue))?);
+        }
+        self.midi_outputs.as_mut().map_or(Ok(()), |outputs| outputs.open(name))
+    }
+
+    /// Stops sending to MIDI output `name`. Returns false if it wasn't open.
+    pub fn close_midi_output(&mut self, name: &str) -> bool {
+        self.midi_outputs.as_mut().is_some_and(|outputs| outputs.close(name))
+    }
+
+    /// Names of the open MIDI outputs.
+    pub fn midi_outputs(&self) -> Vec<String> {
+        self.midi_outputs.as_ref().map(|outputs| outputs.open_outputs()).unwrap_or_default()
+    }
+
+    /// Queues a MIDI message as if it had arrived on an input now, e.g. from an on-screen keyboard. Returns false
+    /// if the MIDI queue is full or the message is longer than three bytes.
+    pub fn send_midi(&self, message: &[u8]) -> bool {
+        MidiEvent::new(midi::now_micros(), u16::MAX, message).is_some_and(|event| self.midi_queue.push(event).is_ok())
+    }
+
+    /// Moves playback to another output device (`None` for the system default). The graph, node state and
+    /// pending ring buffer audio are kept; the old device fades out and the new one fades in. If the new device
+    /// can't be opened the engine goes back to the previous one.
+    pub fn switch_output_device(&mut self, name: Option<String>) -> Result<(), String> {
+        // JACK has no devices to switch; its ports are routed with `jack_connect`.
+        if !self.is_running() || audiohost::is_native_jack(self.config.audio_host.as_deref()) {
+            self.config.output_device = name;
+            return Ok(());
+        }
+        // PipeWire's output device is the sink its node links to; relinking means reconnecting the streams.
+        if audiohost::is_native_pipewire(self.config.audio_host.as_deref()) {
+            self.fade_out_and_close(true);
+            let previous = std::mem::replace(&mut self.config.output_device, name);
+            return self.reopen(true).or_else(|cause| {
+                self.config.output_device = previous;
+                let _ = self.restart_streams(self.config.output_device.clone());
+                Err(cause)
+            });
+        }
+
+        self.fade_out_and_close(false);
+        let host = audiohost::host(self.config.audio_host.as_deref())?;
+        let previous = std::mem::replace(&mut self.config.output_device, name);
+        self.fade.store(FADE_IN, Ordering::Release);
+        match self.open_output_stream(&host) {
+            Ok(()) => Ok(()),
+            Err(cause) => {
+                self.config.output_device = previous;
+                if let Err(fallback) = self.open_output_stream(&host) {
+                    let _ = transition(&self.state, self.engine_id, EngineState::Error { cause: fallback });
+                }
+                Err(cause)
+            }
+        }
+    }
+
+    /// Fades the output to silence (waiting at most a quarter second for the callback), then closes the output
+    /// stream and, with `input`, the capture stream. Ring buffer contents and the graph are untouched.
+    fn fade_out_and_close(&mut self, input: bool) {
+        self.fade.store(FADE_OUT, Ordering::Release);
+        let deadline = Instant::now() + Duration::from_millis(250);
+        while self.fade.load(Ordering::Acquire) != FADE_SILENT && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        self.fade.store(FADE_SILENT, Ordering::Release);
+        self.stream = None;
+        self.latency.stream_closed();
+        if input {
+            self.input_stream = None;
+        }
+    }
+
+    /// Changes the engine's nominal sample rate: every node is prepared again at `rate` and the streams are
+    /// reopened. Devices that can't run at `rate` stay at their own rate and the engine resamples between them,
+    /// so nodes always see `rate`.
+    pub fn set_sample_rate(&mut self, rate: u32) -> Result<(), String> {
+        if rate == 0 { return Err("Sample rate must be positive".to_string()); }
+        let running = self.is_running();
+        if running { self.fade_out_and_close(true); }
+        self.pump = None;
+
+        self.sample_rate = rate;
+        self.config.sample_rate = rate;
+        if let Ok(mut graph) = self.graph.lock() {
+            graph.prepare(rate, self.block_size());
+        }
+        self.reopen(running)
+    }
+
+    /// Changes the block size, trading latency for stability. The streams are reopened and the ring buffers
+    /// reallocated at sizes scaled to the new block (queued playback is carried over as far as it fits); the graph
+    /// keeps its nodes and routing and is prepared again for the new block size. Clones of the old `buffer` or
+    /// `capture` rings taken before the call no longer reach the engine.
+    pub fn set_buffer_size(&mut self, frames: usize) -> Result<(), String> {
+        if frames == 0 { return Err("Buffer size must be positive".to_string()); }
+        let running = self.is_running();
+        if running { self.fade_out_and_close(true); }
+        self.pump = None;
+
+        let scale = |capacity: usize| (capacity * frames / self.buffer_size.max(1)).max(frames).next_power_of_two();
+        let (ring_capacity, capture_capacity) = (scale(self.config.ring_buffer_capacity), scale(self.config.capture_ring_capacity));
+        let layout = self.config.layout;
+        self.reallocate_rings(ring_capacity, capture_capacity.max(frames * layout.channels()), layout)?;
+        self.buffer_size = frames;
+        self.config.buffer_size = frames;
+        if let Ok(mut graph) = self.graph.lock() {
+            graph.prepare(self.sample_rate, self.block_size());
+        }
+        self.reopen(running)
+    }
+
+    /// Frames the graph processes at a time.
+    pub fn block_size(&self) -> usize {
+        self.config.block_size.unwrap_or(self.buffer_size).max(1)
+    }
+
+    /// Sets the internal block size (`None` follows the buffer size). The graph is prepared again for it and the
+    /// streams are reopened.
+    pub fn set_block_size(&mut self, frames: Option<usize>) -> Result<(), String> {
+        if frames == Some(0) { return Err("Block size must be positive".to_string()); }
+        let running = self.is_running();
+        if running { self.fade_out_and_close(true); }
+        self.pump = None;
+
+        self.config.block_size = frames;
+        if let Ok(mut graph) = self.graph.lock() {
+            graph.prepare(self.sample_rate, self.block_size());
+        }
+        self.reopen(running)
+    }
+
+    /// Changes the channel layout of the ring buffers and the graph, e.g. to process 5.1 or use every input of a
+    /// multichannel interface. Nodes are told through `AudioNode::set_channel_layout` and prepared again; queued
+    /// playback is converted to the new layout. Rings are resized in proportion to the channel count, and clones
+    /// of the old `buffer` or `capture` rings no longer reach the engine.
+    pub fn set_channel_layout(&mut self, layout: ChannelLayout) -> Result<(), String> {
+        let running = self.is_running();
+        if running { self.fade_out_and_close(true); }
+        self.pump = None;
+
+        let (from, to) = (self.config.layout.channels(), layout.channels());
+        let scale = |capacity: usize| (capacity * to).div_ceil(from).next_power_of_two();
+        let (ring_capacity, capture_capacity) = (scale(self.config.ring_buffer_capacity), scale(self.config.capture_ring_capacity));
+        self.reallocate_rings(ring_capacity, capture_capacity, layout)?;
+        self.config.layout = layout;
+        if let Ok(mut graph) = self.graph.lock() {
+            graph.set_layout(layout, self.sample_rate);
+        }
+        self.reopen(running)
+    }
+
+    pub fn channel_layout(&self) -> ChannelLayout {
+        self.config.layout
+    }
+
+    /// Total latency of the rack in frames at the engine rate: the slowest path through the graph, which every
+    /// other path is delayed to match.
+    pub fn latency_samples(&self) -> usize {
+        self.graph.latency_samples()
+    }
+
+    /// How long a sample pushed now takes to be heard: the audio queued ahead of it in the ring buffer, the
+    /// stream's buffering and the device latency (as the driver reports it, or one period where it doesn't), and
+    /// the rack latency. Stream latency is zero while the engine is stopped.
+    pub fn latency(&self) -> LatencyReport {
+        let queued = self.buffer.read_slice().len() / self.config.layout.channels();
+        self.latency.report(queued as u64, self.latency_samples() as u64, self.sample_rate)
+    }
+
+    /// Replaces the ring buffers, carrying queued playback over (converted to `layout`) as far as it fits.
+    /// The streams must be closed.
+    fn reallocate_rings(&mut self, ring_capacity: usize, capture_capacity: usize, layout: ChannelLayout) -> Result<(), String> {
+        let buffer = Buffer::new(ring_capacity).map_err(|e| format!("Failed to allocate ring buffer: {}", e))?;
+        let capture = Buffer::new(capture_capacity).map_err(|e| format!("Failed to allocate capture ring buffer: {}", e))?;
+        let live_input = Buffer::new(capture_capacity).map_err(|e| format!("Failed to allocate capture ring buffer: {}", e))?;
+
+        let pending = self.buffer.read_slice();
+        let mut carried = vec![0.0f32; pending.len() / self.config.layout.channels() * layout.channels()];
+        let frames = ChannelMap::new(self.config.layout, layout).apply(pending, &mut carried);
+        let len = (frames * layout.channels()).min(ring_capacity / layout.channels() * layout.channels());
+        if let Some(slice) = buffer.write_slice(len) {
+            slice.copy_from_slice(&carried[..len]);
+            buffer.commit_write(len);
+        }
+
+        self.buffer = Arc::new(buffer);
+        self.capture = Arc::new(capture);
+        self.live_input = Arc::new(live_input);
+        self.config.ring_buffer_capacity = ring_capacity;
+        self.config.capture_ring_capacity = capture_capacity;
+        Ok(())
+    }
+
+    /// Reopens the streams after a reconfiguration if the engine was running, fading in.
+    fn reopen(&mut self, running: bool) -> Result<(), String> {
+        // Made for the old configuration; `process_block` makes a new one.
+        self.pump = None;
+        // The network stream announces the engine's rate, which may just have changed.
+        if let Some(config) = self.rtp_stream.as_ref().map(|(_, sender)| sender.config().clone()) {
+            if let Err(cause) = self.start_rtp_stream(config) {
+                eprintln!("[DspEngine] RTP stream stopped: {}", cause);
+            }
+        }
+        if !running { return Ok(()); }
+        self.fade.store(FADE_IN, Ordering::Release);
+        self.open_stream().map_err(|cause| {
+            let _ = transition(&self.state, self.engine_id, EngineState::Error { cause: cause.clone() });
+            cause
+        })?;
+        // The extra outputs resample from the engine's rate and block size, which may just have changed.
+        self.open_aggregate_outputs();
+        Ok(())
+    }
+
+    /// Rate the output device runs at. Equal to `sample_rate` unless the engine is resampling.
+    pub fn device_sample_rate(&self) -> u32 {
+        self.device_rate
+    }
+
+    /// Name of the output device currently playing, if the engine has been started.
+    pub fn device_name(&self) -> Option<&str> {
+        self.device_name.as_deref()
+    }
+
+    /// Moves playback to the system default output after the current device disappeared, restarting the
+    /// engine if the loss had already put it into the error state. Returns the new device's name.
+    pub fn fall_back_to_default(&mut self) -> Result<String, String> {
+        match self.state() {
+            EngineState::Error { .. } => { self.restart_streams(None)?; }
+            _ => self.switch_output_device(None)?,
+        }
+        Ok(self.device_name.clone().unwrap_or_default())
+    }
+
+    /// Rebuilds the streams on `device` (`None` for the system default) after they died: the engine went into the
+    /// error state, or its callback stopped coming, which is reported as an error first. The graph, node state and
+    /// queued audio are kept and the output fades in. Returns the device's name.
+    pub fn restart_streams(&mut self, device: Option<String>) -> Result<String, String> {
+        if matches!(self.state(), EngineState::Starting | EngineState::Running | EngineState::Draining) {
+            let _ = transition(&self.state, self.engine_id, EngineState::Error { cause: "audio callback stalled".into() });
+        }
+        self.stream = None;
+        self.input_stream = None;
+        self.latency.stream_closed();
+        self.config.output_device = device;
+        self.start_with(FADE_IN)?;
+        Ok(self.device_name.clone().unwrap_or_default())
+    }
+
+    fn open_output_stream(&mut self, host: &cpal::Host) -> Result<(), String> {
+        #[cfg(all(windows, feature = "wasapi"))]
+        if self.native_wasapi() {
+            return self.open_wasapi_stream();
+        }
+        let device = match &self.config.output_device {
+            Some(name) => host
+                .output_devices()
+                .map_err(|e| e.to_string())?
+                .find(|d| d.name().map(|n| n == *name).unwrap_or(false))
+                .ok_or(format!("Output device '{}' not found", name))?,
+            None => host.default_output_device().ok_or("No output device found")?,
+        };
+        let device_name = device.name().ok();
+        // Rendered in f32 and converted when the device only takes integers.
+        let (device_channels, device_rate, sample_format) = output_stream_format(&device, self.config.layout.channels() as u16, self.sample_rate)?;
         let config = cpal::StreamConfig {
-            channels: 2,
-            sample_rate: cpal::SampleRate(self.sample_rate),
+            channels: device_channels,
+            sample_rate: cpal::SampleRate(device_rate),
             buffer_size: cpal::BufferSize::Fixed(self.buffer_size as u32),
         };
+        let converter = FormatConverter::new(self.buffer_size * 2, device_channels as usize, self.config.output_dither);
+        if sample_format != SampleFormat::F32 {
+            println!("[DspEngine] Output device takes {:?} samples, converting{}", sample_format, if self.config.output_dither { " with dither" } else { "" });
+        }
+
+        let mut callback = self.output_callback(device_channels, device_rate)?;
+        // cpal knows when each buffer will be played; the gap to the callback is the device latency.
+        let latency = Arc::clone(&self.latency);
+        let callback = move |output: &mut [f32], info: &cpal::OutputCallbackInfo| {
+            let timestamp = info.timestamp();
+            if let Some(delay) = timestamp.playback.duration_since(&timestamp.callback) {
+                latency.set_device(delay);
+            }
+            callback(output)
+        };
+        let error_state = Arc::clone(&self.state);
+        let device_lost = Arc::clone(&self.device_lost);
+        let engine_id = self.engine_id;
+        let on_error = move |err: cpal::StreamError| {
+            eprintln!("Critical Audio Stream Error: {}", err);
+            if matches!(err, cpal::StreamError::DeviceNotAvailable) {
+                device_lost.store(true, Ordering::Release);
+            }
+            let _ = transition(&error_state, engine_id, EngineState::Error { cause: err.to_string() });
+        };
+
+        let stream = match sample_format {
+            SampleFormat::F32 => {
+                let mut callback = callback;
+                device.build_output_stream(&config, move |output: &mut [f32], info: &cpal::OutputCallbackInfo| callback(output, info), on_error, None)
+            }
+            SampleFormat::I32 => build_converted_output::<i32>(&device, &config, callback, on_error, converter),
+            SampleFormat::I16 => build_converted_output::<i16>(&device, &config, callback, on_error, converter),
+            SampleFormat::U16 => build_converted_output::<u16>(&device, &config, callback, on_error, converter),
+            other => return Err(format!("Output sample format {:?} is not supported", other)),
+        }
+        .map_err(|e| e.to_string())?;
+
+        // Start playback
+        stream.play().map_err(|e| e.to_string())?;
+        
+        // Keep the stream alive for as long as the engine plays
+        self.stream = Some(SendStream::Cpal(stream));
+        self.device_name = device_name;
+        self.device_rate = device_rate;
+        self.device_lost.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    /// Builds the output callback for a device with `device_channels` at `device_rate`: applies the queued
+    /// commands, renders the graph at the engine rate and mixes, fades and limits the result into the
+    /// interleaved buffer it is given. Takes the graph out for as long as the callback lives.
+    fn output_callback(&mut self, device_channels: u16, device_rate: u32) -> Result<impl FnMut(&mut [f32]) + Send + 'static, String> {
+        let layout = self.config.layout;
+        let channels = layout.channels();
+        // The graph always renders whole blocks: the resampler asks for them, and without one the FIFO cuts
+        // device callbacks of any size out of them.
+        let block = self.block_size();
+        let mut resampler = (device_rate != self.sample_rate).then(|| Resampler::new(channels, self.sample_rate, device_rate, block));
+        let mut fifo = BlockFifo::new(block, channels);
+        if resampler.is_some() {
+            println!("[DspEngine] Output device runs at {} Hz, resampling from {} Hz", device_rate, self.sample_rate);
+        }
+        // Rendered in the engine layout, then mixed to the device's channels through `mixdown` when they differ.
+        let device_layout = ChannelLayout::from_channels(device_channels as usize);
+        let output_map = (device_layout.channels() != channels).then(|| ChannelMap::new(layout, device_layout));
+        if output_map.is_some() {
+            println!("[DspEngine] Output device has {} channels, mixing from {}", device_channels, layout.name());
+        }
+        let mut mixdown = vec![0.0f32; if output_map.is_some() { self.buffer_size.max(1) * channels } else { 0 }];
 
         // Clone Arcs for use inside the audio thread closure
-        let ring_buffer = Arc::clone(&self.buffer);
         let in_queue = Arc::clone(&self.command_queue);
-        let active_nodes = Arc::clone(&self.nodes);
+        let mut render = self.render_state(block)?;
+        let heartbeat = Arc::clone(&self.heartbeat);
+        let usage = Arc::clone(&self.usage);
+        let fade = Arc::clone(&self.fade);
+        let mut audio_thread: Option<ThreadHandle> = None;
+        let max_block = self.buffer_size;
+        let realtime = self.config.realtime_priority;
+        let nominal_period = Duration::from_secs_f64(self.buffer_size as f64 / device_rate.max(1) as f64);
+        let mut commands = self.command_context(block, true)?;
+        if self.xrun_monitor.is_none() {
+            self.xrun_monitor = Some(XrunMonitor::spawn(Arc::clone(&self.xruns), self.engine_id, xrun::XRUN_POLL_INTERVAL)?);
+        }
+        let xruns = Arc::clone(&self.xruns);
+        // One device period until the driver reports the real figure (the cpal backends do, every callback).
+        let latency = Arc::clone(&self.latency);
+        latency.stream_opened(self.sample_rate, nominal_period);
+        let protection = Arc::clone(&self.output_protection);
+        let standby = Arc::clone(&self.standby);
+        let mut silenced = standby.load(Ordering::Relaxed);
+        let mut limiter = OutputLimiter::new(device_rate, device_channels as usize);
+        limiter.set_ceiling_db(self.config.output_ceiling_db);
 
-        let stream = device.build_output_stream(
-            &config,
-            move |output: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                
-                // --- 1. DYNAMIC COMMAND PROCESSING ---
-                // We use try_lock to avoid blocking the audio thread.
-                if let Ok(mut commands) = in_queue.try_lock() {
-                    for cmd in commands.drain(..) {
-                        match cmd.command_id {
-                            0 => { // Command: Add Plugin/Node
-                                if let Ok(mut pm) = PMANAGER.lock() {
-                                    if let Some(node) = pm.create_node(&cmd.description) {
-                                        if let Ok(mut nodes) = active_nodes.lock() {
-                                            nodes.push(node);
-                                        }
-                                    }
-                                }
-                            }
-                            2 => { // Command: Set Node Parameter
-                                if let Ok(mut nodes) = active_nodes.lock() {
-                                    if let Some(node) = nodes.iter_mut().find(|n| n.get_id() == cmd.node_id) {
-                                        node.set_param(cmd.param_id, &cmd.payload);
-                                    }
-                                }
-                            }
-                            _ => {}
-                        }
+        let callback = move |output: &mut [f32]| {
+            let callback_start = Instant::now();
+            heartbeat.fetch_add(1, Ordering::Relaxed);
+            let thread = audio_thread.get_or_insert_with(|| register_audio_thread(realtime, nominal_period));
+            let fade_state = fade.load(Ordering::Acquire);
+            if fade_state == FADE_SILENT {
+                output.fill(0.0);
+                return;
+            }
+
+            // --- 1. DYNAMIC COMMAND PROCESSING ---
+            // Lock-free and bounded: every command queued before this callback starts is applied in it;
+            // anything sent meanwhile waits for the next callback.
+            commands.apply_queued(&in_queue, &mut render.graph);
+
+            // --- 2. RENDER AT THE ENGINE RATE ---
+            match output_map.as_ref() {
+                Some(map) => {
+                    let device_channels = device_channels as usize;
+                    for piece in output.chunks_mut(max_block.max(1) * device_channels) {
+                        let block = &mut mixdown[..piece.len() / device_channels * channels];
+                        render_at_device_rate(&mut resampler, &mut fifo, &mut render, block);
+                        map.apply(block, piece);
                     }
                 }
+                None => render_at_device_rate(&mut resampler, &mut fifo, &mut render, output),
+            }
 
-                // --- 2. FETCH RAW AUDIO FROM RING BUFFER ---
-                let available = ring_buffer.read_slice();
-                let len = output.len().min(available.len());
-                
-                // Copy samples from the input buffer to the hardware output
-                output[..len].copy_from_slice(&available[..len]);
-                
-                // Zero out the rest of the buffer if we have a shortage of data (underflow)
-                if len < output.len() {
-                    output[len..].fill(0.0);
-                }
-                
-                ring_buffer.consume(len);
-
-                // --- 3. SEQUENTIAL DSP PROCESSING (THE RACK) ---
-                // We process the audio through every node in the vector sequentially.
-                // Note: try_lock is critical here to ensure zero-latency.
-                if let Ok(mut nodes) = active_nodes.try_lock() {
-                    for node in nodes.iter_mut() {
-                        node.process(output);
-                    }
+            match fade_state {
+                FADE_OUT => {
+                    apply_ramp(output, device_channels as usize, 1.0, 0.0);
+                    fade.store(FADE_SILENT, Ordering::Release);
+                }
+                FADE_IN => {
+                    apply_ramp(output, device_channels as usize, 0.0, 1.0);
+                    let _ = fade.compare_exchange(FADE_IN, FADE_NONE, Ordering::AcqRel, Ordering::Relaxed);
+                }
+                _ => {}
+            }
+            let quiet = standby.load(Ordering::Relaxed);
+            match (silenced, quiet) {
+                (false, true) => apply_ramp(output, device_channels as usize, 1.0, 0.0),
+                (true, true) => output.fill(0.0),
+                (true, false) => apply_ramp(output, device_channels as usize, 0.0, 1.0),
+                (false, false) => {}
+            }
+            silenced = quiet;
+
+            // --- 3. PROTECT THE LISTENER ---
+            if protection.load(Ordering::Relaxed) {
+                limiter.process(output);
+            } else {
+                limiter.reset();
+            }
+
+            let held_back = match resampler.as_ref() {
+                Some(resampler) => resampler.pending_frames(),
+                None => fifo.buffered() / channels,
+            };
+            latency.set_pending(render.ring_buffer.read_slice().len() / channels, held_back);
+
+            let period = Duration::from_secs_f64((output.len() / device_channels as usize) as f64 / device_rate as f64);
+            let busy = callback_start.elapsed();
+            usage.record_callback(busy);
+            thread.record(busy, period);
+            if busy > period {
+                xruns.deadline_miss();
+            }
+        };
+        Ok(callback)
+    }
+
+    /// Everything `RenderState::render` needs, for renders of up to `block` frames. Checks the graph out until
+    /// the render state is dropped.
+    fn render_state(&self, block: usize) -> Result<RenderState, String> {
+        let graph = self.graph.checkout()?;
+        let channels = self.config.layout.channels();
+        if let Ok(mut automation) = self.automation.lock() {
+            automation.prepare(block);
+        }
+        if let Ok(mut modulation) = self.modulation.lock() {
+            modulation.prepare(self.sample_rate);
+        }
+        Ok(RenderState {
+            ring_buffer: Arc::clone(&self.buffer),
+            live_input: Arc::clone(&self.live_input),
+            captured: vec![0.0f32; block * channels],
+            channels,
+            duplex: self.config.duplex,
+            monitor_gain: Arc::clone(&self.monitor_gain),
+            graph,
+            taps: Arc::clone(&self.taps),
+            meters: Arc::clone(&self.meters),
+            usage: Arc::clone(&self.usage),
+            position: Arc::clone(&self.position),
+            sample_rate: self.sample_rate,
+            midi_queue: Arc::clone(&self.midi_queue),
+            midi: Vec::with_capacity(self.midi_queue.capacity()),
+            midi_out_queue: Arc::clone(&self.midi_out_queue),
+            transport: Arc::clone(&self.transport),
+            master: Arc::clone(&self.master),
+            master_ramp: MasterRamp::new(self.master.gain()),
+            panic: Arc::clone(&self.panic),
+            automation: Arc::clone(&self.automation),
+            params: Vec::with_capacity(automation::MAX_BLOCK_CHANGES),
+            modulation: Arc::clone(&self.modulation),
+            xruns: Arc::clone(&self.xruns),
+            feeds: Arc::clone(&self.output_feeds),
+            feeding: false,
+            denormal_dither: self.config.denormal_dither,
+            midi_by_arrival: true,
+        })
+    }
+
+    /// Everything needed to apply queued commands, starting the reaper removed nodes are handed to.
+    /// `live` contexts never wait for the modulation matrix, see `CommandContext::apply_queued`.
+    fn command_context(&mut self, block: usize, live: bool) -> Result<CommandContext, String> {
+        if self.reaper.is_none() {
+            self.reaper = Some(NodeReaper::spawn(Arc::clone(&self.graveyard), Duration::from_millis(50))?);
+        }
+        Ok(CommandContext {
+            factory: NodeFactory { strict_rt: self.config.strict_rt, layout: self.config.layout, sample_rate: self.sample_rate, max_block: block },
+            graveyard: Arc::clone(&self.graveyard),
+            transport: Arc::clone(&self.transport),
+            master: Arc::clone(&self.master),
+            panic: Arc::clone(&self.panic),
+            modulation: Arc::clone(&self.modulation),
+            engine_id: self.engine_id,
+            scene: Arc::clone(&self.scene),
+            live,
+            held: None,
+            rejections: ArrayQueue::new(self.config.command_queue_capacity.max(1)),
+            recalls: ArrayQueue::new(self.config.command_queue_capacity.max(1)),
+            rejected_name: intern::intern("Node Rejected"),
+            routing_rejected_name: intern::intern("Routing Rejected"),
+            recalled_name: intern::intern("Scene Recalled"),
+        })
+    }
+
+    /// Runs the rack without an audio device, as fast as the CPU allows, and returns `duration` of its output
+    /// (interleaved in the engine layout), e.g. for bouncing or tests. `input` feeds the graph input like pushed
+    /// samples, followed by silence. Queued commands are applied before each block, and the transport,
+    /// automation and modulation run as they would live. Refused while the engine is running.
+    pub fn render_offline(&mut self, input: &[f32], duration: Duration) -> Result<Vec<f32>, String> {
+        if self.stream.is_some() { return Err("Stop the engine before rendering offline".into()); }
+        self.pump = None;
+        let block = self.block_size();
+        let channels = self.config.layout.channels();
+        let frames = (duration.as_secs_f64() * self.sample_rate as f64).round() as usize;
+        let mut commands = self.command_context(block, false)?;
+        let mut render = self.render_state(block)?;
+        let mut output = vec![0.0f32; frames * channels];
+        // Like the audio thread, but only for the duration of the render.
+        let _flush = FlushToZero::enable();
+        for (index, chunk) in output.chunks_mut(block * channels).enumerate() {
+            commands.apply_queued(&self.command_queue, &mut render.graph);
+            let input = input.get(index * block * channels..).unwrap_or(&[]);
+            let len = input.len().min(chunk.len());
+            chunk[..len].copy_from_slice(&input[..len]);
+            render.process(chunk, 0, false);
+        }
+        Ok(output)
+    }
+
+    /// `render_offline` written to a 32-bit float WAV file at `path`.
+    pub fn bounce(&mut self, input: &[f32], duration: Duration, path: &Path) -> Result<(), String> {
+        let rendered = self.render_offline(input, duration)?;
+        wav::write_f32(path, &rendered, self.config.layout.channels() as u16, self.sample_rate)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Renderer for a host that drives the rack from its own audio callback instead of an engine stream, e.g. a
+    /// DAW running OpenTune as a plugin. Holds the graph until dropped, so the engine can't start meanwhile;
+    /// refused while it is running.
+    pub fn host_renderer(&mut self) -> Result<HostRenderer, String> {
+        if self.stream.is_some() { return Err("Stop the engine before rendering from a host".into()); }
+        let block = self.block_size();
+        Ok(HostRenderer {
+            commands: self.command_context(block, true)?,
+            render: self.render_state(block)?,
+            command_queue: Arc::clone(&self.command_queue),
+            block,
+            channels: self.config.layout.channels(),
+        })
+    }
+
+    /// Renders `output` (interleaved in the engine layout) as the device callback would, without an audio stream:
+    /// queued commands are applied first, pushed samples and captured input feed the graph, and queued MIDI lands
+    /// at the start of the block. Rendered in blocks of the engine's block size, the last one possibly shorter. For
+    /// tests that push, queue, pump and assert deterministically; refused while the engine is running. The graph
+    /// stays with the pump until the engine starts, renders offline or is reconfigured: send commands meanwhile.
+    pub fn process_block(&mut self, output: &mut [f32]) -> Result<(), String> {
+        if self.stream.is_some() { return Err("Stop the engine before pumping blocks".into()); }
+        if self.pump.is_none() {
+            let mut pump = self.host_renderer()?;
+            pump.render.midi_by_arrival = false;
+            self.pump = Some(pump);
+        }
+        if let Some(pump) = self.pump.as_mut() {
+            pump.pump(output);
+        }
+        Ok(())
+    }
+
+    /// Stops the audio thread and clears the active stream.
+    pub fn stop(&mut self) {
+        match self.state() {
+            EngineState::Stopped | EngineState::Starting => return,
+            EngineState::Running => { let _ = transition(&self.state, self.engine_id, EngineState::Draining); }
+            _ => {}
+        }
+        self.stream = None;
+        self.input_stream = None;
+        self.latency.stream_closed();
+        for output in self.aggregate_outputs.iter_mut() {
+            output.stream = None;
+        }
+        let _ = transition(&self.state, self.engine_id, EngineState::Stopped);
+        println!("[DspEngine] Audio Thread Stopped.");
+    }
+
+    /// Opens the default input device at the engine's sample rate. Input is converted to the engine layout
+    /// (see `ChannelMap`; a mono input feeds both fronts) and written to both capture rings.
+    fn open_input_stream(&mut self, host: &cpal::Host) -> Result<(), String> {
+        let device = host.default_input_device().ok_or("No input device found")?;
+        let default_config = device.default_input_config().map_err(|e| e.to_string())?;
+        let channels = default_config.channels();
+        let input_rate = if supports_rate(device.supported_input_configs().ok(), channels, self.sample_rate) {
+            self.sample_rate
+        } else {
+            default_config.sample_rate().0
+        };
+        let config = cpal::StreamConfig {
+            channels,
+            sample_rate: cpal::SampleRate(input_rate),
+            buffer_size: cpal::BufferSize::Fixed(self.buffer_size as u32),
+        };
+        let mut capture = self.capture_callback(channels.max(1) as usize, input_rate);
+        let error_state = Arc::clone(&self.state);
+        let device_lost = Arc::clone(&self.device_lost);
+        let engine_id = self.engine_id;
+
+        let stream = device.build_input_stream(
+            &config,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| capture(data),
+            move |err| {
+                eprintln!("Critical Audio Input Error: {}", err);
+                if matches!(err, cpal::StreamError::DeviceNotAvailable) {
+                    device_lost.store(true, Ordering::Release);
                 }
+                let _ = transition(&error_state, engine_id, EngineState::Error { cause: err.to_string() });
             },
-            |err| eprintln!("Critical Audio Stream Error: {}", err),
             None
         ).map_err(|e| e.to_string())?;
 
-        // Start playback
         stream.play().map_err(|e| e.to_string())?;
-        
-        // Store the stream globally so it doesn't get dropped
-        if let Ok(mut gs) = ACTIVE_STREAM.lock() {
-            *gs = Some(SendStream(stream));
+        self.input_stream = Some(SendStream::Cpal(stream));
+        Ok(())
+    }
+
+    /// Builds the input callback for a device with `device_channels` at `input_rate`: converts the interleaved
+    /// input it is given to the engine layout and rate and writes it to both capture rings.
+    fn capture_callback(&self, device_channels: usize, input_rate: u32) -> impl FnMut(&[f32]) + Send + 'static {
+        // Input arrives in blocks of this many frames at most; larger callbacks are handled in pieces.
+        let chunk_frames = self.buffer_size.max(64);
+        let layout = self.config.layout;
+        let map = ChannelMap::new(ChannelLayout::from_channels(device_channels), layout);
+        let mut resampler = (input_rate != self.sample_rate).then(|| Resampler::new(layout.channels(), input_rate, self.sample_rate, chunk_frames));
+        let converted_frames = chunk_frames * self.sample_rate as usize / input_rate.max(1) as usize + 2;
+        let mut mapped = vec![0.0f32; chunk_frames * layout.channels()];
+        let mut converted = vec![0.0f32; converted_frames * layout.channels()];
+        let rings = [Arc::clone(&self.capture), Arc::clone(&self.live_input)];
+        let xruns = Arc::clone(&self.xruns);
+
+        move |data: &[f32]| {
+            let engine_channels = layout.channels();
+            for piece in data.chunks(chunk_frames * device_channels) {
+                let frames = map.apply(piece, &mut mapped);
+                let block = match resampler.as_mut() {
+                    Some(resampler) => {
+                        resampler.push(&mapped[..frames * engine_channels]);
+                        let produced = resampler.pull(&mut converted);
+                        &converted[..produced * engine_channels]
+                    }
+                    None => &mapped[..frames * engine_channels],
+                };
+                for (index, ring) in rings.iter().enumerate() {
+                    // A full ring means nobody is reading; drop this block rather than block the input thread.
+                    // Only the live input ring counts as an overrun: the application may ignore `capture`.
+                    let Some(slice) = ring.write_slice(block.len()) else {
+                        if index == 1 { xruns.capture_overrun(); }
+                        continue;
+                    };
+                    slice.copy_from_slice(block);
+                    ring.commit_write(block.len());
+                }
+            }
         }
-        
-        self.is_running = true;
-        println!("[DspEngine] Audio Thread Started successfully.");
+    }
+
+    /// Opens the native JACK client: one output port per engine channel, and input ports feeding the capture
+    /// rings when capturing. Connections made with `jack_connect` are restored; ports without any are
+    /// autoconnected to the physical ports if the options say so.
+    #[cfg(feature = "jack")]
+    fn open_jack_stream(&mut self) -> Result<(), String> {
+        let options = self.config.jack.clone();
+        let (client, rate) = JackStream::connect(&options)?;
+        let channels = self.config.layout.channels();
+        let render = self.output_callback(channels as u16, rate)?;
+        let capture = (self.config.capture_input || self.config.duplex).then(|| Box::new(self.capture_callback(channels, rate)) as _);
+        let error_state = Arc::clone(&self.state);
+        let device_lost = Arc::clone(&self.device_lost);
+        let engine_id = self.engine_id;
+        let on_shutdown = move |reason: &str| {
+            eprintln!("JACK server shut down: {}", reason);
+            device_lost.store(true, Ordering::Release);
+            let _ = transition(&error_state, engine_id, EngineState::Error { cause: format!("JACK server shut down: {}", reason) });
+        };
+        let stream = JackStream::start(client, &options, channels, channels, Box::new(render), capture, Arc::clone(&self.transport), self.sample_rate, Arc::clone(&self.xruns), Box::new(on_shutdown))?;
+
+        for (ours, other) in self.jack_connections.iter() {
+            let Some(port) = stream.port(ours) else { continue };
+            let result = if ours.starts_with("out_") { stream.connect_ports(port, other) } else { stream.connect_ports(other, port) };
+            if let Err(e) = result { eprintln!("[DspEngine] {}", e); }
+        }
+        if options.autoconnect {
+            let connected = |port: &String| self.jack_connections.iter().any(|(ours, _)| port.rsplit(':').next() == Some(ours.as_str()));
+            for (port, playback) in stream.output_ports().iter().zip(stream.physical_ports(true)) {
+                if !connected(port) { let _ = stream.connect_ports(port, &playback); }
+            }
+            for (port, capture) in stream.input_ports().iter().zip(stream.physical_ports(false)) {
+                if !connected(port) { let _ = stream.connect_ports(&capture, port); }
+            }
+        }
+        println!("[DspEngine] JACK client '{}' running at {} Hz", stream.client_name(), rate);
+
+        self.stream = Some(SendStream::Jack(stream));
+        self.input_stream = None;
+        self.device_name = Some(audiohost::JACK.to_string());
+        self.device_rate = rate;
+        self.device_lost.store(false, Ordering::Release);
         Ok(())
     }
 
-    /// Stops the audio thread and clears the active stream.
-    pub fn stop(&mut self) {
-        if let Ok(mut gs) = ACTIVE_STREAM.lock() {
-            *gs = None;
+    #[cfg(feature = "jack")]
+    fn jack_stream(&self) -> Result<&JackStream, String> {
+        match self.stream.as_ref() {
+            Some(SendStream::Jack(stream)) => Ok(stream),
+            _ => Err("The JACK client is not running".to_string()),
         }
-        self.is_running = false;
-        println!("[DspEngine] Audio Thread Stopped.");
     }
 
-    /// Helper to push samples into the engine for playback
+    /// Full names of the JACK client's ports, outputs then inputs; empty unless it is running.
+    #[cfg(feature = "jack")]
+    pub fn jack_ports(&self) -> Vec<String> {
+        self.jack_stream().map(|s| s.output_ports().iter().chain(s.input_ports()).cloned().collect()).unwrap_or_default()
+    }
+
+    /// Audio ports of the other JACK clients: with `inputs` those the engine's outputs can connect to, otherwise
+    /// those its inputs can.
+    #[cfg(feature = "jack")]
+    pub fn jack_other_ports(&self, inputs: bool) -> Vec<String> {
+        self.jack_stream().map(|s| s.other_ports(inputs)).unwrap_or_default()
+    }
+
+    /// Connects the engine's JACK port `port` (short name: `out_1`, `in_2`, ...) to or from `other` (full name,
+    /// e.g. `system:playback_1`). Remembered and restored whenever the client is opened again.
+    #[cfg(feature = "jack")]
+    pub fn jack_connect(&mut self, port: &str, other: &str) -> Result<(), String> {
+        let stream = self.jack_stream()?;
+        let own = stream.port(port).ok_or(format!("No JACK port '{}'", port))?;
+        if port.starts_with("out_") { stream.connect_ports(own, other)? } else { stream.connect_ports(other, own)? }
+        let connection = (port.to_string(), other.to_string());
+        if !self.jack_connections.contains(&connection) { self.jack_connections.push(connection); }
+        Ok(())
+    }
+
+    /// Undoes `jack_connect` (or an autoconnection, or one made in another JACK tool).
+    #[cfg(feature = "jack")]
+    pub fn jack_disconnect(&mut self, port: &str, other: &str) -> Result<(), String> {
+        self.jack_connections.retain(|(ours, theirs)| !(ours == port && theirs == other));
+        let stream = self.jack_stream()?;
+        let own = stream.port(port).ok_or(format!("No JACK port '{}'", port))?;
+        if port.starts_with("out_") { stream.disconnect_ports(own, other) } else { stream.disconnect_ports(other, own) }
+    }
+
+    /// Whether the output bypasses cpal for an exclusive or low-latency WASAPI stream.
+    #[cfg(all(windows, feature = "wasapi"))]
+    fn native_wasapi(&self) -> bool {
+        self.config.wasapi.mode != WasapiMode::Shared && self.config.audio_host.as_deref().map_or(true, |host| host.eq_ignore_ascii_case(audiohost::WASAPI))
+    }
+
+    /// Opens the output device in the configured WASAPI mode, at the period and format the device agrees to.
+    #[cfg(all(windows, feature = "wasapi"))]
+    fn open_wasapi_stream(&mut self) -> Result<(), String> {
+        let channels = self.config.layout.channels() as u16;
+        let output = WasapiOutput::open(self.config.output_device.as_deref(), &self.config.wasapi, channels, self.sample_rate, self.buffer_size as u32)?;
+        let render = self.output_callback(output.channels(), output.sample_rate())?;
+        let error_state = Arc::clone(&self.state);
+        let device_lost = Arc::clone(&self.device_lost);
+        let engine_id = self.engine_id;
+        let on_error = move |reason: &str, lost: bool| {
+            eprintln!("Critical Audio Stream Error: {}", reason);
+            if lost {
+                device_lost.store(true, Ordering::Release);
+            }
+            let _ = transition(&error_state, engine_id, EngineState::Error { cause: reason.to_string() });
+        };
+        let (latency, device_name) = (output.latency(), output.device_name().to_string());
+        let stream = WasapiStream::start(output, Box::new(render), self.config.output_dither, Box::new(on_error))?;
+        println!(
+            "[DspEngine] WASAPI {:?} on '{}': {} frame period, {:.1} ms output latency",
+            latency.mode, device_name, latency.period_frames, latency.output_latency().as_secs_f64() * 1000.0
+        );
+
+        self.stream = Some(SendStream::Wasapi(stream));
+        self.device_name = Some(device_name);
+        self.device_rate = latency.sample_rate;
+        self.latency.set_device(latency.output_latency());
+        self.device_lost.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    /// Period, buffer and latency the device agreed to, while the output runs in an exclusive or low-latency
+    /// WASAPI mode.
+    #[cfg(all(windows, feature = "wasapi"))]
+    pub fn wasapi_latency(&self) -> Option<WasapiLatency> {
+        match self.stream.as_ref() {
+            Some(SendStream::Wasapi(stream)) => Some(stream.latency()),
+            _ => None,
+        }
+    }
+
+    /// Changes the WASAPI mode and period. A running output is reopened with them and the latency the device
+    /// agreed to is returned; if the device refuses (e.g. another application holds it exclusively) the
+    /// previous options are restored.
+    #[cfg(all(windows, feature = "wasapi"))]
+    pub fn set_wasapi_options(&mut self, options: WasapiOptions) -> Result<Option<WasapiLatency>, String> {
+        if !self.is_running() {
+            self.config.wasapi = options;
+            return Ok(None);
+        }
+
+        let host = audiohost::host(self.config.audio_host.as_deref())?;
+        self.fade_out_and_close(false);
+        let previous = std::mem::replace(&mut self.config.wasapi, options);
+        self.fade.store(FADE_IN, Ordering::Release);
+        match self.open_output_stream(&host) {
+            Ok(()) => Ok(self.wasapi_latency()),
+            Err(cause) => {
+                self.config.wasapi = previous;
+                if let Err(fallback) = self.open_output_stream(&host) {
+                    let _ = transition(&self.state, self.engine_id, EngineState::Error { cause: fallback });
+                }
+                Err(cause)
+            }
+        }
+    }
+
+    /// Adds the engine to the PipeWire graph: an output node with a port per engine channel, linked to
+    /// `output_device` (a sink's node name) or the default sink, and an input node when capturing. The streams
+    /// run at the engine rate and PipeWire converts for the nodes they are linked to.
+    #[cfg(feature = "pipewire")]
+    fn open_pipewire_stream(&mut self) -> Result<(), String> {
+        let layout = self.config.layout;
+        let channels = layout.channels();
+        let render = self.output_callback(channels as u16, self.sample_rate)?;
+        let capture = (self.config.capture_input || self.config.duplex).then(|| Box::new(self.capture_callback(channels, self.sample_rate)) as _);
+        let error_state = Arc::clone(&self.state);
+        let device_lost = Arc::clone(&self.device_lost);
+        let engine_id = self.engine_id;
+        let on_error = move |reason: &str| {
+            eprintln!("PipeWire stream error: {}", reason);
+            device_lost.store(true, Ordering::Release);
+            let _ = transition(&error_state, engine_id, EngineState::Error { cause: format!("PipeWire stream error: {}", reason) });
+        };
+        let target = self.config.output_device.clone().filter(|name| name != audiohost::PIPEWIRE);
+        let stream = PipeWireStream::start(&self.config.pipewire, layout, self.sample_rate, self.buffer_size as u32, target, Box::new(render), capture, Box::new(on_error))?;
+        println!("[DspEngine] PipeWire node '{}' running at {} Hz", stream.node_name(), self.sample_rate);
+
+        self.stream = Some(SendStream::PipeWire(stream));
+        self.input_stream = None;
+        self.device_name = Some(audiohost::PIPEWIRE.to_string());
+        self.device_rate = self.sample_rate;
+        self.device_lost.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    /// Runs the engine without hardware: the null backend calls the callbacks from a timer thread at the engine's
+    /// rate and buffer size, capturing silence when input is enabled.
+    fn open_null_stream(&mut self) -> Result<(), String> {
+        let channels = self.config.layout.channels();
+        let render = self.output_callback(channels as u16, self.sample_rate)?;
+        let capture = (self.config.capture_input || self.config.duplex).then(|| Box::new(self.capture_callback(channels, self.sample_rate)) as _);
+        let stream = NullStream::start(&self.config.null, channels, self.sample_rate, self.buffer_size, Box::new(render), capture)?;
+        println!("[DspEngine] Null backend running at {} Hz{}", self.sample_rate, if self.config.null.free_run { ", free-running" } else { "" });
+
+        self.stream = Some(SendStream::Null(stream));
+        self.input_stream = None;
+        self.device_name = Some(audiohost::NULL.to_string());
+        // Nothing plays the output, so it is "heard" as soon as it is rendered.
+        self.latency.set_device(Duration::ZERO);
+        self.device_rate = self.sample_rate;
+        self.device_lost.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    /// Frames the null backend has rendered since the engine started on it.
+    pub fn null_frames(&self) -> Option<u64> {
+        match self.stream.as_ref() {
+            Some(SendStream::Null(stream)) => Some(stream.frames()),
+            _ => None,
+        }
+    }
+
+    /// Frames per cycle the PipeWire graph settled on, once the engine runs on the PipeWire backend.
+    #[cfg(feature = "pipewire")]
+    pub fn pipewire_quantum(&self) -> Option<u32> {
+        match self.stream.as_ref() {
+            Some(SendStream::PipeWire(stream)) => Some(stream.quantum()),
+            _ => None,
+        }
+    }
+
+    /// Plays `source` on a further output device alongside the main one, e.g. headphones on a second interface
+    /// next to the main interface. The device is looked up on the engine's host (the system default host under
+    /// the native JACK and PipeWire backends) and runs on its own clock: the engine resamples to it and keeps
+    /// tracking the drift between the two. Opened now if the engine runs, otherwise when it starts. Returns the
+    /// output's id.
+    pub fn add_aggregate_output(&mut self, device: &str, source: OutputSource) -> Result<usize, String> {
+        if self.aggregate_outputs.iter().any(|output| output.device == device) {
+            return Err(format!("'{}' is already an aggregate output", device));
+        }
+        let feed = self.output_feeds.claim(source)?;
+        let mut output = AggregateOutput { feed, device: device.to_string(), stream: None, device_rate: 0, stats: None };
+        if self.is_running() {
+            if let Err(cause) = self.open_aggregate_output(&mut output) {
+                self.output_feeds.release(feed);
+                return Err(cause);
+            }
+        }
+        self.aggregate_outputs.push(output);
+        Ok(feed)
+    }
+
+    /// Closes aggregate output `id`. Returns false if there is none.
+    pub fn remove_aggregate_output(&mut self, id: usize) -> bool {
+        let Some(index) = self.aggregate_outputs.iter().position(|output| output.feed == id) else { return false };
+        drop(self.aggregate_outputs.remove(index));
+        self.output_feeds.release(id);
+        true
+    }
+
+    /// Changes what aggregate output `id` plays, without reopening its device.
+    pub fn set_aggregate_source(&mut self, id: usize, source: OutputSource) -> Result<(), String> {
+        if !self.aggregate_outputs.iter().any(|output| output.feed == id) || !self.output_feeds.set_source(id, source) {
+            return Err(format!("No aggregate output {}", id));
+        }
+        Ok(())
+    }
+
+    pub fn aggregate_outputs(&self) -> Vec<AggregateOutputInfo> {
+        self.aggregate_outputs
+            .iter()
+            .filter_map(|output| {
+                Some(AggregateOutputInfo {
+                    id: output.feed,
+                    device: output.device.clone(),
+                    source: self.output_feeds.source(output.feed)?,
+                    sample_rate: output.stream.is_some().then_some(output.device_rate),
+                    drift_ppm: output.stats.as_ref().map_or(0.0, |stats| stats.drift_ppm()),
+                    underruns: output.stats.as_ref().map_or(0, |stats| stats.underruns()),
+                })
+            })
+            .collect()
+    }
+
+    /// (Re)opens every aggregate output. One that fails is logged and stays closed; the main output plays on.
+    fn open_aggregate_outputs(&mut self) {
+        let mut outputs = std::mem::take(&mut self.aggregate_outputs);
+        for output in outputs.iter_mut() {
+            output.stream = None;
+            if let Err(cause) = self.open_aggregate_output(output) {
+                eprintln!("[DspEngine] Aggregate output '{}' not opened: {}", output.device, cause);
+            }
+        }
+        self.aggregate_outputs = outputs;
+    }
+
+    /// Opens `output`'s device with the engine's channels and rate where it supports them, reading its feed
+    /// through a `DriftReader`.
+    fn open_aggregate_output(&self, output: &mut AggregateOutput) -> Result<(), String> {
+        let host_name = self.config.audio_host.as_deref().filter(|&name| !audiohost::is_native(Some(name)));
+        let host = audiohost::host(host_name)?;
+        let device = host
+            .output_devices()
+            .map_err(|e| e.to_string())?
+            .find(|d| d.name().map(|n| n == output.device).unwrap_or(false))
+            .ok_or(format!("Output device '{}' not found", output.device))?;
+        let layout = self.config.layout;
+        let (device_channels, device_rate, sample_format) = output_stream_format(&device, layout.channels() as u16, self.sample_rate)?;
+        let config = cpal::StreamConfig {
+            channels: device_channels,
+            sample_rate: cpal::SampleRate(device_rate),
+            buffer_size: cpal::BufferSize::Fixed(self.buffer_size as u32),
+        };
+        let converter = FormatConverter::new(self.buffer_size * 2, device_channels as usize, self.config.output_dither);
+
+        let mut reader = DriftReader::new(Arc::clone(&self.output_feeds), output.feed, layout, self.sample_rate, self.block_size(), device_channels as usize, device_rate);
+        let stats = reader.stats();
+        let callback = move |buffer: &mut [f32], _: &cpal::OutputCallbackInfo| reader.render(buffer);
+        let name = output.device.clone();
+        let on_error = move |err: cpal::StreamError| eprintln!("[DspEngine] Aggregate output '{}' error: {}", name, err);
+        let stream = match sample_format {
+            SampleFormat::F32 => {
+                let mut callback = callback;
+                device.build_output_stream(&config, move |buffer: &mut [f32], info: &cpal::OutputCallbackInfo| callback(buffer, info), on_error, None)
+            }
+            SampleFormat::I32 => build_converted_output::<i32>(&device, &config, callback, on_error, converter),
+            SampleFormat::I16 => build_converted_output::<i16>(&device, &config, callback, on_error, converter),
+            SampleFormat::U16 => build_converted_output::<u16>(&device, &config, callback, on_error, converter),
+            other => return Err(format!("Output sample format {:?} is not supported", other)),
+        }
+        .map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+        println!("[DspEngine] Aggregate output '{}' running at {} Hz, {} channels", output.device, device_rate, device_channels);
+
+        output.stream = Some(SendStream::Cpal(stream));
+        output.device_rate = device_rate;
+        output.stats = Some(stats);
+        Ok(())
+    }
+
+    /// Streams the master output to the network as RTP with L24 payload, AES67-compatible, for receivers such as
+    /// broadcast consoles and install amplifiers. Runs whether or not the engine is playing, replacing a stream
+    /// already running, and restarts with a new session description when the sample rate or block size changes.
+    /// Returns the SDP to give receivers.
+    pub fn start_rtp_stream(&mut self, config: RtpConfig) -> Result<String, String> {
+        self.stop_rtp_stream();
+        let feed = self.output_feeds.claim(OutputSource::Master)?;
+        match RtpSender::spawn(Arc::clone(&self.output_feeds), feed, self.config.layout.channels(), self.sample_rate, self.block_size(), config) {
+            Ok(sender) => {
+                let sdp = sender.sdp();
+                println!("[DspEngine] Streaming RTP to {}", sender.config().destination);
+                self.rtp_stream = Some((feed, sender));
+                Ok(sdp)
+            }
+            Err(cause) => {
+                self.output_feeds.release(feed);
+                Err(cause)
+            }
+        }
+    }
+
+    /// Stops the network stream. Returns false if none was running.
+    pub fn stop_rtp_stream(&mut self) -> bool {
+        let Some((feed, sender)) = self.rtp_stream.take() else { return false };
+        drop(sender);
+        self.output_feeds.release(feed);
+        true
+    }
+
+    pub fn rtp_stream(&self) -> Option<RtpStreamInfo> {
+        self.rtp_stream.as_ref().map(|(_, sender)| sender.info())
+    }
+
+    /// Level of the live input fed through the graph in duplex mode (linear, 0.0 mutes monitoring).
+    pub fn set_monitor_gain(&self, gain: f32) {
+        self.monitor_gain.store(gain.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn monitor_gain(&self) -> f32 {
+        f32::from_bits(self.monitor_gain.load(Ordering::Relaxed))
+    }
+
+    /// Switches the output protection limiter on or off (see `EngineConfig::output_protection`).
+    pub fn set_output_protection(&self, on: bool) {
+        self.output_protection.store(on, Ordering::Relaxed);
+    }
+
+    pub fn output_protection(&self) -> bool {
+        self.output_protection.load(Ordering::Relaxed)
+    }
+
+    /// Reads captured input (interleaved in the engine layout) into `out`. Returns the number of samples copied.
+    pub fn capture_samples(&self, out: &mut [f32]) -> usize {
+        let available = self.capture.read_slice();
+        let len = out.len().min(available.len());
+        out[..len].copy_from_slice(&available[..len]);
+        self.capture.consume(len);
+        len
+    }
+
+    /// Helper to push samples into the engine for playback. Returns 0, counting an overrun, if the ring buffer
+    /// has no room for all of them.
     pub fn push_samples(&self, samples: &[f32]) -> usize {
         if let Some(write_slice) = self.buffer.write_slice(samples.len()) {
             write_slice.copy_from_slice(samples);
             self.buffer.commit_write(samples.len());
             samples.len()
         } else {
+            self.xruns.overrun();
             0
         }
     }
-}
\ No newline at end of file
+}
+
+/// What the output callback needs to produce audio at the engine rate, separate from the device callback so
+/// the same rendering can be driven through a resampler.
+struct RenderState {
+    ring_buffer: Arc<Buffer>,
+    live_input: Arc<Buffer>,
+    captured: Vec<f32>,
+    channels: usize,
+    duplex: bool,
+    monitor_gain: Arc<AtomicU32>,
+    /// Checked out of the engine's `SharedGraph` for as long as the render state lives.
+    graph: CheckedOutGraph,
+    taps: Arc<TapSet>,
+    meters: Arc<LevelMeters>,
+    usage: Arc<UsageMeter>,
+    position: Arc<AtomicU64>,
+    sample_rate: u32,
+    midi_queue: Arc<ArrayQueue<MidiEvent>>,
+    /// This block's MIDI, preallocated to the queue's capacity.
+    midi: Vec<MidiEvent>,
+    midi_out_queue: Arc<ArrayQueue<MidiEvent>>,
+    transport: Arc<Transport>,
+    master: Arc<MasterControls>,
+    master_ramp: MasterRamp,
+    panic: Arc<AtomicBool>,
+    automation: Arc<Mutex<Automation>>,
+    /// This block's automation and modulation, preallocated to `automation::MAX_BLOCK_CHANGES`.
+    params: Vec<ParamChange>,
+    modulation: Arc<Mutex<ModMatrix>>,
+    xruns: Arc<XrunCounters>,
+    /// Sources of the extra output devices, fed only live.
+    feeds: Arc<OutputFeeds>,
+    /// Whether the last block got pushed samples, so running dry mid-stream counts as an underrun but an
+    /// engine nobody pushes to doesn't.
+    feeding: bool,
+    denormal_dither: bool,
+    /// Place queued MIDI where it arrived during the last block, as live streams do; otherwise at frame 0.
+    midi_by_arrival: bool,
+}
+
+impl RenderState {
+    fn render(&mut self, output: &mut [f32]) {
+        // A panic drops whatever is queued; `process` then fades the block out and resets the rack.
+        if self.panic.load(Ordering::Relaxed) {
+            self.ring_buffer.consume(self.ring_buffer.read_slice().len());
+            self.live_input.consume(self.live_input.read_slice().len());
+            while self.midi_queue.pop().is_some() {}
+            self.feeding = false;
+        }
+
+        // Pushed samples are the graph input; a shortage (underflow) is filled with silence.
+        let available = self.ring_buffer.read_slice();
+        let len = output.len().min(available.len());
+        output[..len].copy_from_slice(&available[..len]);
+        if len < output.len() {
+            output[len..].fill(0.0);
+            if len > 0 || self.feeding {
+                self.xruns.underrun();
+            }
+        }
+        self.feeding = len > 0;
+        self.ring_buffer.consume(len);
+
+        // Live input for the graph, if capturing. Input that piled up beyond one block (e.g. while the
+        // output stream was starting) is skipped so monitoring latency stays at one block.
+        let backlog = self.live_input.read_slice().len().saturating_sub(output.len());
+        let backlog = backlog - backlog % self.channels;
+        self.live_input.consume(backlog);
+        let available = self.live_input.read_slice();
+        let captured_len = output.len().min(available.len()).min(self.captured.len());
+        self.captured[..captured_len].copy_from_slice(&available[..captured_len]);
+        self.live_input.consume(captured_len);
+        if self.duplex {
+            let gain = f32::from_bits(self.monitor_gain.load(Ordering::Relaxed));
+            simd::mix_gain(output, &self.captured[..captured_len], gain);
+        }
+
+        // MIDI that arrived during the last block, placed at the same offsets in this one.
+        self.midi.clear();
+        while self.midi.len() < self.midi.capacity() {
+            let Some(event) = self.midi_queue.pop() else { break };
+            self.midi.push(event);
+        }
+        if self.midi_by_arrival {
+            midi::schedule(&mut self.midi, midi::now_micros(), output.len() / self.channels, self.sample_rate);
+        } else {
+            self.midi.iter_mut().for_each(|event| event.frame = 0);
+        }
+
+        self.process(output, captured_len, true);
+    }
+
+    /// Runs the graph on `output`, which holds its input, with this block's MIDI, automation and modulation, and
+    /// advances the timeline. `live` sends the graph's MIDI output on to the hardware outputs; offline renders
+    /// wait for the locks instead, so no block is skipped.
+    fn process(&mut self, output: &mut [f32], captured_len: usize, live: bool) {
+        // Nodes run in topological order; splits and merges are resolved by the graph. The graph itself is owned
+        // by this render state, so only automation and modulation can be contended (try_lock when live).
+        let transport = self.transport.info();
+        self.params.clear();
+        if let Some(mut automation) = acquire(&self.automation, live) {
+            automation.render(&transport, output.len() / self.channels, &mut self.params);
+        }
+        let mut modulation = acquire(&self.modulation, live);
+        if let Some(modulation) = modulation.as_mut() {
+            modulation.render(&transport, output.len() / self.channels, &mut self.params);
+        }
+
+        if self.denormal_dither {
+            output.iter_mut().for_each(|s| *s += denormal::DENORMAL_DC);
+        }
+        let graph = &mut *self.graph;
+        graph.set_transport(transport);
+        graph.process(output, &self.captured[..captured_len], &self.midi, &self.params, self.position.load(Ordering::Relaxed), Some(&self.taps), Some(&self.usage), Some(&self.meters), live.then_some(&*self.feeds));
+        if let Some(modulation) = modulation.as_mut() {
+            modulation.follow(graph, output.len() / self.channels);
+        }
+        self.master_ramp.process(&self.master, output, self.channels, self.sample_rate);
+        // Sent when the audio of the same frame is heard, a block from now.
+        let now = midi::now_micros();
+        let frames = (output.len() / self.channels) as u64;
+        let events = if live { graph.midi_output() } else { &[][..] };
+        for mut event in events.iter().copied() {
+            event.timestamp = now + (frames + event.frame as u64) * 1_000_000 / self.sample_rate.max(1) as u64;
+            let _ = self.midi_out_queue.push(event);
+        }
+        if self.panic.swap(false, Ordering::Relaxed) {
+            let fade = ((PANIC_FADE_MS * 0.001 * self.sample_rate as f32) as usize).max(1) * self.channels;
+            let fade = fade.min(output.len());
+            apply_ramp(&mut output[..fade], self.channels, 1.0, 0.0);
+            output[fade..].fill(0.0);
+            self.graph.panic();
+        }
+        if live && !self.feeds.is_empty() {
+            self.feeds.feed(OutputSource::Master, output);
+        }
+        // Commands applied before this block may have restructured the graph.
+        self.graph.publish();
+        self.position.fetch_add((output.len() / self.channels) as u64, Ordering::Relaxed);
+        self.transport.advance((output.len() / self.channels) as u64);
+    }
+}
+
+/// The rack rendered from someone else's audio thread, see `DspEngine::host_renderer`.
+pub struct HostRenderer {
+    commands: CommandContext,
+    render: RenderState,
+    command_queue: Arc<ArrayQueue<Command>>,
+    block: usize,
+    channels: usize,
+}
+
+impl HostRenderer {
+    /// Runs the rack on `io` in place, interleaved in the engine layout, in blocks of at most the engine's block
+    /// size. `midi` is in time order with frames counted from the start of `io`; events beyond the MIDI queue's
+    /// capacity in one block are dropped. Queued commands are applied first. Doesn't allocate, apart from
+    /// creating nodes for queued commands as the audio thread does.
+    pub fn process(&mut self, io: &mut [f32], midi: &[MidiEvent]) {
+        let _flush = FlushToZero::enable();
+        self.apply_commands();
+        for (index, chunk) in io.chunks_mut(self.block * self.channels).enumerate() {
+            let start = (index * self.block) as u32;
+            let end = start + (chunk.len() / self.channels) as u32;
+            let midi_out = &mut self.render.midi;
+            midi_out.clear();
+            for event in midi.iter().filter(|event| (start..end).contains(&event.frame)) {
+                if midi_out.len() == midi_out.capacity() { break; }
+                let mut event = *event;
+                event.frame -= start;
+                midi_out.push(event);
+            }
+            self.render.process(chunk, 0, true);
+        }
+    }
+
+    /// Renders `output` from the engine's pushed samples, captured input and MIDI queue, as the device callback
+    /// does. See `DspEngine::process_block`.
+    fn pump(&mut self, output: &mut [f32]) {
+        let _flush = FlushToZero::enable();
+        self.apply_commands();
+        for chunk in output.chunks_mut(self.block * self.channels) {
+            self.render.render(chunk);
+        }
+    }
+
+    fn apply_commands(&mut self) {
+        self.commands.apply_queued(&self.command_queue, &mut self.render.graph);
+    }
+
+    /// Drops queued MIDI and resets every node from the next block on, as the Panic command does.
+    pub fn panic(&self) {
+        self.render.panic.store(true, Ordering::Relaxed);
+    }
+}
+
+/// What the audio thread needs to apply commands from the queue.
+struct CommandContext {
+    factory: NodeFactory,
+    graveyard: Arc<Graveyard>,
+    transport: Arc<Transport>,
+    master: Arc<MasterControls>,
+    panic: Arc<AtomicBool>,
+    modulation: Arc<Mutex<ModMatrix>>,
+    engine_id: u32,
+    scene: Arc<AtomicU32>,
+    /// On the audio thread: never wait for `modulation` or the plugin manager.
+    live: bool,
+    /// A command that found `modulation` or the plugin manager locked elsewhere, applied first next time.
+    held: Option<Command>,
+    /// Node Rejected responses (node id, reason) not sent yet, so one isn't lost when `RESPONSE_QUEUE` is locked
+    /// elsewhere. Sized to the command queue.
+    rejections: ArrayQueue<(NodeId, &'static str)>,
+    /// Recalled scenes whose 117 (Scene Recalled) response isn't sent yet, kept the same way.
+    recalls: ArrayQueue<u32>,
+    rejected_name: intern::NameId,
+    routing_rejected_name: intern::NameId,
+    recalled_name: intern::NameId,
+}
+
+impl CommandContext {
+    /// Hands the nodes that faded out of the graph, and the recordings of unfrozen, removed and replaced nodes,
+    /// to the reaper. Call after each batch of commands.
+    fn bury_retired(&self, graph: &mut AudioGraph) {
+        while let Some(node) = graph.take_retired() {
+            bury(&self.graveyard, node);
+        }
+        while let Some(frozen) = graph.take_thawed() {
+            bury(&self.graveyard, frozen);
+        }
+    }
+
+    /// Applies the commands queued before this call to `graph`, in order, then buries what they retired. When
+    /// live, a command that edits the modulation matrix, or creates a node, while someone else holds the matrix or
+    /// the plugin manager (e.g. a plugin scan) is held back, with everything queued after it, until the next call.
+    fn apply_queued(&mut self, queue: &ArrayQueue<Command>, graph: &mut AudioGraph) {
+        for _ in 0..queue.len() + self.held.is_some() as usize {
+            let Some(cmd) = self.held.take().or_else(|| queue.pop()) else { break };
+            if !self.apply(&cmd, graph) {
+                self.held = Some(cmd);
+                break;
+            }
+        }
+        self.bury_retired(graph);
+        self.send_responses();
+    }
+
+    /// Queues a 105 (Node Rejected) response for `send_responses`.
+    fn reject(&self, node_id: NodeId, reason: &'static str) {
+        let _ = self.rejections.push((node_id, reason));
+    }
+
+    /// Sends the queued Node Rejected and Scene Recalled responses, or keeps them for the next call if
+    /// `RESPONSE_QUEUE` is busy.
+    fn send_responses(&self) {
+        if self.rejections.is_empty() && self.recalls.is_empty() { return; }
+        let Ok(mut queue) = RESPONSE_QUEUE.try_lock() else { return };
+        while let Some((node_id, reason)) = self.rejections.pop() {
+            queue.push(Command::with_name_id(105, self.rejected_name, reason.as_bytes().to_vec(), node_id, 0, 0, StatState::INACTIVE));
+        }
+        while let Some(scene) = self.recalls.pop() {
+            queue.push(Command::with_name_id(117, self.recalled_name, scene.to_le_bytes().to_vec(), self.engine_id, 0, 0, StatState::ACTIVE));
+        }
+    }
+
+    /// Applies one queued command to `graph`, the renderer's. Runs on the audio thread, or on the rendering thread
+    /// when offline. False if the command needs the modulation matrix or the plugin manager and couldn't get it
+    /// without waiting.
+    fn apply(&self, cmd: &Command, graph: &mut AudioGraph) -> bool {
+        let modulation = match cmd.command_id {
+            1 | 2 | 6 | 21..=25 => match acquire(&self.modulation, self.live) {
+                Some(modulation) => Some(modulation),
+                None => return false,
+            },
+            _ => None,
+        };
+        let plugins = match cmd.command_id {
+            0 | 5 => match acquire(&PMANAGER, self.live) {
+                Some(plugins) => Some(plugins),
+                None => return false,
+            },
+            _ => None,
+        };
+        self.execute(cmd, graph, modulation, plugins);
+        true
+    }
+
+    /// `modulation` is the locked matrix for the commands that edit it, `plugins` the locked plugin manager for
+    /// the commands that create nodes.
+    fn execute(&self, cmd: &Command, graph: &mut AudioGraph, modulation: Option<MutexGuard<'_, ModMatrix>>, plugins: Option<MutexGuard<'_, PluginManager>>) {
+        match cmd.command_id {
+            0 => { // Command: Add Plugin/Node
+                // Checked first so a full rack doesn't create (and drop) a node it can't take.
+                if !graph.has_room() { self.reject(cmd.node_id, "graph is full"); return; }
+                let node = match self.factory.create(cmd, plugins) {
+                    Ok(Some(node)) => node,
+                    Ok(None) => { self.reject(cmd.node_id, "unknown node type"); return; }
+                    Err(reason) => { self.reject(cmd.node_id, reason); return; }
+                };
+                // Fails rather than growing the graph on the audio thread.
+                let id = node.get_id();
+                let added = if bus::bus_index(id).is_some() { graph.add_return_bus(node) } else { graph.append_node(node) };
+                match added {
+                    Ok(()) => { let _ = graph.fade_in_node(id); }
+                    Err(reason) => self.reject(cmd.node_id, reason),
+                }
+            }
+            1 => { // Command: Remove Node
+                if let Some(mut modulation) = modulation {
+                    modulation.remove_node(cmd.node_id);
+                }
+                if let Err(reason) = graph.fade_out_node(cmd.node_id) {
+                    self.reject(cmd.node_id, reason);
+                }
+            }
+            2 => { // Command: Set Node Parameter
+                // A modulated parameter moves around its base value instead.
+                let base = payload_f32(&cmd.payload).filter(|_| cmd.payload.len() == 4);
+                if let (Some(value), Some(mut modulation)) = (base, modulation) {
+                    if modulation.set_base(cmd.node_id, cmd.param_id, value) { return; }
+                }
+                let _ = graph.set_param(cmd.node_id, cmd.param_id, &cmd.payload);
+            }
+            3 | 4 => { // Command: Connect / Disconnect Routing
+                let Some((to, to_port)) = routing_target(&cmd.payload) else { return };
+                let result = if cmd.command_id == 3 {
+                    graph.connect(cmd.node_id, cmd.port_id, to, to_port)
+                } else {
+                    graph.disconnect(cmd.node_id, cmd.port_id, to, to_port)
+                };
+                if let Err(reason) = result {
+                    if let Ok(mut queue) = RESPONSE_QUEUE.try_lock() {
+                        queue.push(Command::with_name_id(110, self.routing_rejected_name, reason.as_bytes().to_vec(), cmd.node_id, 0, cmd.port_id, StatState::INACTIVE));
+                    }
+                }
+            }
+            5 => { // Command: Replace Node
+                let node = match self.factory.create(cmd, plugins) {
+                    Ok(Some(node)) => node,
+                    Ok(None) => { self.reject(cmd.node_id, "unknown node type"); return; }
+                    Err(reason) => { self.reject(cmd.node_id, reason); return; }
+                };
+                if let Err((new, reason)) = graph.crossfade_node(cmd.node_id, node) {
+                    bury(&self.graveyard, new);
+                    self.reject(cmd.node_id, reason);
+                }
+            }
+            6 => { // Command: Clear Rack
+                if let Some(mut modulation) = modulation {
+                    modulation.clear_routes();
+                }
+                graph.clear(|node| bury(&self.graveyard, node));
+            }
+            7 => { // Command: Move Node
+                let Some(before) = cmd.payload.get(..4).and_then(|b| b.try_into().ok()).map(u32::from_le_bytes) else { return };
+                if let Err(reason) = graph.move_node(cmd.node_id, before) {
+                    if let Ok(mut queue) = RESPONSE_QUEUE.try_lock() {
+                        queue.push(Command::with_name_id(110, self.routing_rejected_name, reason.as_bytes().to_vec(), cmd.node_id, 0, 0, StatState::INACTIVE));
+                    }
+                }
+            }
+            8 => { // Command: Set Bypass
+                let bypass = cmd.payload.first().is_some_and(|&b| b != 0);
+                if let Err(reason) = graph.set_bypass(cmd.node_id, bypass) {
+                    self.reject(cmd.node_id, reason);
+                }
+            }
+            10 => self.transport.play(), // Command: Transport Play
+            11 => self.transport.stop(), // Command: Transport Stop
+            12 => self.transport.record(), // Command: Transport Record
+            13 => { // Command: Tempo Nudge
+                let Some(nudge) = payload_f32(&cmd.payload) else { return };
+                self.transport.nudge_tempo(nudge);
+            }
+            14 => { // Command: Locate
+                let Some(frame) = cmd.payload.get(..8).and_then(|b| b.try_into().ok()).map(u64::from_le_bytes) else { return };
+                self.transport.locate(frame);
+            }
+            15 => { // Command: Set Tempo
+                let Some(bpm) = payload_f32(&cmd.payload) else { return };
+                let _ = self.transport.set_tempo(bpm);
+            }
+            16 => { // Command: Set Time Signature
+                let Some(&[b0, b1, u0, u1]) = cmd.payload.get(..4) else { return };
+                let _ = self.transport.set_time_signature(u16::from_le_bytes([b0, b1]), u16::from_le_bytes([u0, u1]));
+            }
+            20 => { // Command: Scene Recall
+                // The engine keeps no scene contents: the application restores them on the Scene Recalled response.
+                let Some(scene) = cmd.payload.get(..4).and_then(|b| b.try_into().ok()).map(u32::from_le_bytes) else { return };
+                if scene == NO_SCENE { return; }
+                self.scene.store(scene, Ordering::Relaxed);
+                let _ = self.recalls.push(scene);
+            }
+            21 => { // Command: Set Mod Source
+                let Some(kind) = ModSourceKind::decode(&cmd.payload) else { return };
+                let Some(mut modulation) = modulation else { return };
+                if let Err(reason) = modulation.set_source(cmd.node_id, kind) {
+                    if let Ok(mut queue) = RESPONSE_QUEUE.try_lock() {
+                        queue.push(Command::with_name_id(110, self.routing_rejected_name, reason.as_bytes().to_vec(), cmd.node_id, cmd.param_id, 0, StatState::INACTIVE));
+                    }
+                }
+            }
+            22 => { // Command: Remove Mod Source
+                if let Some(mut modulation) = modulation {
+                    modulation.remove_source(cmd.node_id);
+                }
+            }
+            23 => { // Command: Set Mod Route
+                let Some((route, base)) = ModRoute::decode(cmd.node_id, cmd.param_id, &cmd.payload) else { return };
+                let Some(mut modulation) = modulation else { return };
+                if let Err(reason) = modulation.set_route(route, base) {
+                    if let Ok(mut queue) = RESPONSE_QUEUE.try_lock() {
+                        queue.push(Command::with_name_id(110, self.routing_rejected_name, reason.as_bytes().to_vec(), cmd.node_id, cmd.param_id, 0, StatState::INACTIVE));
+                    }
+                }
+            }
+            24 => { // Command: Remove Mod Route
+                let Some(source) = cmd.payload.get(..4).and_then(|b| b.try_into().ok()).map(u32::from_le_bytes) else { return };
+                if let Some(mut modulation) = modulation {
+                    modulation.remove_route(source, cmd.node_id, cmd.param_id);
+                }
+            }
+            25 => { // Command: Set Macro
+                let Some(value) = payload_f32(&cmd.payload) else { return };
+                if let Some(mut modulation) = modulation {
+                    let _ = modulation.set_macro(cmd.node_id, value);
+                }
+            }
+            26 => { // Command: Freeze Node
+                let Some(start) = cmd.payload.get(..8).and_then(|b| b.try_into().ok()).map(u64::from_le_bytes) else { return };
+                let Some(frames) = cmd.payload.get(8..16).and_then(|b| b.try_into().ok()).map(u64::from_le_bytes) else { return };
+                // Allocated here like the nodes of Add Node; dropped by the reaper.
+                let frozen = Box::new(FrozenAudio::new(start, frames as usize, self.factory.layout.channels()));
+                if let Err((frozen, reason)) = graph.freeze_node(cmd.node_id, frozen) {
+                    bury(&self.graveyard, frozen);
+                    self.reject(cmd.node_id, reason);
+                }
+            }
+            27 => { // Command: Unfreeze Node
+                if let Err(reason) = graph.unfreeze_node(cmd.node_id) {
+                    self.reject(cmd.node_id, reason);
+                }
+            }
+            40 => { // Command: Set Master Gain
+                let Some(db) = payload_f32(&cmd.payload) else { return };
+                let _ = self.master.set_gain_db(db);
+            }
+            41 => self.master.set_muted(cmd.payload.first().is_some_and(|&b| b != 0)), // Command: Master Mute
+            42 => self.master.set_dimmed(cmd.payload.first().is_some_and(|&b| b != 0)), // Command: Master Dim
+            43 => self.panic.store(true, Ordering::Relaxed), // Command: Panic
+            _ => {}
+        }
+    }
+}
+
+/// What the audio thread needs to build a node for an Add or Replace command.
+struct NodeFactory {
+    strict_rt: bool,
+    layout: ChannelLayout,
+    sample_rate: u32,
+    max_block: usize,
+}
+
+impl NodeFactory {
+    /// Creates the node type `cmd` names from `plugins`, wrapped and prepared for the engine. `None` for an
+    /// unknown type.
+    fn create(&self, cmd: &Command, plugins: Option<MutexGuard<'_, PluginManager>>) -> Result<Option<Box<dyn AudioNode>>, &'static str> {
+        if intern::with_name(cmd.description, |name| name == bus::RETURN_BUS) == Some(true) {
+            if bus::bus_index(cmd.node_id).is_none() { return Err("return bus ids start at bus::BUS_ID_BASE"); }
+            return Ok(Some(Box::new(bus::ReturnBus::new(cmd.node_id))));
+        }
+        let Some(mut pm) = plugins else { return Ok(None) };
+        let Some(Some(mut node)) = intern::with_name(cmd.description, |name| pm.create_node(name)) else {
+            // A plugin the allowlist refused (or that wasn't verified yet) rather than an unknown name.
+            if intern::with_name(cmd.description, |name| pm.is_refused(name)) == Some(true) { return Err("plugin refused by the allowlist"); }
+            return Ok(None);
+        };
+        let safety = intern::with_name(cmd.description, |name| pm.rt_safety_of(name, node.as_ref())).unwrap_or(RtSafety::UNKNOWN);
+        if let (true, Some(reason)) = (self.strict_rt, safety.violation()) {
+            return Err(reason);
+        }
+        // Optional u32 payload: run the node at a fixed block size.
+        if let Ok(bytes) = <[u8; 4]>::try_from(cmd.payload.as_slice()) {
+            let block = u32::from_le_bytes(bytes) as usize;
+            if block > 0 {
+                node = Box::new(FixedBlockAdapter::new(node, block, self.layout.channels()));
+            }
+        }
+        node.set_channel_layout(self.layout);
+        node.prepare(self.sample_rate, self.max_block);
+        Ok(Some(node))
+    }
+}
+
+/// Hands a node taken out of the graph to the reaper. Only if the graveyard is full is it dropped in place.
+fn bury(graveyard: &Graveyard, node: Box<dyn AudioNode>) {
+    let _ = graveyard.push(node);
+}
+
+/// Renders `output` at the device rate, through the resampler when the device runs at another rate. Either
+/// way the graph only ever renders whole blocks.
+fn render_at_device_rate(resampler: &mut Option<Resampler>, fifo: &mut BlockFifo, render: &mut RenderState, output: &mut [f32]) {
+    match resampler.as_mut() {
+        Some(resampler) => resampler.render(output, |block| render.render(block)),
+        None => fifo.render(output, |block| render.render(block)),
+    }
+}
+
+/// Registers the output callback's thread on its first callback, makes it flush denormals to zero and, with
+/// `realtime`, promotes it to real-time scheduling for callbacks due every `period`. A refused promotion is
+/// reported once and otherwise ignored.
+fn register_audio_thread(realtime: bool, period: Duration) -> ThreadHandle {
+    let thread = threads::register_current("opentune-audio", ThreadRole::AudioCallback);
+    denormal::flush_denormals();
+    if realtime {
+        match threads::promote_current(period) {
+            Ok(_) => thread.refresh_priority(),
+            Err(cause) => eprintln!("[DspEngine] Audio thread runs at normal priority: {}", cause),
+        }
+    }
+    thread
+}
+
+/// Channels, rate and sample format to open `device` with for `channels` at `rate`, falling back to the
+/// device's defaults for the channels or rate it doesn't support.
+fn output_stream_format(device: &cpal::Device, channels: u16, rate: u32) -> Result<(u16, u32, SampleFormat), String> {
+    let default_config = device.default_output_config().map_err(|e| e.to_string())?;
+    let device_channels = if supports_channels(device.supported_output_configs().ok(), channels) {
+        channels
+    } else {
+        default_config.channels()
+    };
+    let device_rate = if supports_rate(device.supported_output_configs().ok(), device_channels, rate) {
+        rate
+    } else {
+        default_config.sample_rate().0
+    };
+    let sample_format = output_format(device, device_channels, device_rate).unwrap_or(default_config.sample_format());
+    Ok((device_channels, device_rate, sample_format))
+}
+
+/// Best of `sampleformat::OUTPUT_FORMATS` the device offers for `channels` at `rate`.
+fn output_format(device: &cpal::Device, channels: u16, rate: u32) -> Option<SampleFormat> {
+    let configs = device.supported_output_configs().ok()?;
+    sampleformat::best_output_format(
+        configs.filter(|c| c.channels() == channels && c.min_sample_rate().0 <= rate && rate <= c.max_sample_rate().0).map(|c| c.sample_format()),
+    )
+}
+
+/// Opens an output stream in integer format `T`, rendering in f32 through `converter`.
+fn build_converted_output<T: OutputSample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut callback: impl FnMut(&mut [f32], &cpal::OutputCallbackInfo) + Send + 'static,
+    on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+    mut converter: FormatConverter,
+) -> Result<cpal::Stream, cpal::BuildStreamError> {
+    device.build_output_stream(config, move |output: &mut [T], info: &cpal::OutputCallbackInfo| converter.render(output, |block| callback(block, info)), on_error, None)
+}
+
+/// Whether any of a device's stream configurations has `channels` channels.
+fn supports_channels<I: Iterator<Item = cpal::SupportedStreamConfigRange>>(configs: Option<I>, channels: u16) -> bool {
+    configs.map_or(false, |mut configs| configs.any(|c| c.channels() == channels))
+}
+
+/// Whether any of a device's stream configurations runs `channels` at `rate`.
+fn supports_rate<I: Iterator<Item = cpal::SupportedStreamConfigRange>>(configs: Option<I>, channels: u16, rate: u32) -> bool {
+    configs.map_or(false, |mut configs| {
+        configs.any(|c| c.channels() == channels && c.min_sample_rate().0 <= rate && rate <= c.max_sample_rate().0)
+    })
+}
+
+/// Linear gain ramp across the frames of an interleaved buffer.
+fn apply_ramp(buffer: &mut [f32], channels: usize, from: f32, to: f32) {
+    let frames = (buffer.len() / channels).max(1);
+    for (i, frame) in buffer.chunks_mut(channels).enumerate() {
+        let gain = from + (to - from) * (i + 1) as f32 / frames as f32;
+        for s in frame.iter_mut() {
+            *s *= gain;
+        }
+    }
+}
+
+fn active_scene(scene: &AtomicU32) -> Option<u32> {
+    Some(scene.load(Ordering::Relaxed)).filter(|&scene| scene != NO_SCENE)
+}
+
+/// Locks `mutex` without waiting on the audio thread (`live`), waiting otherwise.
+fn acquire<T>(mutex: &Mutex<T>, live: bool) -> Option<MutexGuard<'_, T>> {
+    if live { mutex.try_lock().ok() } else { mutex.lock().ok() }
+}
+
+fn payload_f32(payload: &[u8]) -> Option<f32> {
+    payload.get(..4).and_then(|b| b.try_into().ok()).map(f32::from_le_bytes)
+}
+
+/// Destination of a Connect / Disconnect Routing command: u32 node + u32 port payload.
+fn routing_target(payload: &[u8]) -> Option<(NodeId, PortId)> {
+    let to = u32::from_le_bytes(payload.get(0..4)?.try_into().ok()?);
+    let port = u32::from_le_bytes(payload.get(4..8)?.try_into().ok()?);
+    Some((to, port))
+}
@@ -0,0 +1,238 @@
+// dsptest.rs
+
+/* DSP Test Helpers for Node Authors */
+
+#![allow(warnings)]
+
+use std::f32::consts::PI;
+
+use crate::dspengine::AudioNode;
+use crate::rng::Rng;
+
+/// Drives a single node with synthetic signals and checks the result.
+///
+/// ```ignore
+/// let mut t = NodeTest::new(MyFilter::new(1)).sample_rate(48000).block_size(64);
+/// t.feed_impulse(4096).assert_finite().assert_no_denormals();
+/// t.assert_magnitude(1000.0, -3.0, 0.5);
+/// t.assert_latency(0, 0);
+/// ```
+pub struct NodeTest {
+    node: Box<dyn AudioNode>,
+    channels: usize,
+    sample_rate: u32,
+    block_size: usize,
+    prepared: bool,
+}
+
+/// Sample rates every internal node must behave identically at.
+pub const CONFORMANCE_RATES: [u32; 4] = [44100, 48000, 96000, 192000];
+
+/// Interleaved output of a render.
+pub struct Rendered {
+    pub channels: usize,
+    pub samples: Vec<f32>,
+}
+
+impl NodeTest {
+    pub fn new(node: impl AudioNode + 'static) -> Self {
+        Self::from_boxed(Box::new(node))
+    }
+
+    pub fn from_boxed(node: Box<dyn AudioNode>) -> Self {
+        NodeTest {
+            node,
+            channels: 2,
+            sample_rate: 44100,
+            block_size: 256,
+            prepared: false,
+        }
+    }
+
+    pub fn channels(mut self, channels: usize) -> Self {
+        self.channels = channels.max(1);
+        self
+    }
+
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self.prepared = false;
+        self
+    }
+
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self.prepared = false;
+        self
+    }
+
+    pub fn node(&mut self) -> &mut dyn AudioNode {
+        self.node.as_mut()
+    }
+
+    /// Sends a parameter change with an f32 payload, as the engine does for `Set Parameter`.
+    pub fn set_param(&mut self, param_id: u32, value: f32) -> &mut Self {
+        self.node.set_param(param_id, &value.to_le_bytes());
+        self
+    }
+
+    /// Copies a mono signal to every channel and processes it block by block.
+    pub fn feed(&mut self, mono: &[f32]) -> Rendered {
+        if !self.prepared {
+            self.node.prepare(self.sample_rate, self.block_size);
+            self.prepared = true;
+        }
+        let mut samples: Vec<f32> = mono.iter().flat_map(|&s| std::iter::repeat_n(s, self.channels)).collect();
+        for block in samples.chunks_mut(self.block_size * self.channels) {
+            self.node.process(block);
+        }
+        Rendered { channels: self.channels, samples }
+    }
+
+    pub fn feed_impulse(&mut self, len: usize) -> Rendered {
+        self.feed(&impulse(len))
+    }
+
+    pub fn feed_sine(&mut self, freq: f32, len: usize, amplitude: f32) -> Rendered {
+        let signal = sine(freq, self.sample_rate, len, amplitude);
+        self.feed(&signal)
+    }
+
+    pub fn feed_noise(&mut self, len: usize, seed: u64) -> Rendered {
+        self.feed(&white_noise(len, seed))
+    }
+
+    /// Gain in dB at `freq`, measured with a steady sine after a settling period.
+    pub fn magnitude_db_at(&mut self, freq: f32) -> f32 {
+        let settle = self.sample_rate as usize / 10;
+        let measure = self.sample_rate as usize / 10;
+        let input = sine(freq, self.sample_rate, settle + measure, 0.5);
+        let out = self.feed(&input).channel(0);
+        let rms_in = rms(&input[settle..]);
+        let rms_out = rms(&out[settle..]);
+        to_db(rms_out / rms_in)
+    }
+
+    pub fn assert_magnitude(&mut self, freq: f32, expected_db: f32, tolerance_db: f32) -> &mut Self {
+        let measured = self.magnitude_db_at(freq);
+        assert!(
+            (measured - expected_db).abs() <= tolerance_db,
+            "{}: magnitude at {} Hz is {:.2} dB, expected {:.2} dB (+/- {:.2})",
+            self.node.get_name(), freq, measured, expected_db, tolerance_db
+        );
+        self
+    }
+
+    /// Checks that an impulse comes out `expected` samples late (the position of the output peak).
+    pub fn assert_latency(&mut self, expected: usize, tolerance: usize) -> &mut Self {
+        let len = (expected + tolerance) * 2 + self.block_size * 4;
+        let out = self.feed_impulse(len);
+        let measured = out.peak_index(0);
+        assert!(
+            measured.abs_diff(expected) <= tolerance,
+            "{}: latency is {} samples, expected {} (+/- {})",
+            self.node.get_name(), measured, expected, tolerance
+        );
+        self
+    }
+}
+
+/// Renders a freshly built node at every rate in `CONFORMANCE_RATES` and checks that its magnitude at the given
+/// frequencies and its impulse latency (in seconds) match the 48 kHz render. `make` must apply the same settings each time.
+pub fn assert_sample_rate_agnostic(make: impl Fn() -> Box<dyn AudioNode>, freqs: &[f32], tolerance_db: f32) {
+    let measure = |rate: u32| {
+        let mut t = NodeTest::from_boxed(make()).sample_rate(rate);
+        let magnitudes: Vec<f32> = freqs.iter().map(|&f| t.magnitude_db_at(f)).collect();
+        let mut t = NodeTest::from_boxed(make()).sample_rate(rate);
+        let latency = t.feed_impulse(rate as usize / 10).peak_index(0) as f32 / rate as f32;
+        (magnitudes, latency)
+    };
+
+    let (reference, reference_latency) = measure(48000);
+    for rate in CONFORMANCE_RATES {
+        let (magnitudes, latency) = measure(rate);
+        let name = make().get_name().to_string();
+        for (i, &f) in freqs.iter().enumerate() {
+            assert!(
+                (magnitudes[i] - reference[i]).abs() <= tolerance_db,
+                "{} at {} Hz: magnitude at {} Hz is {:.2} dB, {:.2} dB at 48 kHz",
+                name, rate, f, magnitudes[i], reference[i]
+            );
+        }
+        // Allow one sample of the coarsest rate for rounding.
+        assert!(
+            (latency - reference_latency).abs() <= 1.0 / CONFORMANCE_RATES[0] as f32,
+            "{} at {} Hz: latency {:.6} s, {:.6} s at 48 kHz",
+            name, rate, latency, reference_latency
+        );
+    }
+}
+
+impl Rendered {
+    pub fn channel(&self, ch: usize) -> Vec<f32> {
+        self.samples.iter().skip(ch).step_by(self.channels).copied().collect()
+    }
+
+    pub fn peak_index(&self, ch: usize) -> usize {
+        self.channel(ch)
+            .iter()
+            .enumerate()
+            .fold((0, 0.0f32), |best, (i, s)| if s.abs() > best.1 { (i, s.abs()) } else { best })
+            .0
+    }
+
+    pub fn peak(&self) -> f32 {
+        self.samples.iter().fold(0.0f32, |p, s| p.max(s.abs()))
+    }
+
+    pub fn rms(&self, ch: usize) -> f32 {
+        rms(&self.channel(ch))
+    }
+
+    pub fn assert_finite(&self) -> &Self {
+        if let Some(i) = self.samples.iter().position(|s| !s.is_finite()) {
+            panic!("Non-finite sample {} at frame {}, channel {}", self.samples[i], i / self.channels, i % self.channels);
+        }
+        self
+    }
+
+    pub fn assert_no_denormals(&self) -> &Self {
+        if let Some(i) = self.samples.iter().position(|s| s.is_subnormal()) {
+            panic!("Denormal sample {:e} at frame {}, channel {}", self.samples[i], i / self.channels, i % self.channels);
+        }
+        self
+    }
+
+    pub fn assert_silent_after(&self, frame: usize, threshold: f32) -> &Self {
+        let tail = &self.samples[(frame * self.channels).min(self.samples.len())..];
+        let peak = tail.iter().fold(0.0f32, |p, s| p.max(s.abs()));
+        assert!(peak <= threshold, "Output not silent after frame {}: peak {:e}", frame, peak);
+        self
+    }
+}
+
+pub fn impulse(len: usize) -> Vec<f32> {
+    let mut out = vec![0.0; len];
+    if let Some(first) = out.first_mut() { *first = 1.0; }
+    out
+}
+
+pub fn sine(freq: f32, sample_rate: u32, len: usize, amplitude: f32) -> Vec<f32> {
+    let w = 2.0 * PI * freq / sample_rate as f32;
+    (0..len).map(|i| amplitude * (w * i as f32).sin()).collect()
+}
+
+/// Uniform white noise in -1.0..1.0 from a fixed seed, so runs are repeatable.
+pub fn white_noise(len: usize, seed: u64) -> Vec<f32> {
+    let mut rng = Rng::new(seed);
+    (0..len).map(|_| rng.bipolar()).collect()
+}
+
+pub fn rms(signal: &[f32]) -> f32 {
+    if signal.is_empty() { return 0.0; }
+    (signal.iter().map(|s| s * s).sum::<f32>() / signal.len() as f32).sqrt()
+}
+
+pub fn to_db(gain: f32) -> f32 {
+    20.0 * gain.max(1.0e-12).log10()
+}
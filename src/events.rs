@@ -0,0 +1,120 @@
+// events.rs
+
+/* Block Events */
+
+#![allow(warnings)]
+
+use crate::dspapi::ParamId;
+use crate::midi::MidiEvent;
+
+/// Parameter and transport changes that can wait for a node's next block; further ones are applied directly.
+pub const MAX_PENDING_EVENTS: usize = 64;
+
+/// Transport state as a node sees it (see `dspengine::Transport`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransportInfo {
+    pub playing: bool,
+    pub recording: bool,
+    /// Quarter notes per minute, as in VST3 and CLAP.
+    pub tempo: f32,
+    /// Timeline position in frames; advances only while playing.
+    pub position: u64,
+    pub beats_per_bar: u16,
+    /// Note value of a beat in the time signature (4 for quarter notes).
+    pub beat_unit: u16,
+}
+
+/// Where the transport is in bars and beats.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MusicalTime {
+    /// Quarter notes from the start of the timeline (PPQ position).
+    pub ppq: f64,
+    /// Bar number, counting from 0.
+    pub bar: u64,
+    /// Start of that bar in quarter notes.
+    pub bar_start_ppq: f64,
+    /// Position within the bar in time signature beats, counting from 0 (2.5 is halfway through the third beat).
+    pub beat: f64,
+}
+
+impl TransportInfo {
+    /// Length of a quarter note at `sample_rate`, for tempo-synced effects.
+    pub fn frames_per_beat(&self, sample_rate: u32) -> f64 {
+        sample_rate as f64 * 60.0 / self.tempo.max(1.0) as f64
+    }
+
+    /// Position in quarter notes from the start of the timeline.
+    pub fn ppq(&self, sample_rate: u32) -> f64 {
+        self.position as f64 / self.frames_per_beat(sample_rate)
+    }
+
+    /// Length of a bar in quarter notes (3 for 6/8).
+    pub fn bar_length(&self) -> f64 {
+        self.beats_per_bar.max(1) as f64 * 4.0 / self.beat_unit.max(1) as f64
+    }
+
+    /// Bar and beat position, counting as if the current tempo and time signature held from the start.
+    pub fn musical_time(&self, sample_rate: u32) -> MusicalTime {
+        let ppq = self.ppq(sample_rate);
+        let bar_length = self.bar_length();
+        let bar = (ppq / bar_length).floor();
+        let bar_start_ppq = bar * bar_length;
+        MusicalTime { ppq, bar: bar as u64, bar_start_ppq, beat: (ppq - bar_start_ppq) * self.beat_unit.max(1) as f64 / 4.0 }
+    }
+}
+
+impl Default for TransportInfo {
+    fn default() -> Self {
+        TransportInfo { playing: false, recording: false, tempo: 120.0, position: 0, beats_per_bar: 4, beat_unit: 4 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    /// Notes and other MIDI messages.
+    Midi(MidiEvent),
+    /// A parameter change, as `set_param` with an f32 payload.
+    Param { id: ParamId, value: f32 },
+    /// The transport started, stopped, jumped, or changed tempo or time signature.
+    Transport(TransportInfo),
+}
+
+/// Something happening at `frame` within the block it is handed out with. Fixed-size, so event lists are
+/// preallocated and never grow on the audio thread.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeEvent {
+    pub frame: u32,
+    pub kind: EventKind,
+}
+
+impl NodeEvent {
+    pub fn midi(event: MidiEvent) -> Self {
+        NodeEvent { frame: event.frame, kind: EventKind::Midi(event) }
+    }
+
+    pub fn param(frame: u32, id: ParamId, value: f32) -> Self {
+        NodeEvent { frame, kind: EventKind::Param { id, value } }
+    }
+
+    pub fn transport(frame: u32, transport: TransportInfo) -> Self {
+        NodeEvent { frame, kind: EventKind::Transport(transport) }
+    }
+
+    /// The same event at another frame.
+    pub fn at_frame(self, frame: u32) -> Self {
+        let kind = match self.kind {
+            EventKind::Midi(event) => EventKind::Midi(event.at_frame(frame)),
+            kind => kind,
+        };
+        NodeEvent { frame, kind }
+    }
+}
+
+/// Inserts `event` after every event at or before its frame, keeping `events` in time order without
+/// allocating. Returns false if `events` is full.
+pub fn insert_in_order(events: &mut Vec<NodeEvent>, event: NodeEvent) -> bool {
+    if events.len() == events.capacity() { return false; }
+    let index = events.iter().rposition(|e| e.frame <= event.frame).map_or(0, |i| i + 1);
+    events.insert(index, event);
+    true
+}
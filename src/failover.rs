@@ -0,0 +1,116 @@
+// failover.rs
+
+/* Redundant Engine Failover Supervisor */
+
+#![allow(warnings)]
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::dspapi::{Command, StatState, RESPONSE_QUEUE};
+use crate::dspengine::{DspEngine, EngineHandle, EngineState};
+use crate::threads::{self, ThreadRole};
+
+/// Runs a primary and a backup engine on the same session, both rendering all the time, and moves the hardware
+/// output to the backup when the primary's audio callback stops ticking. The backup plays in standby (see
+/// `DspEngine::standby`) until then, so the switch opens nothing and takes effect with its next buffer.
+/// The backup's rack must mirror the primary's, e.g. by submitting commands through `EngineSync`, and it
+/// needs the same input pushed to it.
+pub struct FailoverSupervisor {
+    failed_over: Arc<AtomicBool>,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FailoverSupervisor {
+    /// Puts `backup` in standby (starting it if it isn't running) and starts watching `primary`. The watchdog
+    /// trips when the primary's heartbeat doesn't advance for `missed_buffers` buffer periods.
+    pub fn spawn(primary: Arc<Mutex<DspEngine>>, backup: Arc<Mutex<DspEngine>>, missed_buffers: u32) -> Result<Self, String> {
+        let failed_over = Arc::new(AtomicBool::new(false));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let (heartbeat, period, primary_standby) = {
+            let engine = primary.lock().map_err(|_| "Primary engine lock poisoned".to_string())?;
+            let period = Duration::from_secs_f64(engine.buffer_size as f64 / engine.sample_rate as f64);
+            (Arc::clone(&engine.heartbeat), period, Arc::clone(&engine.standby))
+        };
+        let (backup_standby, backup_handle) = {
+            let mut engine = backup.lock().map_err(|_| "Backup engine lock poisoned".to_string())?;
+            engine.standby.store(true, Ordering::Relaxed);
+            if !engine.is_running() {
+                engine.start().map_err(|e| format!("Backup engine failed to start: {}", e))?;
+            }
+            (Arc::clone(&engine.standby), engine.handle())
+        };
+        let timeout = period * missed_buffers.max(1);
+
+        let flag = Arc::clone(&failed_over);
+        let stop = Arc::clone(&shutdown);
+        let thread = thread::Builder::new()
+            .name("opentune-failover".into())
+            .spawn(move || {
+                let _thread = threads::register_current("opentune-failover", ThreadRole::Supervisor);
+                let mut last_beat = heartbeat.load(Ordering::Relaxed);
+                let mut last_change = Instant::now();
+
+                while !stop.load(Ordering::Relaxed) {
+                    // Poll at half a buffer so the switch happens within the next buffer after the trip.
+                    thread::sleep(period / 2);
+
+                    let beat = heartbeat.load(Ordering::Relaxed);
+                    if beat != last_beat {
+                        last_beat = beat;
+                        last_change = Instant::now();
+                        continue;
+                    }
+
+                    // Only a primary that has actually produced audio can stall.
+                    if beat == 0 || last_change.elapsed() < timeout { continue; }
+
+                    // Just flags: the backup's running callback takes over with its next buffer.
+                    primary_standby.store(true, Ordering::Relaxed);
+                    backup_standby.store(false, Ordering::Relaxed);
+                    Self::report(&backup_handle);
+                    // A stalled primary may hold its own lock; never block on it.
+                    if let Ok(mut engine) = primary.try_lock() {
+                        engine.stop();
+                    }
+                    flag.store(true, Ordering::Release);
+                    break;
+                }
+            })
+            .map_err(|e| format!("Failed to spawn failover supervisor: {}", e))?;
+
+        Ok(Self {
+            failed_over,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    /// Sends Failover Engaged: ACTIVE if the backup is playing, otherwise INACTIVE with its state as the cause.
+    fn report(backup: &EngineHandle) {
+        let command = match backup.state() {
+            EngineState::Running => Command::new(100, "Failover Engaged", Vec::new(), backup.engine_id, 0, 0, StatState::ACTIVE),
+            state => Command::new(100, "Failover Engaged", format!("Backup engine is {:?}", state).into_bytes(), backup.engine_id, 0, 0, StatState::INACTIVE),
+        };
+        if let Ok(mut queue) = RESPONSE_QUEUE.lock() {
+            queue.push(command);
+        }
+    }
+
+    pub fn has_failed_over(&self) -> bool {
+        self.failed_over.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for FailoverSupervisor {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
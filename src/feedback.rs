@@ -0,0 +1,151 @@
+// feedback.rs
+
+/* Controller LED / Motor Fader Feedback */
+
+#![allow(warnings)]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use once_cell::sync::Lazy;
+
+use crate::dspapi::{NodeId, ParamId};
+use crate::mapping::{MappingSource, MappingTable, MappingTarget, OSC_PREFIX};
+
+pub static FEEDBACK: Lazy<Arc<Mutex<FeedbackRouter>>> = Lazy::new(|| {
+    Arc::new(Mutex::new(FeedbackRouter::new()))
+});
+
+/// Engine state a controller can display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FeedbackKey {
+    Bypass(NodeId),
+    SceneActive(u32),
+    ClipPlaying(u32),
+    MeterLevel(NodeId),
+    ParamValue { node_id: NodeId, param_id: ParamId },
+}
+
+impl FeedbackKey {
+    /// Built-in OSC address the state is echoed on.
+    pub fn osc_address(&self) -> String {
+        match *self {
+            FeedbackKey::Bypass(node) => format!("{}/node/{}/bypass", OSC_PREFIX, node),
+            FeedbackKey::SceneActive(scene) => format!("{}/scene/{}/active", OSC_PREFIX, scene),
+            FeedbackKey::ClipPlaying(clip) => format!("{}/clip/{}/playing", OSC_PREFIX, clip),
+            FeedbackKey::MeterLevel(node) => format!("{}/node/{}/meter", OSC_PREFIX, node),
+            FeedbackKey::ParamValue { node_id, param_id } => format!("{}/node/{}/param/{}", OSC_PREFIX, node_id, param_id),
+        }
+    }
+}
+
+/// Where a state is sent. Notes light LEDs (on/off velocity), CCs drive rings and motor faders.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeedbackOutput {
+    MidiCc { channel: u8, cc: u8 },
+    MidiNote { channel: u8, note: u8, on_velocity: u8, off_velocity: u8 },
+    Osc(String),
+}
+
+/// A message for the caller to put on the wire.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeedbackMessage {
+    Midi(Vec<u8>),
+    Osc { address: String, value: f32 },
+}
+
+/// Turns engine state changes into controller messages. Only sends when a value actually changes,
+/// so meters and repeated scene recalls don't flood slow MIDI links.
+pub struct FeedbackRouter {
+    bindings: Vec<(FeedbackKey, FeedbackOutput)>,
+    /// Also echo every state on its built-in OSC address.
+    pub osc_echo: bool,
+    /// Last value per key, in 1/127 steps.
+    last: HashMap<FeedbackKey, u8>,
+    values: HashMap<FeedbackKey, f32>,
+}
+
+impl FeedbackRouter {
+    pub fn new() -> Self {
+        Self {
+            bindings: Vec::new(),
+            osc_echo: false,
+            last: HashMap::new(),
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn bind(&mut self, key: FeedbackKey, output: FeedbackOutput) {
+        self.bindings.retain(|(k, o)| !(*k == key && *o == output));
+        self.bindings.push((key, output));
+    }
+
+    pub fn unbind(&mut self, key: FeedbackKey) {
+        self.bindings.retain(|(k, _)| *k != key);
+    }
+
+    /// Binds feedback for every mapped control: parameter values go back to the controlling CC (motor faders,
+    /// encoder rings) and scene recall buttons light while their scene is active.
+    pub fn mirror_mappings(&mut self, table: &MappingTable) {
+        for m in table.mappings.iter() {
+            let key = match m.target {
+                MappingTarget::NodeParam { node_id, param_id } => FeedbackKey::ParamValue { node_id, param_id },
+                MappingTarget::SceneRecall(scene) => FeedbackKey::SceneActive(scene),
+                MappingTarget::Transport(_) => continue,
+            };
+            let output = match m.source.clone() {
+                MappingSource::MidiCc { channel, cc } => FeedbackOutput::MidiCc { channel, cc },
+                MappingSource::MidiNote { channel, note } => FeedbackOutput::MidiNote { channel, note, on_velocity: 127, off_velocity: 0 },
+                MappingSource::Osc(address) => FeedbackOutput::Osc(address),
+            };
+            self.bind(key, output);
+        }
+    }
+
+    /// Reports an on/off state (bypass, scene active, clip playing).
+    pub fn publish_flag(&mut self, key: FeedbackKey, on: bool) -> Vec<FeedbackMessage> {
+        self.publish(key, if on { 1.0 } else { 0.0 })
+    }
+
+    /// Reports a meter level in dBFS, scaled to -60..0 dB.
+    pub fn publish_meter(&mut self, node_id: NodeId, level_db: f32) -> Vec<FeedbackMessage> {
+        self.publish(FeedbackKey::MeterLevel(node_id), ((level_db + 60.0) / 60.0).clamp(0.0, 1.0))
+    }
+
+    /// Reports a normalized (0.0..1.0) value. Returns the messages to send; empty if nothing visibly changed.
+    pub fn publish(&mut self, key: FeedbackKey, value: f32) -> Vec<FeedbackMessage> {
+        let value = value.clamp(0.0, 1.0);
+        let step = (value * 127.0).round() as u8;
+        self.values.insert(key, value);
+        if self.last.insert(key, step) == Some(step) {
+            return vec![];
+        }
+        self.render(key, value)
+    }
+
+    /// Re-sends every known state, e.g. after a controller reconnects or switches pages.
+    pub fn resend_all(&mut self) -> Vec<FeedbackMessage> {
+        let values: Vec<(FeedbackKey, f32)> = self.values.iter().map(|(k, v)| (*k, *v)).collect();
+        values.into_iter().flat_map(|(key, value)| self.render(key, value)).collect()
+    }
+
+    fn render(&self, key: FeedbackKey, value: f32) -> Vec<FeedbackMessage> {
+        let step = (value * 127.0).round() as u8;
+        let mut out: Vec<FeedbackMessage> = self
+            .bindings
+            .iter()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, output)| match output {
+                FeedbackOutput::MidiCc { channel, cc } => FeedbackMessage::Midi(vec![0xB0 | (channel & 0x0F), *cc, step]),
+                FeedbackOutput::MidiNote { channel, note, on_velocity, off_velocity } => {
+                    let velocity = if value >= 0.5 { *on_velocity } else { *off_velocity };
+                    FeedbackMessage::Midi(vec![0x90 | (channel & 0x0F), *note, velocity])
+                }
+                FeedbackOutput::Osc(address) => FeedbackMessage::Osc { address: address.clone(), value },
+            })
+            .collect();
+        if self.osc_echo {
+            out.push(FeedbackMessage::Osc { address: key.osc_address(), value });
+        }
+        out
+    }
+}
@@ -0,0 +1,106 @@
+// freeze.rs
+
+/* Node Freezing */
+
+#![allow(warnings)]
+
+use crate::dspengine::AudioNode;
+use crate::events::TransportInfo;
+use crate::layout::ChannelLayout;
+use crate::rtsafety::RtSafety;
+
+/// A node's output over a range of the timeline, recorded while the transport plays through it and played
+/// back in its place afterwards (see `AudioGraph::freeze_node`), so heavy plugins cost nothing once frozen.
+/// As a node it plays the recording at the transport position and is silent elsewhere.
+pub struct FrozenAudio {
+    start: u64,
+    audio: Vec<f32>,
+    channels: usize,
+    /// Frames recorded from `start` on without a gap.
+    filled: usize,
+    transport: TransportInfo,
+}
+
+impl FrozenAudio {
+    /// Allocates room for `frames` of interleaved audio starting at timeline frame `start`.
+    pub fn new(start: u64, frames: usize, channels: usize) -> Self {
+        let channels = channels.max(1);
+        FrozenAudio { start, audio: vec![0.0; frames * channels], channels, filled: 0, transport: TransportInfo::default() }
+    }
+
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn frames(&self) -> usize {
+        self.audio.len() / self.channels
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Share of the range recorded so far, 0.0..1.0.
+    pub fn progress(&self) -> f32 {
+        if self.frames() == 0 { 1.0 } else { self.filled as f32 / self.frames() as f32 }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.filled == self.frames()
+    }
+
+    /// Records `output`, the block just rendered at the transport position, if the transport is playing and the
+    /// block continues the recording. Playing from before `start` through to the end completes it.
+    pub fn record(&mut self, output: &[f32]) {
+        if !self.transport.playing || self.is_complete() { return; }
+        let frames = output.len() / self.channels;
+        let from = self.start + self.filled as u64;
+        let position = self.transport.position;
+        if position > from || position + frames as u64 <= from { return; }
+        let skip = (from - position) as usize;
+        let run = (frames - skip).min(self.frames() - self.filled);
+        let at = self.filled * self.channels;
+        self.audio[at..at + run * self.channels].copy_from_slice(&output[skip * self.channels..(skip + run) * self.channels]);
+        self.filled += run;
+    }
+}
+
+impl AudioNode for FrozenAudio {
+    fn prepare(&mut self, _sample_rate: u32, _max_block_size: usize) {}
+
+    /// A different channel count makes the recording useless, so it starts over.
+    fn set_channel_layout(&mut self, layout: ChannelLayout) {
+        if layout.channels() == self.channels { return; }
+        let frames = self.frames();
+        self.channels = layout.channels();
+        self.audio = vec![0.0; frames * self.channels];
+        self.filled = 0;
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        buffer.fill(0.0);
+        if !self.transport.playing { return; }
+        let frames = (buffer.len() / self.channels) as u64;
+        let end = self.start + self.filled as u64;
+        let (from, to) = (self.transport.position.max(self.start), (self.transport.position + frames).min(end));
+        if from >= to { return; }
+        let out = ((from - self.transport.position) as usize * self.channels)..((to - self.transport.position) as usize * self.channels);
+        let at = ((from - self.start) as usize * self.channels)..((to - self.start) as usize * self.channels);
+        buffer[out].copy_from_slice(&self.audio[at]);
+    }
+
+    fn set_param(&mut self, _param_id: u32, _payload: &[u8]) {}
+
+    fn get_id(&self) -> u32 { 0 }
+
+    fn get_name(&self) -> &str { "Frozen Audio" }
+
+    fn rt_safety(&self) -> RtSafety { RtSafety::SAFE }
+
+    fn set_transport(&mut self, transport: &TransportInfo) {
+        self.transport = *transport;
+    }
+
+    /// Nothing is held back between blocks: playback follows the transport. The recording itself is kept.
+    fn reset(&mut self) {}
+}
@@ -0,0 +1,1306 @@
+// graph.rs
+
+/* Routable Audio Graph */
+
+#![allow(warnings)]
+
+use std::time::{Duration, Instant};
+
+use crate::aggregate::{OutputFeeds, OutputSource};
+use crate::automation::{self, ParamChange};
+use crate::bus;
+use crate::dsp::simd;
+use crate::dspapi::{NodeId, ParamId, PortId};
+use crate::dspengine::AudioNode;
+use crate::events::{self, EventKind, NodeEvent, TransportInfo};
+use crate::layout::ChannelLayout;
+use crate::midi::{self, MidiEvent, MidiWriter};
+use crate::planar::PlanarBuffer;
+use crate::smoothing::{self, ParamSmoother};
+use crate::freeze::FrozenAudio;
+use crate::oversample::Oversampled;
+use crate::strip::{self, NodeStrip};
+use crate::taps::{TapPoint, TapSet};
+use crate::meters::LevelMeters;
+use crate::usage::UsageMeter;
+
+/// Pseudo-node carrying the engine's incoming signal (the playback ring buffer).
+pub const GRAPH_INPUT: NodeId = u32::MAX;
+/// Pseudo-node whose input is what reaches the hardware.
+pub const GRAPH_OUTPUT: NodeId = u32::MAX - 1;
+/// Pseudo-node carrying live input captured from the input device (silence when capture is off).
+pub const GRAPH_CAPTURE: NodeId = u32::MAX - 2;
+/// Pseudo-node whose MIDI output is the events arriving from the open MIDI inputs.
+pub const GRAPH_MIDI_INPUT: NodeId = u32::MAX - 3;
+/// Pseudo-node whose MIDI input goes to the open MIDI outputs (see `midi_output`).
+pub const GRAPH_MIDI_OUTPUT: NodeId = u32::MAX - 4;
+/// The MIDI port of a node, on either end of an edge. Connects nodes that produce MIDI to nodes that accept it.
+pub const MIDI_PORT: PortId = PortId::MAX;
+/// Default for the longest delay the graph inserts to line up paths of different latency, in frames.
+pub const DEFAULT_MAX_COMPENSATION: usize = 8192;
+
+/// A connection from an output port to an input port. Several edges into one port are summed (merge);
+/// several edges out of one port each get the same signal (split).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub from: NodeId,
+    pub from_port: PortId,
+    pub to: NodeId,
+    pub to_port: PortId,
+}
+
+// Slot indices used for the pseudo-nodes in the resolved edge list.
+const INPUT_SLOT: usize = usize::MAX;
+const OUTPUT_SLOT: usize = usize::MAX - 1;
+const CAPTURE_SLOT: usize = usize::MAX - 2;
+const MIDI_INPUT_SLOT: usize = usize::MAX - 3;
+const MIDI_OUTPUT_SLOT: usize = usize::MAX - 4;
+/// Marks a resolved edge that isn't a send.
+const NO_SEND: usize = usize::MAX;
+
+struct GraphNode {
+    /// The node, inside the slot's oversampling wrapper (at factor 1 it passes everything straight through).
+    node: Box<Oversampled>,
+    /// The node's input mix, processed in place into its output.
+    buffer: Vec<f32>,
+    /// Latency from the graph input to this node's output along its slowest path.
+    latency: usize,
+    /// Recent output, for delaying it into inputs fed by slower paths.
+    history: DelayLine,
+    /// Recent input, for delaying the dry signal by the node's own latency.
+    dry_history: DelayLine,
+    /// Host controls of the slot (bypass, mix, trim, gain, pan); kept when the node is replaced.
+    strip: NodeStrip,
+    /// MIDI the node produced in the current block.
+    midi_out: Vec<MidiEvent>,
+    /// Parameter and transport changes waiting for the next block of a node that handles events.
+    pending: Vec<NodeEvent>,
+    /// Parameter changes being spread over the smoothing time.
+    smoother: ParamSmoother,
+    /// The node's recorded output, played back instead of running the node once complete.
+    frozen: Option<Box<FrozenAudio>>,
+    /// Fading to its dry signal, to be taken out once there (see `fade_out_node`).
+    removing: bool,
+    /// The node this one replaced, still run on the same input while the two crossfade (see `crossfade_node`).
+    outgoing: Option<Box<dyn AudioNode>>,
+    /// Frames of that crossfade done.
+    crossfaded: usize,
+}
+
+/// A post-fader send: the edge from `from`'s main output to return bus `bus`, scaled by `level`.
+struct Send {
+    from: NodeId,
+    bus: NodeId,
+    level: f32,
+    /// Level the last block ended at; the next one ramps from here to `level`.
+    applied: f32,
+}
+
+/// Nodes connected by edges and run in topological order. Everything is preallocated for `max_nodes` and
+/// `max_edges`, so adding, connecting and processing on the audio thread never reallocate; changes that would
+/// exceed capacity or create a cycle are refused. A new graph routes its input straight to its output.
+pub struct AudioGraph {
+    nodes: Vec<GraphNode>,
+    edges: Vec<Edge>,
+    /// Scratch copy of `edges`, so a refused `move_node` can be undone without allocating.
+    edge_backup: Vec<Edge>,
+    /// Sends, each backed by an edge in `edges`.
+    sends: Vec<Send>,
+    /// `edges` resolved to (source slot, destination slot, destination port, index in `sends` or `NO_SEND`),
+    /// rebuilt with the schedule.
+    resolved: Vec<(usize, usize, PortId, usize)>,
+    /// Slot indices in processing order.
+    order: Vec<usize>,
+    spare_buffers: Vec<Vec<f32>>,
+    /// Delay lines of free slots, (output, dry) as in `GraphNode`.
+    spare_histories: Vec<(DelayLine, DelayLine)>,
+    /// Event lists of free slots, (MIDI output, pending) as in `GraphNode`.
+    spare_events: Vec<(Vec<MidiEvent>, Vec<NodeEvent>)>,
+    spare_smoothers: Vec<ParamSmoother>,
+    /// Empty oversampling wrappers of free slots, prepared so nodes are oversampled without allocating.
+    spare_oversamplers: Vec<Box<Oversampled>>,
+    /// Recordings of nodes that were unfrozen, removed or replaced, until `take_thawed` hands them out.
+    thawed: Vec<Box<FrozenAudio>>,
+    /// Nodes that finished fading out, until `take_retired` hands them out.
+    retired: Vec<Box<dyn AudioNode>>,
+    indegree: Vec<usize>,
+    input: Vec<f32>,
+    capture: Vec<f32>,
+    /// A node's input, kept while its host controls blend it with the output.
+    dry: Vec<f32>,
+    /// Input, then output, of a replaced node while it crossfades into its successor.
+    fading: Vec<f32>,
+    /// Planes for nodes that process deinterleaved audio.
+    planar: PlanarBuffer,
+    /// Mix of one sidechain port, handed to the node before it processes.
+    sidechain: Vec<f32>,
+    /// The MIDI falling in the block being processed, with frames relative to it.
+    block_midi: Vec<MidiEvent>,
+    /// The parameter changes falling in the block being processed, for nodes that handle events.
+    block_params: Vec<ParamChange>,
+    /// MIDI routed to the node being processed, merged from its MIDI connections.
+    midi_in: Vec<MidiEvent>,
+    /// MIDI routed to `GRAPH_MIDI_OUTPUT` during the last `process` call.
+    midi_output: Vec<MidiEvent>,
+    /// Events for the node being processed, when it handles events.
+    events: Vec<NodeEvent>,
+    transport: TransportInfo,
+    /// Samples in the last block processed, for reading signals back (see `level`).
+    block_len: usize,
+    /// Time parameter changes are smoothed over, in milliseconds; 0 applies them directly.
+    smoothing_ms: f32,
+    input_history: DelayLine,
+    capture_history: DelayLine,
+    /// Longest compensating delay in frames; paths that differ by more are only partly lined up.
+    max_compensation: usize,
+    sample_rate: u32,
+    channels: usize,
+    layout: ChannelLayout,
+    block_frames: usize,
+    /// Bumped whenever the nodes or edges change.
+    revision: u64,
+    /// Send All Sound Off and All Notes Off to every MIDI node with the next block (see `panic`).
+    notes_off: bool,
+}
+
+impl AudioGraph {
+    pub fn new(max_nodes: usize, max_edges: usize, channels: usize, block_frames: usize) -> Self {
+        let channels = channels.max(1);
+        let block_frames = block_frames.max(1);
+        let mut graph = AudioGraph {
+            nodes: Vec::with_capacity(max_nodes),
+            edges: Vec::with_capacity(max_edges),
+            edge_backup: Vec::with_capacity(max_edges),
+            sends: Vec::with_capacity(max_edges),
+            resolved: Vec::with_capacity(max_edges),
+            order: Vec::with_capacity(max_nodes),
+            spare_buffers: (0..max_nodes).map(|_| vec![0.0; block_frames * channels]).collect(),
+            spare_histories: (0..max_nodes).map(|_| (DelayLine::default(), DelayLine::default())).collect(),
+            spare_events: (0..max_nodes)
+                .map(|_| (Vec::with_capacity(midi::MAX_BLOCK_EVENTS), Vec::with_capacity(events::MAX_PENDING_EVENTS)))
+                .collect(),
+            spare_smoothers: (0..max_nodes).map(|_| ParamSmoother::new()).collect(),
+            spare_oversamplers: (0..max_nodes).map(|_| Box::new(Oversampled::empty(channels))).collect(),
+            thawed: Vec::with_capacity(max_nodes),
+            retired: Vec::with_capacity(max_nodes),
+            indegree: vec![0; max_nodes],
+            input: vec![0.0; block_frames * channels],
+            capture: vec![0.0; block_frames * channels],
+            dry: vec![0.0; block_frames * channels],
+            fading: vec![0.0; block_frames * channels],
+            planar: PlanarBuffer::new(channels, block_frames),
+            sidechain: vec![0.0; block_frames * channels],
+            block_midi: Vec::with_capacity(midi::MAX_BLOCK_EVENTS),
+            block_params: Vec::with_capacity(automation::MAX_BLOCK_CHANGES),
+            midi_in: Vec::with_capacity(midi::MAX_BLOCK_EVENTS),
+            midi_output: Vec::with_capacity(midi::MAX_BLOCK_EVENTS),
+            events: Vec::with_capacity(events::MAX_PENDING_EVENTS + midi::MAX_BLOCK_EVENTS),
+            transport: TransportInfo::default(),
+            block_len: 0,
+            smoothing_ms: smoothing::DEFAULT_SMOOTHING_MS,
+            input_history: DelayLine::default(),
+            capture_history: DelayLine::default(),
+            max_compensation: DEFAULT_MAX_COMPENSATION,
+            sample_rate: 44100,
+            channels,
+            layout: ChannelLayout::from_channels(channels),
+            block_frames,
+            revision: 0,
+            notes_off: false,
+        };
+        graph.edges.push(Edge { from: GRAPH_INPUT, from_port: 0, to: GRAPH_OUTPUT, to_port: 0 });
+        graph.resize_histories();
+        for oversampler in graph.spare_oversamplers.iter_mut() {
+            oversampler.prepare_buffers(graph.sample_rate, block_frames);
+        }
+        graph.reschedule();
+        graph
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.nodes.capacity()
+    }
+
+    /// Whether `add_node` can find a slot: one is free, or a node fading out can give up its own.
+    pub fn has_room(&self) -> bool {
+        self.nodes.len() < self.nodes.capacity() || self.nodes.iter().any(|n| n.removing)
+    }
+
+    pub fn edge_capacity(&self) -> usize {
+        self.edges.capacity()
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn layout(&self) -> ChannelLayout {
+        self.layout
+    }
+
+    /// Switches every buffer and node to `layout` and prepares the nodes again. Call from the control thread.
+    pub fn set_layout(&mut self, layout: ChannelLayout, sample_rate: u32) {
+        self.layout = layout;
+        self.channels = layout.channels();
+        for node in self.nodes.iter_mut() {
+            node.node.set_channel_layout(layout);
+        }
+        for oversampler in self.spare_oversamplers.iter_mut() {
+            oversampler.set_channel_layout(layout);
+        }
+        self.prepare(sample_rate, self.block_frames);
+    }
+
+    /// Longest delay, in frames, inserted to line up paths of different latency.
+    pub fn max_compensation(&self) -> usize {
+        self.max_compensation
+    }
+
+    /// Sets the longest compensating delay and reallocates the delay lines. Call from the control thread.
+    pub fn set_max_compensation(&mut self, frames: usize) {
+        self.max_compensation = frames;
+        self.resize_histories();
+    }
+
+    /// Bytes preallocated for node buffers and scheduling.
+    pub fn allocated_bytes(&self) -> usize {
+        let buffers = (self.nodes.capacity() + 5) * self.block_frames * self.channels * std::mem::size_of::<f32>();
+        buffers
+            + self.compensation_bytes()
+            + (self.nodes.capacity() + 3) * midi::MAX_BLOCK_EVENTS * std::mem::size_of::<MidiEvent>()
+            + (self.nodes.capacity() + 1) * events::MAX_PENDING_EVENTS * std::mem::size_of::<NodeEvent>()
+            + self.events.capacity() * std::mem::size_of::<NodeEvent>()
+            + self.block_params.capacity() * std::mem::size_of::<ParamChange>()
+            + self.nodes.capacity() * smoothing::MAX_SMOOTHED_PARAMS * 20
+            + self.nodes.capacity() * Oversampled::allocated_bytes(self.channels, self.block_frames)
+            + self.nodes.capacity() * (std::mem::size_of::<GraphNode>() + 2 * std::mem::size_of::<usize>() + std::mem::size_of::<Box<dyn AudioNode>>())
+            + self.edges.capacity() * (2 * std::mem::size_of::<Edge>() + std::mem::size_of::<(usize, usize, PortId, usize)>() + std::mem::size_of::<Send>())
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &dyn AudioNode> {
+        self.nodes.iter().map(|n| n.node.as_ref() as &dyn AudioNode)
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&dyn AudioNode> {
+        self.slot(id).map(|s| self.nodes[s].node.as_ref() as &dyn AudioNode)
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> Option<&mut dyn AudioNode> {
+        self.slot(id).map(|s| self.nodes[s].node.as_mut() as &mut dyn AudioNode)
+    }
+
+    /// Sets a parameter of node `id`. Host parameters (see `strip::HOST_PARAM_BASE`, f32 payload) go to the
+    /// slot's host controls or its sends, everything else to the node: f32 values as an event with its next
+    /// block if it handles events, otherwise spread over the smoothing time (see `set_param_smoothing`) unless
+    /// the node opts out.
+    pub fn set_param(&mut self, id: NodeId, param_id: ParamId, payload: &[u8]) -> Result<(), &'static str> {
+        self.apply_param(id, param_id, payload, true)
+    }
+
+    fn apply_param(&mut self, id: NodeId, param_id: ParamId, payload: &[u8], smooth: bool) -> Result<(), &'static str> {
+        let slot = self.slot(id).ok_or("no such node")?;
+        let frames = if smooth { self.smoothing_frames() } else { 0 };
+        let node = &mut self.nodes[slot];
+        if !strip::is_host_param(param_id) {
+            match <[u8; 4]>::try_from(payload) {
+                Ok(bytes) if node.node.handles_events() => {
+                    if node.pending.len() == node.pending.capacity() { node.apply_pending_params(); }
+                    node.pending.push(NodeEvent::param(0, param_id, f32::from_le_bytes(bytes)));
+                }
+                Ok(bytes) => {
+                    let frames = if node.node.host_smoothing(param_id) { frames } else { 0 };
+                    if let Some(value) = node.smoother.set(param_id, f32::from_le_bytes(bytes), frames) {
+                        node.node.set_param(param_id, &value.to_le_bytes());
+                    }
+                }
+                _ => node.node.set_param(param_id, payload),
+            }
+            return Ok(());
+        }
+        let value = <[u8; 4]>::try_from(payload).map(f32::from_le_bytes).map_err(|_| "host parameters take an f32")?;
+        if let Some(index) = strip::send_bus(param_id) {
+            return self.set_send(id, bus::BUS_ID_BASE + index, value);
+        }
+        if param_id == strip::HOST_PARAM_BYPASS && node.removing { return Err("node is being removed"); }
+        if !node.strip.set_param(param_id, value) { return Err("no such host parameter"); }
+        if param_id == strip::HOST_PARAM_OVERSAMPLING { self.apply_oversampling(slot); }
+        Ok(())
+    }
+
+    /// Runs the node in `slot` at its strip's oversampling factor. The slot's wrapper is preallocated, so only the
+    /// node's own `prepare` at the new rate runs.
+    fn apply_oversampling(&mut self, slot: usize) {
+        let node = &mut self.nodes[slot];
+        node.node.set_factor(node.strip.oversampling());
+    }
+
+    /// Time parameter changes are smoothed over, in milliseconds.
+    pub fn param_smoothing(&self) -> f32 {
+        self.smoothing_ms
+    }
+
+    /// Sets the time `set_param` spreads changes over; 0 applies them directly. Automation is never smoothed.
+    pub fn set_param_smoothing(&mut self, ms: f32) {
+        self.smoothing_ms = ms.max(0.0);
+    }
+
+    fn smoothing_frames(&self) -> usize {
+        (self.smoothing_ms * self.sample_rate as f32 / 1000.0) as usize
+    }
+
+    /// Peak level of the signal at `point` in the last block processed, e.g. for envelope followers.
+    pub fn level(&self, point: TapPoint) -> Option<f32> {
+        let signal = match point {
+            TapPoint::Input => &self.input[..self.block_len],
+            TapPoint::Capture => &self.capture[..self.block_len],
+            TapPoint::AfterNode(id) | TapPoint::Connection { from: id, .. } => self.nodes[self.slot(id)?].buffer.get(..self.block_len)?,
+        };
+        Some(simd::peak(signal))
+    }
+
+    pub fn transport(&self) -> TransportInfo {
+        self.transport
+    }
+
+    /// Sets the transport for the coming blocks (the engine calls this every render). Nodes that handle events get
+    /// the new state at the start of their next block when anything changed other than the position moving on
+    /// by the frames processed.
+    pub fn set_transport(&mut self, transport: TransportInfo) {
+        if transport == self.transport { return; }
+        self.transport = transport;
+        for node in self.nodes.iter_mut().filter(|n| n.node.handles_events()) {
+            node.queue_transport(transport);
+        }
+    }
+
+    /// Adds a return bus (see `bus::ReturnBus`) routed to the graph output alongside the main chain.
+    pub fn add_return_bus(&mut self, node: Box<dyn AudioNode>) -> Result<(), &'static str> {
+        let id = node.get_id();
+        if bus::bus_index(id).is_none() { return Err("return bus ids start at bus::BUS_ID_BASE"); }
+        if self.edges.len() >= self.edges.capacity() { return Err("no free connection slots"); }
+        self.add_node(node)?;
+        self.connect(id, 0, GRAPH_OUTPUT, 0)
+    }
+
+    /// Sends node `from`'s output, after its host controls, to return bus `bus` at `level` (linear), creating
+    /// the send if needed. Level changes ramp over a block. A send is removed by disconnecting it or removing
+    /// either end.
+    pub fn set_send(&mut self, from: NodeId, bus: NodeId, level: f32) -> Result<(), &'static str> {
+        if bus::bus_index(bus).is_none() || self.slot(bus).is_none() { return Err("no such return bus"); }
+        if self.slot(from).is_none() { return Err("no such node"); }
+        let level = level.max(0.0);
+        if let Some(send) = self.sends.iter_mut().find(|s| s.from == from && s.bus == bus) {
+            send.level = level;
+            return Ok(());
+        }
+        if self.sends.len() == self.sends.capacity() { return Err("no free send slots"); }
+        self.sends.push(Send { from, bus, level, applied: 0.0 });
+        let edge = Edge { from, from_port: 0, to: bus, to_port: 0 };
+        if self.edges.contains(&edge) {
+            self.reschedule();
+            return Ok(());
+        }
+        let connected = self.connect(from, 0, bus, 0);
+        if connected.is_err() { self.sends.pop(); }
+        connected
+    }
+
+    /// Level of node `from`'s send to return bus `bus`.
+    pub fn send_level(&self, from: NodeId, bus: NodeId) -> Option<f32> {
+        self.sends.iter().find(|s| s.from == from && s.bus == bus).map(|s| s.level)
+    }
+
+    /// Bypasses node `id` (or brings it back), crossfading so the switch doesn't click. A bypassed node keeps
+    /// its latency so the paths after it stay aligned.
+    pub fn set_bypass(&mut self, id: NodeId, bypass: bool) -> Result<(), &'static str> {
+        let slot = self.slot(id).ok_or("no such node")?;
+        if self.nodes[slot].removing { return Err("node is being removed"); }
+        self.nodes[slot].strip.set_bypass(bypass);
+        Ok(())
+    }
+
+    /// Freezes node `id`: while the transport plays through the range `frozen` covers, the node's output is
+    /// recorded into it, and once it is complete the recording plays in place of the node, which no longer runs.
+    /// The slot's host controls still apply. To freeze a sub-chain, freeze its last node and bypass the others
+    /// once `freeze_progress` reaches 1.0. A previous recording goes to `take_thawed`.
+    pub fn freeze_node(&mut self, id: NodeId, frozen: Box<FrozenAudio>) -> Result<(), (Box<FrozenAudio>, &'static str)> {
+        let Some(slot) = self.slot(id) else { return Err((frozen, "no such node")) };
+        if frozen.channels() != self.channels { return Err((frozen, "frozen audio has the wrong channel count")); }
+        if let Some(old) = self.nodes[slot].frozen.replace(frozen) { self.thawed.push(old); }
+        Ok(())
+    }
+
+    /// Runs node `id` again; its recording goes to `take_thawed`.
+    pub fn unfreeze_node(&mut self, id: NodeId) -> Result<(), &'static str> {
+        let slot = self.slot(id).ok_or("no such node")?;
+        let frozen = self.nodes[slot].frozen.take().ok_or("node is not frozen")?;
+        self.thawed.push(frozen);
+        Ok(())
+    }
+
+    /// How much of node `id`'s freeze range has been recorded (0.0..1.0), if it is frozen.
+    pub fn freeze_progress(&self, id: NodeId) -> Option<f32> {
+        self.nodes[self.slot(id)?].frozen.as_ref().map(|f| f.progress())
+    }
+
+    /// Hands out a recording no longer in use, for the caller to drop off the audio thread.
+    pub fn take_thawed(&mut self) -> Option<Box<FrozenAudio>> {
+        self.thawed.pop()
+    }
+
+    /// Hands out a node that finished fading out or crossfading away, for the caller to drop off the audio thread.
+    pub fn take_retired(&mut self) -> Option<Box<dyn AudioNode>> {
+        self.retired.pop()
+    }
+
+    /// Crossfades node `id`'s slot from its dry signal to its output over the host controls' ramp, so a node
+    /// added mid-playback doesn't click in.
+    pub fn fade_in_node(&mut self, id: NodeId) -> Result<(), &'static str> {
+        let slot = self.slot(id).ok_or("no such node")?;
+        self.nodes[slot].strip.fade_in();
+        Ok(())
+    }
+
+    /// Removes node `id` like `remove_node`, but without a click: the slot first crossfades to its dry signal,
+    /// which is what the reconnected path carries, and the node is taken out at the end of the block that gets
+    /// there. Until then it is still in the graph; it then goes to `take_retired`. Adding a node with the same id
+    /// meanwhile takes it out at once.
+    pub fn fade_out_node(&mut self, id: NodeId) -> Result<(), &'static str> {
+        let slot = self.slot(id).ok_or("no such node")?;
+        let node = &mut self.nodes[slot];
+        node.removing = true;
+        node.strip.set_bypass(true);
+        Ok(())
+    }
+
+    /// Replaces node `id` like `replace_node`, but without a click: the old node keeps running on the same input
+    /// and is crossfaded into the new one over the host controls' ramp, then goes to `take_retired`. Returns the
+    /// new node if it can't be placed.
+    pub fn crossfade_node(&mut self, id: NodeId, node: Box<dyn AudioNode>) -> Result<(), (Box<dyn AudioNode>, &'static str)> {
+        if self.slot(id).is_some_and(|s| self.nodes[s].removing) { return Err((node, "node is being removed")); }
+        let new_id = node.get_id();
+        let old = self.replace_node(id, node)?;
+        let Some(slot) = self.slot(new_id) else { return Ok(()) };
+        let replaced = &mut self.nodes[slot];
+        if replaced.strip.skips_node() {
+            self.retire(old);
+            return Ok(());
+        }
+        let previous = replaced.outgoing.replace(old);
+        replaced.crossfaded = 0;
+        if let Some(previous) = previous { self.retire(previous); }
+        Ok(())
+    }
+
+    /// Queues a node taken out on the audio thread for `take_retired`. Only if the queue is full is it dropped in
+    /// place.
+    fn retire(&mut self, node: Box<dyn AudioNode>) {
+        if self.retired.len() < self.retired.capacity() { self.retired.push(node); }
+    }
+
+    /// Takes out node `id` at once if it is fading out.
+    fn finish_removal(&mut self, id: NodeId) {
+        if !self.slot(id).is_some_and(|s| self.nodes[s].removing) { return; }
+        if let Ok(node) = self.remove_node(id) { self.retire(node); }
+    }
+
+    /// Host controls of node `id`.
+    pub fn strip(&self, id: NodeId) -> Option<&NodeStrip> {
+        self.slot(id).map(|s| &self.nodes[s].strip)
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// Silences the rack after a stuck note or a runaway node: every node is reset (`AudioNode::reset`), its
+    /// buffers and delay lines are cleared and its queued MIDI dropped, and nodes that take MIDI get All Sound
+    /// Off and All Notes Off on every channel with the next block. Replaced nodes still crossfading away are retired
+    /// at once. Never allocates.
+    pub fn panic(&mut self) {
+        for slot in 0..self.nodes.len() {
+            if let Some(outgoing) = self.nodes[slot].outgoing.take() { self.retire(outgoing); }
+        }
+        for node in self.nodes.iter_mut() {
+            node.node.reset();
+            node.buffer.fill(0.0);
+            node.history.clear();
+            node.dry_history.clear();
+            node.midi_out.clear();
+            node.pending.retain(|e| !matches!(e.kind, EventKind::Midi(_)));
+        }
+        self.input_history.clear();
+        self.capture_history.clear();
+        self.midi_output.clear();
+        self.notes_off = true;
+    }
+
+    /// Counts structural changes (nodes added, removed, replaced or moved, edges and sends changed), so
+    /// observers can tell whether what they know of the graph is current.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Node ids in the order they are processed.
+    pub fn schedule(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.order.iter().map(|&s| self.nodes[s].node.get_id())
+    }
+
+    /// Latency from the graph input to the output along the slowest path. Faster paths are delayed to match,
+    /// so this is the latency of everything the graph outputs. Up to date as of the last processed block.
+    pub fn latency_samples(&self) -> usize {
+        self.input_latency(OUTPUT_SLOT)
+    }
+
+    /// MIDI routed to `GRAPH_MIDI_OUTPUT` during the last `process` call, in time order, with frames relative to
+    /// the start of its `io`.
+    pub fn midi_output(&self) -> &[MidiEvent] {
+        &self.midi_output
+    }
+
+    /// Latency of node `id`'s output, including everything upstream, as of the last processed block.
+    pub fn node_latency(&self, id: NodeId) -> Option<usize> {
+        self.slot(id).map(|s| self.nodes[s].latency)
+    }
+
+    /// Bytes preallocated for the latency compensation delay lines.
+    pub fn compensation_bytes(&self) -> usize {
+        (2 * self.nodes.capacity() + 2) * self.history_len() * std::mem::size_of::<f32>()
+    }
+
+    /// Prepares every node and resizes the buffers. Call from the control thread.
+    pub fn prepare(&mut self, sample_rate: u32, block_frames: usize) {
+        self.block_frames = block_frames.max(1);
+        self.sample_rate = sample_rate;
+        let len = self.block_frames * self.channels;
+        self.block_len = 0;
+        self.input.resize(len, 0.0);
+        self.capture.resize(len, 0.0);
+        self.dry.resize(len, 0.0);
+        self.fading.resize(len, 0.0);
+        self.sidechain.resize(len, 0.0);
+        self.planar.resize(self.channels, self.block_frames);
+        for buffer in self.spare_buffers.iter_mut() {
+            buffer.resize(len, 0.0);
+        }
+        for oversampler in self.spare_oversamplers.iter_mut() {
+            oversampler.prepare_buffers(sample_rate, self.block_frames);
+        }
+        for node in self.nodes.iter_mut() {
+            node.buffer.resize(len, 0.0);
+            node.node.prepare(sample_rate, self.block_frames);
+            node.strip.prepare(sample_rate);
+        }
+        self.resize_histories();
+        self.reschedule();
+    }
+
+    fn history_len(&self) -> usize {
+        (self.max_compensation + self.block_frames) * self.channels
+    }
+
+    /// Sizes every delay line for `max_compensation` plus a block, clearing them.
+    fn resize_histories(&mut self) {
+        let len = self.history_len();
+        self.input_history.resize(len);
+        self.capture_history.resize(len);
+        for node in self.nodes.iter_mut() {
+            node.history.resize(len);
+            node.dry_history.resize(len);
+        }
+        for (history, dry_history) in self.spare_histories.iter_mut() {
+            history.resize(len);
+            dry_history.resize(len);
+        }
+    }
+
+    /// Adds an unconnected node. The node should already be prepared.
+    pub fn add_node(&mut self, node: Box<dyn AudioNode>) -> Result<(), &'static str> {
+        let id = node.get_id();
+        if matches!(id, GRAPH_INPUT | GRAPH_OUTPUT | GRAPH_CAPTURE | GRAPH_MIDI_INPUT | GRAPH_MIDI_OUTPUT) {
+            return Err("node id is reserved for the graph input/output");
+        }
+        self.finish_removal(id);
+        if self.slot(id).is_some() {
+            return Err("a node with this id is already in the graph");
+        }
+        if self.nodes.len() == self.nodes.capacity() {
+            return Err("graph is full");
+        }
+        let buffer = self.spare_buffers.pop().ok_or("graph is full")?;
+        let (mut history, mut dry_history) = self.spare_histories.pop().ok_or("graph is full")?;
+        let (mut midi_out, mut pending) = self.spare_events.pop().ok_or("graph is full")?;
+        let mut smoother = self.spare_smoothers.pop().ok_or("graph is full")?;
+        let mut wrapper = self.spare_oversamplers.pop().ok_or("graph is full")?;
+        wrapper.replace_inner(node);
+        smoother.clear();
+        history.clear();
+        dry_history.clear();
+        midi_out.clear();
+        pending.clear();
+        let mut strip = NodeStrip::default();
+        strip.prepare(self.sample_rate);
+        let mut added = GraphNode { node: wrapper, buffer, latency: 0, history, dry_history, strip, midi_out, pending, smoother, frozen: None, removing: false, outgoing: None, crossfaded: 0 };
+        if added.node.handles_events() { added.queue_transport(self.transport); }
+        self.nodes.push(added);
+        self.reschedule();
+        Ok(())
+    }
+
+    /// Adds a node at the end of the main chain: whatever fed the output now feeds the node, and the node
+    /// feeds the output. This keeps the behaviour of the old sequential rack for callers that don't route.
+    /// Return bus paths stay connected to the output.
+    pub fn append_node(&mut self, node: Box<dyn AudioNode>) -> Result<(), &'static str> {
+        let id = node.get_id();
+        if self.edges.len() >= self.edges.capacity() {
+            return Err("no free connection slots");
+        }
+        self.add_node(node)?;
+        for i in 0..self.edges.len() {
+            let edge = self.edges[i];
+            if edge.to == GRAPH_OUTPUT && !self.is_return_path(edge.from) {
+                self.edges[i].to = id;
+                self.edges[i].to_port = 0;
+            }
+        }
+        self.edges.push(Edge { from: id, from_port: 0, to: GRAPH_OUTPUT, to_port: 0 });
+        self.reschedule();
+        Ok(())
+    }
+
+    /// Takes a node out of the graph and returns it; the caller decides where it is dropped. Whatever fed the
+    /// node's main input is connected straight to whatever its main output fed, so removing a node from a chain
+    /// closes the gap. Never allocates.
+    pub fn remove_node(&mut self, id: NodeId) -> Result<Box<dyn AudioNode>, &'static str> {
+        let slot = self.slot(id).ok_or("no such node")?;
+        self.bridge(id);
+        self.edges.retain(|e| e.from != id && e.to != id);
+        self.sends.retain(|s| s.from != id && s.bus != id);
+        let mut removed = self.nodes.swap_remove(slot);
+        self.spare_buffers.push(removed.buffer);
+        self.spare_histories.push((removed.history, removed.dry_history));
+        self.spare_events.push((removed.midi_out, removed.pending));
+        self.spare_smoothers.push(removed.smoother);
+        let node = removed.node.take_inner();
+        self.spare_oversamplers.push(removed.node);
+        if let Some(frozen) = removed.frozen { self.thawed.push(frozen); }
+        if let Some(outgoing) = removed.outgoing { self.retire(outgoing); }
+        self.reschedule();
+        Ok(node)
+    }
+
+    /// Moves a node along the main chain so it runs just before `before` (`GRAPH_OUTPUT`: at the end): its main
+    /// input and output are spliced out like `remove_node`, then it takes over `before`'s main input. Other ports
+    /// keep their connections. Refused, leaving the graph unchanged, if that would create a cycle. Never allocates.
+    pub fn move_node(&mut self, id: NodeId, before: NodeId) -> Result<(), &'static str> {
+        if self.slot(id).is_none() { return Err("no such node"); }
+        if before == id { return Err("cannot move a node before itself"); }
+        if before != GRAPH_OUTPUT && self.slot(before).is_none() { return Err("no such node to move before"); }
+
+        self.edge_backup.clear();
+        self.edge_backup.extend_from_slice(&self.edges);
+        self.bridge(id);
+        let sends = &self.sends;
+        self.edges.retain(|e| !(e.to == id && e.to_port == 0) && !(e.from == id && e.from_port == 0 && !is_send(sends, e)));
+        for i in 0..self.edges.len() {
+            let edge = self.edges[i];
+            if edge.to == before && edge.to_port == 0 && !(before == GRAPH_OUTPUT && self.is_return_path(edge.from)) {
+                self.edges[i].to = id;
+            }
+        }
+        let link = Edge { from: id, from_port: 0, to: before, to_port: 0 };
+        if self.edges.len() < self.edges.capacity() && !self.edges.contains(&link) {
+            self.edges.push(link);
+            if self.reschedule() { return Ok(()); }
+        }
+        self.edges.clear();
+        self.edges.extend_from_slice(&self.edge_backup);
+        self.reschedule();
+        Err("move would create a cycle or needs more connection slots")
+    }
+
+    /// Whether node `id` is a return bus or only fed, through main inputs, by return buses: the part of the graph
+    /// that the main chain's edits leave alone.
+    fn is_return_path(&self, id: NodeId) -> bool {
+        if bus::bus_index(id).is_some() { return true; }
+        let mut inputs = self.edges.iter().filter(|e| e.to == id && e.to_port == 0).peekable();
+        inputs.peek().is_some() && inputs.all(|e| self.is_return_path(e.from))
+    }
+
+    /// Connects whatever feeds node `id`'s main input to whatever its main output feeds, as far as free connection
+    /// slots allow. Sends aren't bridged either way, so removing a return bus doesn't wire its senders to the
+    /// output. The node's own edges are left for the caller to remove.
+    fn bridge(&mut self, id: NodeId) {
+        let count = self.edges.len();
+        for i in 0..count {
+            let into = self.edges[i];
+            if into.to != id || into.to_port != 0 || is_send(&self.sends, &into) { continue; }
+            for j in 0..count {
+                let out = self.edges[j];
+                if out.from != id || out.from_port != 0 || is_send(&self.sends, &out) { continue; }
+                let bridge = Edge { from: into.from, from_port: into.from_port, to: out.to, to_port: out.to_port };
+                if !self.edges.contains(&bridge) && self.edges.len() < self.edges.capacity() {
+                    self.edges.push(bridge);
+                }
+            }
+        }
+    }
+
+    /// Puts `node` in the place of node `id`, keeping its connections (those to ports the new node doesn't have,
+    /// MIDI included, are dropped). The new node should already be prepared. Returns the old node, or the new one if it can't
+    /// be placed, so the caller controls where either is dropped.
+    pub fn replace_node(&mut self, id: NodeId, node: Box<dyn AudioNode>) -> Result<Box<dyn AudioNode>, (Box<dyn AudioNode>, &'static str)> {
+        let new_id = node.get_id();
+        if new_id != id { self.finish_removal(new_id); }
+        let Some(slot) = self.slot(id) else { return Err((node, "no such node")) };
+        if new_id != id && self.resolve(new_id).is_some() {
+            return Err((node, "node id is already taken or reserved"));
+        }
+        let (inputs, outputs) = (node.input_ports() as PortId, node.output_ports() as PortId);
+        let (accepts_midi, produces_midi) = (node.accepts_midi(), node.produces_midi());
+        let old = self.nodes[slot].node.replace_inner(node);
+        // The oversampling factor is kept with the slot's other host controls.
+        self.apply_oversampling(slot);
+        self.nodes[slot].latency = 0;
+        self.nodes[slot].pending.clear();
+        self.nodes[slot].smoother.clear();
+        if let Some(frozen) = self.nodes[slot].frozen.take() { self.thawed.push(frozen); }
+        if self.nodes[slot].node.handles_events() { self.nodes[slot].queue_transport(self.transport); }
+        for edge in self.edges.iter_mut() {
+            if edge.from == id { edge.from = new_id; }
+            if edge.to == id { edge.to = new_id; }
+        }
+        for send in self.sends.iter_mut() {
+            if send.from == id { send.from = new_id; }
+        }
+        self.sends.retain(|s| s.bus != id || bus::bus_index(new_id).is_some());
+        for send in self.sends.iter_mut() {
+            if send.bus == id { send.bus = new_id; }
+        }
+        let has_port = |port: PortId, ports: PortId, midi: bool| if port == MIDI_PORT { midi } else { port < ports };
+        self.edges.retain(|e| {
+            !(e.from == new_id && !has_port(e.from_port, outputs, produces_midi))
+                && !(e.to == new_id && !has_port(e.to_port, inputs, accepts_midi))
+        });
+        self.reschedule();
+        Ok(old)
+    }
+
+    /// Removes every node, handing each to `reap`, and routes the input straight to the output again.
+    pub fn clear(&mut self, mut reap: impl FnMut(Box<dyn AudioNode>)) {
+        while let Some(mut removed) = self.nodes.pop() {
+            self.spare_buffers.push(removed.buffer);
+            self.spare_histories.push((removed.history, removed.dry_history));
+            self.spare_events.push((removed.midi_out, removed.pending));
+            self.spare_smoothers.push(removed.smoother);
+            if let Some(frozen) = removed.frozen { reap(frozen); }
+            if let Some(outgoing) = removed.outgoing { reap(outgoing); }
+            reap(removed.node.take_inner());
+            self.spare_oversamplers.push(removed.node);
+        }
+        self.edges.clear();
+        self.sends.clear();
+        self.edges.push(Edge { from: GRAPH_INPUT, from_port: 0, to: GRAPH_OUTPUT, to_port: 0 });
+        self.reschedule();
+    }
+
+    /// Connects `from:from_port` to `to:to_port`. Refused if either end doesn't exist, the port is out of range,
+    /// the edge already exists, or it would create a cycle. `MIDI_PORT` on both ends routes MIDI instead of audio.
+    pub fn connect(&mut self, from: NodeId, from_port: PortId, to: NodeId, to_port: PortId) -> Result<(), &'static str> {
+        let edge = Edge { from, from_port, to, to_port };
+        if matches!(from, GRAPH_OUTPUT | GRAPH_MIDI_OUTPUT) || matches!(to, GRAPH_INPUT | GRAPH_CAPTURE | GRAPH_MIDI_INPUT) {
+            return Err("the graph output has no outputs and the graph inputs no inputs");
+        }
+        if (from_port == MIDI_PORT) != (to_port == MIDI_PORT) {
+            return Err("MIDI ports only connect to MIDI ports");
+        }
+        self.check_port(from, from_port, false)?;
+        self.check_port(to, to_port, true)?;
+        if self.edges.contains(&edge) {
+            return Err("already connected");
+        }
+        if self.edges.len() == self.edges.capacity() {
+            return Err("no free connection slots");
+        }
+        self.edges.push(edge);
+        if !self.reschedule() {
+            self.edges.pop();
+            self.reschedule();
+            return Err("connection would create a cycle");
+        }
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self, from: NodeId, from_port: PortId, to: NodeId, to_port: PortId) -> Result<(), &'static str> {
+        let edge = Edge { from, from_port, to, to_port };
+        let index = self.edges.iter().position(|e| *e == edge).ok_or("not connected")?;
+        self.edges.remove(index);
+        if from_port == 0 && to_port == 0 { self.sends.retain(|s| !(s.from == from && s.bus == to)); }
+        self.reschedule();
+        Ok(())
+    }
+
+    /// Runs the graph over `io`, which holds the graph input on entry and the graph output on return
+    /// (interleaved, `channels` wide). `capture` is the matching live input; missing samples are silence.
+    /// `midi` (frames relative to the start of `io`) comes from `GRAPH_MIDI_INPUT`, and goes to every node that
+    /// accepts MIDI but has no MIDI connections; MIDI reaching `GRAPH_MIDI_OUTPUT` is kept in `midi_output`.
+    /// `params` (in time order, frames as for `midi`) reach nodes that handle events at their frame, and other
+    /// nodes through `set_param` before the block they fall in. Taps, usage, meters and the feeds of extra
+    /// outputs are fed per node when given.
+    pub fn process(&mut self, io: &mut [f32], capture: &[f32], midi: &[MidiEvent], params: &[ParamChange], position: u64, taps: Option<&TapSet>, usage: Option<&UsageMeter>, meters: Option<&LevelMeters>, feeds: Option<&OutputFeeds>) {
+        let block_len = self.block_frames * self.channels;
+        let mut offset = 0;
+        self.midi_output.clear();
+        while offset < io.len() {
+            let len = (io.len() - offset).min(block_len);
+            let chunk = &mut io[offset..offset + len];
+            let chunk_position = position + (offset / self.channels) as u64;
+            let captured = capture.get(offset..).unwrap_or(&[]);
+            let captured = &captured[..captured.len().min(len)];
+            self.capture[..captured.len()].copy_from_slice(captured);
+            self.capture[captured.len()..len].fill(0.0);
+            let (first, frames) = ((offset / self.channels) as u32, (len / self.channels) as u32);
+            self.block_midi.clear();
+            for event in midi.iter().filter(|e| e.frame >= first && e.frame < first + frames) {
+                if self.block_midi.len() == self.block_midi.capacity() { break; }
+                self.block_midi.push(event.at_frame(event.frame - first));
+            }
+            if self.notes_off { midi::prepend_notes_off(&mut self.block_midi); }
+            self.block_params.clear();
+            for change in params.iter().filter(|c| c.frame >= first && c.frame < first + frames) {
+                let handles_events = self.node(change.node_id).is_some_and(|n| n.handles_events());
+                if handles_events && !strip::is_host_param(change.param_id) {
+                    if self.block_params.len() == self.block_params.capacity() { break; }
+                    self.block_params.push(change.at_frame(change.frame - first));
+                } else {
+                    let _ = self.apply_param(change.node_id, change.param_id, &change.value.to_le_bytes(), false);
+                }
+            }
+            self.process_block(chunk, chunk_position, taps, usage, meters, feeds);
+            self.collect_midi_output(first);
+            if self.transport.playing { self.transport.position += frames as u64; }
+            offset += len;
+        }
+    }
+
+    fn process_block(&mut self, io: &mut [f32], position: u64, taps: Option<&TapSet>, usage: Option<&UsageMeter>, meters: Option<&LevelMeters>, feeds: Option<&OutputFeeds>) {
+        let len = io.len();
+        let taps = taps.filter(|t| !t.is_empty());
+        let feeds = feeds.filter(|f| !f.is_empty());
+        let period = Duration::from_secs_f64((len / self.channels) as f64 / self.sample_rate.max(1) as f64);
+        self.input[..len].copy_from_slice(io);
+        self.block_len = len;
+        self.input_history.push(&self.input[..len]);
+        self.capture_history.push(&self.capture[..len]);
+        if let Some(taps) = taps {
+            taps.measure(TapPoint::Input, io, position, 0);
+            taps.measure(TapPoint::Capture, &self.capture[..len], position, 0);
+        }
+
+        for step in 0..self.order.len() {
+            let slot = self.order[step];
+            // Taken out so the sources can be read while mixing; `take` leaves an empty Vec and doesn't allocate.
+            let mut buffer = std::mem::take(&mut self.nodes[slot].buffer);
+            let mix = &mut buffer[..len];
+            // Sidechains line up with the main input, whichever is slower.
+            let latency = self.input_latency(slot);
+            self.mix_inputs(slot, 0, latency, mix);
+            if !self.nodes[slot].strip.skips_node() {
+                let mut sidechain = std::mem::take(&mut self.sidechain);
+                for port in 1..self.nodes[slot].node.input_ports() as PortId {
+                    if !self.resolved.iter().any(|&(_, to, p, _)| to == slot && p == port) { continue; }
+                    self.mix_inputs(slot, port, latency, &mut sidechain[..len]);
+                    self.nodes[slot].node.set_sidechain_input(port, &sidechain[..len]);
+                }
+                self.sidechain = sidechain;
+                self.nodes[slot].node.set_transport(&self.transport);
+                let (accepts_midi, handles_events) = (self.nodes[slot].node.accepts_midi(), self.nodes[slot].node.handles_events());
+                if accepts_midi || handles_events {
+                    let mut routed_midi = std::mem::take(&mut self.midi_in);
+                    let routed = self.gather_midi(slot, &mut routed_midi);
+                    if routed && self.notes_off { midi::prepend_notes_off(&mut routed_midi); }
+                    let midi = if !accepts_midi { &[][..] } else if routed { &routed_midi[..] } else { &self.block_midi[..] };
+                    let node = &mut self.nodes[slot];
+                    if handles_events {
+                        // Queued changes all fall on frame 0, so they go first and the MIDI keeps its order.
+                        self.events.clear();
+                        for event in node.pending.drain(..) {
+                            self.events.push(match event.kind {
+                                EventKind::Transport(_) => NodeEvent::transport(event.frame, self.transport),
+                                _ => event,
+                            });
+                        }
+                        for &event in midi.iter().take(self.events.capacity() - self.events.len()) {
+                            self.events.push(NodeEvent::midi(event));
+                        }
+                        let id = node.node.get_id();
+                        for change in self.block_params.iter().filter(|c| c.node_id == id) {
+                            if !events::insert_in_order(&mut self.events, NodeEvent::param(change.frame, change.param_id, change.value)) { break; }
+                        }
+                    } else {
+                        node.node.set_midi_input(midi);
+                    }
+                    self.midi_in = routed_midi;
+                }
+            }
+
+            let node = &mut self.nodes[slot];
+            node.midi_out.clear();
+            if node.smoother.is_moving() {
+                let target = &mut node.node;
+                node.smoother.advance(len / self.channels, |param_id, value| target.set_param(param_id, &value.to_le_bytes()));
+            }
+            node.strip.apply_trim(mix, self.channels);
+            // The dry signal is delayed by the node's own latency, so mixing and bypassing stay aligned and a
+            // bypassed node keeps its latency instead of shifting everything after it.
+            let own = node.node.latency_samples().min(self.max_compensation);
+            let delay = own * self.channels;
+            if delay > 0 { node.dry_history.push(mix); }
+            if node.strip.skips_node() {
+                if delay > 0 {
+                    mix.fill(0.0);
+                    node.dry_history.add_delayed(mix, delay, GainRamp::UNITY);
+                }
+                // Nothing left to crossfade from.
+                if let Some(outgoing) = node.outgoing.take() {
+                    if self.retired.len() < self.retired.capacity() { self.retired.push(outgoing); }
+                }
+            } else {
+                let needs_dry = node.strip.needs_dry();
+                if needs_dry {
+                    let dry = &mut self.dry[..len];
+                    if delay > 0 {
+                        dry.fill(0.0);
+                        node.dry_history.add_delayed(dry, delay, GainRamp::UNITY);
+                    } else {
+                        dry.copy_from_slice(mix);
+                    }
+                }
+                if node.outgoing.is_some() { self.fading[..len].copy_from_slice(mix); }
+                let start = Instant::now();
+                if let Some(frozen) = node.frozen.as_mut() { frozen.set_transport(&self.transport); }
+                match node.frozen.as_mut().filter(|f| f.is_complete()) {
+                    Some(frozen) => frozen.process(mix),
+                    None => {
+                        if node.node.handles_events() {
+                            node.node.process_events(mix, &self.events);
+                        } else if node.node.is_planar() {
+                            self.planar.deinterleave(mix);
+                            node.node.process_planar(&mut self.planar);
+                            self.planar.interleave(mix);
+                        } else {
+                            node.node.process(mix);
+                        }
+                        if node.node.produces_midi() {
+                            node.node.take_midi_output(&mut MidiWriter::new(&mut node.midi_out));
+                        }
+                        if let Some(frozen) = node.frozen.as_mut() { frozen.record(mix); }
+                    }
+                }
+                if let Some(outgoing) = node.outgoing.as_mut() {
+                    // The replaced node gets the same input, without events, and fades out as its successor fades in.
+                    let old = &mut self.fading[..len];
+                    if outgoing.handles_events() {
+                        outgoing.process_events(old, &[]);
+                    } else if outgoing.is_planar() {
+                        self.planar.deinterleave(old);
+                        outgoing.process_planar(&mut self.planar);
+                        self.planar.interleave(old);
+                    } else {
+                        outgoing.process(old);
+                    }
+                    let ramp = node.strip.ramp_frames();
+                    for (new, old) in mix.chunks_mut(self.channels).zip(old.chunks(self.channels)) {
+                        node.crossfaded += 1;
+                        let t = (node.crossfaded as f32 / ramp).min(1.0);
+                        for (n, o) in new.iter_mut().zip(old) {
+                            *n = o + (*n - o) * t;
+                        }
+                    }
+                    if node.crossfaded as f32 >= ramp {
+                        let done = node.outgoing.take();
+                        if let Some(done) = done.filter(|_| self.retired.len() < self.retired.capacity()) { self.retired.push(done); }
+                    }
+                }
+                if let Some(usage) = usage { usage.record_node(step, node.node.get_id(), start.elapsed(), period); }
+                if needs_dry { node.strip.finish(&self.dry[..len], mix, self.channels); }
+            }
+            node.latency = latency + own;
+            node.strip.apply_output(mix, self.channels);
+            node.history.push(mix);
+            if let Some(taps) = taps { taps.measure_node(node.node.get_id(), mix, position, node.latency); }
+            if let Some(feeds) = feeds { feeds.feed(OutputSource::Node(node.node.get_id()), mix); }
+            if let Some(meters) = meters { meters.measure_node(step, node.node.get_id(), mix, self.layout, self.sample_rate); }
+            node.buffer = buffer;
+        }
+
+        let latency = self.input_latency(OUTPUT_SLOT);
+        self.mix_inputs(OUTPUT_SLOT, 0, latency, io);
+        for send in self.sends.iter_mut() {
+            send.applied = send.level;
+        }
+        self.notes_off = false;
+        if let Some(meters) = meters {
+            meters.truncate(self.order.len());
+            meters.measure_master(io, self.layout, self.sample_rate);
+        }
+        // Nodes that faded out during the block leave now, so the next one runs the reconnected path.
+        while let Some(slot) = self.nodes.iter().position(|n| n.removing && n.strip.skips_node()) {
+            let id = self.nodes[slot].node.get_id();
+            if let Ok(node) = self.remove_node(id) { self.retire(node); }
+        }
+    }
+
+    /// Merges the MIDI connected to `slot` into `events`. Returns false if `slot` has no MIDI connections.
+    fn gather_midi(&self, slot: usize, events: &mut Vec<MidiEvent>) -> bool {
+        events.clear();
+        let mut routed = false;
+        for &(from, to, port, _) in self.resolved.iter() {
+            if to != slot || port != MIDI_PORT { continue; }
+            routed = true;
+            for event in self.midi_source(from) {
+                midi::insert_in_order(events, *event);
+            }
+        }
+        routed
+    }
+
+    /// Adds the MIDI reaching `GRAPH_MIDI_OUTPUT` in the block just processed, which starts at frame `first`.
+    fn collect_midi_output(&mut self, first: u32) {
+        let mut events = std::mem::take(&mut self.midi_in);
+        if self.gather_midi(MIDI_OUTPUT_SLOT, &mut events) {
+            for event in events.iter() {
+                if self.midi_output.len() == self.midi_output.capacity() { break; }
+                self.midi_output.push(event.at_frame(event.frame + first));
+            }
+        }
+        self.midi_in = events;
+    }
+
+    fn midi_source(&self, slot: usize) -> &[MidiEvent] {
+        match slot {
+            MIDI_INPUT_SLOT => &self.block_midi,
+            _ => &self.nodes[slot].midi_out,
+        }
+    }
+
+    /// Latency of the slowest audio path into any of `slot`'s inputs.
+    fn input_latency(&self, slot: usize) -> usize {
+        self.resolved.iter().filter(|&&(_, to, port, _)| to == slot && port != MIDI_PORT).map(|&(from, _, _, _)| self.latency_of(from)).max().unwrap_or(0)
+    }
+
+    /// Sums everything connected to input `port` of `slot` into `mix`, delaying each source so it arrives with
+    /// `latency` and scaling sends by their level.
+    fn mix_inputs(&self, slot: usize, port: PortId, latency: usize, mix: &mut [f32]) {
+        mix.fill(0.0);
+        for &(from, to, to_port, send) in self.resolved.iter() {
+            if to != slot || to_port != port { continue; }
+            let gain = match self.sends.get(send) {
+                Some(send) => GainRamp { from: send.applied, to: send.level, frames: mix.len() / self.channels, channels: self.channels },
+                None => GainRamp::UNITY,
+            };
+            let delay = (latency - self.latency_of(from)).min(self.max_compensation) * self.channels;
+            if delay == 0 {
+                add_ramped(mix, self.source(from, mix.len()), gain, 0);
+            } else {
+                self.history(from).add_delayed(mix, delay, gain);
+            }
+        }
+    }
+
+    fn source(&self, slot: usize, len: usize) -> &[f32] {
+        match slot {
+            INPUT_SLOT => &self.input[..len],
+            CAPTURE_SLOT => &self.capture[..len],
+            _ => &self.nodes[slot].buffer[..len],
+        }
+    }
+
+    fn history(&self, slot: usize) -> &DelayLine {
+        match slot {
+            INPUT_SLOT => &self.input_history,
+            CAPTURE_SLOT => &self.capture_history,
+            _ => &self.nodes[slot].history,
+        }
+    }
+
+    fn latency_of(&self, slot: usize) -> usize {
+        if slot == INPUT_SLOT || slot == CAPTURE_SLOT { 0 } else { self.nodes[slot].latency }
+    }
+
+    fn slot(&self, id: NodeId) -> Option<usize> {
+        self.nodes.iter().position(|n| n.node.get_id() == id)
+    }
+
+    fn resolve(&self, id: NodeId) -> Option<usize> {
+        match id {
+            GRAPH_INPUT => Some(INPUT_SLOT),
+            GRAPH_CAPTURE => Some(CAPTURE_SLOT),
+            GRAPH_OUTPUT => Some(OUTPUT_SLOT),
+            GRAPH_MIDI_INPUT => Some(MIDI_INPUT_SLOT),
+            GRAPH_MIDI_OUTPUT => Some(MIDI_OUTPUT_SLOT),
+            _ => self.slot(id),
+        }
+    }
+
+    fn check_port(&self, id: NodeId, port: PortId, input: bool) -> Result<(), &'static str> {
+        if port == MIDI_PORT {
+            return match id {
+                GRAPH_MIDI_INPUT | GRAPH_MIDI_OUTPUT => Ok(()),
+                GRAPH_INPUT | GRAPH_OUTPUT | GRAPH_CAPTURE => Err("no such port"),
+                _ => {
+                    let node = self.node(id).ok_or("no such node")?;
+                    if input && node.accepts_midi() || !input && node.produces_midi() { Ok(()) } else { Err("node has no MIDI port") }
+                }
+            };
+        }
+        let ports = match id {
+            GRAPH_INPUT | GRAPH_OUTPUT | GRAPH_CAPTURE => 1,
+            GRAPH_MIDI_INPUT | GRAPH_MIDI_OUTPUT => 0,
+            _ => {
+                let node = self.node(id).ok_or("no such node")?;
+                if input { node.input_ports() } else { node.output_ports() }
+            }
+        };
+        if (port as usize) < ports { Ok(()) } else { Err("no such port") }
+    }
+
+    /// Rebuilds the resolved edges and the processing order (Kahn's algorithm over the preallocated scratch).
+    /// Returns false if the edges contain a cycle.
+    fn reschedule(&mut self) -> bool {
+        self.revision = self.revision.wrapping_add(1);
+        self.resolved.clear();
+        for edge in self.edges.iter() {
+            if let (Some(from), Some(to)) = (self.resolve(edge.from), self.resolve(edge.to)) {
+                let send = if edge.from_port == 0 && edge.to_port == 0 {
+                    self.sends.iter().position(|s| s.from == edge.from && s.bus == edge.to).unwrap_or(NO_SEND)
+                } else {
+                    NO_SEND
+                };
+                self.resolved.push((from, to, edge.to_port, send));
+            }
+        }
+
+        let count = self.nodes.len();
+        self.indegree[..count].fill(0);
+        for &(from, to, _, _) in self.resolved.iter() {
+            if from < count && to < count { self.indegree[to] += 1; }
+        }
+        self.order.clear();
+        for slot in 0..count {
+            if self.indegree[slot] == 0 { self.order.push(slot); }
+        }
+        let mut next = 0;
+        while next < self.order.len() {
+            let slot = self.order[next];
+            next += 1;
+            for &(from, to, _, _) in self.resolved.iter() {
+                if from == slot && to < count {
+                    self.indegree[to] -= 1;
+                    if self.indegree[to] == 0 { self.order.push(to); }
+                }
+            }
+        }
+        self.order.len() == count
+    }
+}
+
+impl GraphNode {
+    /// Applies the queued parameter changes directly, making room in `pending` without reordering them.
+    fn apply_pending_params(&mut self) {
+        for event in self.pending.iter() {
+            if let EventKind::Param { id, value } = event.kind {
+                self.node.set_param(id, &value.to_le_bytes());
+            }
+        }
+        self.pending.retain(|e| !matches!(e.kind, EventKind::Param { .. }));
+    }
+
+    /// Queues `transport` for the next block, replacing a state still waiting there.
+    fn queue_transport(&mut self, transport: TransportInfo) {
+        self.pending.retain(|e| !matches!(e.kind, EventKind::Transport(_)));
+        if self.pending.len() == self.pending.capacity() { self.apply_pending_params(); }
+        self.pending.push(NodeEvent::transport(0, transport));
+    }
+}
+
+/// Ring of a signal's most recent samples, read back to delay it. Preallocated; never grows.
+#[derive(Default)]
+struct DelayLine {
+    samples: Vec<f32>,
+    write: usize,
+}
+
+impl DelayLine {
+    fn resize(&mut self, len: usize) {
+        self.samples.clear();
+        self.samples.resize(len, 0.0);
+        self.write = 0;
+    }
+
+    fn clear(&mut self) {
+        self.samples.fill(0.0);
+    }
+
+    /// Appends a block. Blocks must not be longer than the line.
+    fn push(&mut self, block: &[f32]) {
+        let len = self.samples.len();
+        if len == 0 { return; }
+        let first = (len - self.write).min(block.len());
+        self.samples[self.write..self.write + first].copy_from_slice(&block[..first]);
+        self.samples[..block.len() - first].copy_from_slice(&block[first..]);
+        self.write = (self.write + block.len()) % len;
+    }
+
+    /// Adds the last pushed block, `delay` samples late and scaled by `gain`, into `dst` (which is as long as
+    /// that block).
+    fn add_delayed(&self, dst: &mut [f32], delay: usize, gain: GainRamp) {
+        let len = self.samples.len();
+        if dst.len() + delay > len { return; }
+        let start = (self.write + 2 * len - dst.len() - delay) % len;
+        let first = (len - start).min(dst.len());
+        let (head, tail) = dst.split_at_mut(first);
+        add_ramped(head, &self.samples[start..start + first], gain, 0);
+        add_ramped(tail, &self.samples, gain, first);
+    }
+}
+
+/// Gain moving linearly from `from` to `to` over a block of `frames`, so send level changes don't click.
+#[derive(Clone, Copy)]
+struct GainRamp {
+    from: f32,
+    to: f32,
+    frames: usize,
+    channels: usize,
+}
+
+impl GainRamp {
+    const UNITY: GainRamp = GainRamp { from: 1.0, to: 1.0, frames: 1, channels: 1 };
+
+    fn at(&self, sample: usize) -> f32 {
+        let frame = sample / self.channels.max(1);
+        self.from + (self.to - self.from) * (frame + 1).min(self.frames) as f32 / self.frames.max(1) as f32
+    }
+}
+
+/// Adds `src` into `dst` scaled by `gain`, where `dst[0]` is sample `offset` of the ramp's block.
+fn add_ramped(dst: &mut [f32], src: &[f32], gain: GainRamp, offset: usize) {
+    if gain.from == 1.0 && gain.to == 1.0 { return simd::mix(dst, src); }
+    if gain.from == gain.to { return simd::mix_gain(dst, src, gain.to); }
+    for (i, (d, s)) in dst.iter_mut().zip(src).enumerate() {
+        *d += s * gain.at(offset + i);
+    }
+}
+
+/// Whether `edge` carries one of `sends`.
+fn is_send(sends: &[Send], edge: &Edge) -> bool {
+    edge.from_port == 0 && edge.to_port == 0 && sends.iter().any(|s| s.from == edge.from && s.bus == edge.to)
+}
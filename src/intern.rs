@@ -0,0 +1,60 @@
+// intern.rs
+
+/* Name Interning Table */
+
+#![allow(warnings)]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
+
+/// Small handle for an interned name. `0` is always the empty string.
+pub type NameId = u32;
+
+pub const EMPTY_NAME: NameId = 0;
+
+/// Lookup side: only touched when interning, never from the audio thread.
+static LOOKUP: Lazy<Mutex<HashMap<Arc<str>, NameId>>> = Lazy::new(|| {
+    let mut map = HashMap::new();
+    map.insert(Arc::from(""), EMPTY_NAME);
+    Mutex::new(map)
+});
+
+/// Resolve side: an immutable snapshot swapped atomically, so reads never lock or allocate.
+static NAMES: Lazy<ArcSwap<Vec<Arc<str>>>> = Lazy::new(|| {
+    ArcSwap::from_pointee(vec![Arc::from("")])
+});
+
+/// Returns the handle for `name`, adding it to the table if needed.
+/// Allocates on first use of a name, so call it from the GUI/control side, not the audio callback.
+pub fn intern(name: &str) -> NameId {
+    let mut lookup = LOOKUP.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(&id) = lookup.get(name) {
+        return id;
+    }
+
+    let shared: Arc<str> = Arc::from(name);
+    let mut names = Vec::clone(&NAMES.load());
+    let id = names.len() as NameId;
+    names.push(Arc::clone(&shared));
+    NAMES.store(Arc::new(names));
+    lookup.insert(shared, id);
+    id
+}
+
+/// Looks up a handle without interning. Takes the lookup lock, so not for the audio thread.
+pub fn find(name: &str) -> Option<NameId> {
+    LOOKUP.lock().ok()?.get(name).copied()
+}
+
+/// Runs `f` with the name behind `id`. Wait-free and allocation-free, safe on the audio thread.
+pub fn with_name<R>(id: NameId, f: impl FnOnce(&str) -> R) -> Option<R> {
+    let names = NAMES.load();
+    names.get(id as usize).map(|name| f(name))
+}
+
+/// Shared copy of the name behind `id`.
+pub fn resolve(id: NameId) -> Option<Arc<str>> {
+    NAMES.load().get(id as usize).cloned()
+}
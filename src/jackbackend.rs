@@ -0,0 +1,222 @@
+// jackbackend.rs
+
+/* Native JACK Backend */
+
+#![allow(warnings)]
+
+use std::sync::Arc;
+
+use jack::{AsyncClient, AudioIn, AudioOut, Client, ClientOptions, ClientStatus, Control, NotificationHandler, Port, PortFlags, ProcessHandler, ProcessScope, TransportState};
+
+use crate::dspengine::Transport;
+use crate::xrun::XrunCounters;
+
+/// Longest stretch of a JACK period rendered in one piece; longer periods are rendered in several.
+pub const MAX_PERIOD_FRAMES: usize = 8192;
+/// JACK's port type for audio.
+const AUDIO_TYPE: &str = "32 bit float mono audio";
+
+/// How the engine presents itself to JACK.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JackOptions {
+    /// Client name; JACK makes it unique if it is taken.
+    pub client_name: String,
+    /// Connect the ports to the physical playback and capture ports on start, where no connections were made
+    /// with `DspEngine::jack_connect`.
+    pub autoconnect: bool,
+    /// Follow the JACK transport: start, stop, relocate, and take its tempo and time signature when a timebase
+    /// master provides them. The engine never moves the JACK transport itself.
+    pub follow_transport: bool,
+}
+
+impl Default for JackOptions {
+    fn default() -> Self {
+        JackOptions { client_name: "OpenTune".to_string(), autoconnect: true, follow_transport: true }
+    }
+}
+
+/// Renders one interleaved stretch of the outputs.
+pub type RenderFn = Box<dyn FnMut(&mut [f32]) + Send>;
+/// Takes one interleaved stretch of the inputs.
+pub type CaptureFn = Box<dyn FnMut(&[f32]) + Send>;
+/// Called when the server shuts down or throws the client out, with the reason.
+pub type ShutdownFn = Box<dyn FnMut(&str) + Send + Sync>;
+
+/// Keeps the engine transport in step with JACK's.
+struct TransportFollower {
+    transport: Arc<Transport>,
+    engine_rate: u32,
+    jack_rate: u32,
+}
+
+impl TransportFollower {
+    /// Called at the start of each period of `frames`. Realtime-safe.
+    fn sync(&self, client: &Client, frames: usize) {
+        let Ok(jack) = client.transport().query() else { return };
+        let info = self.transport.info();
+        match jack.state {
+            TransportState::Rolling if !info.playing => self.transport.play(),
+            TransportState::Stopped if info.playing => self.transport.stop(),
+            _ => {}
+        }
+        // The engine renders in its own blocks, so it may run up to a period apart; only real jumps relocate.
+        let scale = |f: u64| f * self.engine_rate as u64 / self.jack_rate.max(1) as u64;
+        let frame = scale(jack.pos.frame() as u64);
+        if frame.abs_diff(info.position) > scale(2 * frames as u64) {
+            self.transport.locate(frame);
+        }
+        if let Some(bbt) = jack.pos.bbt() {
+            if (bbt.bpm as f32 - info.tempo).abs() > 0.001 { let _ = self.transport.set_tempo(bbt.bpm as f32); }
+            let (beats, unit) = (bbt.sig_num as u16, bbt.sig_denom as u16);
+            if (beats, unit) != (info.beats_per_bar, info.beat_unit) { let _ = self.transport.set_time_signature(beats, unit); }
+        }
+    }
+}
+
+struct Process {
+    outputs: Vec<Port<AudioOut>>,
+    inputs: Vec<Port<AudioIn>>,
+    /// Outputs, then inputs, interleaved for the engine.
+    rendered: Vec<f32>,
+    captured: Vec<f32>,
+    render: RenderFn,
+    capture: Option<CaptureFn>,
+    follower: Option<TransportFollower>,
+}
+
+impl ProcessHandler for Process {
+    fn process(&mut self, client: &Client, scope: &ProcessScope) -> Control {
+        let frames = scope.n_frames() as usize;
+        if let Some(follower) = self.follower.as_ref() { follower.sync(client, frames); }
+        let mut start = 0;
+        while start < frames {
+            let len = (frames - start).min(MAX_PERIOD_FRAMES);
+            if let Some(capture) = self.capture.as_mut() {
+                let channels = self.inputs.len();
+                let block = &mut self.captured[..len * channels];
+                for (c, port) in self.inputs.iter().enumerate() {
+                    for (i, &s) in port.as_slice(scope)[start..start + len].iter().enumerate() {
+                        block[i * channels + c] = s;
+                    }
+                }
+                capture(block);
+            }
+            let channels = self.outputs.len();
+            let block = &mut self.rendered[..len * channels];
+            (self.render)(block);
+            for (c, port) in self.outputs.iter_mut().enumerate() {
+                for (i, s) in port.as_mut_slice(scope)[start..start + len].iter_mut().enumerate() {
+                    *s = block[i * channels + c];
+                }
+            }
+            start += len;
+        }
+        Control::Continue
+    }
+}
+
+struct Notifications {
+    on_shutdown: ShutdownFn,
+    xruns: Arc<XrunCounters>,
+}
+
+impl NotificationHandler for Notifications {
+    fn shutdown(&mut self, _status: ClientStatus, reason: &str) {
+        (self.on_shutdown)(reason);
+    }
+
+    fn xrun(&mut self, _: &Client) -> Control {
+        self.xruns.underrun();
+        Control::Continue
+    }
+}
+
+/// A running JACK client with one output port per engine channel (`out_1`, `out_2`, ...) and, when capturing,
+/// one input port per channel (`in_1`, ...). Dropping it deactivates the client and closes it.
+pub struct JackStream {
+    client: AsyncClient<Notifications, Process>,
+    outputs: Vec<String>,
+    inputs: Vec<String>,
+}
+
+impl JackStream {
+    /// Connects to the running JACK server (never starts one). Returns the client and the server's sample rate,
+    /// which the engine needs to set up its rendering before `start`.
+    pub fn connect(options: &JackOptions) -> Result<(Client, u32), String> {
+        let (client, _) = Client::new(&options.client_name, ClientOptions::NO_START_SERVER)
+            .map_err(|e| format!("Failed to connect to the JACK server: {}", e))?;
+        let rate = client.sample_rate() as u32;
+        Ok((client, rate))
+    }
+
+    /// Registers `channels` output ports, and `inputs` input ports when `capture` is given, and starts
+    /// processing. `transport` is followed per `options`; `engine_rate` is the rate its positions count in.
+    pub fn start(client: Client, options: &JackOptions, channels: usize, inputs: usize, render: RenderFn, capture: Option<CaptureFn>, transport: Arc<Transport>, engine_rate: u32, xruns: Arc<XrunCounters>, on_shutdown: ShutdownFn) -> Result<Self, String> {
+        let inputs = if capture.is_some() { inputs } else { 0 };
+        let register_err = |e: jack::Error| format!("Failed to register a JACK port: {}", e);
+        let output_ports = (1..=channels).map(|n| client.register_port(&format!("out_{}", n), AudioOut::default())).collect::<Result<Vec<_>, _>>().map_err(register_err)?;
+        let input_ports = (1..=inputs).map(|n| client.register_port(&format!("in_{}", n), AudioIn::default())).collect::<Result<Vec<_>, _>>().map_err(register_err)?;
+        let outputs = output_ports.iter().filter_map(|p| p.name().ok()).collect();
+        let input_names = input_ports.iter().filter_map(|p| p.name().ok()).collect();
+        let jack_rate = client.sample_rate() as u32;
+        let follower = options.follow_transport.then(|| TransportFollower { transport, engine_rate, jack_rate });
+        let process = Process {
+            rendered: vec![0.0; MAX_PERIOD_FRAMES * channels],
+            captured: vec![0.0; MAX_PERIOD_FRAMES * inputs],
+            outputs: output_ports,
+            inputs: input_ports,
+            render,
+            capture,
+            follower,
+        };
+        let client = client.activate_async(Notifications { on_shutdown, xruns }, process).map_err(|e| format!("Failed to activate the JACK client: {}", e))?;
+        Ok(JackStream { client, outputs, inputs: input_names })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.client.as_client().sample_rate() as u32
+    }
+
+    /// The client's name as JACK made it unique.
+    pub fn client_name(&self) -> &str {
+        self.client.as_client().name()
+    }
+
+    /// Full names of this client's output ports, in channel order.
+    pub fn output_ports(&self) -> &[String] {
+        &self.outputs
+    }
+
+    /// Full names of this client's input ports, in channel order; empty unless capturing.
+    pub fn input_ports(&self) -> &[String] {
+        &self.inputs
+    }
+
+    /// Full name of this client's port `short` (`out_1`, `in_2`, ...).
+    pub fn port(&self, short: &str) -> Option<&String> {
+        self.outputs.iter().chain(self.inputs.iter()).find(|name| name.rsplit(':').next() == Some(short))
+    }
+
+    /// Audio ports of other clients: with `inputs`, those that take audio (where the outputs can go), otherwise
+    /// those that produce it.
+    pub fn other_ports(&self, inputs: bool) -> Vec<String> {
+        let flags = if inputs { PortFlags::IS_INPUT } else { PortFlags::IS_OUTPUT };
+        let prefix = format!("{}:", self.client_name());
+        self.client.as_client().ports(None, Some(AUDIO_TYPE), flags).into_iter().filter(|p| !p.starts_with(&prefix)).collect()
+    }
+
+    /// Physical ports, for autoconnecting: with `inputs` the playback ports, otherwise the capture ports.
+    pub fn physical_ports(&self, inputs: bool) -> Vec<String> {
+        let direction = if inputs { PortFlags::IS_INPUT } else { PortFlags::IS_OUTPUT };
+        self.client.as_client().ports(None, Some(AUDIO_TYPE), direction | PortFlags::IS_PHYSICAL)
+    }
+
+    /// Connects two ports by full name, output first.
+    pub fn connect_ports(&self, from: &str, to: &str) -> Result<(), String> {
+        self.client.as_client().connect_ports_by_name(from, to).map_err(|e| format!("Failed to connect {} to {}: {}", from, to, e))
+    }
+
+    pub fn disconnect_ports(&self, from: &str, to: &str) -> Result<(), String> {
+        self.client.as_client().disconnect_ports_by_name(from, to).map_err(|e| format!("Failed to disconnect {} from {}: {}", from, to, e))
+    }
+}
@@ -0,0 +1,155 @@
+// latency.rs
+
+/* End-to-End Output Latency */
+
+#![allow(warnings)]
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::dspapi::{Command, StatState, RESPONSE_QUEUE};
+use crate::sharedgraph::SharedGraph;
+use crate::threads::{self, ThreadRole};
+
+/// Marks `LatencyMeter::device_nanos` while no output stream is open.
+const NO_STREAM: u64 = u64::MAX;
+
+/// How long a sample pushed now takes to reach the speakers, split by where it waits. All figures are frames at
+/// the engine rate; a recording application shifts its takes back by `total_frames` to line them up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LatencyReport {
+    pub sample_rate: u32,
+    /// Pushed samples waiting in the playback ring buffer.
+    pub queued_frames: u64,
+    /// Rendered audio not yet played: the engine's block FIFO or resampler, then the device and its driver.
+    /// Zero while no output stream is open.
+    pub stream_frames: u64,
+    /// Delay through the rack: the slowest path through the graph (see `DspEngine::latency_samples`).
+    pub plugin_frames: u64,
+}
+
+impl LatencyReport {
+    pub fn total_frames(&self) -> u64 {
+        self.queued_frames + self.stream_frames + self.plugin_frames
+    }
+
+    pub fn total(&self) -> Duration {
+        Duration::from_secs_f64(self.total_frames() as f64 / self.sample_rate.max(1) as f64)
+    }
+}
+
+/// Latency figures the output callback keeps up to date. Written by the audio thread, read from anywhere.
+pub struct LatencyMeter {
+    sample_rate: AtomicU32,
+    queued: AtomicU64,
+    buffered: AtomicU64,
+    device_nanos: AtomicU64,
+}
+
+impl LatencyMeter {
+    pub fn new(sample_rate: u32) -> Self {
+        LatencyMeter {
+            sample_rate: AtomicU32::new(sample_rate),
+            queued: AtomicU64::new(0),
+            buffered: AtomicU64::new(0),
+            device_nanos: AtomicU64::new(NO_STREAM),
+        }
+    }
+
+    /// A stream opened at `sample_rate` (the engine's), expected to delay its output by `device` until the driver
+    /// reports better.
+    pub fn stream_opened(&self, sample_rate: u32, device: Duration) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+        self.buffered.store(0, Ordering::Relaxed);
+        self.set_device(device);
+    }
+
+    pub fn stream_closed(&self) {
+        self.buffered.store(0, Ordering::Relaxed);
+        self.device_nanos.store(NO_STREAM, Ordering::Relaxed);
+    }
+
+    /// Time from handing a buffer to the device until it is heard, as the driver reports it.
+    pub fn set_device(&self, device: Duration) {
+        self.device_nanos.store((device.as_nanos() as u64).min(NO_STREAM - 1), Ordering::Relaxed);
+    }
+
+    /// Called after every callback with the playback ring's fill and the rendered frames held back.
+    pub fn set_pending(&self, queued: usize, buffered: usize) {
+        self.queued.store(queued as u64, Ordering::Relaxed);
+        self.buffered.store(buffered as u64, Ordering::Relaxed);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    /// Whether an output stream is open, so the stream figures mean something.
+    pub fn is_open(&self) -> bool {
+        self.device_nanos.load(Ordering::Relaxed) != NO_STREAM
+    }
+
+    pub fn report(&self, queued_frames: u64, plugin_frames: u64, sample_rate: u32) -> LatencyReport {
+        let stream_frames = match self.device_nanos.load(Ordering::Relaxed) {
+            NO_STREAM => 0,
+            nanos => self.buffered.load(Ordering::Relaxed) + (nanos as u128 * sample_rate as u128 / 1_000_000_000) as u64,
+        };
+        LatencyReport { sample_rate, queued_frames, stream_frames, plugin_frames }
+    }
+
+    /// The figures as of the last callback.
+    pub fn current(&self, plugin_frames: u64) -> LatencyReport {
+        self.report(self.queued.load(Ordering::Relaxed), plugin_frames, self.sample_rate())
+    }
+}
+
+/// 115: Latency (u32 sample rate + u32 queued frames + u32 stream frames + u32 plugin frames, see
+/// `LatencyReport`), `node_id` is the engine.
+pub fn latency_event(engine_id: u32, report: &LatencyReport) -> Command {
+    let mut payload = Vec::with_capacity(16);
+    payload.extend_from_slice(&report.sample_rate.to_le_bytes());
+    for frames in [report.queued_frames, report.stream_frames, report.plugin_frames] {
+        payload.extend_from_slice(&(frames.min(u32::MAX as u64) as u32).to_le_bytes());
+    }
+    Command::new(115, "Latency", payload, engine_id, 0, 0, StatState::ACTIVE)
+}
+
+/// Background thread publishing the output latency on `RESPONSE_QUEUE` while a stream runs.
+pub struct LatencyPublisher {
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LatencyPublisher {
+    /// Publishes a Latency response every `interval` while an output stream is open.
+    pub fn spawn(meter: Arc<LatencyMeter>, graph: Arc<SharedGraph>, engine_id: u32, interval: Duration) -> Result<Self, String> {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&shutdown);
+        let thread = thread::Builder::new()
+            .name("opentune-latency-publisher".into())
+            .spawn(move || {
+                let _thread = threads::register_current("opentune-latency-publisher", ThreadRole::Worker);
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(interval);
+                    if !meter.is_open() { continue; }
+                    let report = meter.current(graph.latency_samples() as u64);
+                    if let Ok(mut queue) = RESPONSE_QUEUE.lock() {
+                        queue.push(latency_event(engine_id, &report));
+                    }
+                }
+            })
+            .map_err(|e| format!("Failed to spawn latency publisher: {}", e))?;
+        Ok(Self { shutdown, thread: Some(thread) })
+    }
+}
+
+impl Drop for LatencyPublisher {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
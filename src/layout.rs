@@ -0,0 +1,159 @@
+// layout.rs
+
+/* Channel Layouts and Up/Downmixing */
+
+#![allow(warnings)]
+
+use std::f32::consts::FRAC_1_SQRT_2;
+
+/// Loudspeaker a channel feeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speaker {
+    Mono,
+    FrontLeft,
+    FrontRight,
+    Center,
+    Lfe,
+    SurroundLeft,
+    SurroundRight,
+    RearLeft,
+    RearRight,
+    /// Channel of a discrete layout, with no speaker position.
+    Discrete(u16),
+}
+
+/// How the channels of an interleaved buffer are laid out. Named layouts use the WAVE/SMPTE channel order:
+/// 5.1 is L R C LFE Ls Rs and 7.1 is L R C LFE Lrs Rrs Ls Rs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelLayout {
+    Mono,
+    Stereo,
+    Surround51,
+    Surround71,
+    /// Any number of channels without speaker positions, e.g. the inputs of a multichannel interface.
+    Discrete(u16),
+}
+
+impl Default for ChannelLayout {
+    fn default() -> Self {
+        ChannelLayout::Stereo
+    }
+}
+
+impl ChannelLayout {
+    /// The named layout with `channels` channels, or a discrete one.
+    pub fn from_channels(channels: usize) -> Self {
+        match channels {
+            1 => ChannelLayout::Mono,
+            2 => ChannelLayout::Stereo,
+            6 => ChannelLayout::Surround51,
+            8 => ChannelLayout::Surround71,
+            n => ChannelLayout::Discrete(n.clamp(1, u16::MAX as usize) as u16),
+        }
+    }
+
+    pub fn channels(self) -> usize {
+        match self {
+            ChannelLayout::Mono => 1,
+            ChannelLayout::Stereo => 2,
+            ChannelLayout::Surround51 => 6,
+            ChannelLayout::Surround71 => 8,
+            ChannelLayout::Discrete(n) => (n as usize).max(1),
+        }
+    }
+
+    /// Speaker of channel `index`.
+    pub fn speaker(self, index: usize) -> Option<Speaker> {
+        use Speaker::*;
+        if index >= self.channels() { return None; }
+        let named: &[Speaker] = match self {
+            ChannelLayout::Mono => &[Mono],
+            ChannelLayout::Stereo => &[FrontLeft, FrontRight],
+            ChannelLayout::Surround51 => &[FrontLeft, FrontRight, Center, Lfe, SurroundLeft, SurroundRight],
+            ChannelLayout::Surround71 => &[FrontLeft, FrontRight, Center, Lfe, RearLeft, RearRight, SurroundLeft, SurroundRight],
+            ChannelLayout::Discrete(_) => return Some(Discrete(index as u16)),
+        };
+        Some(named[index])
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            ChannelLayout::Mono => "Mono".to_string(),
+            ChannelLayout::Stereo => "Stereo".to_string(),
+            ChannelLayout::Surround51 => "5.1".to_string(),
+            ChannelLayout::Surround71 => "7.1".to_string(),
+            ChannelLayout::Discrete(n) => format!("{} channels", n),
+        }
+    }
+
+    fn position(self, speaker: Speaker) -> Option<usize> {
+        (0..self.channels()).find(|&i| self.speaker(i) == Some(speaker))
+    }
+}
+
+/// Precomputed conversion between two layouts, so buffers can be converted on the audio thread.
+/// Shared speakers are copied; missing ones are folded in with the usual downmix weights (centre and surrounds
+/// at -3 dB, LFE dropped) or, upmixing, left silent apart from mono feeding both fronts. Discrete layouts map
+/// channel by channel.
+#[derive(Debug, Clone)]
+pub struct ChannelMap {
+    from: usize,
+    to: usize,
+    /// `to * from` weights, row per output channel.
+    matrix: Vec<f32>,
+}
+
+impl ChannelMap {
+    pub fn new(from: ChannelLayout, to: ChannelLayout) -> Self {
+        let (inputs, outputs) = (from.channels(), to.channels());
+        let mut matrix = vec![0.0f32; inputs * outputs];
+        let discrete = matches!(from, ChannelLayout::Discrete(_)) || matches!(to, ChannelLayout::Discrete(_));
+        for input in 0..inputs {
+            let mut send = |speaker: Speaker, gain: f32| {
+                if let Some(output) = to.position(speaker) {
+                    matrix[output * inputs + input] += gain;
+                    true
+                } else {
+                    false
+                }
+            };
+            if discrete {
+                if input < outputs { matrix[input * inputs + input] = 1.0; }
+                continue;
+            }
+            let speaker = from.speaker(input).unwrap_or(Speaker::Mono);
+            if send(speaker, 1.0) { continue; }
+            use Speaker::*;
+            match speaker {
+                Mono => {
+                    if !(send(FrontLeft, 1.0) & send(FrontRight, 1.0)) { send(Center, 1.0); }
+                }
+                FrontLeft | FrontRight => { send(Mono, 0.5); }
+                Center => {
+                    if !(send(FrontLeft, FRAC_1_SQRT_2) & send(FrontRight, FRAC_1_SQRT_2)) { send(Mono, FRAC_1_SQRT_2); }
+                }
+                SurroundLeft | RearLeft => {
+                    let other = if speaker == RearLeft { SurroundLeft } else { RearLeft };
+                    if !send(other, 1.0) && !send(FrontLeft, FRAC_1_SQRT_2) { send(Mono, 0.5 * FRAC_1_SQRT_2); }
+                }
+                SurroundRight | RearRight => {
+                    let other = if speaker == RearRight { SurroundRight } else { RearRight };
+                    if !send(other, 1.0) && !send(FrontRight, FRAC_1_SQRT_2) { send(Mono, 0.5 * FRAC_1_SQRT_2); }
+                }
+                Lfe | Discrete(_) => {}
+            }
+        }
+        ChannelMap { from: inputs, to: outputs, matrix }
+    }
+
+    /// Converts whole frames of `input` into `output`. Returns the frames written.
+    pub fn apply(&self, input: &[f32], output: &mut [f32]) -> usize {
+        let frames = (input.len() / self.from).min(output.len() / self.to);
+        for (source, target) in input.chunks_exact(self.from).zip(output.chunks_exact_mut(self.to)).take(frames) {
+            for (out, weights) in target.iter_mut().zip(self.matrix.chunks_exact(self.from)) {
+                *out = source.iter().zip(weights).map(|(s, w)| s * w).sum();
+            }
+        }
+        frames
+    }
+}
@@ -0,0 +1,73 @@
+// lib.rs
+
+/* OpenTune Engine Library */
+
+pub mod dspapi;
+pub mod dspengine;
+pub mod graph;
+pub mod sharedgraph;
+pub mod planar;
+pub mod dsp;
+pub mod strip;
+pub mod smoothing;
+pub mod freeze;
+pub mod bus;
+pub mod midi;
+pub mod events;
+pub mod plugtime;
+#[cfg(feature = "link")]
+pub mod link;
+pub mod reaper;
+pub mod layout;
+pub mod audiohost;
+pub mod aggregate;
+pub mod rtpsink;
+#[cfg(feature = "jack")]
+pub mod jackbackend;
+#[cfg(feature = "pipewire")]
+pub mod pipewirebackend;
+#[cfg(all(windows, feature = "wasapi"))]
+pub mod wasapibackend;
+#[cfg(feature = "clap-plugin")]
+pub mod clapplugin;
+pub mod nullbackend;
+pub mod devices;
+pub mod recovery;
+pub mod resample;
+pub mod pmanager;
+pub mod mrbr;
+pub mod automation;
+pub mod modmatrix;
+pub mod modulation;
+pub mod mapping;
+pub mod surface;
+pub mod feedback;
+pub mod taps;
+pub mod meters;
+pub mod master;
+pub mod strings;
+pub mod accessibility;
+pub mod sync;
+pub mod failover;
+pub mod session;
+pub mod alignment;
+pub mod dsptest;
+pub mod rng;
+pub mod intern;
+pub mod quirks;
+pub mod blockadapter;
+pub mod oversample;
+pub mod analysis;
+pub mod wav;
+pub mod sampleformat;
+pub mod threads;
+pub mod usage;
+pub mod xrun;
+pub mod latency;
+pub mod rtsafety;
+pub mod denormal;
+pub mod allowlist;
+pub mod preset;
+pub mod clients;
+pub mod transfer;
+pub mod audit;
@@ -0,0 +1,158 @@
+// link.rs
+
+/* Ableton Link Sync */
+
+// No Link network stack ships with the engine: like the ASIO SDK, the Link SDK is the application's to bind
+// (e.g. with the rusty_link crate), implementing `LinkSession` over its session state. Without one, nothing syncs.
+
+#![allow(warnings)]
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::dspengine::Transport;
+use crate::latency::LatencyMeter;
+use crate::threads::{self, ThreadRole};
+
+/// Phase difference, in beats, the transport may drift from the Link timeline before it is moved back in line.
+pub const PHASE_TOLERANCE: f64 = 0.01;
+
+/// A joined Link session, as the Link SDK's session state exposes it. Implemented over the SDK binding; times
+/// are microseconds on the session's own clock.
+pub trait LinkSession: Send {
+    fn clock_micros(&self) -> i64;
+    fn tempo(&self) -> f64;
+    /// Proposes a new session tempo, effective at `at_micros`.
+    fn set_tempo(&mut self, bpm: f64, at_micros: i64);
+    /// Beat on the session timeline at `at_micros`, phase-aligned to `quantum` beats.
+    fn beat_at(&self, at_micros: i64, quantum: f64) -> f64;
+    fn is_playing(&self) -> bool;
+    fn set_playing(&mut self, playing: bool, at_micros: i64);
+    fn num_peers(&self) -> usize;
+}
+
+/// Keeps the engine transport's tempo, phase and (optionally) play state in step with a Link session. Tempo
+/// changes win from whichever side made them last; the phase always follows Link.
+pub struct LinkSync {
+    session: Box<dyn LinkSession>,
+    transport: Arc<Transport>,
+    sample_rate: u32,
+    /// Beats per phase cycle, usually a bar.
+    pub quantum: f64,
+    /// Time from rendering a frame to hearing it, so what is heard lines up with the other peers. Added to the
+    /// stream's own latency when following a `LatencyMeter` (see `follow_latency`).
+    pub output_latency_micros: i64,
+    latency: Option<Arc<LatencyMeter>>,
+    /// Follow and share starts and stops as well.
+    pub start_stop_sync: bool,
+    /// Tempo both sides agreed on at the last sync, at the transport's precision: the session's tempo is compared
+    /// rounded to it, so following a fractional peer tempo isn't mistaken for a local edit.
+    tempo: f32,
+    playing: bool,
+}
+
+impl LinkSync {
+    pub fn new(session: Box<dyn LinkSession>, transport: Arc<Transport>, sample_rate: u32, quantum: f64) -> Self {
+        let info = transport.info();
+        LinkSync {
+            session,
+            transport,
+            sample_rate,
+            quantum: quantum.max(1.0),
+            output_latency_micros: 0,
+            latency: None,
+            start_stop_sync: false,
+            tempo: info.tempo,
+            playing: info.playing,
+        }
+    }
+
+    pub fn num_peers(&self) -> usize {
+        self.session.num_peers()
+    }
+
+    /// Takes the output stream's latency from `meter` at every sync, as the engine does.
+    pub fn follow_latency(&mut self, meter: Arc<LatencyMeter>) {
+        self.latency = Some(meter);
+    }
+
+    fn latency_micros(&self) -> i64 {
+        let stream = self.latency.as_ref().filter(|meter| meter.is_open()).map_or(0, |meter| {
+            let report = meter.current(0);
+            (report.stream_frames as f64 * 1e6 / report.sample_rate.max(1) as f64) as i64
+        });
+        self.output_latency_micros + stream
+    }
+
+    /// Exchanges tempo and play state with the session and moves the transport onto the Link phase. Call
+    /// regularly from a control thread (see `spawn`).
+    pub fn sync(&mut self) {
+        let now = self.session.clock_micros();
+        let local = self.transport.info();
+
+        if local.tempo != self.tempo {
+            self.tempo = local.tempo;
+            self.session.set_tempo(self.tempo as f64, now);
+        } else if self.session.tempo() as f32 != self.tempo {
+            self.tempo = self.session.tempo() as f32;
+            let _ = self.transport.set_tempo(self.tempo);
+        }
+
+        if self.start_stop_sync {
+            if local.playing != self.playing {
+                self.playing = local.playing;
+                self.session.set_playing(self.playing, now);
+            } else if self.session.is_playing() != self.playing {
+                self.playing = self.session.is_playing();
+                if self.playing { self.transport.play(); } else { self.transport.stop(); }
+            }
+        }
+
+        // The frame rendered next is heard after the output latency; that is when it must match Link's beat.
+        let local = self.transport.info();
+        let beat = self.session.beat_at(now + self.latency_micros(), self.quantum);
+        let offset = (beat - local.ppq(self.sample_rate)).rem_euclid(self.quantum);
+        let offset = if offset >= self.quantum / 2.0 { offset - self.quantum } else { offset };
+        if offset.abs() > PHASE_TOLERANCE {
+            let frames_per_beat = local.frames_per_beat(self.sample_rate);
+            let mut target = local.position as f64 + offset * frames_per_beat;
+            // Too close to the start to move back: go forward to the same phase instead.
+            if target < 0.0 { target += self.quantum * frames_per_beat; }
+            self.transport.locate(target.round() as u64);
+        }
+    }
+
+    /// Runs `sync` every `interval` on its own thread until the returned handle is dropped.
+    pub fn spawn(mut self, interval: Duration) -> Result<LinkThread, String> {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&shutdown);
+        let thread = thread::Builder::new()
+            .name("opentune-link".into())
+            .spawn(move || {
+                let _thread = threads::register_current("opentune-link", ThreadRole::Worker);
+                while !stop.load(Ordering::Relaxed) {
+                    self.sync();
+                    thread::sleep(interval);
+                }
+            })
+            .map_err(|e| format!("Failed to spawn Link sync thread: {}", e))?;
+        Ok(LinkThread { shutdown, thread: Some(thread) })
+    }
+}
+
+/// The running sync thread; dropping it stops syncing.
+pub struct LinkThread {
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for LinkThread {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -1,9 +1,48 @@
-mod dspapi;
-mod dspengine;
-mod pmanager;
-mod mrbr;
+use std::path::Path;
+
+use opentune::audit;
+use opentune::session::Session;
 
 pub fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("replay") {
+        if let Err(e) = replay(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     println!("Welcome to OpenTune DSP Engine!");
     // Initialize and start the DSP engine here
-}
\ No newline at end of file
+}
+
+/// `opentune replay <audit log> <session> [<output session>] [--engine <id>]`: applies a recorded show's control
+/// actions to a session and lists what changed. With `--engine` only the commands sent to that engine are applied.
+/// The result is written to the output path when given.
+fn replay(args: &[String]) -> Result<(), String> {
+    let usage = "Usage: opentune replay <audit log> <session> [<output session>] [--engine <id>]";
+    let mut args = args.to_vec();
+    let engine = match args.iter().position(|a| a == "--engine") {
+        Some(at) => {
+            let id = args.get(at + 1).and_then(|id| id.parse::<u32>().ok()).ok_or(usage.to_string())?;
+            args.drain(at..at + 2);
+            Some(id)
+        }
+        None => None,
+    };
+    let (Some(log), Some(session_path)) = (args.first(), args.get(1)) else {
+        return Err(usage.to_string());
+    };
+    let entries = audit::read_log(Path::new(log))?;
+    let original = Session::load(Path::new(session_path))?;
+    let mut session = original.clone();
+    let applied = audit::replay_into_session(&mut session, &entries, engine);
+    println!("Replayed {} of {} logged commands", applied, entries.len());
+    for change in Session::diff(&original, &session) {
+        println!("  {}", change.describe());
+    }
+    if let Some(out) = args.get(2) {
+        session.save(Path::new(out))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,438 @@
+// mapping.rs
+
+/* MIDI-Learn / OSC Mapping Layer */
+
+#![allow(warnings)]
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+
+use crate::dspapi::{Command, NodeId, ParamId, StatState, RESPONSE_QUEUE};
+
+pub static MAPPER: Lazy<Arc<Mutex<MappingTable>>> = Lazy::new(|| {
+    Arc::new(Mutex::new(MappingTable::new()))
+});
+
+pub const OSC_PREFIX: &str = "/opentune";
+
+/// A physical control: a MIDI CC, a MIDI note (pads/buttons) or an OSC address.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MappingSource {
+    MidiCc { channel: u8, cc: u8 },
+    MidiNote { channel: u8, note: u8 },
+    Osc(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransportAction {
+    Play,
+    Stop,
+    Record,
+    /// Nudges the tempo by the given amount of BPM.
+    TempoNudge(f32),
+}
+
+/// Anything in the engine a control can drive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MappingTarget {
+    NodeParam { node_id: NodeId, param_id: ParamId },
+    Transport(TransportAction),
+    SceneRecall(u32),
+}
+
+impl MappingTarget {
+    /// The OSC address a target answers to without an explicit mapping.
+    pub fn osc_address(&self) -> String {
+        match self {
+            MappingTarget::NodeParam { node_id, param_id } => format!("{}/node/{}/param/{}", OSC_PREFIX, node_id, param_id),
+            MappingTarget::Transport(TransportAction::Play) => format!("{}/transport/play", OSC_PREFIX),
+            MappingTarget::Transport(TransportAction::Stop) => format!("{}/transport/stop", OSC_PREFIX),
+            MappingTarget::Transport(TransportAction::Record) => format!("{}/transport/record", OSC_PREFIX),
+            MappingTarget::Transport(TransportAction::TempoNudge(_)) => format!("{}/transport/tempo/nudge", OSC_PREFIX),
+            MappingTarget::SceneRecall(scene) => format!("{}/scene/recall/{}", OSC_PREFIX, scene),
+        }
+    }
+
+    /// Parses one of the built-in OSC addresses back into a target.
+    pub fn from_osc_address(address: &str) -> Option<Self> {
+        let rest = address.strip_prefix(OSC_PREFIX)?;
+        let parts: Vec<&str> = rest.split('/').filter(|s| !s.is_empty()).collect();
+        match parts.as_slice() {
+            ["transport", "play"] => Some(MappingTarget::Transport(TransportAction::Play)),
+            ["transport", "stop"] => Some(MappingTarget::Transport(TransportAction::Stop)),
+            ["transport", "record"] => Some(MappingTarget::Transport(TransportAction::Record)),
+            ["transport", "tempo", "nudge"] => Some(MappingTarget::Transport(TransportAction::TempoNudge(0.0))),
+            ["scene", "recall", scene] => scene.parse().ok().map(MappingTarget::SceneRecall),
+            ["node", node, "param", param] => Some(MappingTarget::NodeParam {
+                node_id: node.parse().ok()?,
+                param_id: param.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Builds the engine command for a normalized (0.0..1.0) control value.
+    /// Node parameters follow the value continuously; transport and scene targets fire for any value of 0.5 or
+    /// more, so mappings pass them only the press (see `Mapping::trigger`).
+    pub fn to_command(&self, value: f32, min: f32, max: f32) -> Option<Command> {
+        let pressed = value >= 0.5;
+        match *self {
+            MappingTarget::NodeParam { node_id, param_id } => {
+                let scaled = min + (max - min) * value.clamp(0.0, 1.0);
+                Some(Command::new(2, "Set Parameter", scaled.to_le_bytes().to_vec(), node_id, param_id, 0, StatState::ACTIVE))
+            }
+            MappingTarget::Transport(action) if pressed => Some(match action {
+                TransportAction::Play => Command::new(10, "Transport Play", Vec::new(), 0, 0, 0, StatState::ACTIVE),
+                TransportAction::Stop => Command::new(11, "Transport Stop", Vec::new(), 0, 0, 0, StatState::ACTIVE),
+                TransportAction::Record => Command::new(12, "Transport Record", Vec::new(), 0, 0, 0, StatState::ACTIVE),
+                TransportAction::TempoNudge(bpm) => Command::new(13, "Tempo Nudge", bpm.to_le_bytes().to_vec(), 0, 0, 0, StatState::ACTIVE),
+            }),
+            MappingTarget::SceneRecall(scene) if pressed => {
+                Some(Command::new(20, "Scene Recall", scene.to_le_bytes().to_vec(), 0, 0, 0, StatState::ACTIVE))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How a control's press/release gestures drive its target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MappingMode {
+    /// The control value is passed straight through.
+    Direct,
+    /// On while held, off when released (after the release delay).
+    Momentary,
+    /// Each press flips between on and off.
+    Toggle,
+    /// A short press latches on; a long hold acts momentary. Pressing a latched control releases it.
+    Latch,
+}
+
+/// How a CC value is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CcEncoding {
+    /// 0..127 is the position of the control.
+    Absolute,
+    /// Endless encoder: 1..63 turn up, 65..127 turn down (127 = -1).
+    TwosComplement,
+    /// Endless encoder: 64 is no movement, 65 = +1, 63 = -1.
+    BinOffset,
+}
+
+/// Holding a `Latch` control longer than this makes its release act like `Momentary`.
+pub const LATCH_HOLD_THRESHOLD: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub source: MappingSource,
+    pub target: MappingTarget,
+    /// Output range for node parameters.
+    pub min: f32,
+    pub max: f32,
+    pub mode: MappingMode,
+    /// Delay before a release turns the target off in `Momentary` and `Latch` modes.
+    pub release_delay: Duration,
+    /// Soft takeover: ignore a `Direct` control until it passes the parameter's current value.
+    pub pickup: bool,
+    pub encoding: CcEncoding,
+    /// Normalized change per encoder tick.
+    pub sensitivity: f32,
+    /// Extra gain for fast turns: encoders report larger tick counts when spun quickly, and each tick
+    /// beyond the first is scaled by `1 + acceleration`.
+    pub acceleration: f32,
+    state: GestureState,
+}
+
+#[derive(Debug, Clone, Default)]
+struct GestureState {
+    down: bool,
+    on: bool,
+    /// Set when the current press started on an already latched control.
+    unlatch_on_release: bool,
+    pressed_at: Option<Instant>,
+    release_at: Option<Instant>,
+    /// Last known normalized value of the target, from the engine or another controller.
+    target_value: Option<f32>,
+    last_control: Option<f32>,
+    picked_up: bool,
+}
+
+/// Distance (normalized) at which a control counts as having reached the parameter.
+pub const PICKUP_THRESHOLD: f32 = 0.02;
+
+impl Mapping {
+    pub fn new(source: MappingSource, target: MappingTarget) -> Self {
+        Self {
+            source,
+            target,
+            min: 0.0,
+            max: 1.0,
+            mode: MappingMode::Direct,
+            release_delay: Duration::ZERO,
+            pickup: false,
+            encoding: CcEncoding::Absolute,
+            sensitivity: 1.0 / 128.0,
+            acceleration: 0.0,
+            state: GestureState::default(),
+        }
+    }
+
+    /// Feeds a control value through the mapping's mode and returns the value to send, if any. An encoder moves a
+    /// parameter directly; for other targets its position is a control value like any other.
+    fn gesture(&mut self, value: f32, now: Instant) -> Option<f32> {
+        let value = match (self.encoding, self.target) {
+            (CcEncoding::Absolute, _) => value,
+            (_, MappingTarget::NodeParam { .. }) => return self.relative(value),
+            _ => self.relative(value)?,
+        };
+        if self.mode == MappingMode::Direct {
+            return match self.target {
+                MappingTarget::NodeParam { .. } => self.follow(value),
+                _ => self.trigger(value),
+            };
+        }
+
+        let pressed = value >= 0.5;
+        if pressed == self.state.down { return None; }
+        self.state.down = pressed;
+
+        match (self.mode, pressed) {
+            (MappingMode::Momentary, true) => {
+                self.state.release_at = None;
+                self.switch(true)
+            }
+            (MappingMode::Toggle, true) => {
+                let on = !self.state.on;
+                self.switch(on)
+            }
+            (MappingMode::Latch, true) => {
+                self.state.release_at = None;
+                self.state.pressed_at = Some(now);
+                self.state.unlatch_on_release = self.state.on;
+                self.switch(true)
+            }
+            (MappingMode::Momentary, false) => self.release(now),
+            (MappingMode::Latch, false) => {
+                let held = self.state.pressed_at.map_or(Duration::ZERO, |t| now.duration_since(t));
+                if self.state.unlatch_on_release || held >= LATCH_HOLD_THRESHOLD {
+                    self.release(now)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Endless encoder: moves the target from its last known value by the decoded tick count.
+    fn relative(&mut self, value: f32) -> Option<f32> {
+        let raw = (value * 127.0).round() as i32;
+        let ticks = match self.encoding {
+            CcEncoding::TwosComplement => if raw >= 64 { raw - 128 } else { raw },
+            CcEncoding::BinOffset => raw - 64,
+            CcEncoding::Absolute => return Some(value),
+        };
+        if ticks == 0 { return None; }
+
+        let magnitude = ticks.unsigned_abs() as f32;
+        let accelerated = 1.0 + (magnitude - 1.0) * (1.0 + self.acceleration);
+        let step = self.sensitivity * accelerated * ticks.signum() as f32;
+
+        let current = self.state.target_value.unwrap_or(0.0);
+        let next = (current + step).clamp(0.0, 1.0);
+        self.state.target_value = Some(next);
+        if next == current { None } else { Some(next) }
+    }
+
+    /// Continuous control with optional pickup. Emits a `Pickup Engaged` response when the control catches the value.
+    fn follow(&mut self, value: f32) -> Option<f32> {
+        let state = &mut self.state;
+        let last = state.last_control.replace(value);
+
+        if self.pickup && !state.picked_up {
+            if let Some(target) = state.target_value {
+                let near = (value - target).abs() <= PICKUP_THRESHOLD;
+                let crossed = last.is_some_and(|l| (l - target).signum() != (value - target).signum());
+                if !near && !crossed { return None; }
+
+                if let MappingTarget::NodeParam { node_id, param_id } = self.target {
+                    if let Ok(mut queue) = RESPONSE_QUEUE.lock() {
+                        queue.push(Command::new(102, "Pickup Engaged", value.to_le_bytes().to_vec(), node_id, param_id, 0, StatState::ACTIVE));
+                    }
+                }
+            }
+            state.picked_up = true;
+        }
+
+        state.target_value = Some(value);
+        Some(value)
+    }
+
+    /// One-shot targets (transport, scenes): fires once when the control crosses 0.5 upwards, so a fader held
+    /// high doesn't repeat the action on every message.
+    fn trigger(&mut self, value: f32) -> Option<f32> {
+        let pressed = value >= 0.5;
+        if pressed == self.state.down { return None; }
+        self.state.down = pressed;
+        pressed.then_some(1.0)
+    }
+
+    fn release(&mut self, now: Instant) -> Option<f32> {
+        if self.release_delay.is_zero() {
+            return self.switch(false);
+        }
+        self.state.release_at = Some(now + self.release_delay);
+        None
+    }
+
+    fn switch(&mut self, on: bool) -> Option<f32> {
+        if self.state.on == on { return None; }
+        self.state.on = on;
+        Some(if on { 1.0 } else { 0.0 })
+    }
+}
+
+pub struct MappingTable {
+    pub mappings: Vec<Mapping>,
+    /// Target waiting for the next incoming control (MIDI-learn).
+    learning: Option<MappingTarget>,
+}
+
+impl MappingTable {
+    pub fn new() -> Self {
+        Self {
+            mappings: Vec::new(),
+            learning: None,
+        }
+    }
+
+    pub fn map(&mut self, source: MappingSource, target: MappingTarget) {
+        self.mappings.retain(|m| m.source != source);
+        self.mappings.push(Mapping::new(source, target));
+    }
+
+    /// Changes the gesture behavior of an existing mapping.
+    pub fn set_mode(&mut self, source: &MappingSource, mode: MappingMode, release_delay: Duration) {
+        if let Some(m) = self.mappings.iter_mut().find(|m| &m.source == source) {
+            m.mode = mode;
+            m.release_delay = release_delay;
+            m.state = GestureState::default();
+        }
+    }
+
+    /// Configures an existing CC mapping for an endless encoder. False if `source` isn't a mapped CC.
+    pub fn set_encoding(&mut self, source: &MappingSource, encoding: CcEncoding, sensitivity: f32, acceleration: f32) -> bool {
+        if !matches!(source, MappingSource::MidiCc { .. }) { return false; }
+        let Some(m) = self.mappings.iter_mut().find(|m| &m.source == source) else { return false };
+        m.encoding = encoding;
+        m.sensitivity = sensitivity;
+        m.acceleration = acceleration.max(0.0);
+        m.state = GestureState::default();
+        true
+    }
+
+    /// Enables or disables soft takeover on an existing mapping.
+    pub fn set_pickup(&mut self, source: &MappingSource, pickup: bool) {
+        if let Some(m) = self.mappings.iter_mut().find(|m| &m.source == source) {
+            m.pickup = pickup;
+            m.state.picked_up = false;
+        }
+    }
+
+    /// Reports a parameter value changed elsewhere (GUI, automation, another controller).
+    /// Controls with pickup enabled have to catch up with it again before they take effect.
+    pub fn set_param_value(&mut self, node_id: NodeId, param_id: ParamId, value: f32) {
+        let target = MappingTarget::NodeParam { node_id, param_id };
+        for m in self.mappings.iter_mut().filter(|m| m.target == target) {
+            let range = m.max - m.min;
+            let normalized = if range != 0.0 { ((value - m.min) / range).clamp(0.0, 1.0) } else { 0.0 };
+            m.state.target_value = Some(normalized);
+            m.state.picked_up = false;
+        }
+    }
+
+    /// Emits the delayed releases that are due. Call periodically from the control thread.
+    pub fn poll(&mut self) -> Vec<Command> {
+        let now = Instant::now();
+        let mut out = Vec::new();
+        for m in self.mappings.iter_mut() {
+            if m.state.release_at.is_some_and(|t| t <= now) {
+                m.state.release_at = None;
+                if let Some(value) = m.switch(false) {
+                    out.extend(m.target.to_command(value, m.min, m.max));
+                }
+            }
+        }
+        out
+    }
+
+    pub fn unmap(&mut self, source: &MappingSource) {
+        self.mappings.retain(|m| &m.source != source);
+    }
+
+    /// Arms MIDI-learn: the next MIDI or OSC control received is bound to `target`.
+    pub fn start_learn(&mut self, target: MappingTarget) {
+        self.learning = Some(target);
+    }
+
+    pub fn cancel_learn(&mut self) {
+        self.learning = None;
+    }
+
+    pub fn is_learning(&self) -> bool {
+        self.learning.is_some()
+    }
+
+    /// Translates a raw MIDI message into engine commands.
+    pub fn handle_midi(&mut self, message: &[u8]) -> Vec<Command> {
+        let Some((source, value)) = Self::parse_midi(message) else { return vec![] };
+        self.handle_source(source, value)
+    }
+
+    /// Translates an OSC message into engine commands. Unmapped built-in addresses drive their target directly.
+    pub fn handle_osc(&mut self, address: &str, value: f32) -> Vec<Command> {
+        let source = MappingSource::Osc(address.to_string());
+        if self.learning.is_none() && !self.mappings.iter().any(|m| m.source == source) {
+            // The nudge address carries the BPM amount as its argument.
+            return match MappingTarget::from_osc_address(address) {
+                Some(MappingTarget::Transport(TransportAction::TempoNudge(_))) => {
+                    MappingTarget::Transport(TransportAction::TempoNudge(value)).to_command(1.0, 0.0, 1.0)
+                }
+                Some(target) => target.to_command(value, 0.0, 1.0),
+                None => None,
+            }
+            .into_iter()
+            .collect();
+        }
+        self.handle_source(source, value)
+    }
+
+    fn handle_source(&mut self, source: MappingSource, value: f32) -> Vec<Command> {
+        if let Some(target) = self.learning.take() {
+            self.map(source, target);
+            return vec![];
+        }
+
+        let now = Instant::now();
+        let mut out = Vec::new();
+        for m in self.mappings.iter_mut().filter(|m| m.source == source) {
+            if let Some(value) = m.gesture(value, now) {
+                out.extend(m.target.to_command(value, m.min, m.max));
+            }
+        }
+        out
+    }
+
+    /// Returns the control and its normalized value for CC and note messages.
+    pub fn parse_midi(message: &[u8]) -> Option<(MappingSource, f32)> {
+        let status = *message.first()?;
+        let channel = status & 0x0F;
+        match (status & 0xF0, message.get(1), message.get(2)) {
+            (0xB0, Some(&cc), Some(&value)) => Some((MappingSource::MidiCc { channel, cc }, value as f32 / 127.0)),
+            (0x90, Some(&note), Some(&velocity)) => Some((MappingSource::MidiNote { channel, note }, velocity as f32 / 127.0)),
+            (0x80, Some(&note), _) => Some((MappingSource::MidiNote { channel, note }, 0.0)),
+            _ => None,
+        }
+    }
+}
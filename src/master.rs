@@ -0,0 +1,111 @@
+// master.rs
+
+/* Master Volume, Mute and Dim */
+
+#![allow(warnings)]
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::dsp::simd;
+
+/// Attenuation of the dim switch.
+pub const DIM_DB: f32 = -20.0;
+/// Master gains at or below this are silence.
+pub const MIN_GAIN_DB: f32 = -96.0;
+/// Highest master gain accepted.
+pub const MAX_GAIN_DB: f32 = 12.0;
+/// Time a change of the master controls is ramped over.
+pub const RAMP_SECONDS: f32 = 0.02;
+
+/// Engine-level volume, mute and dim applied to the rack output, set through the Set Master Gain, Master Mute
+/// and Master Dim commands and read from anywhere.
+#[derive(Debug)]
+pub struct MasterControls {
+    /// f32 bits of the gain in dB.
+    gain_db: AtomicU32,
+    muted: AtomicBool,
+    dimmed: AtomicBool,
+}
+
+impl MasterControls {
+    pub fn new() -> Self {
+        MasterControls { gain_db: AtomicU32::new(0.0f32.to_bits()), muted: AtomicBool::new(false), dimmed: AtomicBool::new(false) }
+    }
+
+    pub fn gain_db(&self) -> f32 {
+        f32::from_bits(self.gain_db.load(Ordering::Relaxed))
+    }
+
+    /// Clamped to `MIN_GAIN_DB..=MAX_GAIN_DB`; NaN is refused.
+    pub fn set_gain_db(&self, db: f32) -> Result<(), &'static str> {
+        if db.is_nan() { return Err("master gain must be a number"); }
+        self.gain_db.store(db.clamp(MIN_GAIN_DB, MAX_GAIN_DB).to_bits(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    pub fn is_dimmed(&self) -> bool {
+        self.dimmed.load(Ordering::Relaxed)
+    }
+
+    pub fn set_dimmed(&self, dimmed: bool) {
+        self.dimmed.store(dimmed, Ordering::Relaxed);
+    }
+
+    /// Linear gain the controls add up to.
+    pub fn gain(&self) -> f32 {
+        if self.is_muted() { return 0.0; }
+        let db = self.gain_db() + if self.is_dimmed() { DIM_DB } else { 0.0 };
+        if db <= MIN_GAIN_DB { 0.0 } else { 10f32.powf(db / 20.0) }
+    }
+}
+
+impl Default for MasterControls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The audio thread's side of the master controls: follows their gain in straight lines so changes don't click.
+#[derive(Debug, Clone)]
+pub struct MasterRamp {
+    current: f32,
+    target: f32,
+    /// Gain change per frame.
+    step: f32,
+}
+
+impl MasterRamp {
+    pub fn new(gain: f32) -> Self {
+        MasterRamp { current: gain, target: gain, step: 0.0 }
+    }
+
+    /// Applies `controls` to interleaved `io`, ramping over `RAMP_SECONDS` from where the last block left off.
+    pub fn process(&mut self, controls: &MasterControls, io: &mut [f32], channels: usize, sample_rate: u32) {
+        let target = controls.gain();
+        if target != self.target {
+            let ramp_frames = (RAMP_SECONDS * sample_rate as f32).max(1.0);
+            self.target = target;
+            self.step = ((target - self.current).abs() / ramp_frames).max(f32::EPSILON);
+        }
+        if self.current == self.target {
+            if self.current != 1.0 { simd::gain(io, self.current); }
+            return;
+        }
+        for frame in io.chunks_mut(channels.max(1)) {
+            self.current = if self.current < self.target {
+                (self.current + self.step).min(self.target)
+            } else {
+                (self.current - self.step).max(self.target)
+            };
+            frame.iter_mut().for_each(|s| *s *= self.current);
+        }
+    }
+}
@@ -0,0 +1,269 @@
+// meters.rs
+
+/* Level and Loudness Metering */
+
+#![allow(warnings)]
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::dsp::simd;
+use crate::dspapi::NodeId;
+use crate::layout::{ChannelLayout, Speaker};
+
+/// Rate the held peak falls at once the signal drops.
+pub const PEAK_FALL_DB_PER_SECOND: f32 = 20.0;
+/// Time the RMS level averages over.
+pub const RMS_SECONDS: f32 = 0.3;
+/// Window of the short-term loudness (EBU R128).
+pub const SHORT_TERM_SECONDS: f32 = 3.0;
+/// Channels included in the loudness; further channels of a discrete layout still count for peak and RMS.
+pub const MAX_LOUDNESS_CHANNELS: usize = 8;
+
+/// The short-term window is summed in bins of this length.
+const BIN_SECONDS: f32 = 0.1;
+const BINS: usize = (SHORT_TERM_SECONDS / BIN_SECONDS) as usize;
+const EMPTY_SLOT: u64 = u64::MAX;
+
+/// Latest levels of one signal, linear unless noted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelReading {
+    /// Highest sample, held and falling at `PEAK_FALL_DB_PER_SECOND`, so a meter polled at any rate sees every peak.
+    pub peak: f32,
+    /// Over about `RMS_SECONDS`, all channels together.
+    pub rms: f32,
+    /// K-weighted loudness over the last `SHORT_TERM_SECONDS` (ITU-R BS.1770), in LUFS; `f32::NEG_INFINITY` in silence.
+    pub short_term: f32,
+}
+
+/// Levels of one node's output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeLevel {
+    pub node_id: NodeId,
+    pub level: LevelReading,
+}
+
+/// Two-stage K-weighting filter (BS.1770 pre-filter and RLB high-pass) as biquad coefficients.
+#[derive(Debug, Clone, Copy, Default)]
+struct KWeighting {
+    shelf: [f32; 5],
+    highpass: [f32; 5],
+}
+
+impl KWeighting {
+    fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate.max(1) as f64;
+        // Shelf: +4 dB above about 1.7 kHz.
+        let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0];
+        // High-pass at about 38 Hz.
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let highpass = [1.0, -2.0, 1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0];
+        KWeighting { shelf: shelf.map(|c| c as f32), highpass: highpass.map(|c| c as f32) }
+    }
+}
+
+/// Transposed direct form II; `z` is the filter's two state values.
+fn biquad(c: &[f32; 5], z: &mut [f32], x: f32) -> f32 {
+    let y = c[0] * x + z[0];
+    z[0] = c[1] * x - c[3] * y + z[1];
+    z[1] = c[2] * x - c[4] * y;
+    y
+}
+
+/// Loudness weight of a channel (BS.1770): surrounds count more, the LFE not at all.
+fn channel_weight(layout: ChannelLayout, channel: usize) -> f32 {
+    match layout.speaker(channel) {
+        Some(Speaker::Lfe) => 0.0,
+        Some(Speaker::SurroundLeft | Speaker::SurroundRight | Speaker::RearLeft | Speaker::RearRight) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// What the rendering thread keeps between blocks to measure one signal.
+struct MeterState {
+    sample_rate: u32,
+    weighting: KWeighting,
+    /// Filter state per channel: two values for each stage.
+    filters: [[f32; 4]; MAX_LOUDNESS_CHANNELS],
+    /// Weighted energy per bin; `bin` is being filled.
+    bins: [f64; BINS],
+    bin: usize,
+    bin_frames: usize,
+    /// Completed bins in the window, at most `BINS - 1`.
+    full_bins: usize,
+    power: f32,
+    peak: f32,
+}
+
+impl MeterState {
+    fn new() -> Self {
+        MeterState {
+            sample_rate: 0,
+            weighting: KWeighting::default(),
+            filters: [[0.0; 4]; MAX_LOUDNESS_CHANNELS],
+            bins: [0.0; BINS],
+            bin: 0,
+            bin_frames: 0,
+            full_bins: 0,
+            power: 0.0,
+            peak: 0.0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.filters = [[0.0; 4]; MAX_LOUDNESS_CHANNELS];
+        self.bins = [0.0; BINS];
+        self.bin = 0;
+        self.bin_frames = 0;
+        self.full_bins = 0;
+        self.power = 0.0;
+        self.peak = 0.0;
+    }
+
+    /// Measures one interleaved block. Returns the new reading.
+    fn measure(&mut self, buffer: &[f32], layout: ChannelLayout, sample_rate: u32) -> LevelReading {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.weighting = KWeighting::new(sample_rate);
+            self.clear();
+        }
+        let channels = layout.channels().max(1);
+        let frames = buffer.len() / channels;
+        let seconds = frames as f32 / sample_rate.max(1) as f32;
+
+        let fall = 10f32.powf(-PEAK_FALL_DB_PER_SECOND * seconds / 20.0);
+        self.peak = simd::peak(buffer).max(self.peak * fall);
+        let power = buffer.iter().map(|s| s * s).sum::<f32>() / buffer.len().max(1) as f32;
+        self.power += (power - self.power) * (seconds / (seconds + RMS_SECONDS));
+
+        let bin_len = (BIN_SECONDS * sample_rate as f32) as usize;
+        let weights: [f32; MAX_LOUDNESS_CHANNELS] = std::array::from_fn(|c| if c < channels { channel_weight(layout, c) } else { 0.0 });
+        for frame in buffer.chunks_exact(channels) {
+            let mut energy = 0.0f32;
+            for (c, &x) in frame.iter().take(MAX_LOUDNESS_CHANNELS).enumerate() {
+                let z = &mut self.filters[c];
+                let y = biquad(&self.weighting.shelf, &mut z[..2], x);
+                let y = biquad(&self.weighting.highpass, &mut z[2..], y);
+                energy += weights[c] * y * y;
+            }
+            self.bins[self.bin] += energy as f64;
+            self.bin_frames += 1;
+            if self.bin_frames >= bin_len.max(1) {
+                self.bin = (self.bin + 1) % BINS;
+                self.bins[self.bin] = 0.0;
+                self.bin_frames = 0;
+                self.full_bins = (self.full_bins + 1).min(BINS - 1);
+            }
+        }
+        let window = self.full_bins * bin_len + self.bin_frames;
+        let mean = self.bins.iter().sum::<f64>() / window.max(1) as f64;
+        let short_term = if mean > 0.0 { (-0.691 + 10.0 * mean.log10()) as f32 } else { f32::NEG_INFINITY };
+
+        LevelReading { peak: self.peak, rms: self.power.sqrt(), short_term }
+    }
+}
+
+struct MeterSlot {
+    node_id: AtomicU64,
+    peak: AtomicU32,
+    rms: AtomicU32,
+    short_term: AtomicU32,
+    /// Only ever taken by the rendering thread, so never contended.
+    state: Mutex<MeterState>,
+}
+
+impl MeterSlot {
+    fn new() -> Self {
+        MeterSlot {
+            node_id: AtomicU64::new(EMPTY_SLOT),
+            peak: AtomicU32::new(0),
+            rms: AtomicU32::new(0),
+            short_term: AtomicU32::new(f32::NEG_INFINITY.to_bits()),
+            state: Mutex::new(MeterState::new()),
+        }
+    }
+
+    fn reading(&self) -> LevelReading {
+        LevelReading {
+            peak: f32::from_bits(self.peak.load(Ordering::Relaxed)),
+            rms: f32::from_bits(self.rms.load(Ordering::Relaxed)),
+            short_term: f32::from_bits(self.short_term.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn measure(&self, node_id: u64, buffer: &[f32], layout: ChannelLayout, sample_rate: u32) {
+        let Ok(mut state) = self.state.try_lock() else { return };
+        if self.node_id.swap(node_id, Ordering::Relaxed) != node_id {
+            state.clear();
+        }
+        let reading = state.measure(buffer, layout, sample_rate);
+        self.peak.store(reading.peak.to_bits(), Ordering::Relaxed);
+        self.rms.store(reading.rms.to_bits(), Ordering::Relaxed);
+        self.short_term.store(reading.short_term.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Peak, RMS and short-term loudness of the rack output and of every node's output, measured on the rendering
+/// thread each block. Readers only load atomics, so a GUI can poll at any rate without ever blocking the audio.
+pub struct LevelMeters {
+    master: MeterSlot,
+    /// One per rack slot, in processing order.
+    slots: Vec<MeterSlot>,
+}
+
+impl LevelMeters {
+    pub fn new(capacity: usize) -> Self {
+        Self { master: MeterSlot::new(), slots: (0..capacity).map(|_| MeterSlot::new()).collect() }
+    }
+
+    pub fn allocated_bytes(&self) -> usize {
+        (self.slots.len() + 1) * std::mem::size_of::<MeterSlot>()
+    }
+
+    /// Levels of the rack output, before the master controls and the output protection limiter.
+    pub fn master(&self) -> LevelReading {
+        self.master.reading()
+    }
+
+    /// Levels of every node's output, in processing order.
+    pub fn nodes(&self) -> Vec<NodeLevel> {
+        self.slots
+            .iter()
+            .filter_map(|slot| {
+                let id = slot.node_id.load(Ordering::Relaxed);
+                (id != EMPTY_SLOT).then(|| NodeLevel { node_id: id as NodeId, level: slot.reading() })
+            })
+            .collect()
+    }
+
+    pub fn node(&self, node_id: NodeId) -> Option<LevelReading> {
+        self.slots.iter().find(|slot| slot.node_id.load(Ordering::Relaxed) == node_id as u64).map(MeterSlot::reading)
+    }
+
+    /// Measures the rack output. Rendering thread only; never allocates.
+    pub fn measure_master(&self, buffer: &[f32], layout: ChannelLayout, sample_rate: u32) {
+        self.master.measure(0, buffer, layout, sample_rate);
+    }
+
+    /// Measures the output of the node in rack slot `index`. A different node in the slot starts afresh.
+    /// Rendering thread only; never allocates.
+    pub fn measure_node(&self, index: usize, node_id: NodeId, buffer: &[f32], layout: ChannelLayout, sample_rate: u32) {
+        if let Some(slot) = self.slots.get(index) {
+            slot.measure(node_id as u64, buffer, layout, sample_rate);
+        }
+    }
+
+    /// Forgets the slots from `len` on, after the rack shrank.
+    pub fn truncate(&self, len: usize) {
+        for slot in self.slots.iter().skip(len) {
+            slot.node_id.store(EMPTY_SLOT, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,270 @@
+// midi.rs
+
+/* MIDI Input and Output */
+
+#![allow(warnings)]
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crossbeam::queue::ArrayQueue;
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use once_cell::sync::Lazy;
+
+use crate::threads::{self, ThreadRole};
+
+/// Most events a node is handed for one block; further ones in the same block are dropped.
+pub const MAX_BLOCK_EVENTS: usize = 512;
+/// Channel mode controllers sent on panic.
+pub const ALL_SOUND_OFF: u8 = 120;
+pub const ALL_NOTES_OFF: u8 = 123;
+
+static EPOCH: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Microseconds on the clock MIDI events are stamped with.
+pub fn now_micros() -> u64 {
+    EPOCH.elapsed().as_micros() as u64
+}
+
+/// One channel or system message of up to three bytes. Fixed-size, so queueing and copying events never
+/// allocates; SysEx is not carried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MidiEvent {
+    /// Arrival time in `now_micros` microseconds.
+    pub timestamp: u64,
+    /// Frame within the block the event applies at, set by the engine before nodes see it.
+    pub frame: u32,
+    /// Index of the input it arrived on, in the order the inputs were opened.
+    pub port: u16,
+    len: u8,
+    data: [u8; 3],
+}
+
+impl MidiEvent {
+    /// `None` for an empty message or one longer than three bytes.
+    pub fn new(timestamp: u64, port: u16, message: &[u8]) -> Option<Self> {
+        if message.is_empty() || message.len() > 3 { return None; }
+        let mut data = [0u8; 3];
+        data[..message.len()].copy_from_slice(message);
+        Some(MidiEvent { timestamp, frame: 0, port, len: message.len() as u8, data })
+    }
+
+    /// The same message at another frame.
+    pub fn at_frame(self, frame: u32) -> Self {
+        MidiEvent { frame, ..self }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data[..self.len as usize]
+    }
+
+    pub fn status(&self) -> u8 {
+        self.data[0]
+    }
+
+    /// Channel (0-15) of a channel message.
+    pub fn channel(&self) -> Option<u8> {
+        (0x80..0xF0).contains(&self.data[0]).then(|| self.data[0] & 0x0F)
+    }
+}
+
+/// Places events that arrived up to one block before `now` at the same position in the block about to be
+/// rendered, so timing within a block survives at the cost of a block of latency. Older events go to frame 0.
+pub fn schedule(events: &mut [MidiEvent], now: u64, frames: usize, sample_rate: u32) {
+    let frames = frames.max(1);
+    for event in events.iter_mut() {
+        let age = (now.saturating_sub(event.timestamp) * sample_rate as u64 / 1_000_000) as usize;
+        event.frame = (frames - age.min(frames)).min(frames - 1) as u32;
+    }
+}
+
+/// Where a node writes the MIDI it generated during a block (see `AudioNode::take_midi_output`). Bounded by
+/// preallocated storage: events beyond `MAX_BLOCK_EVENTS` are refused rather than allocated for.
+pub struct MidiWriter<'a> {
+    events: &'a mut Vec<MidiEvent>,
+}
+
+impl<'a> MidiWriter<'a> {
+    pub fn new(events: &'a mut Vec<MidiEvent>) -> Self {
+        MidiWriter { events }
+    }
+
+    /// Adds `message` at `frame` of the block. Returns false if the message doesn't fit an event or the block
+    /// is full. Events should be written in time order.
+    pub fn push(&mut self, frame: u32, message: &[u8]) -> bool {
+        if self.events.len() == self.events.capacity() { return false; }
+        let Some(event) = MidiEvent::new(0, 0, message) else { return false };
+        self.events.push(event.at_frame(frame));
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+/// Inserts `event` after every event at or before its frame, keeping `events` in time order without
+/// allocating. Returns false if `events` is full.
+pub fn insert_in_order(events: &mut Vec<MidiEvent>, event: MidiEvent) -> bool {
+    if events.len() == events.capacity() { return false; }
+    let index = events.iter().rposition(|e| e.frame <= event.frame).map_or(0, |i| i + 1);
+    events.insert(index, event);
+    true
+}
+
+/// Puts All Sound Off and All Notes Off for every channel at frame 0, ahead of the events already there, as far
+/// as `events` has room. Never allocates.
+pub fn prepend_notes_off(events: &mut Vec<MidiEvent>) {
+    for channel in (0..16u8).rev() {
+        for controller in [ALL_NOTES_OFF, ALL_SOUND_OFF] {
+            if events.len() == events.capacity() { return; }
+            if let Some(event) = MidiEvent::new(0, 0, &[0xB0 | channel, controller, 0]) {
+                events.insert(0, event);
+            }
+        }
+    }
+}
+
+/// Names of the MIDI inputs currently available.
+pub fn input_ports() -> Result<Vec<String>, String> {
+    let input = MidiInput::new("OpenTune").map_err(|e| e.to_string())?;
+    Ok(input.ports().iter().filter_map(|p| input.port_name(p).ok()).collect())
+}
+
+/// Open MIDI inputs, all feeding one lock-free queue that the audio thread drains.
+pub struct MidiInputs {
+    queue: Arc<ArrayQueue<MidiEvent>>,
+    connections: Vec<(String, MidiInputConnection<()>)>,
+    next_port: u16,
+    /// Messages lost because the queue was full or they don't fit a `MidiEvent`.
+    pub dropped: Arc<AtomicU64>,
+}
+
+impl MidiInputs {
+    pub fn new(queue: Arc<ArrayQueue<MidiEvent>>) -> Self {
+        MidiInputs { queue, connections: Vec::new(), next_port: 0, dropped: Arc::new(AtomicU64::new(0)) }
+    }
+
+    /// Starts listening to the input called `name`. Opening an input that is already open does nothing.
+    pub fn open(&mut self, name: &str) -> Result<(), String> {
+        if self.connections.iter().any(|(n, _)| n == name) { return Ok(()); }
+        let mut input = MidiInput::new("OpenTune").map_err(|e| e.to_string())?;
+        input.ignore(Ignore::Sysex);
+        let port = input
+            .ports()
+            .into_iter()
+            .find(|p| input.port_name(p).is_ok_and(|n| n == name))
+            .ok_or(format!("MIDI input '{}' not found", name))?;
+        let (queue, dropped, index) = (Arc::clone(&self.queue), Arc::clone(&self.dropped), self.next_port);
+        let connection = input
+            .connect(&port, "opentune-in", move |_, message, _| {
+                let queued = MidiEvent::new(now_micros(), index, message).is_some_and(|event| queue.push(event).is_ok());
+                if !queued { dropped.fetch_add(1, Ordering::Relaxed); }
+            }, ())
+            .map_err(|e| format!("Failed to open MIDI input '{}': {}", name, e))?;
+        self.next_port += 1;
+        self.connections.push((name.to_string(), connection));
+        Ok(())
+    }
+
+    /// Stops listening to `name`. Returns false if it wasn't open.
+    pub fn close(&mut self, name: &str) -> bool {
+        let Some(index) = self.connections.iter().position(|(n, _)| n == name) else { return false };
+        let (_, connection) = self.connections.remove(index);
+        connection.close();
+        true
+    }
+
+    pub fn open_inputs(&self) -> impl Iterator<Item = &str> {
+        self.connections.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+/// Names of the MIDI outputs currently available.
+pub fn output_ports() -> Result<Vec<String>, String> {
+    let output = MidiOutput::new("OpenTune").map_err(|e| e.to_string())?;
+    Ok(output.ports().iter().filter_map(|p| output.port_name(p).ok()).collect())
+}
+
+type OutputConnections = Arc<Mutex<Vec<(String, MidiOutputConnection)>>>;
+
+/// Open MIDI outputs and the thread that sends them what the audio thread queues. Each event is sent to every
+/// open output once its `timestamp` is reached, so the audio thread never touches a MIDI driver.
+pub struct MidiOutputs {
+    connections: OutputConnections,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MidiOutputs {
+    /// Starts the sender thread on `queue`.
+    pub fn spawn(queue: Arc<ArrayQueue<MidiEvent>>) -> Result<Self, String> {
+        let connections: OutputConnections = Arc::new(Mutex::new(Vec::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (outputs, stop) = (Arc::clone(&connections), Arc::clone(&shutdown));
+        let thread = thread::Builder::new()
+            .name("opentune-midi-out".into())
+            .spawn(move || {
+                let _thread = threads::register_current("opentune-midi-out", ThreadRole::Worker);
+                let mut pending: Option<MidiEvent> = None;
+                while !stop.load(Ordering::Relaxed) {
+                    while let Some(event) = pending.take().or_else(|| queue.pop()) {
+                        if event.timestamp > now_micros() {
+                            pending = Some(event);
+                            break;
+                        }
+                        if let Ok(mut outputs) = outputs.lock() {
+                            for (_, connection) in outputs.iter_mut() {
+                                let _ = connection.send(event.bytes());
+                            }
+                        }
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+            })
+            .map_err(|e| format!("Failed to spawn MIDI output thread: {}", e))?;
+        Ok(MidiOutputs { connections, shutdown, thread: Some(thread) })
+    }
+
+    /// Starts sending to the output called `name`. Opening an output that is already open does nothing.
+    pub fn open(&mut self, name: &str) -> Result<(), String> {
+        let mut connections = self.connections.lock().map_err(|_| "MIDI output lock poisoned".to_string())?;
+        if connections.iter().any(|(n, _)| n == name) { return Ok(()); }
+        let output = MidiOutput::new("OpenTune").map_err(|e| e.to_string())?;
+        let port = output
+            .ports()
+            .into_iter()
+            .find(|p| output.port_name(p).is_ok_and(|n| n == name))
+            .ok_or(format!("MIDI output '{}' not found", name))?;
+        let connection = output.connect(&port, "opentune-out").map_err(|e| format!("Failed to open MIDI output '{}': {}", name, e))?;
+        connections.push((name.to_string(), connection));
+        Ok(())
+    }
+
+    /// Stops sending to `name`. Returns false if it wasn't open.
+    pub fn close(&mut self, name: &str) -> bool {
+        let Ok(mut connections) = self.connections.lock() else { return false };
+        let Some(index) = connections.iter().position(|(n, _)| n == name) else { return false };
+        let (_, connection) = connections.remove(index);
+        connection.close();
+        true
+    }
+
+    pub fn open_outputs(&self) -> Vec<String> {
+        self.connections.lock().map(|c| c.iter().map(|(name, _)| name.clone()).collect()).unwrap_or_default()
+    }
+}
+
+impl Drop for MidiOutputs {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,359 @@
+// modmatrix.rs
+
+/* Host Modulation Matrix */
+
+#![allow(warnings)]
+
+use crate::automation::{self, ParamChange};
+use crate::dspapi::{NodeId, ParamId};
+use crate::events::TransportInfo;
+use crate::graph::{self, AudioGraph};
+use crate::taps::TapPoint;
+
+/// Modulation sources an engine can hold; further ones are refused.
+pub const MAX_MOD_SOURCES: usize = 32;
+/// Source-to-parameter routes an engine can hold; further ones are refused.
+pub const MAX_MOD_ROUTES: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LfoShape {
+    Sine,
+    /// Starts at -1.0 and peaks halfway through the cycle.
+    Triangle,
+    /// Rises from -1.0 to 1.0.
+    Saw,
+    Square,
+}
+
+impl LfoShape {
+    fn from_code(code: u8) -> Option<Self> {
+        [LfoShape::Sine, LfoShape::Triangle, LfoShape::Saw, LfoShape::Square].get(code as usize).copied()
+    }
+
+    /// Bipolar value at `phase` (0.0..1.0) of a cycle.
+    fn value(self, phase: f64) -> f32 {
+        match self {
+            LfoShape::Sine => (phase * std::f64::consts::TAU).sin() as f32,
+            LfoShape::Triangle => (1.0 - 4.0 * (phase - 0.5).abs()) as f32,
+            LfoShape::Saw => (2.0 * phase - 1.0) as f32,
+            LfoShape::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LfoRate {
+    /// Cycles per second, free running.
+    Hz(f32),
+    /// Quarter notes per cycle. Locked to the transport position while playing, running at the tempo when stopped.
+    Beats(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModSourceKind {
+    /// A bipolar (-1.0..1.0) low-frequency oscillator.
+    Lfo { shape: LfoShape, rate: LfoRate },
+    /// Follows the peak level of a signal, 0.0..1.0 for unclipped audio, with attack and release times in ms.
+    Envelope { point: TapPoint, attack_ms: f32, release_ms: f32 },
+    /// A knob moved with `set_macro`, 0.0..1.0.
+    Macro,
+}
+
+impl ModSourceKind {
+    /// Decodes a Set Mod Source payload (see `dspapi::Command`).
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        let f32_at = |at: usize| payload.get(at..at + 4).and_then(|b| b.try_into().ok()).map(f32::from_le_bytes);
+        match *payload.first()? {
+            0 => {
+                let shape = LfoShape::from_code(*payload.get(1)?)?;
+                let rate = f32_at(3)?;
+                let rate = if *payload.get(2)? != 0 { LfoRate::Beats(rate) } else { LfoRate::Hz(rate) };
+                Some(ModSourceKind::Lfo { shape, rate })
+            }
+            1 => {
+                let node = u32::from_le_bytes(payload.get(1..5)?.try_into().ok()?);
+                let point = if node == graph::GRAPH_INPUT { TapPoint::Input } else { TapPoint::AfterNode(node) };
+                Some(ModSourceKind::Envelope { point, attack_ms: f32_at(5)?, release_ms: f32_at(9)? })
+            }
+            2 => Some(ModSourceKind::Macro),
+            _ => None,
+        }
+    }
+
+    /// The Set Mod Source payload for this kind.
+    pub fn encode(&self) -> Vec<u8> {
+        match *self {
+            ModSourceKind::Lfo { shape, rate } => {
+                let (synced, rate) = match rate { LfoRate::Hz(hz) => (0, hz), LfoRate::Beats(beats) => (1, beats) };
+                let mut payload = vec![0, shape as u8, synced];
+                payload.extend_from_slice(&rate.to_le_bytes());
+                payload
+            }
+            ModSourceKind::Envelope { point, attack_ms, release_ms } => {
+                let node = point.node().unwrap_or(graph::GRAPH_INPUT);
+                let mut payload = vec![1];
+                payload.extend_from_slice(&node.to_le_bytes());
+                payload.extend_from_slice(&attack_ms.to_le_bytes());
+                payload.extend_from_slice(&release_ms.to_le_bytes());
+                payload
+            }
+            ModSourceKind::Macro => vec![2],
+        }
+    }
+
+    fn is_bipolar(&self) -> bool {
+        matches!(self, ModSourceKind::Lfo { .. })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModSource {
+    pub id: u32,
+    pub kind: ModSourceKind,
+    value: f32,
+    /// Position in the LFO cycle, 0.0..1.0.
+    phase: f64,
+}
+
+impl ModSource {
+    /// Current output, in the source's own range.
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+}
+
+/// The range a route maps its source to before scaling by the depth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Polarity {
+    /// 0.0..1.0: the parameter moves up from its base value (down for a negative depth).
+    Unipolar,
+    /// -1.0..1.0: the parameter moves around its base value.
+    Bipolar,
+}
+
+/// Modulation of one node parameter by one source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModRoute {
+    pub source: u32,
+    pub node_id: NodeId,
+    pub param_id: ParamId,
+    /// Parameter units per unit of the source in `polarity`'s range; negative inverts.
+    pub depth: f32,
+    pub polarity: Polarity,
+}
+
+impl ModRoute {
+    /// Decodes a Set Mod Route command for `node_id`:`param_id`, along with the base value it may carry.
+    pub fn decode(node_id: NodeId, param_id: ParamId, payload: &[u8]) -> Option<(Self, Option<f32>)> {
+        let source = u32::from_le_bytes(payload.get(0..4)?.try_into().ok()?);
+        let depth = f32::from_le_bytes(payload.get(4..8)?.try_into().ok()?);
+        let polarity = if *payload.get(8)? != 0 { Polarity::Bipolar } else { Polarity::Unipolar };
+        let base = payload.get(9..13).and_then(|b| b.try_into().ok()).map(f32::from_le_bytes);
+        Some((ModRoute { source, node_id, param_id, depth, polarity }, base))
+    }
+
+    /// The Set Mod Route payload for this route, optionally setting the parameter's base value.
+    pub fn encode(&self, base: Option<f32>) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(13);
+        payload.extend_from_slice(&self.source.to_le_bytes());
+        payload.extend_from_slice(&self.depth.to_le_bytes());
+        payload.push((self.polarity == Polarity::Bipolar) as u8);
+        if let Some(base) = base { payload.extend_from_slice(&base.to_le_bytes()); }
+        payload
+    }
+}
+
+/// A modulated parameter: the value it rests at and the last value sent.
+#[derive(Debug, Clone, Copy)]
+struct ModTarget {
+    node_id: NodeId,
+    param_id: ParamId,
+    base: f32,
+    sent: Option<f32>,
+}
+
+/// Host-level modulation: LFOs, envelope followers and macro knobs routed to any node parameter. Run on the
+/// audio thread and configured through engine commands; preallocated, so it never allocates once created.
+/// Modulated values reach nodes as parameter changes every `resolution` frames, alongside automation.
+pub struct ModMatrix {
+    sources: Vec<ModSource>,
+    routes: Vec<ModRoute>,
+    targets: Vec<ModTarget>,
+    sample_rate: u32,
+    /// Frames between the changes sent while a value moves; lower is smoother and costs more events.
+    pub resolution: usize,
+}
+
+impl ModMatrix {
+    pub fn new(sample_rate: u32) -> Self {
+        ModMatrix {
+            sources: Vec::with_capacity(MAX_MOD_SOURCES),
+            routes: Vec::with_capacity(MAX_MOD_ROUTES),
+            targets: Vec::with_capacity(MAX_MOD_ROUTES),
+            sample_rate: sample_rate.max(1),
+            resolution: automation::DEFAULT_RESOLUTION,
+        }
+    }
+
+    /// Call when the engine's sample rate changes.
+    pub fn prepare(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate.max(1);
+    }
+
+    pub fn sources(&self) -> &[ModSource] {
+        &self.sources
+    }
+
+    pub fn routes(&self) -> &[ModRoute] {
+        &self.routes
+    }
+
+    /// Adds source `id`, or changes its kind keeping its routes.
+    pub fn set_source(&mut self, id: u32, kind: ModSourceKind) -> Result<(), &'static str> {
+        if let Some(source) = self.sources.iter_mut().find(|s| s.id == id) {
+            source.kind = kind;
+            return Ok(());
+        }
+        if self.sources.len() == self.sources.capacity() { return Err("modulation matrix is full"); }
+        self.sources.push(ModSource { id, kind, value: 0.0, phase: 0.0 });
+        Ok(())
+    }
+
+    /// Removes source `id` and its routes.
+    pub fn remove_source(&mut self, id: u32) {
+        self.sources.retain(|s| s.id != id);
+        self.routes.retain(|r| r.source != id);
+    }
+
+    /// Moves macro knob `id` (0.0..1.0).
+    pub fn set_macro(&mut self, id: u32, value: f32) -> Result<(), &'static str> {
+        match self.sources.iter_mut().find(|s| s.id == id) {
+            Some(source) if source.kind == ModSourceKind::Macro => {
+                source.value = value.clamp(0.0, 1.0);
+                Ok(())
+            }
+            Some(_) => Err("not a macro source"),
+            None => Err("no such modulation source"),
+        }
+    }
+
+    /// Adds `route`, or changes the depth and polarity of the one between the same source and parameter. `base`
+    /// sets the value the parameter is modulated around; it is 0.0 for a newly modulated parameter otherwise.
+    pub fn set_route(&mut self, route: ModRoute, base: Option<f32>) -> Result<(), &'static str> {
+        if !self.sources.iter().any(|s| s.id == route.source) { return Err("no such modulation source"); }
+        let same = |r: &ModRoute| r.source == route.source && r.node_id == route.node_id && r.param_id == route.param_id;
+        match self.routes.iter_mut().find(|r| same(r)) {
+            Some(existing) => *existing = route,
+            None => {
+                if self.routes.len() == self.routes.capacity() { return Err("modulation matrix is full"); }
+                if self.target(route.node_id, route.param_id).is_none() {
+                    // Removed targets are pruned in `render`, so there may be none free until then.
+                    if self.targets.len() == self.targets.capacity() { return Err("modulation matrix is full"); }
+                    self.targets.push(ModTarget { node_id: route.node_id, param_id: route.param_id, base: 0.0, sent: None });
+                }
+                self.routes.push(route);
+            }
+        }
+        if let Some(base) = base { self.set_base(route.node_id, route.param_id, base); }
+        Ok(())
+    }
+
+    /// Removes the route from `source` to `param_id` of `node_id`. Once the parameter has no routes left it
+    /// returns to its base value.
+    pub fn remove_route(&mut self, source: u32, node_id: NodeId, param_id: ParamId) {
+        self.routes.retain(|r| !(r.source == source && r.node_id == node_id && r.param_id == param_id));
+    }
+
+    /// Removes every route to `node_id`, e.g. when the node is deleted.
+    pub fn remove_node(&mut self, node_id: NodeId) {
+        self.routes.retain(|r| r.node_id != node_id);
+        self.targets.retain(|t| t.node_id != node_id);
+    }
+
+    /// Removes every route, keeping the sources.
+    pub fn clear_routes(&mut self) {
+        self.routes.clear();
+        self.targets.clear();
+    }
+
+    /// Sets the value a modulated parameter moves around. Returns false if the parameter isn't modulated, so
+    /// the caller can set it directly instead.
+    pub fn set_base(&mut self, node_id: NodeId, param_id: ParamId, value: f32) -> bool {
+        let Some(index) = self.target(node_id, param_id) else { return false };
+        self.targets[index].base = value;
+        self.targets[index].sent = None;
+        true
+    }
+
+    fn target(&self, node_id: NodeId, param_id: ParamId) -> Option<usize> {
+        self.targets.iter().position(|t| t.node_id == node_id && t.param_id == param_id)
+    }
+
+    /// Updates the envelope followers from the block `graph` just processed (`frames` long). Call after
+    /// `AudioGraph::process`; the followers act on the next render.
+    pub fn follow(&mut self, graph: &AudioGraph, frames: usize) {
+        let sample_rate = self.sample_rate as f32;
+        for source in self.sources.iter_mut() {
+            let ModSourceKind::Envelope { point, attack_ms, release_ms } = source.kind else { continue };
+            let level = graph.level(point).unwrap_or(0.0);
+            let ms = if level > source.value { attack_ms } else { release_ms };
+            let time = ms.max(0.0) * sample_rate / 1000.0;
+            let keep = if time > 0.0 { (-(frames as f32) / time).exp() } else { 0.0 };
+            source.value = level + (source.value - level) * keep;
+        }
+    }
+
+    /// Takes changes in `out` to modulated parameters (automation) as their new base values, then adds the
+    /// modulated values for a render of `frames` starting at the transport's position, keeping `out` in time
+    /// order. Only values that differ from the last one sent are added.
+    pub fn render(&mut self, transport: &TransportInfo, frames: usize, out: &mut Vec<ParamChange>) {
+        if self.targets.is_empty() { return; }
+        let targets = &mut self.targets;
+        out.retain(|change| match targets.iter_mut().find(|t| t.node_id == change.node_id && t.param_id == change.param_id) {
+            Some(target) => {
+                target.base = change.value;
+                target.sent = None;
+                false
+            }
+            None => true,
+        });
+
+        let step = self.resolution.max(1);
+        let frames_per_beat = transport.frames_per_beat(self.sample_rate);
+        for frame in (0..frames).step_by(step) {
+            for source in self.sources.iter_mut() {
+                let ModSourceKind::Lfo { shape, rate } = source.kind else { continue };
+                if let (LfoRate::Beats(beats), true) = (rate, transport.playing) {
+                    let cycle = beats.max(1.0e-3) as f64 * frames_per_beat;
+                    source.phase = ((transport.position + frame as u64) as f64 / cycle).fract();
+                }
+                source.value = shape.value(source.phase);
+                let cycles = match rate {
+                    LfoRate::Hz(hz) => hz.max(0.0) as f64 * step as f64 / self.sample_rate as f64,
+                    LfoRate::Beats(beats) => step as f64 / (beats.max(1.0e-3) as f64 * frames_per_beat),
+                };
+                source.phase = (source.phase + cycles).fract();
+            }
+
+            for target in self.targets.iter_mut() {
+                let mut value = target.base;
+                for route in self.routes.iter().filter(|r| r.node_id == target.node_id && r.param_id == target.param_id) {
+                    let Some(source) = self.sources.iter().find(|s| s.id == route.source) else { continue };
+                    let signal = match (source.kind.is_bipolar(), route.polarity) {
+                        (true, Polarity::Unipolar) => (source.value + 1.0) * 0.5,
+                        (false, Polarity::Bipolar) => source.value * 2.0 - 1.0,
+                        _ => source.value,
+                    };
+                    value += signal * route.depth;
+                }
+                if target.sent == Some(value) { continue; }
+                let change = ParamChange { node_id: target.node_id, param_id: target.param_id, frame: frame as u32, value };
+                if automation::insert_in_order(out, 0, change) { target.sent = Some(value); }
+            }
+        }
+
+        // Parameters whose last route went have been sent back to their base value above.
+        let routes = &self.routes;
+        self.targets.retain(|t| t.sent.is_none() || routes.iter().any(|r| r.node_id == t.node_id && r.param_id == t.param_id));
+    }
+}
@@ -0,0 +1,439 @@
+// modulation.rs
+
+/* Modulation Routing */
+
+#![allow(warnings)]
+
+use crate::dspapi::ParamId;
+use crate::dspengine::AudioNode;
+use crate::events::{NodeEvent, TransportInfo};
+use crate::layout::ChannelLayout;
+use crate::midi::{MidiEvent, MidiWriter};
+use crate::rng::Rng;
+use crate::rtsafety::RtSafety;
+
+/// Audio-rate patch inside the rack: `source` renders a modulation signal from a copy of the input (a sidechain,
+/// or silence for self-oscillating sources), and one of its channels drives `param_id` of `target` sample by sample.
+/// The connection `source -> target:MOD_PORT_BASE + param_id` of a session graph becomes one of these.
+pub struct AudioRateModulation {
+    pub source: Box<dyn AudioNode>,
+    pub target: Box<dyn AudioNode>,
+    pub param_id: ParamId,
+    /// Scale applied to the source signal, in the parameter's units per unit of signal.
+    pub depth: f32,
+    pub source_channel: usize,
+    /// Feed the source silence instead of the input.
+    pub source_silent: bool,
+    /// Utilities applied to the modulation signal before it reaches the target, in order.
+    pub shapers: Vec<Box<dyn ModProcessor>>,
+    channels: usize,
+    scratch: Vec<f32>,
+    modulation: Vec<f32>,
+}
+
+impl AudioRateModulation {
+    /// Fails if `target` doesn't accept audio-rate modulation on `param_id`.
+    pub fn new(source: Box<dyn AudioNode>, target: Box<dyn AudioNode>, param_id: ParamId, depth: f32, channels: usize) -> Result<Self, String> {
+        if !target.audio_rate_params().contains(&param_id) {
+            return Err(format!("{} does not accept audio-rate modulation on param {}", target.get_name(), param_id));
+        }
+        Ok(AudioRateModulation {
+            source,
+            target,
+            param_id,
+            depth,
+            source_channel: 0,
+            source_silent: false,
+            shapers: Vec::new(),
+            channels: channels.max(1),
+            scratch: Vec::new(),
+            modulation: Vec::new(),
+        })
+    }
+
+    /// Runs the source on `buffer` and hands its shaped signal to the target for the coming block.
+    fn modulate(&mut self, buffer: &[f32]) {
+        let len = buffer.len().min(self.scratch.len());
+        let frames = len / self.channels;
+        let scratch = &mut self.scratch[..len];
+        if self.source_silent { scratch.fill(0.0); } else { scratch.copy_from_slice(&buffer[..len]); }
+        self.source.process(scratch);
+
+        let channel = self.source_channel.min(self.channels - 1);
+        for (m, frame) in self.modulation[..frames].iter_mut().zip(scratch.chunks(self.channels)) {
+            let value = self.shapers.iter_mut().fold(frame[channel], |v, shaper| shaper.tick(v, 0.0));
+            *m = value * self.depth;
+        }
+        self.target.set_param_modulation(self.param_id, &self.modulation[..frames]);
+    }
+}
+
+impl AudioNode for AudioRateModulation {
+    fn prepare(&mut self, sample_rate: u32, max_block_size: usize) {
+        self.scratch.resize(max_block_size * self.channels, 0.0);
+        self.modulation.resize(max_block_size, 0.0);
+        self.source.prepare(sample_rate, max_block_size);
+        for shaper in self.shapers.iter_mut() {
+            shaper.prepare(sample_rate);
+        }
+        self.target.prepare(sample_rate, max_block_size);
+    }
+
+    fn set_channel_layout(&mut self, layout: ChannelLayout) {
+        self.channels = layout.channels();
+        self.source.set_channel_layout(layout);
+        self.target.set_channel_layout(layout);
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        self.modulate(buffer);
+        self.target.process(buffer);
+    }
+
+    /// Both nodes get the transport, so tempo-synced sources stay in time.
+    fn set_transport(&mut self, transport: &TransportInfo) {
+        self.source.set_transport(transport);
+        self.target.set_transport(transport);
+    }
+
+    fn handles_events(&self) -> bool { self.target.handles_events() }
+
+    fn host_smoothing(&self, param_id: u32) -> bool { self.target.host_smoothing(param_id) }
+
+    fn process_events(&mut self, audio: &mut [f32], events: &[NodeEvent]) {
+        self.modulate(audio);
+        self.target.process_events(audio, events);
+    }
+
+    fn set_param(&mut self, param_id: u32, payload: &[u8]) {
+        self.target.set_param(param_id, payload);
+    }
+
+    fn get_id(&self) -> u32 { self.target.get_id() }
+
+    fn get_name(&self) -> &str { self.target.get_name() }
+
+    fn latency_samples(&self) -> usize { self.target.latency_samples() }
+
+    fn input_ports(&self) -> usize { self.target.input_ports() }
+
+    fn output_ports(&self) -> usize { self.target.output_ports() }
+
+    fn set_sidechain_input(&mut self, port: u32, input: &[f32]) {
+        self.target.set_sidechain_input(port, input);
+    }
+
+    fn accepts_midi(&self) -> bool { self.target.accepts_midi() }
+
+    fn set_midi_input(&mut self, events: &[MidiEvent]) {
+        self.target.set_midi_input(events);
+    }
+
+    fn produces_midi(&self) -> bool { self.target.produces_midi() }
+
+    fn take_midi_output(&mut self, out: &mut MidiWriter) {
+        self.target.take_midi_output(out);
+    }
+
+    fn rt_safety(&self) -> RtSafety {
+        self.source.rt_safety().combine(self.target.rt_safety())
+    }
+
+    fn reset(&mut self) {
+        self.source.reset();
+        self.shapers.iter_mut().for_each(|shaper| shaper.reset());
+        self.target.reset();
+    }
+}
+
+/// A per-sample processor on modulation signals. `a` is the signal; `b` is a second input
+/// (trigger for sample & hold, the other operand for math and min/max).
+pub trait ModProcessor: Send {
+    fn prepare(&mut self, sample_rate: u32) {}
+    fn tick(&mut self, a: f32, b: f32) -> f32;
+    fn reset(&mut self) {}
+
+    /// Runs a block. `b` may be shorter than `a` (missing values are 0.0).
+    fn render(&mut self, a: &[f32], b: &[f32], out: &mut [f32]) {
+        for (i, (o, &x)) in out.iter_mut().zip(a).enumerate() {
+            *o = self.tick(x, b.get(i).copied().unwrap_or(0.0));
+        }
+    }
+}
+
+/// Holds `a` from the moment `b` rises through 0.5 until the next rising edge.
+pub struct SampleAndHold {
+    held: f32,
+    gate: bool,
+}
+
+impl SampleAndHold {
+    pub fn new() -> Self {
+        SampleAndHold { held: 0.0, gate: false }
+    }
+}
+
+impl ModProcessor for SampleAndHold {
+    fn tick(&mut self, a: f32, b: f32) -> f32 {
+        let gate = b >= 0.5;
+        if gate && !self.gate { self.held = a; }
+        self.gate = gate;
+        self.held
+    }
+
+    fn reset(&mut self) {
+        self.held = 0.0;
+        self.gate = false;
+    }
+}
+
+/// Slew limiter with separate rise and fall times (time to cover a change of 1.0).
+pub struct Lag {
+    pub rise_seconds: f32,
+    pub fall_seconds: f32,
+    sample_rate: u32,
+    value: f32,
+}
+
+impl Lag {
+    pub fn new(rise_seconds: f32, fall_seconds: f32) -> Self {
+        Lag { rise_seconds, fall_seconds, sample_rate: 44100, value: 0.0 }
+    }
+}
+
+impl ModProcessor for Lag {
+    fn prepare(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate.max(1);
+    }
+
+    fn tick(&mut self, a: f32, _b: f32) -> f32 {
+        let seconds = if a > self.value { self.rise_seconds } else { self.fall_seconds };
+        let max_step = if seconds > 0.0 { 1.0 / (seconds * self.sample_rate as f32) } else { f32::INFINITY };
+        self.value += (a - self.value).clamp(-max_step, max_step);
+        self.value
+    }
+
+    fn reset(&mut self) {
+        self.value = 0.0;
+    }
+}
+
+/// Stateless operations on one or two modulation signals.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MathOp {
+    Add,
+    Subtract,
+    Multiply,
+    Min,
+    Max,
+    /// `a * scale + offset`
+    Scale { scale: f32, offset: f32 },
+    /// `1.0 - a`, for unipolar signals.
+    Invert,
+    Abs,
+    Clamp { min: f32, max: f32 },
+    /// Snaps a unipolar signal to `steps` levels.
+    Quantize { steps: u32 },
+}
+
+impl ModProcessor for MathOp {
+    fn tick(&mut self, a: f32, b: f32) -> f32 {
+        match *self {
+            MathOp::Add => a + b,
+            MathOp::Subtract => a - b,
+            MathOp::Multiply => a * b,
+            MathOp::Min => a.min(b),
+            MathOp::Max => a.max(b),
+            MathOp::Scale { scale, offset } => a * scale + offset,
+            MathOp::Invert => 1.0 - a,
+            MathOp::Abs => a.abs(),
+            MathOp::Clamp { min, max } => a.clamp(min, max),
+            MathOp::Quantize { steps } if steps > 1 => {
+                let levels = (steps - 1) as f32;
+                (a * levels).round() / levels
+            }
+            MathOp::Quantize { .. } => a,
+        }
+    }
+}
+
+/// Processors applied in series; `b` is passed to every stage.
+pub struct ModChain {
+    pub stages: Vec<Box<dyn ModProcessor>>,
+}
+
+impl ModChain {
+    pub fn new(stages: Vec<Box<dyn ModProcessor>>) -> Self {
+        ModChain { stages }
+    }
+}
+
+impl ModProcessor for ModChain {
+    fn prepare(&mut self, sample_rate: u32) {
+        for stage in self.stages.iter_mut() {
+            stage.prepare(sample_rate);
+        }
+    }
+
+    fn tick(&mut self, a: f32, b: f32) -> f32 {
+        self.stages.iter_mut().fold(a, |v, stage| stage.tick(v, b))
+    }
+
+    fn reset(&mut self) {
+        for stage in self.stages.iter_mut() {
+            stage.reset();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RandomMode {
+    /// A new random value on every trigger, held until the next.
+    Stepped,
+    /// Glides (cosine) from one random value to the next across each trigger interval.
+    Smooth,
+}
+
+/// Random / humanize modulation source, retriggered every `division` beats at `bpm` (or by rising edges on `b`).
+/// Output is bipolar, `depth` scaled; small depths give humanize-style variation. Values come from a session-seeded
+/// stream, so offline renders repeat exactly after `reset`.
+pub struct RandomSource {
+    pub mode: RandomMode,
+    pub bpm: f32,
+    /// Trigger interval in beats, e.g. 0.25 for sixteenths.
+    pub division: f32,
+    pub depth: f32,
+    /// Interleaved channels when used as a node.
+    pub channels: usize,
+    stream_id: u64,
+    rng: Rng,
+    sample_rate: u32,
+    /// Samples since the last trigger.
+    phase: f64,
+    from: f32,
+    to: f32,
+    gate: bool,
+    id: u32,
+}
+
+impl RandomSource {
+    /// `stream_id` should be stable for the session (e.g. derived from the node id) to keep renders reproducible.
+    pub fn new(id: u32, stream_id: u64, mode: RandomMode, bpm: f32, division: f32) -> Self {
+        let mut rng = Rng::for_stream(stream_id);
+        let to = rng.bipolar();
+        RandomSource {
+            mode,
+            bpm,
+            division,
+            depth: 1.0,
+            channels: 2,
+            stream_id,
+            rng,
+            sample_rate: 44100,
+            phase: 0.0,
+            from: to,
+            to,
+            gate: false,
+            id,
+        }
+    }
+
+    pub fn set_tempo(&mut self, bpm: f32) {
+        self.bpm = bpm;
+    }
+
+    fn interval(&self) -> f64 {
+        let beats_per_second = self.bpm.max(1.0) as f64 / 60.0;
+        (self.division.max(1.0e-3) as f64 / beats_per_second * self.sample_rate as f64).max(1.0)
+    }
+
+    fn trigger(&mut self) {
+        self.from = self.current();
+        self.to = self.rng.bipolar();
+        self.phase = 0.0;
+    }
+
+    fn current(&self) -> f32 {
+        match self.mode {
+            RandomMode::Stepped => self.to,
+            RandomMode::Smooth => {
+                let t = (self.phase / self.interval()).min(1.0) as f32;
+                let eased = 0.5 - 0.5 * (std::f32::consts::PI * t).cos();
+                self.from + (self.to - self.from) * eased
+            }
+        }
+    }
+
+    fn advance(&mut self) -> f32 {
+        self.phase += 1.0;
+        if self.phase >= self.interval() {
+            self.trigger();
+        }
+        self.current() * self.depth
+    }
+}
+
+impl ModProcessor for RandomSource {
+    fn prepare(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate.max(1);
+    }
+
+    fn tick(&mut self, _a: f32, b: f32) -> f32 {
+        let gate = b >= 0.5;
+        if gate && !self.gate { self.trigger(); }
+        self.gate = gate;
+        self.advance()
+    }
+
+    fn reset(&mut self) {
+        self.rng.reseed(self.stream_id);
+        self.to = self.rng.bipolar();
+        self.from = self.to;
+        self.phase = 0.0;
+        self.gate = false;
+    }
+}
+
+/// As a node the source ignores its input and writes its signal to every channel,
+/// so it can drive an `AudioRateModulation` with `source_silent` set.
+impl AudioNode for RandomSource {
+    fn prepare(&mut self, sample_rate: u32, _max_block_size: usize) {
+        ModProcessor::prepare(self, sample_rate);
+    }
+
+    fn set_channel_layout(&mut self, layout: ChannelLayout) {
+        self.channels = layout.channels();
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        for frame in buffer.chunks_mut(self.channels.max(1)) {
+            let value = self.advance();
+            frame.fill(value);
+        }
+    }
+
+    fn set_param(&mut self, param_id: u32, payload: &[u8]) {
+        let Ok(bytes) = payload.try_into() else { return };
+        let value = f32::from_le_bytes(bytes);
+        match param_id {
+            0 => self.bpm = value,
+            1 => self.division = value,
+            2 => self.depth = value,
+            3 => self.mode = if value >= 0.5 { RandomMode::Smooth } else { RandomMode::Stepped },
+            _ => {}
+        }
+    }
+
+    fn get_id(&self) -> u32 { self.id }
+
+    fn get_name(&self) -> &str { "Random" }
+
+    fn param_name(&self, param_id: u32) -> Option<String> {
+        ["Tempo", "Division", "Depth", "Smooth"].get(param_id as usize).map(|s| s.to_string())
+    }
+
+    /// Division and mode are discrete.
+    fn host_smoothing(&self, param_id: u32) -> bool { !matches!(param_id, 1 | 3) }
+
+    fn rt_safety(&self) -> RtSafety { RtSafety::SAFE }
+}
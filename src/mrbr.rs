@@ -131,6 +131,8 @@ impl MagicRingBuffer {
     }
 
     // --- Accessor Methods ---
+    // The producer owns the write region exclusively (SPSC), so handing out `&mut` from `&self` is sound.
+    #[allow(clippy::mut_from_ref)]
     pub fn write_slice(&self, len: usize) -> Option<&mut [f32]> {
         let w = self.write_idx.0.load(Ordering::Relaxed);
         let r = self.read_idx.0.load(Ordering::Acquire);
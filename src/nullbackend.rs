@@ -0,0 +1,93 @@
+// nullbackend.rs
+
+/* Null Audio Backend */
+
+#![allow(warnings)]
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Periods the timer may fall behind (e.g. a suspended VM) before it stops catching up and restarts its clock.
+const MAX_LAG_PERIODS: u32 = 4;
+
+/// How the null backend drives the engine.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NullOptions {
+    /// Render periods back to back as fast as the CPU allows instead of at the sample rate, e.g. for CI.
+    pub free_run: bool,
+    /// Stop calling the engine after this many frames, so a test processes an exact amount of audio; the engine
+    /// still reports itself running, which a `recovery` supervisor would take for a stall. `None` runs until stopped.
+    pub max_frames: Option<u64>,
+}
+
+/// Renders one interleaved period of the outputs.
+pub type RenderFn = Box<dyn FnMut(&mut [f32]) + Send>;
+/// Takes one interleaved period of the inputs.
+pub type CaptureFn = Box<dyn FnMut(&[f32]) + Send>;
+
+/// A stream with no hardware behind it: a timer thread calls the engine's callbacks every period with silent
+/// input and throws the output away. For servers, CI machines and tests.
+pub struct NullStream {
+    frames: Arc<AtomicU64>,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl NullStream {
+    /// Starts calling `render` (and `capture`, if given) with periods of `period_frames` of `channels` at `rate`.
+    pub fn start(options: &NullOptions, channels: usize, rate: u32, period_frames: usize, mut render: RenderFn, mut capture: Option<CaptureFn>) -> Result<Self, String> {
+        let channels = channels.max(1);
+        let period_frames = period_frames.max(1);
+        let period = Duration::from_secs_f64(period_frames as f64 / rate.max(1) as f64);
+        let frames = Arc::new(AtomicU64::new(0));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (counter, stop, options) = (Arc::clone(&frames), Arc::clone(&shutdown), options.clone());
+        let thread = thread::Builder::new()
+            .name("opentune-null".into())
+            .spawn(move || {
+                let mut output = vec![0.0f32; period_frames * channels];
+                let input = vec![0.0f32; period_frames * channels];
+                let mut next = Instant::now();
+                while !stop.load(Ordering::Relaxed) {
+                    let rendered = counter.load(Ordering::Relaxed);
+                    let frames = match options.max_frames {
+                        Some(limit) if rendered >= limit => break,
+                        Some(limit) => ((limit - rendered) as usize).min(period_frames),
+                        None => period_frames,
+                    };
+                    if let Some(capture) = capture.as_mut() {
+                        capture(&input[..frames * channels]);
+                    }
+                    render(&mut output[..frames * channels]);
+                    counter.fetch_add(frames as u64, Ordering::Release);
+                    if options.free_run { continue; }
+
+                    next += period;
+                    let now = Instant::now();
+                    if now < next {
+                        thread::sleep(next - now);
+                    } else if now - next > period * MAX_LAG_PERIODS {
+                        next = now;
+                    }
+                }
+            })
+            .map_err(|e| format!("Failed to spawn null backend thread: {}", e))?;
+        Ok(NullStream { frames, shutdown, thread: Some(thread) })
+    }
+
+    /// Frames rendered since the stream started.
+    pub fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for NullStream {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,432 @@
+// oversample.rs
+
+/* Per-Node Oversampling */
+
+#![allow(warnings)]
+
+use std::f64::consts::PI;
+
+use crate::dspengine::AudioNode;
+use crate::events::{self, EventKind, NodeEvent, TransportInfo};
+use crate::layout::ChannelLayout;
+use crate::midi::{self, MidiEvent, MidiWriter};
+use crate::planar::PlanarBuffer;
+use crate::rtsafety::RtSafety;
+use crate::strip::HOST_PARAM_OVERSAMPLING;
+
+/// Highest supported factor; every buffer is sized for it, so changing the factor never allocates.
+pub const MAX_OVERSAMPLING: usize = 8;
+/// Filter taps per engine-rate frame. Each filter is `factor * PHASE_TAPS + 1` taps long at the oversampled
+/// rate, and the pair delays the signal by exactly `PHASE_TAPS` frames at the engine rate.
+const PHASE_TAPS: usize = 32;
+/// Sidechain ports (after the main input) resampled along with it; the graph keeps its wrappers for any node, so
+/// they are sized for this many. Further ports are left unset while oversampling.
+const MAX_SIDECHAINS: usize = 4;
+/// Passband edge as a share of the engine rate's Nyquist frequency: enough room for the Blackman window's
+/// transition band to reach the stopband before the first image and alias.
+const CUTOFF: f64 = 0.9;
+
+/// Supported factor for a requested one: 1, 2, 4 or 8, rounding down.
+pub fn oversampling_factor(value: f32) -> usize {
+    match value {
+        v if v >= 8.0 => 8,
+        v if v >= 4.0 => 4,
+        v if v >= 2.0 => 2,
+        _ => 1,
+    }
+}
+
+/// Linear-phase windowed-sinc (Blackman) lowpass at `factor` times the engine rate, cutting below the engine
+/// rate's Nyquist frequency, with unity DC gain.
+fn design(factor: usize) -> Vec<f32> {
+    let len = factor * PHASE_TAPS + 1;
+    let center = (len - 1) as f64 / 2.0;
+    let cutoff = CUTOFF / factor as f64;
+    let mut kernel: Vec<f64> = (0..len)
+        .map(|i| {
+            let x = i as f64 - center;
+            let sinc = if x == 0.0 { 1.0 } else { (PI * x * cutoff).sin() / (PI * x * cutoff) };
+            let w = i as f64 / (len - 1) as f64;
+            sinc * (0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos())
+        })
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    kernel.iter().map(|k| (k / sum) as f32).collect()
+}
+
+/// Polyphase interpolator: each input frame becomes `factor` frames, filtered by a kernel from `design`.
+struct Upsampler {
+    channels: usize,
+    /// `PHASE_TAPS` past input frames followed by the current block, interleaved.
+    history: Vec<f32>,
+}
+
+impl Upsampler {
+    fn new(channels: usize, max_frames: usize) -> Self {
+        Upsampler { channels, history: vec![0.0; (PHASE_TAPS + max_frames) * channels] }
+    }
+
+    fn reset(&mut self) {
+        self.history.fill(0.0);
+    }
+
+    /// Writes `input.len() * factor` samples to `output`. Only the taps of one phase meet a non-zero sample of
+    /// the zero-stuffed input, so each output frame costs `PHASE_TAPS` multiplies instead of the whole kernel.
+    fn process(&mut self, kernel: &[f32], factor: usize, input: &[f32], output: &mut [f32]) {
+        let channels = self.channels;
+        let frames = (input.len() / channels).min(self.history.len() / channels - PHASE_TAPS);
+        self.history[PHASE_TAPS * channels..(PHASE_TAPS + frames) * channels].copy_from_slice(&input[..frames * channels]);
+        let gain = factor as f32;
+        for n in 0..frames {
+            for phase in 0..factor {
+                let out = &mut output[(n * factor + phase) * channels..(n * factor + phase + 1) * channels];
+                for (c, sample) in out.iter_mut().enumerate() {
+                    let mut acc = 0.0;
+                    for (k, tap) in kernel[phase..].iter().step_by(factor).enumerate() {
+                        acc += tap * self.history[(PHASE_TAPS + n - k) * channels + c];
+                    }
+                    *sample = acc * gain;
+                }
+            }
+        }
+        self.history.copy_within(frames * channels..(frames + PHASE_TAPS) * channels, 0);
+    }
+}
+
+/// Polyphase decimator: filters with a kernel from `design` and keeps every `factor`th frame, computing only those.
+struct Downsampler {
+    channels: usize,
+    /// `factor * PHASE_TAPS` past oversampled frames followed by the current block, interleaved.
+    history: Vec<f32>,
+}
+
+impl Downsampler {
+    fn new(channels: usize, max_frames: usize) -> Self {
+        Downsampler { channels, history: vec![0.0; (PHASE_TAPS + max_frames) * MAX_OVERSAMPLING * channels] }
+    }
+
+    fn reset(&mut self) {
+        self.history.fill(0.0);
+    }
+
+    /// Writes `input.len() / factor` samples to `output`.
+    fn process(&mut self, kernel: &[f32], factor: usize, input: &[f32], output: &mut [f32]) {
+        let channels = self.channels;
+        let past = kernel.len() - 1;
+        let frames = (output.len() / channels).min(input.len() / channels / factor);
+        let input_frames = frames * factor;
+        self.history[past * channels..(past + input_frames) * channels].copy_from_slice(&input[..input_frames * channels]);
+        for m in 0..frames {
+            let newest = past + m * factor;
+            for (c, sample) in output[m * channels..(m + 1) * channels].iter_mut().enumerate() {
+                let mut acc = 0.0;
+                for (i, tap) in kernel.iter().enumerate() {
+                    acc += tap * self.history[(newest - i) * channels + c];
+                }
+                *sample = acc;
+            }
+        }
+        self.history.copy_within(input_frames * channels..(input_frames + past) * channels, 0);
+    }
+}
+
+/// Runs the wrapped node at `factor` times the engine rate: its input (and sidechains) is upsampled, processed
+/// and filtered back down, so nonlinear nodes (saturators, clippers) alias far less without the whole engine
+/// changing rate. The node is prepared at the oversampled rate and block size and sees MIDI, events, modulation
+/// and the transport position at that rate. The filters add `PHASE_TAPS` frames of latency. At factor 1 it
+/// passes everything straight through. Set the factor with `strip::HOST_PARAM_OVERSAMPLING`, as the graph does.
+///
+/// The graph keeps one wrapper per slot, prepared on the control thread, and swaps nodes in and out of it with
+/// `replace_inner`, so switching the factor on the audio thread never allocates (the node's own `prepare` at the
+/// new rate aside).
+pub struct Oversampled {
+    inner: Box<dyn AudioNode>,
+    factor: usize,
+    channels: usize,
+    /// Engine rate and block size from the last `prepare`; the node runs at `factor` times them.
+    sample_rate: u32,
+    max_block: usize,
+    /// Kernels for 2x, 4x and 8x.
+    kernels: [Vec<f32>; 3],
+    up: Upsampler,
+    down: Downsampler,
+    /// One upsampler per sidechain port, from port 1 up, `MAX_SIDECHAINS` of them.
+    sidechains: Vec<Upsampler>,
+    /// The node's audio at the oversampled rate.
+    buffer: Vec<f32>,
+    /// Upsampled sidechain input and stretched modulation, handed to the node right away.
+    scratch: Vec<f32>,
+    /// MIDI for the next block with frames at the oversampled rate.
+    midi: Vec<MidiEvent>,
+    /// MIDI the node produced, frames at the oversampled rate.
+    midi_out: Vec<MidiEvent>,
+    events: Vec<NodeEvent>,
+}
+
+impl Oversampled {
+    /// Wraps `inner` for interleaved audio of `channels`. Call `prepare` before processing; it prepares `inner`
+    /// again at the oversampled rate.
+    pub fn new(inner: Box<dyn AudioNode>, factor: usize, channels: usize) -> Self {
+        let mut wrapper = Self::empty(channels);
+        wrapper.inner = inner;
+        wrapper.factor = oversampling_factor(factor as f32);
+        wrapper
+    }
+
+    /// A wrapper without a node yet, for `replace_inner`. Call `prepare` before processing.
+    pub fn empty(channels: usize) -> Self {
+        let channels = channels.max(1);
+        Oversampled {
+            inner: Box::new(Vacant),
+            factor: 1,
+            channels,
+            sample_rate: 0,
+            max_block: 0,
+            kernels: [design(2), design(4), design(8)],
+            up: Upsampler::new(channels, 0),
+            down: Downsampler::new(channels, 0),
+            sidechains: Vec::new(),
+            buffer: Vec::new(),
+            scratch: Vec::new(),
+            midi: Vec::with_capacity(midi::MAX_BLOCK_EVENTS),
+            midi_out: Vec::with_capacity(midi::MAX_BLOCK_EVENTS),
+            events: Vec::with_capacity(events::MAX_PENDING_EVENTS + midi::MAX_BLOCK_EVENTS),
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    /// Puts `node` in the wrapper at factor 1 and returns the node it held. `node` should already be prepared
+    /// at the engine rate. Never allocates.
+    pub fn replace_inner(&mut self, node: Box<dyn AudioNode>) -> Box<dyn AudioNode> {
+        self.factor = 1;
+        self.reset_filters();
+        std::mem::replace(&mut self.inner, node)
+    }
+
+    /// Takes the node out, leaving the wrapper empty. Never allocates.
+    pub fn take_inner(&mut self) -> Box<dyn AudioNode> {
+        self.replace_inner(Box::new(Vacant))
+    }
+
+    /// Sizes the buffers for blocks of up to `max_block_size` frames at `sample_rate`, without preparing the
+    /// node. Call from the control thread.
+    pub fn prepare_buffers(&mut self, sample_rate: u32, max_block_size: usize) {
+        let max_block = max_block_size.max(1);
+        self.sample_rate = sample_rate;
+        self.max_block = max_block;
+        self.up = Upsampler::new(self.channels, max_block);
+        self.down = Downsampler::new(self.channels, max_block);
+        self.sidechains = (0..MAX_SIDECHAINS).map(|_| Upsampler::new(self.channels, max_block)).collect();
+        self.buffer = vec![0.0; max_block * MAX_OVERSAMPLING * self.channels];
+        self.scratch = vec![0.0; max_block * MAX_OVERSAMPLING * self.channels];
+        self.midi.clear();
+        self.midi_out.clear();
+    }
+
+    /// Bytes `prepare_buffers` allocates for `channels` and blocks of `max_block` frames, kernels included.
+    pub fn allocated_bytes(channels: usize, max_block: usize) -> usize {
+        let samples = (PHASE_TAPS + max_block) * channels * (1 + MAX_SIDECHAINS + MAX_OVERSAMPLING)
+            + 2 * max_block * MAX_OVERSAMPLING * channels
+            + (2 + 4 + 8) * PHASE_TAPS + 3;
+        samples * std::mem::size_of::<f32>()
+            + 2 * midi::MAX_BLOCK_EVENTS * std::mem::size_of::<MidiEvent>()
+            + (events::MAX_PENDING_EVENTS + midi::MAX_BLOCK_EVENTS) * std::mem::size_of::<NodeEvent>()
+    }
+
+    /// Switches to `factor` (rounded down to a supported one), preparing the node again at the new rate and
+    /// clearing the filters. Doesn't allocate here, but the node's own `prepare` may.
+    pub fn set_factor(&mut self, factor: usize) {
+        let factor = oversampling_factor(factor as f32);
+        if factor == self.factor { return; }
+        self.factor = factor;
+        self.reset_filters();
+        if self.sample_rate > 0 {
+            self.inner.prepare(self.sample_rate * factor as u32, self.max_block * factor);
+        }
+    }
+
+    fn reset_filters(&mut self) {
+        self.up.reset();
+        self.down.reset();
+        self.sidechains.iter_mut().for_each(Upsampler::reset);
+    }
+
+    /// Upsamples `buffer`, has the node process it (with `events` if it handles events) and filters it back.
+    fn process_oversampled(&mut self, buffer: &mut [f32], events: Option<&[NodeEvent]>) {
+        let factor = self.factor;
+        let len = buffer.len().min(self.max_block * self.channels);
+        let (buffer, oversampled) = (&mut buffer[..len], len * factor);
+        let kernel = &self.kernels[factor.trailing_zeros() as usize - 1];
+        self.up.process(kernel, factor, buffer, &mut self.buffer[..oversampled]);
+        match events {
+            Some(events) => {
+                self.events.clear();
+                for event in events.iter().take(self.events.capacity()) {
+                    let event = event.at_frame(event.frame * factor as u32);
+                    self.events.push(match event.kind {
+                        EventKind::Transport(transport) => NodeEvent::transport(event.frame, scale_transport(transport, factor)),
+                        _ => event,
+                    });
+                }
+                self.inner.process_events(&mut self.buffer[..oversampled], &self.events);
+            }
+            None => self.inner.process(&mut self.buffer[..oversampled]),
+        }
+        self.down.process(kernel, factor, &self.buffer[..oversampled], buffer);
+    }
+}
+
+/// `transport` with its position counted in oversampled frames.
+fn scale_transport(transport: TransportInfo, factor: usize) -> TransportInfo {
+    TransportInfo { position: transport.position * factor as u64, ..transport }
+}
+
+impl AudioNode for Oversampled {
+    fn prepare(&mut self, sample_rate: u32, max_block_size: usize) {
+        self.prepare_buffers(sample_rate, max_block_size);
+        self.inner.prepare(sample_rate * self.factor as u32, self.max_block * self.factor);
+    }
+
+    /// Takes the new channel count; the following `prepare` sizes the buffers for it.
+    fn set_channel_layout(&mut self, layout: ChannelLayout) {
+        self.channels = layout.channels();
+        self.inner.set_channel_layout(layout);
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        if self.factor == 1 {
+            self.inner.process(buffer);
+        } else {
+            self.process_oversampled(buffer, None);
+        }
+    }
+
+    fn is_planar(&self) -> bool { self.factor == 1 && self.inner.is_planar() }
+
+    fn process_planar(&mut self, audio: &mut PlanarBuffer) {
+        self.inner.process_planar(audio);
+    }
+
+    fn set_param(&mut self, param_id: u32, payload: &[u8]) {
+        if param_id == HOST_PARAM_OVERSAMPLING {
+            if let Ok(bytes) = <[u8; 4]>::try_from(payload) {
+                self.set_factor(oversampling_factor(f32::from_le_bytes(bytes)));
+            }
+            return;
+        }
+        self.inner.set_param(param_id, payload);
+    }
+
+    fn get_id(&self) -> u32 { self.inner.get_id() }
+
+    fn get_name(&self) -> &str { self.inner.get_name() }
+
+    fn param_name(&self, param_id: u32) -> Option<String> { self.inner.param_name(param_id) }
+
+    /// The node's latency converted to engine frames (rounded up), plus the filters'.
+    fn latency_samples(&self) -> usize {
+        match self.factor {
+            1 => self.inner.latency_samples(),
+            factor => PHASE_TAPS + self.inner.latency_samples().div_ceil(factor),
+        }
+    }
+
+    fn rt_safety(&self) -> RtSafety { self.inner.rt_safety() }
+
+    fn audio_rate_params(&self) -> &[u32] { self.inner.audio_rate_params() }
+
+    /// Repeats every value `factor` times, one per oversampled frame.
+    fn set_param_modulation(&mut self, param_id: u32, modulation: &[f32]) {
+        if self.factor == 1 {
+            self.inner.set_param_modulation(param_id, modulation);
+            return;
+        }
+        let frames = modulation.len().min(self.scratch.len() / self.factor);
+        for (value, stretched) in modulation[..frames].iter().zip(self.scratch.chunks_mut(self.factor)) {
+            stretched.fill(*value);
+        }
+        self.inner.set_param_modulation(param_id, &self.scratch[..frames * self.factor]);
+    }
+
+    fn input_ports(&self) -> usize { self.inner.input_ports() }
+
+    fn set_sidechain_input(&mut self, port: u32, input: &[f32]) {
+        let index = (port as usize).wrapping_sub(1);
+        if self.factor == 1 {
+            self.inner.set_sidechain_input(port, input);
+            return;
+        }
+        if index >= self.sidechains.len() { return; }
+        let len = input.len().min(self.max_block * self.channels);
+        let kernel = &self.kernels[self.factor.trailing_zeros() as usize - 1];
+        self.sidechains[index].process(kernel, self.factor, &input[..len], &mut self.scratch[..len * self.factor]);
+        self.inner.set_sidechain_input(port, &self.scratch[..len * self.factor]);
+    }
+
+    fn accepts_midi(&self) -> bool { self.inner.accepts_midi() }
+
+    fn set_midi_input(&mut self, events: &[MidiEvent]) {
+        if self.factor == 1 {
+            self.inner.set_midi_input(events);
+            return;
+        }
+        self.midi.clear();
+        for event in events.iter().take(self.midi.capacity()) {
+            self.midi.push(event.at_frame(event.frame * self.factor as u32));
+        }
+        self.inner.set_midi_input(&self.midi);
+    }
+
+    fn produces_midi(&self) -> bool { self.inner.produces_midi() }
+
+    fn take_midi_output(&mut self, out: &mut MidiWriter) {
+        if self.factor == 1 {
+            self.inner.take_midi_output(out);
+            return;
+        }
+        self.midi_out.clear();
+        self.inner.take_midi_output(&mut MidiWriter::new(&mut self.midi_out));
+        for event in self.midi_out.iter() {
+            out.push(event.frame / self.factor as u32, event.bytes());
+        }
+    }
+
+    fn output_ports(&self) -> usize { self.inner.output_ports() }
+
+    fn set_transport(&mut self, transport: &TransportInfo) {
+        self.inner.set_transport(&scale_transport(*transport, self.factor));
+    }
+
+    fn handles_events(&self) -> bool { self.inner.handles_events() }
+
+    fn host_smoothing(&self, param_id: u32) -> bool { self.inner.host_smoothing(param_id) }
+
+    fn process_events(&mut self, audio: &mut [f32], events: &[NodeEvent]) {
+        if self.factor == 1 {
+            self.inner.process_events(audio, events);
+        } else {
+            self.process_oversampled(audio, Some(events));
+        }
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+        self.reset_filters();
+    }
+}
+
+/// Stands in for the node while the wrapper is empty.
+struct Vacant;
+
+impl AudioNode for Vacant {
+    fn process(&mut self, _buffer: &mut [f32]) {}
+
+    fn set_param(&mut self, _param_id: u32, _payload: &[u8]) {}
+
+    fn get_id(&self) -> u32 { 0 }
+
+    fn get_name(&self) -> &str { "" }
+}
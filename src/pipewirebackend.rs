@@ -0,0 +1,276 @@
+// pipewirebackend.rs
+
+/* Native PipeWire Backend */
+
+#![allow(warnings)]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+
+use pipewire as pw;
+use pw::properties::properties;
+use pw::spa;
+use pw::stream::{Stream, StreamFlags, StreamState};
+use spa::param::audio::{AudioFormat, AudioInfoRaw};
+use spa::pod::Pod;
+
+use crate::layout::{ChannelLayout, Speaker};
+use crate::threads::{self, ThreadRole};
+
+/// Longest stretch of a PipeWire quantum rendered in one piece; longer quanta are rendered in several.
+pub const MAX_QUANTUM_FRAMES: usize = 8192;
+
+/// How the engine presents itself in the PipeWire graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipeWireOptions {
+    /// Node name, shown in qpwgraph, Helvum and the desktop's sound settings. The capture node gets ` Input`
+    /// appended.
+    pub node_name: String,
+    /// Quantum (frames per graph cycle) to ask for; `None` asks for the engine buffer size. The graph runs at
+    /// the smallest quantum any active node asks for, within the server's limits.
+    pub quantum: Option<u32>,
+    /// Force the quantum while the engine runs instead of only asking for it (`node.force-quantum`).
+    pub force_quantum: bool,
+    /// Let the session manager link the nodes to the default sink and source (or `EngineConfig::output_device`,
+    /// a node name). Without it the nodes start unlinked, for routing in a patchbay.
+    pub autoconnect: bool,
+}
+
+impl Default for PipeWireOptions {
+    fn default() -> Self {
+        PipeWireOptions { node_name: "OpenTune".to_string(), quantum: None, force_quantum: false, autoconnect: true }
+    }
+}
+
+/// Renders one interleaved stretch of the outputs.
+pub type RenderFn = Box<dyn FnMut(&mut [f32]) + Send>;
+/// Takes one interleaved stretch of the inputs.
+pub type CaptureFn = Box<dyn FnMut(&[f32]) + Send>;
+/// Called when a stream fails or the server goes away, with the reason.
+pub type ErrorFn = Box<dyn FnMut(&str) + Send>;
+
+/// SPA position of the channels of `layout`, so the ports are named after their speakers (`output_FL`, ...).
+fn positions(layout: ChannelLayout) -> [u32; 64] {
+    let mut positions = [spa::sys::SPA_AUDIO_CHANNEL_UNKNOWN; 64];
+    for (index, position) in positions.iter_mut().enumerate().take(layout.channels()) {
+        *position = match layout.speaker(index) {
+            Some(Speaker::Mono) => spa::sys::SPA_AUDIO_CHANNEL_MONO,
+            Some(Speaker::FrontLeft) => spa::sys::SPA_AUDIO_CHANNEL_FL,
+            Some(Speaker::FrontRight) => spa::sys::SPA_AUDIO_CHANNEL_FR,
+            Some(Speaker::Center) => spa::sys::SPA_AUDIO_CHANNEL_FC,
+            Some(Speaker::Lfe) => spa::sys::SPA_AUDIO_CHANNEL_LFE,
+            Some(Speaker::SurroundLeft) => spa::sys::SPA_AUDIO_CHANNEL_SL,
+            Some(Speaker::SurroundRight) => spa::sys::SPA_AUDIO_CHANNEL_SR,
+            Some(Speaker::RearLeft) => spa::sys::SPA_AUDIO_CHANNEL_RL,
+            Some(Speaker::RearRight) => spa::sys::SPA_AUDIO_CHANNEL_RR,
+            Some(Speaker::Discrete(n)) => spa::sys::SPA_AUDIO_CHANNEL_AUX0 + n as u32,
+            None => spa::sys::SPA_AUDIO_CHANNEL_UNKNOWN,
+        };
+    }
+    positions
+}
+
+/// The one format the streams offer: interleaved f32 at the engine rate in `layout`. PipeWire converts to and
+/// from whatever the nodes they are linked to use.
+fn format_param(layout: ChannelLayout, rate: u32) -> Result<Vec<u8>, String> {
+    let mut info = AudioInfoRaw::new();
+    info.set_format(AudioFormat::F32LE);
+    info.set_rate(rate);
+    info.set_channels(layout.channels() as u32);
+    info.set_position(positions(layout));
+    let object = spa::pod::Object {
+        type_: spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+        id: spa::param::ParamType::EnumFormat.as_raw(),
+        properties: info.into(),
+    };
+    spa::pod::serialize::PodSerializer::serialize(std::io::Cursor::new(Vec::new()), &spa::pod::Value::Object(object))
+        .map(|(cursor, _)| cursor.into_inner())
+        .map_err(|e| format!("Failed to build the PipeWire format: {:?}", e))
+}
+
+/// Node properties of one stream; `category` is `Playback` or `Capture`.
+fn stream_properties(options: &PipeWireOptions, name: &str, category: &str, quantum: u32, rate: u32, target: Option<&str>) -> pw::properties::Properties {
+    let mut props = properties! {
+        *pw::keys::MEDIA_TYPE => "Audio",
+        *pw::keys::MEDIA_CATEGORY => category,
+        *pw::keys::MEDIA_ROLE => "Production",
+        *pw::keys::APP_NAME => options.node_name.as_str(),
+        *pw::keys::NODE_NAME => name,
+        *pw::keys::NODE_DESCRIPTION => name,
+        *pw::keys::NODE_LATENCY => format!("{}/{}", quantum, rate),
+        *pw::keys::NODE_RATE => format!("1/{}", rate),
+    };
+    if options.force_quantum { props.insert(*pw::keys::NODE_FORCE_QUANTUM, quantum.to_string()); }
+    if let Some(target) = target { props.insert(*pw::keys::TARGET_OBJECT, target); }
+    props
+}
+
+/// A running PipeWire client: an output node with a port per engine channel and, when capturing, an input node
+/// likewise, both driven by PipeWire's realtime thread. The main loop runs on a thread of its own; dropping the
+/// stream stops it and removes the nodes from the graph.
+pub struct PipeWireStream {
+    quit: pw::channel::Sender<()>,
+    thread: Option<JoinHandle<()>>,
+    quantum: Arc<AtomicU32>,
+    node_name: String,
+}
+
+impl PipeWireStream {
+    /// Connects to the PipeWire server and starts the nodes. `rate` is the engine rate the streams run at,
+    /// `buffer_size` the quantum asked for unless the options name one, `target` the node to link the output to
+    /// (`None` for the default sink). `capture` adds the input node, linked to the default source.
+    pub fn start(options: &PipeWireOptions, layout: ChannelLayout, rate: u32, buffer_size: u32, target: Option<String>, render: RenderFn, capture: Option<CaptureFn>, on_error: ErrorFn) -> Result<Self, String> {
+        let quantum = options.quantum.unwrap_or(buffer_size).clamp(16, MAX_QUANTUM_FRAMES as u32);
+        let negotiated = Arc::new(AtomicU32::new(quantum));
+        let (quit, quit_rx) = pw::channel::channel::<()>();
+        let (ready_tx, ready_rx) = mpsc::channel::<Result<(), String>>();
+        let options = options.clone();
+        let node_name = options.node_name.clone();
+        let current = Arc::clone(&negotiated);
+
+        let thread = thread::Builder::new()
+            .name("opentune-pipewire".into())
+            .spawn(move || {
+                let _thread = threads::register_current("opentune-pipewire", ThreadRole::Other);
+                let on_error = Rc::new(RefCell::new(on_error));
+                let result = (|| -> Result<_, String> {
+                    let mainloop = pw::main_loop::MainLoop::new(None).map_err(|e| format!("Failed to create the PipeWire loop: {}", e))?;
+                    let context = pw::context::Context::new(&mainloop).map_err(|e| format!("Failed to create the PipeWire context: {}", e))?;
+                    let core = context.connect(None).map_err(|e| format!("Failed to connect to the PipeWire server: {}", e))?;
+                    Ok((mainloop, context, core))
+                })();
+                let (mainloop, _context, core) = match result {
+                    Ok(parts) => parts,
+                    Err(e) => { let _ = ready_tx.send(Err(e)); return; }
+                };
+
+                // The server going away shows up as an error on the core object.
+                let core_error = Rc::clone(&on_error);
+                let _core_listener = core
+                    .add_listener_local()
+                    .error(move |id, _seq, _res, message| {
+                        if id == pw::core::PW_ID_CORE { (core_error.borrow_mut())(message); }
+                    })
+                    .register();
+
+                let quit_loop = mainloop.clone();
+                let _quit = quit_rx.attach(mainloop.loop_(), move |_| quit_loop.quit());
+
+                let streams = (|| -> Result<_, String> {
+                    let mut flags = StreamFlags::MAP_BUFFERS | StreamFlags::RT_PROCESS;
+                    if options.autoconnect { flags |= StreamFlags::AUTOCONNECT; }
+                    let format = format_param(layout, rate)?;
+                    let stream_err = |e: pw::Error| format!("Failed to create the PipeWire stream: {}", e);
+
+                    let output = Stream::new(&core, &options.node_name, stream_properties(&options, &options.node_name, "Playback", quantum, rate, target.as_deref())).map_err(stream_err)?;
+                    let channels = layout.channels();
+                    let output_error = Rc::clone(&on_error);
+                    let output_listener = output
+                        .add_local_listener_with_user_data((render, vec![0.0f32; MAX_QUANTUM_FRAMES * channels]))
+                        .state_changed(move |_, _, _, state| {
+                            if let StreamState::Error(message) = state { (output_error.borrow_mut())(&message); }
+                        })
+                        .process(move |stream, (render, rendered)| {
+                            let Some(mut buffer) = stream.dequeue_buffer() else { return };
+                            let requested = buffer.requested() as usize;
+                            let Some(data) = buffer.datas_mut().first_mut() else { return };
+                            let stride = channels * std::mem::size_of::<f32>();
+                            let written = match data.data() {
+                                Some(bytes) => {
+                                    let available = bytes.len() / stride;
+                                    let frames = if requested > 0 { requested.min(available) } else { available };
+                                    current.store(frames as u32, Ordering::Relaxed);
+                                    for piece in bytes[..frames * stride].chunks_mut(MAX_QUANTUM_FRAMES * stride) {
+                                        let block = &mut rendered[..piece.len() / std::mem::size_of::<f32>()];
+                                        render(block);
+                                        for (dst, s) in piece.chunks_exact_mut(4).zip(block.iter()) {
+                                            dst.copy_from_slice(&s.to_le_bytes());
+                                        }
+                                    }
+                                    frames
+                                }
+                                None => 0,
+                            };
+                            let chunk = data.chunk_mut();
+                            *chunk.offset_mut() = 0;
+                            *chunk.stride_mut() = stride as i32;
+                            *chunk.size_mut() = (written * stride) as u32;
+                        })
+                        .register()
+                        .map_err(stream_err)?;
+                    let mut params = [Pod::from_bytes(&format).ok_or("Invalid PipeWire format")?];
+                    output.connect(spa::utils::Direction::Output, None, flags, &mut params).map_err(|e| format!("Failed to connect the PipeWire output: {}", e))?;
+
+                    let Some(mut capture) = capture else { return Ok((output, output_listener, None)) };
+                    let input_name = format!("{} Input", options.node_name);
+                    let input = Stream::new(&core, &input_name, stream_properties(&options, &input_name, "Capture", quantum, rate, None)).map_err(stream_err)?;
+                    let input_error = Rc::clone(&on_error);
+                    let input_listener = input
+                        .add_local_listener_with_user_data(vec![0.0f32; MAX_QUANTUM_FRAMES * channels])
+                        .state_changed(move |_, _, _, state| {
+                            if let StreamState::Error(message) = state { (input_error.borrow_mut())(&message); }
+                        })
+                        .process(move |stream, captured| {
+                            let Some(mut buffer) = stream.dequeue_buffer() else { return };
+                            let Some(data) = buffer.datas_mut().first_mut() else { return };
+                            let (offset, size) = (data.chunk().offset() as usize, data.chunk().size() as usize);
+                            let Some(bytes) = data.data() else { return };
+                            let bytes = &bytes[offset.min(bytes.len())..(offset + size).min(bytes.len())];
+                            for piece in bytes.chunks(captured.len() * std::mem::size_of::<f32>()) {
+                                let block = &mut captured[..piece.len() / std::mem::size_of::<f32>()];
+                                for (s, src) in block.iter_mut().zip(piece.chunks_exact(4)) {
+                                    *s = f32::from_le_bytes([src[0], src[1], src[2], src[3]]);
+                                }
+                                capture(block);
+                            }
+                        })
+                        .register()
+                        .map_err(stream_err)?;
+                    let mut params = [Pod::from_bytes(&format).ok_or("Invalid PipeWire format")?];
+                    input.connect(spa::utils::Direction::Input, None, flags, &mut params).map_err(|e| format!("Failed to connect the PipeWire input: {}", e))?;
+                    Ok((output, output_listener, Some((input, input_listener))))
+                })();
+                let _streams = match streams {
+                    Ok(streams) => streams,
+                    Err(e) => { let _ = ready_tx.send(Err(e)); return; }
+                };
+
+                let _ = ready_tx.send(Ok(()));
+                mainloop.run();
+            })
+            .map_err(|e| format!("Failed to spawn the PipeWire thread: {}", e))?;
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(PipeWireStream { quit, thread: Some(thread), quantum: negotiated, node_name }),
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                Err(e)
+            }
+            Err(_) => {
+                let _ = thread.join();
+                Err("The PipeWire thread exited during setup".to_string())
+            }
+        }
+    }
+
+    /// Frames in the last cycle PipeWire ran the output for: the quantum the graph settled on.
+    pub fn quantum(&self) -> u32 {
+        self.quantum.load(Ordering::Relaxed)
+    }
+
+    pub fn node_name(&self) -> &str {
+        &self.node_name
+    }
+}
+
+impl Drop for PipeWireStream {
+    fn drop(&mut self) {
+        let _ = self.quit.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// planar.rs
+
+/* Planar (Deinterleaved) Buffers */
+
+#![allow(warnings)]
+
+use crate::dsp::simd;
+
+/// Deinterleaved audio: one contiguous slice per channel, as VST3, CLAP and LV2 expect. All planes live in a
+/// single allocation sized up front, so filling one on the audio thread never allocates.
+#[derive(Debug, Clone)]
+pub struct PlanarBuffer {
+    data: Vec<f32>,
+    channels: usize,
+    /// Distance between the starts of two planes: the most frames the buffer can hold.
+    stride: usize,
+    frames: usize,
+}
+
+impl PlanarBuffer {
+    pub fn new(channels: usize, max_frames: usize) -> Self {
+        let (channels, stride) = (channels.max(1), max_frames.max(1));
+        PlanarBuffer { data: vec![0.0; channels * stride], channels, stride, frames: 0 }
+    }
+
+    /// Changes the shape and clears the buffer. Allocates; call from the control thread.
+    pub fn resize(&mut self, channels: usize, max_frames: usize) {
+        self.channels = channels.max(1);
+        self.stride = max_frames.max(1);
+        self.data.clear();
+        self.data.resize(self.channels * self.stride, 0.0);
+        self.frames = 0;
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Frames currently held by every plane.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn max_frames(&self) -> usize {
+        self.stride
+    }
+
+    pub fn channel(&self, index: usize) -> &[f32] {
+        let start = index * self.stride;
+        &self.data[start..start + self.frames]
+    }
+
+    pub fn channel_mut(&mut self, index: usize) -> &mut [f32] {
+        let start = index * self.stride;
+        &mut self.data[start..start + self.frames]
+    }
+
+    /// Every plane at once, e.g. to hand a plugin its channel pointers.
+    pub fn channels_mut(&mut self) -> impl Iterator<Item = &mut [f32]> {
+        let frames = self.frames;
+        self.data.chunks_exact_mut(self.stride).map(move |plane| &mut plane[..frames])
+    }
+
+    /// Splits interleaved `input` (`channels` wide) into the planes. Frames beyond `max_frames` are dropped.
+    /// Returns the frames taken.
+    pub fn deinterleave(&mut self, input: &[f32]) -> usize {
+        self.frames = (input.len() / self.channels).min(self.stride);
+        simd::deinterleave(input, self.channels, &mut self.data, self.stride);
+        self.frames
+    }
+
+    /// Writes the planes back into interleaved `output`, as far as it has room. Returns the frames written.
+    pub fn interleave(&self, output: &mut [f32]) -> usize {
+        let frames = (output.len() / self.channels).min(self.frames);
+        simd::interleave(&self.data, self.stride, self.channels, &mut output[..frames * self.channels]);
+        frames
+    }
+}
@@ -0,0 +1,94 @@
+// plugtime.rs
+
+/* Plugin Time Info */
+
+#![allow(warnings)]
+
+use crate::events::TransportInfo;
+
+// VST3 `ProcessContext::StatesAndFlags`.
+pub const VST3_PLAYING: u32 = 1 << 1;
+pub const VST3_RECORDING: u32 = 1 << 3;
+pub const VST3_PROJECT_TIME_MUSIC_VALID: u32 = 1 << 9;
+pub const VST3_TEMPO_VALID: u32 = 1 << 10;
+pub const VST3_BAR_POSITION_VALID: u32 = 1 << 11;
+pub const VST3_TIME_SIG_VALID: u32 = 1 << 13;
+
+// CLAP `clap_transport_flags`.
+pub const CLAP_TRANSPORT_HAS_TEMPO: u32 = 1 << 0;
+pub const CLAP_TRANSPORT_HAS_BEATS_TIMELINE: u32 = 1 << 1;
+pub const CLAP_TRANSPORT_HAS_SECONDS_TIMELINE: u32 = 1 << 2;
+pub const CLAP_TRANSPORT_HAS_TIME_SIGNATURE: u32 = 1 << 3;
+pub const CLAP_TRANSPORT_IS_PLAYING: u32 = 1 << 4;
+pub const CLAP_TRANSPORT_IS_RECORDING: u32 = 1 << 5;
+/// Fixed-point scale of `clap_beattime` and `clap_sectime`.
+pub const CLAP_TIME_FACTOR: f64 = (1u64 << 31) as f64;
+
+/// The transport fields of a VST3 `ProcessContext`, for a wrapper to copy into the one it passes with each
+/// `process` call.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vst3ProcessContext {
+    pub state: u32,
+    pub sample_rate: f64,
+    pub project_time_samples: i64,
+    /// Position in quarter notes.
+    pub project_time_music: f64,
+    /// Start of the current bar in quarter notes.
+    pub bar_position_music: f64,
+    pub tempo: f64,
+    pub time_sig_numerator: i32,
+    pub time_sig_denominator: i32,
+}
+
+impl Vst3ProcessContext {
+    pub fn new(transport: &TransportInfo, sample_rate: u32) -> Self {
+        let time = transport.musical_time(sample_rate);
+        let mut state = VST3_PROJECT_TIME_MUSIC_VALID | VST3_TEMPO_VALID | VST3_BAR_POSITION_VALID | VST3_TIME_SIG_VALID;
+        if transport.playing { state |= VST3_PLAYING; }
+        if transport.recording { state |= VST3_RECORDING; }
+        Vst3ProcessContext {
+            state,
+            sample_rate: sample_rate as f64,
+            project_time_samples: transport.position as i64,
+            project_time_music: time.ppq,
+            bar_position_music: time.bar_start_ppq,
+            tempo: transport.tempo as f64,
+            time_sig_numerator: transport.beats_per_bar as i32,
+            time_sig_denominator: transport.beat_unit as i32,
+        }
+    }
+}
+
+/// The fields of a CLAP `clap_event_transport`, for a wrapper to send at the start of each `process` call.
+/// Times are CLAP fixed point (see `CLAP_TIME_FACTOR`); no loop is reported.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ClapTransport {
+    pub flags: u32,
+    pub song_pos_beats: i64,
+    pub song_pos_seconds: i64,
+    pub tempo: f64,
+    pub bar_start: i64,
+    pub bar_number: i32,
+    pub tsig_num: u16,
+    pub tsig_denom: u16,
+}
+
+impl ClapTransport {
+    pub fn new(transport: &TransportInfo, sample_rate: u32) -> Self {
+        let time = transport.musical_time(sample_rate);
+        let mut flags = CLAP_TRANSPORT_HAS_TEMPO | CLAP_TRANSPORT_HAS_BEATS_TIMELINE | CLAP_TRANSPORT_HAS_SECONDS_TIMELINE | CLAP_TRANSPORT_HAS_TIME_SIGNATURE;
+        if transport.playing { flags |= CLAP_TRANSPORT_IS_PLAYING; }
+        if transport.recording { flags |= CLAP_TRANSPORT_IS_RECORDING; }
+        let seconds = transport.position as f64 / sample_rate.max(1) as f64;
+        ClapTransport {
+            flags,
+            song_pos_beats: (time.ppq * CLAP_TIME_FACTOR).round() as i64,
+            song_pos_seconds: (seconds * CLAP_TIME_FACTOR).round() as i64,
+            tempo: transport.tempo as f64,
+            bar_start: (time.bar_start_ppq * CLAP_TIME_FACTOR).round() as i64,
+            bar_number: time.bar.min(i32::MAX as u64) as i32,
+            tsig_num: transport.beats_per_bar,
+            tsig_denom: transport.beat_unit,
+        }
+    }
+}
@@ -6,15 +6,26 @@
 
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use once_cell::sync::Lazy;
 use walkdir::WalkDir;
 
+use crate::allowlist::{ArtifactKind, ALLOWLIST};
 use crate::dspengine::AudioNode;
-use crate::dspapi::NodeId;
+use crate::dspapi::{Command, NodeId, StatState, RESPONSE_QUEUE};
+use crate::quirks::QuirksDb;
+use crate::rtsafety::{self, RtSafety};
+use crate::threads::{self, ThreadRole};
 
 pub static PMANAGER: Lazy<Arc<Mutex<PluginManager>>> = Lazy::new(|| {
-    Arc::new(Mutex::new(PluginManager::new()))
+    let manager = Arc::new(Mutex::new(PluginManager::new()));
+    PluginManager::start_background_scan(&manager);
+    manager
 });
 
 #[derive(Debug, Clone, Copy)]
@@ -29,27 +40,44 @@ pub enum PluginFormat {
 #[derive(Debug, Clone)]
 pub struct PluginMetadata {
     pub name: String,
+    /// Key into the quirks database. The bundle name until the wrappers read the real CLAP/VST3 id.
+    pub uid: String,
     pub path: PathBuf,
     pub format: PluginFormat,
+    /// The bundle disappeared from disk after it was discovered. Kept so sessions using it can report what is missing.
+    pub missing: bool,
 }
 
 type NodeCreator = Box<dyn Fn() -> Box<dyn AudioNode> + Send + Sync>;
 
 pub struct PluginManager {
     pub registry: HashMap<String, NodeCreator>,
+    /// Plugins found on disk. The background scan merges its results before sending `Plugin Scan Complete`.
     pub discovered_plugins: HashMap<String, PluginMetadata>,
+    /// Per-plugin workarounds applied to every node this manager creates.
+    pub quirks: QuirksDb,
+    /// Measured real-time behaviour per plugin name, overriding what the plugin declares.
+    pub rt_reports: HashMap<String, RtSafety>,
+    /// Allowlist verdict per discovered plugin, reached when it was scanned or with `verify_plugin`, so creating
+    /// a node (on the audio thread) never hashes files. Call `verify_plugins` after changing the allowlist.
+    verdicts: HashMap<String, Result<(), String>>,
     next_node_id: NodeId,
+    scan_ready: Arc<AtomicBool>,
 }
 
 impl PluginManager {
+    /// An empty manager. `PMANAGER` starts a background scan for it; others call `scan_standard_paths` or
+    /// `start_background_scan`.
     pub fn new() -> Self {
-        let mut manager = Self {
+        Self {
             registry: HashMap::new(),
             discovered_plugins: HashMap::new(),
+            quirks: QuirksDb::new(),
+            rt_reports: HashMap::new(),
+            verdicts: HashMap::new(),
             next_node_id: 1000,
-        };
-        manager.scan_standard_paths();
-        manager
+            scan_ready: Arc::new(AtomicBool::new(false)),
+        }
     }
 
     pub fn register<F>(&mut self, name: &str, creator: F)
@@ -59,65 +87,136 @@ impl PluginManager {
         self.registry.insert(name.to_string(), Box::new(creator));
     }
 
-    pub fn scan_standard_paths(&mut self) {
-        let paths = if cfg!(target_os = "windows") {
-            vec![
-                "C:\\Program Files\\Common Files\\VST3",
-                "C:\\Program Files\\Common Files\\CLAP",
-            ]
-        } else if cfg!(target_os = "linux") {
-            vec![
-                "/usr/lib/vst3",
-                "/usr/lib/clap",
-                "/usr/lib/lv2",
-            ]
-        } else {
-            vec!["/Library/Audio/Plug-Ins/Components"]
-        };
+    /// Scans the standard plugin paths on a loader thread so construction (and the first `PMANAGER.lock()`)
+    /// never waits on the filesystem. The results are merged under the manager lock, then `is_scan_ready` is set
+    /// and a `Plugin Scan Complete` response sent.
+    pub fn start_background_scan(manager: &Arc<Mutex<PluginManager>>) {
+        let Ok(pm) = manager.lock() else { return };
+        let ready = Arc::clone(&pm.scan_ready);
+        drop(pm);
+        ready.store(false, Ordering::Release);
 
-        for path in paths {
-            if Path::new(path).exists() {
-                self.scan_directory(path);
+        let shared = Arc::clone(manager);
+        let spawned = thread::Builder::new().name("opentune-scan".into()).spawn(move || {
+            let _thread = threads::register_current("opentune-scan", ThreadRole::Loader);
+            let found: Vec<PluginMetadata> = standard_paths().into_iter().flat_map(scan_directory).collect();
+            let count = found.len() as u32;
+            let verdicts = verify_all(&found);
+            let Ok(mut pm) = shared.lock() else { return };
+            pm.apply_scan(found);
+            pm.verdicts.extend(verdicts);
+            drop(pm);
+            ready.store(true, Ordering::Release);
+            if let Ok(mut queue) = RESPONSE_QUEUE.lock() {
+                queue.push(Command::new(103, "Plugin Scan Complete", count.to_le_bytes().to_vec(), 0, 0, 0, StatState::ACTIVE));
+            }
+        });
+        if let Err(e) = spawned {
+            eprintln!("[PManager] Background scan failed to start ({}), scanning synchronously", e);
+            if let Ok(mut pm) = manager.lock() {
+                pm.scan_standard_paths();
             }
         }
     }
 
-    fn scan_directory(&mut self, dir: &str) {
-        for entry in WalkDir::new(dir).max_depth(3).into_iter().filter_map(|e| e.ok()) {
-            let path = entry.path();
-            let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
-
-            let format = match ext {
-                "vst3" => Some(PluginFormat::Vst3),
-                "clap" => Some(PluginFormat::Clap),
-                "lv2" => Some(PluginFormat::Lv2),
-                _ => None,
-            };
-
-            if let Some(fmt) = format {
-                let name = path.file_stem().unwrap().to_str().unwrap().to_string();
-                self.discovered_plugins.insert(name.clone(), PluginMetadata {
-                    name,
-                    path: path.to_path_buf(),
-                    format: fmt,
-                });
+    /// True once the background scan has finished and its results are in `discovered_plugins`.
+    pub fn is_scan_ready(&self) -> bool {
+        self.scan_ready.load(Ordering::Acquire)
+    }
+
+    /// Scans the standard plugin paths on the calling thread.
+    pub fn scan_standard_paths(&mut self) {
+        let found: Vec<PluginMetadata> = standard_paths().into_iter().flat_map(scan_directory).collect();
+        let verdicts = verify_all(&found);
+        self.apply_scan(found);
+        self.verdicts.extend(verdicts);
+        self.scan_ready.store(true, Ordering::Release);
+    }
+
+    /// Replaces the discovered set with a full scan result. Plugins no longer found are flagged `missing`
+    /// rather than dropped. Returns the number of plugins added and newly missing.
+    pub fn apply_scan(&mut self, found: Vec<PluginMetadata>) -> (u32, u32) {
+        let mut added = 0;
+        let mut removed = 0;
+        for meta in self.discovered_plugins.values_mut() {
+            if !meta.missing && !found.iter().any(|f| f.name == meta.name) {
+                meta.missing = true;
+                removed += 1;
             }
         }
+        for meta in found {
+            let known = self.discovered_plugins.get(&meta.name).is_some_and(|m| !m.missing);
+            if !known { added += 1; }
+            self.discovered_plugins.insert(meta.name.clone(), meta);
+        }
+        (added, removed)
+    }
+
+    /// Checks discovered plugin `name` against the allowlist and remembers the verdict for `create_node`.
+    /// Hashes the bundle with `manager` unlocked, so the audio thread can keep creating nodes meanwhile; still,
+    /// call it from a control thread, e.g. before requesting the node.
+    pub fn verify_plugin(manager: &Mutex<PluginManager>, name: &str) -> Result<(), String> {
+        let path = {
+            let pm = manager.lock().map_err(|_| "Plugin manager lock poisoned".to_string())?;
+            pm.discovered_plugins.get(name).ok_or(format!("Unknown plugin '{}'", name))?.path.clone()
+        };
+        let verdict = verify(name, &path);
+        let mut pm = manager.lock().map_err(|_| "Plugin manager lock poisoned".to_string())?;
+        pm.verdicts.insert(name.to_string(), verdict.clone());
+        verdict
     }
 
-    // This is the method the engine calls
+    /// Verifies every discovered plugin again, e.g. after the allowlist changed. Like `verify_plugin`, hashes with
+    /// `manager` unlocked.
+    pub fn verify_plugins(manager: &Mutex<PluginManager>) {
+        let Ok(pm) = manager.lock() else { return };
+        let plugins: Vec<(String, PathBuf)> = pm.discovered_plugins.values().map(|m| (m.name.clone(), m.path.clone())).collect();
+        drop(pm);
+        let verdicts: Vec<(String, Result<(), String>)> = plugins.iter().map(|(name, path)| (name.clone(), verify(name, path))).collect();
+        if let Ok(mut pm) = manager.lock() {
+            pm.verdicts.extend(verdicts);
+        }
+    }
+
+    /// Whether `name` is a discovered plugin that may not be loaded: the allowlist refused it, or it hasn't been
+    /// verified yet.
+    pub fn is_refused(&self, name: &str) -> bool {
+        self.discovered_plugins.get(name).is_some_and(|m| !m.missing) && !matches!(self.verdicts.get(name), Some(Ok(())))
+    }
+
+    // This is the method the engine calls. Never touches the filesystem: plugins are verified beforehand.
     pub fn create_node(&mut self, name: &str) -> Option<Box<dyn AudioNode>> {
         if let Some(creator) = self.registry.get(name) {
-            return Some(creator());
+            return Some(self.quirks.apply(name, creator()));
         }
 
-        if let Some(meta) = self.discovered_plugins.get(name).cloned() {
-            return self.load_external_plugin(meta);
+        if let Some(meta) = self.discovered_plugins.get(name).filter(|m| !m.missing).cloned() {
+            if self.is_refused(name) { return None; }
+            let uid = meta.uid.clone();
+            return self.load_external_plugin(meta).map(|node| self.quirks.apply(&uid, node));
         }
 
         None
     }
 
+    /// Real-time behaviour of a node created from `name`: the measured report if there is one, else the node's declaration.
+    pub fn rt_safety_of(&self, name: &str, node: &dyn AudioNode) -> RtSafety {
+        self.rt_reports.get(name).copied().unwrap_or_else(|| node.rt_safety())
+    }
+
+    /// Probes a fresh instance of `name` (see `rtsafety::probe`) and remembers the result for strict mode. The
+    /// probe runs with `manager` unlocked, so the audio thread can keep creating nodes meanwhile.
+    pub fn verify_rt_safety(manager: &Mutex<PluginManager>, name: &str, sample_rate: u32, block_size: usize) -> Result<RtSafety, String> {
+        let node = manager.lock().map_err(|_| "Plugin manager lock poisoned".to_string())?.create_node(name);
+        let mut node = node.ok_or(format!("Unknown plugin '{}'", name))?;
+        let safety = rtsafety::probe(node.as_mut(), sample_rate, block_size, 2);
+        let mut pm = manager.lock().map_err(|_| "Plugin manager lock poisoned".to_string())?;
+        pm.rt_reports.insert(name.to_string(), safety);
+        Ok(safety)
+    }
+
+    /// Wrappers fill the plugin's time info from `AudioNode::set_transport` with `plugtime`, so tempo-synced
+    /// plugins follow the engine transport.
     fn load_external_plugin(&self, meta: PluginMetadata) -> Option<Box<dyn AudioNode>> {
         match meta.format {
             PluginFormat::Vst3 => {
@@ -137,4 +236,136 @@ impl PluginManager {
         self.next_node_id += 1;
         id
     }
-}
\ No newline at end of file
+}
+
+/// Allowlist check of one plugin bundle.
+fn verify(name: &str, path: &Path) -> Result<(), String> {
+    ALLOWLIST
+        .lock()
+        .map_err(|_| "Allowlist lock poisoned".to_string())
+        .and_then(|mut list| list.verify(ArtifactKind::Plugin, name, path))
+}
+
+/// Allowlist verdicts for freshly scanned plugins, reached on the scanning thread.
+fn verify_all(found: &[PluginMetadata]) -> Vec<(String, Result<(), String>)> {
+    found.iter().map(|meta| (meta.name.clone(), verify(&meta.name, &meta.path))).collect()
+}
+
+fn standard_paths() -> Vec<&'static str> {
+    let paths = if cfg!(target_os = "windows") {
+        vec![
+            "C:\\Program Files\\Common Files\\VST3",
+            "C:\\Program Files\\Common Files\\CLAP",
+        ]
+    } else if cfg!(target_os = "linux") {
+        vec![
+            "/usr/lib/vst3",
+            "/usr/lib/clap",
+            "/usr/lib/lv2",
+        ]
+    } else {
+        vec!["/Library/Audio/Plug-Ins/Components"]
+    };
+    paths.into_iter().filter(|p| Path::new(p).exists()).collect()
+}
+
+fn scan_directory(dir: &str) -> Vec<PluginMetadata> {
+    let mut found = Vec::new();
+    for entry in WalkDir::new(dir).max_depth(3).into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+
+        let format = match ext {
+            "vst3" => Some(PluginFormat::Vst3),
+            "clap" => Some(PluginFormat::Clap),
+            "lv2" => Some(PluginFormat::Lv2),
+            _ => None,
+        };
+
+        if let Some(fmt) = format {
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()).map(|s| s.to_string()) else { continue };
+            found.push(PluginMetadata {
+                uid: name.clone(),
+                name,
+                path: path.to_path_buf(),
+                format: fmt,
+                missing: false,
+            });
+        }
+    }
+    found
+}
+
+/// Watches the standard plugin paths and rescans when their contents change, so installs and uninstalls
+/// show up without a manual rescan. Changes are debounced (installers write many files) and announced
+/// with a `Plugin Registry Changed` response.
+pub struct PluginWatcher {
+    watcher: Option<RecommendedWatcher>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PluginWatcher {
+    /// Starts watching. Rescans once no change has been seen for `debounce`.
+    pub fn spawn(manager: Arc<Mutex<PluginManager>>, debounce: Duration) -> Result<Self, String> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if event.is_ok() {
+                let _ = tx.send(());
+            }
+        })
+        .map_err(|e| format!("Failed to create plugin watcher: {}", e))?;
+
+        for path in standard_paths() {
+            watcher
+                .watch(Path::new(path), RecursiveMode::Recursive)
+                .map_err(|e| format!("Failed to watch {}: {}", path, e))?;
+        }
+
+        let thread = thread::Builder::new()
+            .name("opentune-plugin-watch".into())
+            .spawn(move || {
+                let _thread = threads::register_current("opentune-plugin-watch", ThreadRole::Loader);
+                // The channel closes when the watcher is dropped, which ends the loop.
+                while rx.recv().is_ok() {
+                    loop {
+                        match rx.recv_timeout(debounce) {
+                            Ok(()) => continue,
+                            Err(RecvTimeoutError::Timeout) => break,
+                            Err(RecvTimeoutError::Disconnected) => return,
+                        }
+                    }
+
+                    let found: Vec<PluginMetadata> = standard_paths().into_iter().flat_map(scan_directory).collect();
+                    let verdicts = verify_all(&found);
+                    let Ok(mut pm) = manager.lock() else { return };
+                    let (added, removed) = pm.apply_scan(found);
+                    pm.verdicts.extend(verdicts);
+                    drop(pm);
+
+                    if added + removed > 0 {
+                        println!("[PManager] Plugin paths changed: {} added, {} removed", added, removed);
+                        let mut payload = added.to_le_bytes().to_vec();
+                        payload.extend_from_slice(&removed.to_le_bytes());
+                        if let Ok(mut queue) = RESPONSE_QUEUE.lock() {
+                            queue.push(Command::new(104, "Plugin Registry Changed", payload, 0, 0, 0, StatState::ACTIVE));
+                        }
+                    }
+                }
+            })
+            .map_err(|e| format!("Failed to spawn plugin watcher: {}", e))?;
+
+        Ok(Self {
+            watcher: Some(watcher),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for PluginWatcher {
+    fn drop(&mut self) {
+        self.watcher = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// preset.rs
+
+/* Internal Node Preset Interchange */
+
+#![allow(warnings)]
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::dspapi::ParamId;
+use crate::session::NodeState;
+
+/// A preset for one node type, independent of any session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodePreset {
+    pub name: String,
+    /// Registry name of the node type the preset belongs to.
+    pub plugin: String,
+    pub params: BTreeMap<ParamId, f32>,
+    #[serde(default)]
+    pub state: Vec<u8>,
+}
+
+impl NodePreset {
+    pub fn from_node(name: &str, node: &NodeState) -> Self {
+        NodePreset {
+            name: name.to_string(),
+            plugin: node.plugin.clone(),
+            params: node.params.clone(),
+            state: node.state.clone(),
+        }
+    }
+
+    /// Applies the preset to a node of the same type.
+    pub fn apply_to(&self, node: &mut NodeState) -> Result<(), String> {
+        if node.plugin != self.plugin {
+            return Err(format!("Preset '{}' is for {}, not {}", self.name, self.plugin, node.plugin));
+        }
+        node.params.extend(self.params.iter().map(|(k, v)| (*k, *v)));
+        node.state = self.state.clone();
+        Ok(())
+    }
+}
+
+/// VST3 class id the node will ship under as a plugin: 32 uppercase hex digits derived from its registry name,
+/// so presets exported now match the plugin later.
+pub fn class_id(plugin: &str) -> String {
+    let digest = Sha256::digest(format!("opentune.{}", plugin).as_bytes());
+    digest[..16].iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+const VST3_HEADER: &[u8; 4] = b"VST3";
+const VST3_VERSION: i32 = 1;
+// "VST3" + version + 32 byte class id + chunk list offset.
+const VST3_HEADER_SIZE: usize = 4 + 4 + 32 + 8;
+
+/// Writes the preset as a `.vstpreset`. The component state ("Comp" chunk) is the preset's JSON, which the
+/// shipped plugin reads back as its state.
+pub fn export_vstpreset(preset: &NodePreset, path: &Path) -> Result<(), String> {
+    let component = serde_json::to_vec(preset).map_err(|e| e.to_string())?;
+
+    let mut data = Vec::with_capacity(VST3_HEADER_SIZE + component.len() + 32);
+    data.extend_from_slice(VST3_HEADER);
+    data.extend_from_slice(&VST3_VERSION.to_le_bytes());
+    data.extend_from_slice(class_id(&preset.plugin).as_bytes());
+    let list_offset = (VST3_HEADER_SIZE + component.len()) as i64;
+    data.extend_from_slice(&list_offset.to_le_bytes());
+    data.extend_from_slice(&component);
+
+    data.extend_from_slice(b"List");
+    data.extend_from_slice(&1i32.to_le_bytes());
+    data.extend_from_slice(b"Comp");
+    data.extend_from_slice(&(VST3_HEADER_SIZE as i64).to_le_bytes());
+    data.extend_from_slice(&(component.len() as i64).to_le_bytes());
+
+    fs::write(path, data).map_err(|e| format!("Failed to write preset {:?}: {}", path, e))
+}
+
+/// Reads a `.vstpreset` written by `export_vstpreset` (or by the shipped plugin).
+pub fn import_vstpreset(path: &Path) -> Result<NodePreset, String> {
+    let data = fs::read(path).map_err(|e| format!("Failed to read preset {:?}: {}", path, e))?;
+    let invalid = |why: &str| format!("Invalid VST3 preset {:?}: {}", path, why);
+
+    if data.len() < VST3_HEADER_SIZE || &data[..4] != VST3_HEADER {
+        return Err(invalid("missing VST3 header"));
+    }
+    let class = String::from_utf8_lossy(&data[8..40]).into_owned();
+    let read_i64 = |at: usize| data.get(at..at.checked_add(8)?).map(|b| i64::from_le_bytes(b.try_into().unwrap()));
+    let read_i32 = |at: usize| data.get(at..at.checked_add(4)?).map(|b| i32::from_le_bytes(b.try_into().unwrap()));
+    let bytes = |at: usize, len: usize| data.get(at..at.checked_add(len)?);
+    // Offsets, sizes and counts come from the file: anything negative or past its end is a corrupt preset.
+    let list = read_i64(40).ok_or(invalid("truncated header"))?;
+    let list = usize::try_from(list).map_err(|_| invalid("missing chunk list"))?;
+    if bytes(list, 4) != Some(b"List".as_slice()) {
+        return Err(invalid("missing chunk list"));
+    }
+    let count = read_i32(list + 4).ok_or(invalid("truncated chunk list"))?;
+    let count = usize::try_from(count).map_err(|_| invalid("negative chunk count"))?;
+    // Each entry takes 20 bytes after the list header, so no more than that many can be in the file.
+    let count = count.min(data.len().saturating_sub(list + 8) / 20);
+
+    for i in 0..count {
+        let entry = list + 8 + i * 20;
+        if bytes(entry, 4) != Some(b"Comp".as_slice()) { continue; }
+        let offset = read_i64(entry + 4).ok_or(invalid("truncated chunk entry"))?;
+        let size = read_i64(entry + 12).ok_or(invalid("truncated chunk entry"))?;
+        let chunk = usize::try_from(offset).ok().zip(usize::try_from(size).ok())
+            .and_then(|(offset, size)| bytes(offset, size))
+            .ok_or(invalid("component chunk out of range"))?;
+
+        let preset: NodePreset = serde_json::from_slice(chunk).map_err(|_| invalid("component state is not an OpenTune preset"))?;
+        if class_id(&preset.plugin) != class {
+            return Err(invalid(&format!("class id {} does not belong to {}", class, preset.plugin)));
+        }
+        return Ok(preset);
+    }
+    Err(invalid("no component state"))
+}
+
+/// CLAP has no standard preset container: plugins load their own files and index them through preset discovery.
+/// The shipped CLAP build indexes these JSON files (`.otpreset`), so the same file works inside and outside OpenTune.
+pub fn export_clap_preset(preset: &NodePreset, path: &Path) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(preset).map_err(|e| e.to_string())?;
+    fs::write(path, text).map_err(|e| format!("Failed to write preset {:?}: {}", path, e))
+}
+
+pub fn import_clap_preset(path: &Path) -> Result<NodePreset, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read preset {:?}: {}", path, e))?;
+    serde_json::from_str(&text).map_err(|e| format!("Invalid preset file {:?}: {}", path, e))
+}
+
+/// Imports either format, by extension.
+pub fn import(path: &Path) -> Result<NodePreset, String> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("vstpreset") => import_vstpreset(path),
+        _ => import_clap_preset(path),
+    }
+}
@@ -0,0 +1,203 @@
+// quirks.rs
+
+/* Plugin Quirks Database */
+
+#![allow(warnings)]
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::blockadapter::FixedBlockAdapter;
+use crate::dspapi::ParamId;
+use crate::dspengine::AudioNode;
+use crate::events::{EventKind, NodeEvent, TransportInfo};
+use crate::layout::ChannelLayout;
+use crate::midi::{self, MidiEvent, MidiWriter};
+use crate::planar::PlanarBuffer;
+use crate::rtsafety::RtSafety;
+
+/// Workarounds for a single misbehaving plugin.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PluginQuirks {
+    /// Always call `process` with exactly this many frames.
+    #[serde(default)]
+    pub fixed_block_size: Option<usize>,
+    /// Latency to report instead of the plugin's own (wrong) value.
+    #[serde(default)]
+    pub latency_override: Option<usize>,
+    /// Parameters whose f32 values must be kept within (min, max).
+    #[serde(default)]
+    pub param_clamps: Vec<(ParamId, f32, f32)>,
+}
+
+impl PluginQuirks {
+    pub fn is_empty(&self) -> bool {
+        *self == PluginQuirks::default()
+    }
+}
+
+/// Quirks keyed by plugin UID (CLAP id, VST3 class id, or registry name for internal nodes).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QuirksDb {
+    pub entries: HashMap<String, PluginQuirks>,
+}
+
+impl QuirksDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a JSON database and merges it over the current entries.
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read quirks {:?}: {}", path, e))?;
+        let db: QuirksDb = serde_json::from_str(&text).map_err(|e| format!("Invalid quirks file {:?}: {}", path, e))?;
+        self.entries.extend(db.entries);
+        Ok(())
+    }
+
+    pub fn insert(&mut self, uid: &str, quirks: PluginQuirks) {
+        self.entries.insert(uid.to_string(), quirks);
+    }
+
+    pub fn get(&self, uid: &str) -> Option<&PluginQuirks> {
+        self.entries.get(uid)
+    }
+
+    /// Wraps `node` with the workarounds registered for `uid`, or returns it untouched.
+    pub fn apply(&self, uid: &str, node: Box<dyn AudioNode>) -> Box<dyn AudioNode> {
+        match self.get(uid) {
+            Some(quirks) if !quirks.is_empty() => {
+                let (inner, adapter_latency) = match quirks.fixed_block_size {
+                    Some(block) => (Box::new(FixedBlockAdapter::new(node, block, 2)) as Box<dyn AudioNode>, block),
+                    None => (node, 0),
+                };
+                let events = Vec::with_capacity(crate::events::MAX_PENDING_EVENTS + midi::MAX_BLOCK_EVENTS);
+                Box::new(QuirkShim { inner, quirks: quirks.clone(), adapter_latency, events })
+            }
+            _ => node,
+        }
+    }
+}
+
+/// Node wrapper enforcing a plugin's quirks around the real node.
+pub struct QuirkShim {
+    inner: Box<dyn AudioNode>,
+    pub quirks: PluginQuirks,
+    /// Latency added by a fixed block adapter between the shim and the plugin.
+    adapter_latency: usize,
+    /// Events with their parameter values clamped.
+    events: Vec<NodeEvent>,
+}
+
+impl QuirkShim {
+    fn clamp(&self, param_id: ParamId, value: f32) -> f32 {
+        match self.quirks.param_clamps.iter().find(|(id, _, _)| *id == param_id) {
+            Some(&(_, min, max)) => value.clamp(min, max),
+            None => value,
+        }
+    }
+}
+
+impl AudioNode for QuirkShim {
+    fn prepare(&mut self, sample_rate: u32, max_block_size: usize) {
+        self.inner.prepare(sample_rate, max_block_size);
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        self.inner.process(buffer);
+    }
+
+    fn is_planar(&self) -> bool {
+        self.inner.is_planar()
+    }
+
+    fn process_planar(&mut self, audio: &mut PlanarBuffer) {
+        self.inner.process_planar(audio);
+    }
+
+    fn set_channel_layout(&mut self, layout: ChannelLayout) {
+        self.inner.set_channel_layout(layout);
+    }
+
+    fn set_param(&mut self, param_id: u32, payload: &[u8]) {
+        match <[u8; 4]>::try_from(payload) {
+            Ok(bytes) => {
+                let value = self.clamp(param_id, f32::from_le_bytes(bytes));
+                self.inner.set_param(param_id, &value.to_le_bytes());
+            }
+            _ => self.inner.set_param(param_id, payload),
+        }
+    }
+
+    fn set_transport(&mut self, transport: &TransportInfo) {
+        self.inner.set_transport(transport);
+    }
+
+    fn handles_events(&self) -> bool { self.inner.handles_events() }
+
+    fn host_smoothing(&self, param_id: u32) -> bool { self.inner.host_smoothing(param_id) }
+
+    fn process_events(&mut self, audio: &mut [f32], events: &[NodeEvent]) {
+        if self.quirks.param_clamps.is_empty() {
+            return self.inner.process_events(audio, events);
+        }
+        let mut clamped = std::mem::take(&mut self.events);
+        clamped.clear();
+        for event in events.iter().take(clamped.capacity()) {
+            clamped.push(match event.kind {
+                EventKind::Param { id, value } => NodeEvent::param(event.frame, id, self.clamp(id, value)),
+                _ => *event,
+            });
+        }
+        self.inner.process_events(audio, &clamped);
+        self.events = clamped;
+    }
+
+    fn get_id(&self) -> u32 { self.inner.get_id() }
+
+    fn get_name(&self) -> &str { self.inner.get_name() }
+
+    fn param_name(&self, param_id: u32) -> Option<String> { self.inner.param_name(param_id) }
+
+    fn rt_safety(&self) -> RtSafety { self.inner.rt_safety() }
+
+    fn audio_rate_params(&self) -> &[u32] { self.inner.audio_rate_params() }
+
+    fn set_param_modulation(&mut self, param_id: u32, modulation: &[f32]) {
+        self.inner.set_param_modulation(param_id, modulation);
+    }
+
+    fn input_ports(&self) -> usize { self.inner.input_ports() }
+
+    fn output_ports(&self) -> usize { self.inner.output_ports() }
+
+    fn set_sidechain_input(&mut self, port: u32, input: &[f32]) {
+        self.inner.set_sidechain_input(port, input);
+    }
+
+    fn accepts_midi(&self) -> bool { self.inner.accepts_midi() }
+
+    fn set_midi_input(&mut self, events: &[MidiEvent]) {
+        self.inner.set_midi_input(events);
+    }
+
+    fn produces_midi(&self) -> bool { self.inner.produces_midi() }
+
+    fn take_midi_output(&mut self, out: &mut MidiWriter) {
+        self.inner.take_midi_output(out);
+    }
+
+    fn latency_samples(&self) -> usize {
+        match self.quirks.latency_override {
+            Some(latency) => latency + self.adapter_latency,
+            None => self.inner.latency_samples(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.events.clear();
+        self.inner.reset();
+    }
+}
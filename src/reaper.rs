@@ -0,0 +1,60 @@
+// reaper.rs
+
+/* Deferred Node Deallocation */
+
+#![allow(warnings)]
+
+use crossbeam::queue::ArrayQueue;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::dspengine::AudioNode;
+use crate::threads::{self, ThreadRole};
+
+/// Nodes taken out of the graph on the audio thread, waiting to be dropped elsewhere. A plugin's destructor can
+/// free memory, unload libraries or block, none of which may happen inside the callback.
+pub type Graveyard = ArrayQueue<Box<dyn AudioNode>>;
+
+/// Background thread dropping the nodes left in a `Graveyard`.
+pub struct NodeReaper {
+    graveyard: Arc<Graveyard>,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl NodeReaper {
+    /// Starts emptying `graveyard` every `interval`.
+    pub fn spawn(graveyard: Arc<Graveyard>, interval: Duration) -> Result<Self, String> {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&shutdown);
+        let queue = Arc::clone(&graveyard);
+        let thread = thread::Builder::new()
+            .name("opentune-node-reaper".into())
+            .spawn(move || {
+                let _thread = threads::register_current("opentune-node-reaper", ThreadRole::Worker);
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(interval);
+                    while let Some(node) = queue.pop() {
+                        drop(node);
+                    }
+                }
+            })
+            .map_err(|e| format!("Failed to spawn node reaper: {}", e))?;
+
+        Ok(Self { graveyard, shutdown, thread: Some(thread) })
+    }
+}
+
+impl Drop for NodeReaper {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        while let Some(node) = self.graveyard.pop() {
+            drop(node);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+// recovery.rs
+
+/* Audio Stream Recovery Supervisor */
+
+#![allow(warnings)]
+
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::dspapi::{Command, StatState, RESPONSE_QUEUE};
+use crate::dspengine::{DspEngine, EngineState};
+use crate::threads::{self, ThreadRole};
+
+/// Wait after the first failed rebuild; doubled after every further failure up to `MAX_BACKOFF`.
+pub const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(8);
+/// Buffer periods without a callback after which a running stream counts as dead.
+pub const STALLED_BUFFERS: u32 = 16;
+
+/// Stream Recovery status: the stream died (text: the cause).
+pub const RECOVERY_LOST: u8 = 0;
+/// Stream Recovery status: a rebuild failed and will be retried after the backoff (text: the error).
+pub const RECOVERY_FAILED: u8 = 1;
+/// Stream Recovery status: the stream is playing again (text: the device name).
+pub const RECOVERY_RESTORED: u8 = 2;
+
+/// 114: Stream Recovery (u8 status, see `RECOVERY_LOST` and on, + u32 rebuild attempts so far + text),
+/// `node_id` is the engine. ACTIVE once restored.
+pub fn recovery_event(engine_id: u32, status: u8, attempts: u32, text: &str) -> Command {
+    let mut payload = vec![status];
+    payload.extend_from_slice(&attempts.to_le_bytes());
+    payload.extend_from_slice(text.as_bytes());
+    let stat = if status == RECOVERY_RESTORED { StatState::ACTIVE } else { StatState::INACTIVE };
+    Command::new(114, "Stream Recovery", payload, engine_id, 0, 0, stat)
+}
+
+/// One outage being recovered from.
+struct Outage {
+    /// The device the engine was configured for when the stream died, tried first every time.
+    device: Option<String>,
+    attempts: u32,
+    backoff: Duration,
+    next_attempt: Instant,
+}
+
+/// Brings a dead output stream back: when the engine goes into the error state while playing, or its callback
+/// stops coming for `STALLED_BUFFERS` periods, the streams are rebuilt on the same device and, failing that,
+/// on the system default, retrying with exponential backoff until one works or the engine is stopped. Progress
+/// is reported as Stream Recovery responses. An unplugged device is also handled by `devices::DeviceWatcher`;
+/// the two can run together.
+pub struct StreamSupervisor {
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StreamSupervisor {
+    /// Starts supervising, checking the engine every `interval`. `engine` is `&DSPENGINE` or an
+    /// `Arc<Mutex<DspEngine>>` shared with the embedder.
+    pub fn spawn<E: Deref<Target = Mutex<DspEngine>> + Send + 'static>(engine: E, interval: Duration) -> Result<Self, String> {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&shutdown);
+        let thread = thread::Builder::new()
+            .name("opentune-recovery".into())
+            .spawn(move || {
+                let _thread = threads::register_current("opentune-recovery", ThreadRole::Supervisor);
+                let mut outage: Option<Outage> = None;
+                let (mut last_beat, mut last_change, mut was_running) = (0u64, Instant::now(), false);
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(interval);
+                    let Ok(mut engine) = engine.lock() else { return };
+                    let state = engine.state();
+                    let beat = engine.heartbeat.load(Ordering::Relaxed);
+                    let running = state == EngineState::Running;
+                    if beat != last_beat || (running && !was_running) {
+                        last_beat = beat;
+                        last_change = Instant::now();
+                    }
+                    was_running = running;
+                    let period = Duration::from_secs_f64(engine.buffer_size as f64 / engine.sample_rate.max(1) as f64);
+
+                    let cause = match &state {
+                        EngineState::Error { cause } => cause.clone(),
+                        EngineState::Running if last_change.elapsed() > period * STALLED_BUFFERS => "audio callback stalled".to_string(),
+                        EngineState::Running => {
+                            // Back, e.g. through the device watcher.
+                            if let Some(done) = outage.take() {
+                                let name = engine.device_name().unwrap_or_default().to_string();
+                                report(recovery_event(engine.engine_id, RECOVERY_RESTORED, done.attempts, &name));
+                            }
+                            continue;
+                        }
+                        // Stopped by the application, or mid-transition.
+                        _ => {
+                            if matches!(state, EngineState::Stopped) { outage = None; }
+                            continue;
+                        }
+                    };
+
+                    let current = outage.get_or_insert_with(|| {
+                        eprintln!("[Recovery] Audio stream died: {}", cause);
+                        report(recovery_event(engine.engine_id, RECOVERY_LOST, 0, &cause));
+                        Outage { device: engine.config.output_device.clone(), attempts: 0, backoff: INITIAL_BACKOFF, next_attempt: Instant::now() }
+                    });
+                    if Instant::now() < current.next_attempt { continue; }
+
+                    current.attempts += 1;
+                    let mut result = engine.restart_streams(current.device.clone());
+                    if result.is_err() && current.device.is_some() {
+                        result = engine.restart_streams(None);
+                    }
+                    match result {
+                        Ok(name) => {
+                            eprintln!("[Recovery] Audio stream restored on '{}'.", name);
+                            report(recovery_event(engine.engine_id, RECOVERY_RESTORED, current.attempts, &name));
+                            outage = None;
+                            last_change = Instant::now();
+                        }
+                        Err(e) => {
+                            report(recovery_event(engine.engine_id, RECOVERY_FAILED, current.attempts, &e));
+                            current.next_attempt = Instant::now() + current.backoff;
+                            current.backoff = (current.backoff * 2).min(MAX_BACKOFF);
+                        }
+                    }
+                }
+            })
+            .map_err(|e| format!("Failed to spawn stream supervisor: {}", e))?;
+
+        Ok(Self { shutdown, thread: Some(thread) })
+    }
+}
+
+fn report(event: Command) {
+    if let Ok(mut queue) = RESPONSE_QUEUE.lock() {
+        queue.push(event);
+    }
+}
+
+impl Drop for StreamSupervisor {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
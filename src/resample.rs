@@ -0,0 +1,168 @@
+// resample.rs
+
+/* Streaming Sample-Rate Conversion */
+
+#![allow(warnings)]
+
+use std::f64::consts::PI;
+
+/// Kernel half-width in input frames; the filter has `2 * HALF_TAPS` taps.
+const HALF_TAPS: usize = 16;
+const TAPS: usize = HALF_TAPS * 2;
+/// Fractional positions tabulated per input frame; positions in between are interpolated.
+const PHASES: usize = 512;
+
+/// Windowed-sinc (Blackman) resampler for interleaved audio at any ratio. Input is pushed, output pulled;
+/// all buffers are sized at construction, so `push`/`pull`/`render` never allocate and can run on the audio thread.
+pub struct Resampler {
+    channels: usize,
+    from_rate: u32,
+    to_rate: u32,
+    /// Input frames consumed per output frame.
+    step: f64,
+    /// `(PHASES + 1) * TAPS` kernel weights.
+    table: Vec<f32>,
+    /// Pending input, interleaved; starts with `HALF_TAPS` frames of silence.
+    history: Vec<f32>,
+    /// Read position in `history`, in frames.
+    pos: f64,
+    /// Scratch for `render`.
+    block: Vec<f32>,
+}
+
+impl Resampler {
+    /// `max_push_frames` bounds a single `push` (and the block size `render` asks its source for).
+    pub fn new(channels: usize, from_rate: u32, to_rate: u32, max_push_frames: usize) -> Self {
+        let channels = channels.max(1);
+        let from_rate = from_rate.max(1);
+        let to_rate = to_rate.max(1);
+        // Below 1.0 when downsampling, so the kernel also removes what would alias.
+        let cutoff = (to_rate as f64 / from_rate as f64).min(1.0) * 0.97;
+
+        let mut table = vec![0.0f32; (PHASES + 1) * TAPS];
+        for phase in 0..=PHASES {
+            let frac = phase as f64 / PHASES as f64;
+            let row = &mut table[phase * TAPS..(phase + 1) * TAPS];
+            let mut sum = 0.0;
+            for (t, weight) in row.iter_mut().enumerate() {
+                // Tap t reads frame floor(pos) - HALF_TAPS + 1 + t.
+                let x = (t as f64 - (HALF_TAPS as f64 - 1.0)) - frac;
+                let sinc = if x.abs() < 1e-9 { 1.0 } else { (PI * x * cutoff).sin() / (PI * x * cutoff) };
+                let w = (x / HALF_TAPS as f64 + 1.0) / 2.0;
+                let window = if (0.0..=1.0).contains(&w) {
+                    0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos()
+                } else {
+                    0.0
+                };
+                let value = sinc * window;
+                *weight = value as f32;
+                sum += value;
+            }
+            // Unity DC gain at every phase.
+            for weight in row.iter_mut() {
+                *weight /= sum as f32;
+            }
+        }
+
+        let max_push_frames = max_push_frames.max(1);
+        let mut history = Vec::with_capacity((max_push_frames + TAPS + 2) * channels);
+        history.resize(HALF_TAPS * channels, 0.0);
+        Resampler {
+            channels,
+            from_rate,
+            to_rate,
+            step: from_rate as f64 / to_rate as f64,
+            table,
+            history,
+            pos: HALF_TAPS as f64,
+            block: vec![0.0; max_push_frames * channels],
+        }
+    }
+
+    pub fn from_rate(&self) -> u32 {
+        self.from_rate
+    }
+
+    pub fn to_rate(&self) -> u32 {
+        self.to_rate
+    }
+
+    /// Input frames needed beyond each output position: the delay added when input arrives in real time.
+    pub fn latency_frames(&self) -> usize {
+        HALF_TAPS
+    }
+
+    /// Scales the conversion ratio by `ratio` (1.0 is exact): above 1.0 consumes input faster, so a clock
+    /// that runs slow relative to the source can be tracked. Takes effect from the next output frame.
+    pub fn set_drift(&mut self, ratio: f64) {
+        self.step = self.from_rate as f64 / self.to_rate as f64 * ratio;
+    }
+
+    /// Input frames pushed but not yet passed by the read position.
+    pub fn pending_frames(&self) -> usize {
+        ((self.history.len() / self.channels) as f64 - self.pos).max(0.0) as usize
+    }
+
+    /// Clears pending input, e.g. after a stream restart.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.history.resize(HALF_TAPS * self.channels, 0.0);
+        self.pos = HALF_TAPS as f64;
+    }
+
+    /// Appends input. Returns the frames accepted; input beyond the capacity given to `new` is dropped.
+    pub fn push(&mut self, input: &[f32]) -> usize {
+        let room = (self.history.capacity() - self.history.len()) / self.channels;
+        let frames = (input.len() / self.channels).min(room);
+        self.history.extend_from_slice(&input[..frames * self.channels]);
+        frames
+    }
+
+    /// Writes as many output frames as the pending input allows. Returns the frames written.
+    pub fn pull(&mut self, output: &mut [f32]) -> usize {
+        let available = self.history.len() / self.channels;
+        let mut written = 0;
+        for frame in output.chunks_exact_mut(self.channels) {
+            let base = self.pos.floor() as usize;
+            if base + HALF_TAPS >= available { break; }
+
+            let phase = (self.pos - base as f64) * PHASES as f64;
+            let index = (phase as usize).min(PHASES - 1);
+            let blend = (phase - index as f64) as f32;
+            let (a, b) = (&self.table[index * TAPS..(index + 1) * TAPS], &self.table[(index + 1) * TAPS..(index + 2) * TAPS]);
+            let start = (base + 1 - HALF_TAPS) * self.channels;
+
+            frame.fill(0.0);
+            for t in 0..TAPS {
+                let weight = a[t] + (b[t] - a[t]) * blend;
+                let input = &self.history[start + t * self.channels..start + (t + 1) * self.channels];
+                for (out, sample) in frame.iter_mut().zip(input) {
+                    *out += sample * weight;
+                }
+            }
+            self.pos += self.step;
+            written += 1;
+        }
+
+        // Drop input no future output frame can reach.
+        let keep_from = (self.pos.floor() as usize + 1).saturating_sub(HALF_TAPS).min(available);
+        if keep_from > 0 {
+            self.history.drain(..keep_from * self.channels);
+            self.pos -= keep_from as f64;
+        }
+        written
+    }
+
+    /// Fills `output` completely, asking `source` for blocks of input frames whenever more is needed.
+    pub fn render(&mut self, output: &mut [f32], mut source: impl FnMut(&mut [f32])) {
+        let mut done = 0;
+        while output.len() - done >= self.channels {
+            done += self.pull(&mut output[done..]) * self.channels;
+            if done >= output.len() { break; }
+            let mut block = std::mem::take(&mut self.block);
+            source(&mut block);
+            self.push(&block);
+            self.block = block;
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// rng.rs
+
+/* Session-Seeded Random Number Generation */
+
+#![allow(warnings)]
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Seed every stochastic component derives its stream from. Set it from the session before rendering.
+static SESSION_SEED: AtomicU64 = AtomicU64::new(0x4F50_454E_5455_4E45);
+
+pub fn set_session_seed(seed: u64) {
+    SESSION_SEED.store(seed, Ordering::Relaxed);
+}
+
+pub fn session_seed() -> u64 {
+    SESSION_SEED.load(Ordering::Relaxed)
+}
+
+/// SplitMix64 generator. Cheap, allocation-free and safe to use on the audio thread.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    /// An independent stream derived from the session seed. Components pass a stable id
+    /// (e.g. node id and a per-node purpose) so renders stay bit-identical regardless of creation order.
+    pub fn for_stream(stream_id: u64) -> Self {
+        let mut mix = Rng::new(session_seed() ^ stream_id.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        Rng::new(mix.next_u64())
+    }
+
+    /// Rewinds to the start of the stream for `stream_id`, e.g. when an offline render restarts.
+    pub fn reseed(&mut self, stream_id: u64) {
+        *self = Rng::for_stream(stream_id);
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in 0.0..1.0.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniform in -1.0..1.0.
+    pub fn bipolar(&mut self) -> f32 {
+        self.next_f32() * 2.0 - 1.0
+    }
+
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// Triangular distribution in -1.0..1.0, the usual TPDF dither shape.
+    pub fn triangular(&mut self) -> f32 {
+        self.next_f32() - self.next_f32()
+    }
+}
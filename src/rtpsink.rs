@@ -0,0 +1,313 @@
+// rtpsink.rs
+
+/* RTP/AES67 Network Output */
+
+#![allow(warnings)]
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::aggregate::{OutputFeeds, MAX_DRIFT_PPM};
+use crate::rng::Rng;
+use crate::threads::{self, ThreadRole};
+
+/// Dynamic payload type AES67 streams usually announce for L24.
+pub const AES67_PAYLOAD_TYPE: u8 = 97;
+/// Port AES67 devices listen on unless told otherwise.
+pub const AES67_PORT: u16 = 5004;
+/// Rates AES67 receivers accept; 48 kHz is the one every device supports.
+pub const AES67_RATES: [u32; 3] = [44_100, 48_000, 96_000];
+/// Largest payload sent, so packets fit a standard Ethernet MTU without fragmenting.
+pub const MAX_PAYLOAD_BYTES: usize = 1440;
+const RTP_HEADER_BYTES: usize = 12;
+const BYTES_PER_SAMPLE: usize = 3;
+/// Pace correction per unit of relative queue error, in ppm.
+const DRIFT_GAIN_PPM: f64 = 2000.0;
+/// Time constant of the queue level average the pace follows, in seconds.
+const FILL_SMOOTHING_SECONDS: f64 = 1.0;
+
+/// Where and how the master output is streamed.
+#[derive(Debug, Clone)]
+pub struct RtpConfig {
+    /// Multicast group (or unicast receiver) and port.
+    pub destination: SocketAddr,
+    /// Local address to send from, picking the interface on hosts with several. Any interface when `None`.
+    pub source: Option<IpAddr>,
+    /// Audio per packet. AES67 requires receivers to take 1 ms; shorter times lower latency on networks built for it.
+    pub packet_time: Duration,
+    /// Multicast hops allowed; 32 keeps the stream on site.
+    pub ttl: u32,
+    pub payload_type: u8,
+    /// Session name announced in the SDP, as receivers list it.
+    pub session_name: String,
+}
+
+impl Default for RtpConfig {
+    fn default() -> Self {
+        RtpConfig {
+            destination: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 69, 0, 1)), AES67_PORT),
+            source: None,
+            packet_time: Duration::from_millis(1),
+            ttl: 32,
+            payload_type: AES67_PAYLOAD_TYPE,
+            session_name: "OpenTune".to_string(),
+        }
+    }
+}
+
+/// Counters the sender thread keeps.
+#[derive(Default)]
+pub struct RtpStats {
+    packets: AtomicU64,
+    send_errors: AtomicU64,
+    /// Times the engine fell behind and the stream paused until a block was queued again.
+    underruns: AtomicU64,
+}
+
+impl RtpStats {
+    pub fn packets(&self) -> u64 {
+        self.packets.load(Ordering::Relaxed)
+    }
+
+    pub fn send_errors(&self) -> u64 {
+        self.send_errors.load(Ordering::Relaxed)
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+}
+
+/// The stream as reported by `DspEngine::rtp_stream`.
+#[derive(Debug, Clone)]
+pub struct RtpStreamInfo {
+    pub destination: SocketAddr,
+    /// Session description to hand to receivers (or announce over SAP).
+    pub sdp: String,
+    pub packets: u64,
+    pub send_errors: u64,
+    pub underruns: u64,
+}
+
+/// Sends one output feed as RTP with L24 payload (24-bit big-endian PCM, RFC 3190), the format AES67 mandates.
+/// Packets are paced on the sender's clock, trimmed so the audio queued from the engine stays about a block: the
+/// stream follows the engine's clock.
+/// The RTP clock is the engine's sample clock, not PTP: receivers that insist on a PTP reference clock won't lock.
+pub struct RtpSender {
+    config: RtpConfig,
+    local: SocketAddr,
+    channels: usize,
+    sample_rate: u32,
+    packet_frames: usize,
+    /// RTP timestamp of the first packet, announced as the media clock offset.
+    start_timestamp: u32,
+    session_id: u64,
+    stats: Arc<RtpStats>,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RtpSender {
+    /// Starts streaming feed `feed` of `feeds`, interleaved `channels` at `sample_rate`, rendered in blocks of
+    /// `block` frames.
+    pub fn spawn(feeds: Arc<OutputFeeds>, feed: usize, channels: usize, sample_rate: u32, block: usize, config: RtpConfig) -> Result<Self, String> {
+        if !AES67_RATES.contains(&sample_rate) {
+            return Err(format!("AES67 streams run at 44.1, 48 or 96 kHz, not {} Hz", sample_rate));
+        }
+        let packet_frames = ((sample_rate as f64 * config.packet_time.as_secs_f64()).round() as usize).max(1);
+        let payload = packet_frames * channels * BYTES_PER_SAMPLE;
+        if payload > MAX_PAYLOAD_BYTES {
+            return Err(format!("{} channels of {} frames need {} bytes per packet, over the {} an MTU allows; shorten the packet time", channels, packet_frames, payload, MAX_PAYLOAD_BYTES));
+        }
+        if feeds.ring(feed).is_none() {
+            return Err(format!("Output feed {} is not claimed", feed));
+        }
+
+        let bind = SocketAddr::new(config.source.unwrap_or(match config.destination {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED),
+        }), 0);
+        let socket = UdpSocket::bind(bind).map_err(|e| format!("Failed to open RTP socket: {}", e))?;
+        if config.destination.ip().is_multicast() && config.destination.is_ipv4() {
+            socket.set_multicast_ttl_v4(config.ttl).map_err(|e| format!("Failed to set multicast TTL: {}", e))?;
+        }
+        // Connecting picks the interface, so the SDP can name the address packets come from.
+        socket.connect(config.destination).map_err(|e| format!("Failed to reach {}: {}", config.destination, e))?;
+        let local = socket.local_addr().map_err(|e| e.to_string())?;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut rng = Rng::new(now.as_nanos() as u64 ^ feed as u64);
+        let ssrc = rng.next_u64() as u32;
+        let start_timestamp = rng.next_u64() as u32;
+        let stats = Arc::new(RtpStats::default());
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let mut packetizer = Packetizer::new(config.payload_type, ssrc, start_timestamp, (rng.next_u64() >> 48) as u16, packet_frames, channels);
+        let (counters, stop) = (Arc::clone(&stats), Arc::clone(&shutdown));
+        let period = Duration::from_secs_f64(packet_frames as f64 / sample_rate as f64);
+        let samples = packet_frames * channels;
+        // Engine blocks arrive in bursts: keep one queued plus a packet, and send anything far beyond that at once.
+        let target = (block.max(packet_frames) + packet_frames) * channels;
+        let max_backlog = target * 2 + block * channels;
+        let alpha = (period.as_secs_f64() / FILL_SMOOTHING_SECONDS).min(1.0);
+        let thread = thread::Builder::new()
+            .name("opentune-rtp".into())
+            .spawn(move || {
+                let _thread = threads::register_current("opentune-rtp", ThreadRole::Worker);
+                let Some(ring) = feeds.ring(feed) else { return };
+                // Audio queued before the stream started would arrive late.
+                let stale = ring.read_slice().len();
+                ring.consume(stale - stale % channels);
+                let mut next = Instant::now();
+                let mut fill = target as f64;
+                let mut primed = false;
+                let mut resumed = false;
+                while !stop.load(Ordering::Relaxed) {
+                    let queued = ring.read_slice().len();
+                    if !primed {
+                        if queued < target {
+                            thread::sleep(period / 4);
+                            continue;
+                        }
+                        primed = true;
+                        next = Instant::now();
+                        fill = queued as f64;
+                    }
+                    if queued < samples {
+                        // The engine fell behind: queue a block again and mark the packet after the gap.
+                        primed = false;
+                        resumed = true;
+                        counters.underruns.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    let now = Instant::now();
+                    if now < next && queued <= max_backlog {
+                        thread::sleep((next - now).min(period));
+                        continue;
+                    }
+                    // Trim the pace so the queue stays near the target, following the engine's clock.
+                    fill += alpha * (queued as f64 - fill);
+                    let correction = ((fill - target as f64) / target as f64 * DRIFT_GAIN_PPM).clamp(-MAX_DRIFT_PPM, MAX_DRIFT_PPM) * 1e-6;
+                    // A stall restarts the pacing rather than bursting to catch up.
+                    let base = if now > next + period * 4 { now } else { next };
+                    next = base + period.mul_f64(1.0 - correction);
+                    let packet = packetizer.packet(&ring.read_slice()[..samples], resumed);
+                    resumed = false;
+                    match socket.send(packet) {
+                        Ok(_) => { counters.packets.fetch_add(1, Ordering::Relaxed); }
+                        Err(_) => { counters.send_errors.fetch_add(1, Ordering::Relaxed); }
+                    }
+                    ring.consume(samples);
+                }
+            })
+            .map_err(|e| format!("Failed to spawn RTP sender thread: {}", e))?;
+
+        Ok(RtpSender {
+            config,
+            local,
+            channels,
+            sample_rate,
+            packet_frames,
+            start_timestamp,
+            session_id: now.as_secs(),
+            stats,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn config(&self) -> &RtpConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> Arc<RtpStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Session description (RFC 4566) with the AES67 attributes receivers need to subscribe.
+    pub fn sdp(&self) -> String {
+        let family = |ip: IpAddr| if ip.is_ipv4() { "IP4" } else { "IP6" };
+        let destination = self.config.destination;
+        let group = if destination.ip().is_multicast() && destination.is_ipv4() {
+            format!("{}/{}", destination.ip(), self.config.ttl)
+        } else {
+            destination.ip().to_string()
+        };
+        let ptime_ms = self.packet_frames as f64 * 1000.0 / self.sample_rate as f64;
+        let ptime = if ptime_ms.fract() == 0.0 { format!("{}", ptime_ms) } else { format!("{:.3}", ptime_ms) };
+        let pt = self.config.payload_type;
+        [
+            "v=0".to_string(),
+            format!("o=- {} {} IN {} {}", self.session_id, self.session_id, family(self.local.ip()), self.local.ip()),
+            format!("s={}", self.config.session_name),
+            format!("c=IN {} {}", family(destination.ip()), group),
+            "t=0 0".to_string(),
+            format!("m=audio {} RTP/AVP {}", destination.port(), pt),
+            format!("a=rtpmap:{} L24/{}/{}", pt, self.sample_rate, self.channels),
+            format!("a=ptime:{}", ptime),
+            "a=sendonly".to_string(),
+            "a=ts-refclk:local".to_string(),
+            format!("a=mediaclk:direct={}", self.start_timestamp),
+        ]
+        .join("\r\n")
+            + "\r\n"
+    }
+
+    pub fn info(&self) -> RtpStreamInfo {
+        RtpStreamInfo {
+            destination: self.config.destination,
+            sdp: self.sdp(),
+            packets: self.stats.packets(),
+            send_errors: self.stats.send_errors(),
+            underruns: self.stats.underruns(),
+        }
+    }
+}
+
+impl Drop for RtpSender {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Builds consecutive RTP packets in one reused buffer.
+struct Packetizer {
+    buffer: Vec<u8>,
+    sequence: u16,
+    timestamp: u32,
+    packet_frames: usize,
+    /// Whether a packet has gone out, so the first one carries the marker bit.
+    started: bool,
+}
+
+impl Packetizer {
+    fn new(payload_type: u8, ssrc: u32, timestamp: u32, sequence: u16, packet_frames: usize, channels: usize) -> Self {
+        let mut buffer = vec![0u8; RTP_HEADER_BYTES + packet_frames * channels * BYTES_PER_SAMPLE];
+        buffer[0] = 0x80; // version 2, no padding, extension or CSRCs
+        buffer[1] = payload_type & 0x7F;
+        buffer[8..12].copy_from_slice(&ssrc.to_be_bytes());
+        Packetizer { buffer, sequence, timestamp, packet_frames, started: false }
+    }
+
+    /// Packs `samples` (interleaved f32) as the next packet. `resumed` marks the first packet after a gap.
+    fn packet(&mut self, samples: &[f32], resumed: bool) -> &[u8] {
+        let marker = !self.started || resumed;
+        self.buffer[1] = (self.buffer[1] & 0x7F) | if marker { 0x80 } else { 0 };
+        self.buffer[2..4].copy_from_slice(&self.sequence.to_be_bytes());
+        self.buffer[4..8].copy_from_slice(&self.timestamp.to_be_bytes());
+        for (out, &sample) in self.buffer[RTP_HEADER_BYTES..].chunks_exact_mut(BYTES_PER_SAMPLE).zip(samples) {
+            let value = (sample.clamp(-1.0, 1.0) * 8_388_607.0).round() as i32;
+            out.copy_from_slice(&value.to_be_bytes()[1..]);
+        }
+        self.sequence = self.sequence.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(self.packet_frames as u32);
+        self.started = true;
+        &self.buffer
+    }
+}
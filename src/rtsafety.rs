@@ -0,0 +1,120 @@
+// rtsafety.rs
+
+/* Real-Time Safety Declarations and Checks */
+
+#![allow(warnings)]
+
+use crate::dspengine::AudioNode;
+
+/// What a node does inside `process`. Nodes declare this through `AudioNode::rt_safety`;
+/// with the `rt-check` feature the allocation part can be measured with `probe`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RtSafety {
+    pub allocates: bool,
+    /// Takes locks, does I/O or otherwise waits on other threads.
+    pub blocks: bool,
+    /// False for nodes that didn't say; strict mode treats those as unsafe.
+    pub declared: bool,
+}
+
+impl RtSafety {
+    pub const SAFE: RtSafety = RtSafety { allocates: false, blocks: false, declared: true };
+    pub const UNKNOWN: RtSafety = RtSafety { allocates: false, blocks: false, declared: false };
+
+    /// Whether strict mode accepts the node.
+    pub fn is_safe(&self) -> bool {
+        self.declared && !self.allocates && !self.blocks
+    }
+
+    /// Safety of two nodes run together.
+    pub fn combine(self, other: RtSafety) -> RtSafety {
+        RtSafety {
+            allocates: self.allocates || other.allocates,
+            blocks: self.blocks || other.blocks,
+            declared: self.declared && other.declared,
+        }
+    }
+
+    /// Describes why strict mode would reject the node.
+    pub fn violation(&self) -> Option<&'static str> {
+        match (self.declared, self.allocates, self.blocks) {
+            (false, _, _) => Some("does not declare real-time safety"),
+            (_, true, _) => Some("allocates in process"),
+            (_, _, true) => Some("blocks in process"),
+            _ => None,
+        }
+    }
+}
+
+/// Runs `node` for a few blocks and reports what it actually did. Only allocation is measured; blocking is
+/// taken from the node's declaration. Without the `rt-check` feature this returns the declaration unchanged.
+pub fn probe(node: &mut dyn AudioNode, sample_rate: u32, block_size: usize, channels: usize) -> RtSafety {
+    let declared = node.rt_safety();
+    #[cfg(feature = "rt-check")]
+    {
+        node.prepare(sample_rate, block_size);
+        let mut buffer = vec![0.0f32; block_size * channels.max(1)];
+        buffer[0] = 1.0;
+        let before = check::allocations();
+        check::armed(|| {
+            for _ in 0..8 {
+                node.process(&mut buffer);
+            }
+        });
+        let allocates = check::allocations() != before;
+        return RtSafety { allocates, blocks: declared.blocks, declared: true };
+    }
+    declared
+}
+
+/// Allocation counting for `probe`. Installs a global allocator, so only enable the feature in test and QA builds.
+#[cfg(feature = "rt-check")]
+pub mod check {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+    thread_local! {
+        static ARMED: Cell<bool> = const { Cell::new(false) };
+    }
+
+    struct CountingAllocator;
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if ARMED.with(|a| a.get()) {
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            }
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            if ARMED.with(|a| a.get()) {
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            }
+            unsafe { System.realloc(ptr, layout, new_size) }
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAllocator = CountingAllocator;
+
+    /// Allocations made on armed threads so far.
+    pub fn allocations() -> usize {
+        ALLOCATIONS.load(Ordering::Relaxed)
+    }
+
+    /// Counts allocations made by the current thread while `f` runs.
+    pub fn armed<R>(f: impl FnOnce() -> R) -> R {
+        ARMED.with(|a| a.set(true));
+        let result = f();
+        ARMED.with(|a| a.set(false));
+        result
+    }
+}
@@ -0,0 +1,83 @@
+// sampleformat.rs
+
+/* Output Sample Formats */
+
+#![allow(warnings)]
+
+use cpal::SampleFormat;
+
+use crate::rng::Rng;
+
+/// Formats the output can be opened in, best first. The engine renders f32 and converts to the others.
+pub const OUTPUT_FORMATS: [SampleFormat; 4] = [SampleFormat::F32, SampleFormat::I32, SampleFormat::I16, SampleFormat::U16];
+
+/// Random stream the output dither draws from (see `rng::Rng::for_stream`).
+const DITHER_STREAM: u64 = 0x6469_7468_6572;
+
+/// Integer samples a device can take, converted from the engine's f32.
+pub trait OutputSample: cpal::SizedSample + Send + 'static {
+    /// Bits of resolution. Formats finer than f32's 24-bit mantissa gain nothing from dither.
+    const BITS: u32;
+
+    /// Quantizes `x` (full scale at 1.0), adding `dither` LSBs first. Out-of-range input is clipped.
+    fn quantize(x: f32, dither: f32) -> Self;
+}
+
+impl OutputSample for i16 {
+    const BITS: u32 = 16;
+
+    fn quantize(x: f32, dither: f32) -> Self {
+        (x * 32768.0 + dither).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    }
+}
+
+impl OutputSample for u16 {
+    const BITS: u32 = 16;
+
+    fn quantize(x: f32, dither: f32) -> Self {
+        (i16::quantize(x, dither) as i32 + 32768) as u16
+    }
+}
+
+impl OutputSample for i32 {
+    const BITS: u32 = 32;
+
+    fn quantize(x: f32, dither: f32) -> Self {
+        (x as f64 * 2_147_483_648.0 + dither as f64).round().clamp(i32::MIN as f64, i32::MAX as f64) as i32
+    }
+}
+
+/// Picks the best of `OUTPUT_FORMATS` among `available`.
+pub fn best_output_format(available: impl IntoIterator<Item = SampleFormat>) -> Option<SampleFormat> {
+    let available: Vec<SampleFormat> = available.into_iter().collect();
+    OUTPUT_FORMATS.iter().copied().find(|format| available.contains(format))
+}
+
+/// Renders f32 into preallocated scratch and converts it for a device that wants integers, with TPDF dither
+/// (triangular, 2 LSB peak to peak) so quiet material fades into noise instead of turning into distortion.
+pub struct FormatConverter {
+    scratch: Vec<f32>,
+    rng: Rng,
+    dither: bool,
+}
+
+impl FormatConverter {
+    /// Callbacks of up to `max_frames` frames of `channels` are converted in one piece; longer ones are rendered
+    /// in several.
+    pub fn new(max_frames: usize, channels: usize, dither: bool) -> Self {
+        FormatConverter { scratch: vec![0.0; max_frames.max(1) * channels.max(1)], rng: Rng::for_stream(DITHER_STREAM), dither }
+    }
+
+    /// Fills `output` with what `render` produces. Never allocates.
+    pub fn render<T: OutputSample>(&mut self, output: &mut [T], mut render: impl FnMut(&mut [f32])) {
+        let dither = self.dither && T::BITS <= 24;
+        let capacity = self.scratch.len();
+        for piece in output.chunks_mut(capacity) {
+            let scratch = &mut self.scratch[..piece.len()];
+            render(scratch);
+            for (out, &x) in piece.iter_mut().zip(scratch.iter()) {
+                *out = T::quantize(x, if dither { self.rng.triangular() } else { 0.0 });
+            }
+        }
+    }
+}
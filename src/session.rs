@@ -0,0 +1,697 @@
+// session.rs
+
+/* Session Model */
+
+#![allow(warnings)]
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::allowlist::{ArtifactKind, ALLOWLIST};
+use crate::automation::{AutomationLane, Breakpoint};
+use crate::dspapi::{NodeId, ParamId, PortId};
+use crate::rng;
+use crate::strings::tr;
+
+/// Persistent description of one node in the rack.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeState {
+    pub id: NodeId,
+    /// Registry or plugin name passed to `PluginManager::create_node`.
+    pub plugin: String,
+    pub params: BTreeMap<ParamId, f32>,
+    /// Opaque plugin state chunk.
+    pub state: Vec<u8>,
+    /// Parameters whose values are sample counts (delay times, lookahead), rescaled when the sample rate changes.
+    #[serde(default)]
+    pub sample_params: Vec<ParamId>,
+    /// Media the node references (impulse responses, samples, audio files), by role, e.g. "ir" -> path.
+    #[serde(default)]
+    pub assets: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Connection {
+    pub from_node: NodeId,
+    pub from_port: PortId,
+    pub to_node: NodeId,
+    pub to_port: PortId,
+}
+
+/// Input ports from this number up are modulation inputs: port `MOD_PORT_BASE + param_id` modulates that parameter.
+pub const MOD_PORT_BASE: PortId = 0x1000;
+
+impl Connection {
+    /// The parameter this connection modulates at audio rate, if it targets a modulation port.
+    pub fn modulated_param(&self) -> Option<ParamId> {
+        self.to_port.checked_sub(MOD_PORT_BASE)
+    }
+}
+
+/// Persistent automation for one parameter. Breakpoint positions are in samples at the session's sample rate.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LaneState {
+    pub node_id: NodeId,
+    pub param_id: ParamId,
+    #[serde(default)]
+    pub stepped: bool,
+    pub points: Vec<Breakpoint>,
+}
+
+impl LaneState {
+    /// Builds a playable lane.
+    pub fn to_lane(&self) -> AutomationLane {
+        let mut lane = AutomationLane::new(self.node_id, self.param_id);
+        lane.stepped = self.stepped;
+        for p in &self.points {
+            lane.add_point(p.position, p.value, p.shape);
+        }
+        lane
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub name: String,
+    pub sample_rate: u32,
+    /// Seed for every stochastic component, so offline renders of the session are bit-reproducible.
+    #[serde(default)]
+    pub seed: u64,
+    pub nodes: Vec<NodeState>,
+    pub connections: Vec<Connection>,
+    #[serde(default)]
+    pub automation: Vec<LaneState>,
+    #[serde(default)]
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+/// Name of the folder next to a session file that `collect_assets` copies media into.
+pub const ASSETS_DIR: &str = "assets";
+
+/// Outcome of `Session::collect_assets`.
+#[derive(Debug, Clone, Default)]
+pub struct CollectReport {
+    /// Files copied into the assets folder, as (original path, new session-relative path).
+    pub copied: Vec<(String, String)>,
+    /// References already inside the assets folder.
+    pub already_collected: usize,
+    /// References whose file could not be found; left unchanged.
+    pub missing: Vec<String>,
+}
+
+/// Where to look for media a moved session references.
+#[derive(Debug, Clone, Default)]
+pub struct PathSearch {
+    /// Directories searched by file name when a reference can't be found where it points.
+    pub fallback_dirs: Vec<PathBuf>,
+    /// Prefix translations applied to absolute references, e.g. ("C:/Users/ana/Samples", "/home/ana/Samples").
+    /// Matched with `/` separators and case-insensitively, since the session may come from Windows.
+    pub mappings: Vec<(String, String)>,
+}
+
+/// A single difference between two sessions, carrying enough data to undo it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SessionChange {
+    SampleRateChanged { before: u32, after: u32 },
+    NodeAdded(NodeState),
+    NodeRemoved(NodeState),
+    /// Same id, different plugin (or media/sample-parameter declarations): parameter deltas are not reported for replaced nodes.
+    NodeReplaced { before: NodeState, after: NodeState },
+    /// `None` means the parameter is absent on that side.
+    ParamChanged { node_id: NodeId, param_id: ParamId, before: Option<f32>, after: Option<f32> },
+    StateChanged { node_id: NodeId, before: Vec<u8>, after: Vec<u8> },
+    ConnectionAdded(Connection),
+    ConnectionRemoved(Connection),
+    LaneAdded(LaneState),
+    LaneRemoved(LaneState),
+    /// Same node and parameter, different points or stepping.
+    LaneChanged { before: LaneState, after: LaneState },
+}
+
+/// A named snapshot of the session, stored as the changes since the previous checkpoint
+/// (the first one relative to an empty session), so many checkpoints stay cheap in the session file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub name: String,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+    pub seed: u64,
+    pub changes: Vec<SessionChange>,
+}
+
+impl SessionChange {
+    /// One-line human readable summary, for "unsaved changes" lists. Localizable under `session.change.*`.
+    pub fn describe(&self) -> String {
+        let value = |v: &Option<f32>| v.map_or("-".to_string(), |v| v.to_string());
+        match self {
+            SessionChange::SampleRateChanged { before, after } => {
+                tr("session.change.sample_rate", "Sample rate {0} Hz -> {1} Hz", &[&before.to_string(), &after.to_string()])
+            }
+            SessionChange::NodeAdded(n) => tr("session.change.node_added", "Added node {0} ({1})", &[&n.id.to_string(), &n.plugin]),
+            SessionChange::NodeRemoved(n) => tr("session.change.node_removed", "Removed node {0} ({1})", &[&n.id.to_string(), &n.plugin]),
+            SessionChange::NodeReplaced { before, after } => {
+                tr("session.change.node_replaced", "Replaced node {0}: {1} -> {2}", &[&after.id.to_string(), &before.plugin, &after.plugin])
+            }
+            SessionChange::ParamChanged { node_id, param_id, before, after } => tr(
+                "session.change.param",
+                "Node {0} param {1}: {2} -> {3}",
+                &[&node_id.to_string(), &param_id.to_string(), &value(before), &value(after)],
+            ),
+            SessionChange::StateChanged { node_id, .. } => tr("session.change.state", "Node {0} state changed", &[&node_id.to_string()]),
+            SessionChange::ConnectionAdded(c) => tr(
+                "session.change.connected",
+                "Connected {0}:{1} -> {2}:{3}",
+                &[&c.from_node.to_string(), &c.from_port.to_string(), &c.to_node.to_string(), &c.to_port.to_string()],
+            ),
+            SessionChange::ConnectionRemoved(c) => tr(
+                "session.change.disconnected",
+                "Disconnected {0}:{1} -> {2}:{3}",
+                &[&c.from_node.to_string(), &c.from_port.to_string(), &c.to_node.to_string(), &c.to_port.to_string()],
+            ),
+            SessionChange::LaneAdded(l) => {
+                tr("session.change.lane_added", "Added automation for node {0} param {1}", &[&l.node_id.to_string(), &l.param_id.to_string()])
+            }
+            SessionChange::LaneRemoved(l) => {
+                tr("session.change.lane_removed", "Removed automation for node {0} param {1}", &[&l.node_id.to_string(), &l.param_id.to_string()])
+            }
+            SessionChange::LaneChanged { after, .. } => {
+                tr("session.change.lane", "Node {0} param {1} automation changed", &[&after.node_id.to_string(), &after.param_id.to_string()])
+            }
+        }
+    }
+}
+
+impl Session {
+    pub fn new(name: &str, sample_rate: u32) -> Self {
+        Session {
+            name: name.to_string(),
+            sample_rate,
+            seed: 0,
+            nodes: Vec::new(),
+            connections: Vec::new(),
+            automation: Vec::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&NodeState> {
+        self.nodes.iter().find(|n| n.id == id)
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> Option<&mut NodeState> {
+        self.nodes.iter_mut().find(|n| n.id == id)
+    }
+
+    /// Reads a session file without touching the engine; see `activate` for opening it.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read session {:?}: {}", path, e))?;
+        Session::from_json(&text).map_err(|e| format!("Invalid session file {:?}: {}", path, e))
+    }
+
+    /// Parses a session saved with `to_json` or `save`, e.g. one a host stored with its project.
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        serde_json::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// The session as `save` writes it, with media references left as they are.
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+
+    /// Makes this the session the engine plays: stochastic components draw from its seed from now on. Sessions
+    /// only read (imports, replays, diffs) are never activated.
+    pub fn activate(&self) {
+        rng::set_session_seed(self.seed);
+    }
+
+    /// Loads a session and converts it to the device's sample rate if it was saved at another one,
+    /// so sample-based positions and parameters keep their timing. Call `activate` to open it.
+    pub fn load_for_rate(path: &Path, sample_rate: u32) -> Result<Self, String> {
+        let mut session = Session::load(path)?;
+        session.convert_sample_rate(sample_rate);
+        Ok(session)
+    }
+
+    /// Rescales everything stored in samples (automation positions and declared sample parameters) to `sample_rate`.
+    /// Tempo-based values (beats, BPM) and values in seconds need no conversion.
+    pub fn convert_sample_rate(&mut self, sample_rate: u32) {
+        if self.sample_rate == sample_rate || self.sample_rate == 0 { return; }
+        let ratio = sample_rate as f64 / self.sample_rate as f64;
+
+        for node in self.nodes.iter_mut() {
+            for param_id in &node.sample_params {
+                if let Some(value) = node.params.get_mut(param_id) {
+                    *value = (*value as f64 * ratio) as f32;
+                }
+            }
+        }
+
+        for lane in self.automation.iter_mut() {
+            let sample_valued = self.nodes.iter().any(|n| n.id == lane.node_id && n.sample_params.contains(&lane.param_id));
+            for point in lane.points.iter_mut() {
+                point.position = (point.position as f64 * ratio).round() as u64;
+                if sample_valued {
+                    point.value = (point.value as f64 * ratio) as f32;
+                }
+            }
+            // Rounding can collapse neighbouring points onto one position; keep the later one like `add_point` would.
+            lane.points.dedup_by(|later, earlier| {
+                if later.position == earlier.position { *earlier = *later; true } else { false }
+            });
+        }
+
+        self.sample_rate = sample_rate;
+    }
+
+    /// Saves the session. Media inside the session's folder is stored relative to it, with `/` separators on every platform.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let session_dir = path.parent().unwrap_or(Path::new("."));
+        let mut portable = self.clone();
+        for reference in portable.nodes.iter_mut().flat_map(|n| n.assets.values_mut()) {
+            *reference = portable_reference(reference, session_dir);
+        }
+        let text = portable.to_json()?;
+        fs::write(path, text).map_err(|e| format!("Failed to write session {:?}: {}", path, e))
+    }
+
+    /// Turns every media reference into a usable local path: relative references are resolved against the session's
+    /// folder, absolute ones are translated by `search.mappings`, and anything still missing is looked up by file name
+    /// in `search.fallback_dirs`. Returns the references that couldn't be found; those are left unchanged.
+    pub fn resolve_assets(&mut self, session_path: &Path, search: &PathSearch) -> Vec<String> {
+        let session_dir = session_path.parent().unwrap_or(Path::new("."));
+        let mut missing = Vec::new();
+        for reference in self.nodes.iter_mut().flat_map(|n| n.assets.values_mut()) {
+            match resolve_reference(reference, session_dir, search) {
+                Some(found) => *reference = found.to_string_lossy().into_owned(),
+                None => missing.push(reference.clone()),
+            }
+        }
+        missing
+    }
+
+    /// "Collect all and save": copies every referenced media file into an `assets` folder next to `path`,
+    /// rewrites the references to session-relative paths and saves the session there,
+    /// so the folder can be moved to another machine as a whole.
+    pub fn collect_assets(&mut self, path: &Path) -> Result<CollectReport, String> {
+        let session_dir = path.parent().unwrap_or(Path::new("."));
+        let assets_dir = session_dir.join(ASSETS_DIR);
+        fs::create_dir_all(&assets_dir).map_err(|e| format!("Failed to create {:?}: {}", assets_dir, e))?;
+
+        let mut report = CollectReport::default();
+        // Source file -> collected name, so media shared between nodes is copied once.
+        let mut collected: HashMap<std::path::PathBuf, String> = HashMap::new();
+
+        for node in self.nodes.iter_mut() {
+            for reference in node.assets.values_mut() {
+                let source = Path::new(reference.as_str());
+                let source = if source.is_relative() { session_dir.join(source) } else { source.to_path_buf() };
+
+                if source.starts_with(&assets_dir) && source.exists() {
+                    report.already_collected += 1;
+                    continue;
+                }
+                if !source.is_file() {
+                    report.missing.push(reference.clone());
+                    continue;
+                }
+
+                let relative = match collected.get(&source) {
+                    Some(relative) => relative.clone(),
+                    None => {
+                        let file_name = unique_file_name(&assets_dir, &source);
+                        let target = assets_dir.join(&file_name);
+                        fs::copy(&source, &target).map_err(|e| format!("Failed to copy {:?} to {:?}: {}", source, target, e))?;
+                        let relative = format!("{}/{}", ASSETS_DIR, file_name);
+                        collected.insert(source.clone(), relative.clone());
+                        relative
+                    }
+                };
+                report.copied.push((reference.clone(), relative.clone()));
+                *reference = relative;
+            }
+        }
+
+        self.save(path)?;
+        Ok(report)
+    }
+
+    pub fn lane(&self, node_id: NodeId, param_id: ParamId) -> Option<&LaneState> {
+        self.automation.iter().find(|l| l.node_id == node_id && l.param_id == param_id)
+    }
+
+    /// Adds `lane`, replacing the lane of the same node and parameter if there is one.
+    pub fn set_lane(&mut self, lane: LaneState) {
+        match self.automation.iter_mut().find(|l| l.node_id == lane.node_id && l.param_id == lane.param_id) {
+            Some(existing) => *existing = lane,
+            None => self.automation.push(lane),
+        }
+    }
+
+    pub fn remove_lane(&mut self, node_id: NodeId, param_id: ParamId) {
+        self.automation.retain(|l| l.node_id != node_id || l.param_id != param_id);
+    }
+
+    /// First id above every node id in use.
+    pub fn next_free_id(&self) -> NodeId {
+        self.nodes.iter().map(|n| n.id + 1).max().unwrap_or(1)
+    }
+
+    /// Collects `start` and every node reachable from it through connections, in breadth-first order.
+    pub fn downstream_chain(&self, start: NodeId) -> Vec<NodeId> {
+        let mut chain = Vec::new();
+        let mut queue = VecDeque::from([start]);
+        while let Some(id) = queue.pop_front() {
+            if chain.contains(&id) || self.node(id).is_none() { continue; }
+            chain.push(id);
+            queue.extend(self.connections.iter().filter(|c| c.from_node == id).map(|c| c.to_node));
+        }
+        chain
+    }
+
+    /// Copies the given nodes (with params, state, automation and the connections between them) from `source`,
+    /// assigning fresh ids. Connections to nodes outside the selection are dropped.
+    /// Returns the mapping from source ids to the new ids.
+    pub fn import_nodes(&mut self, source: &Session, node_ids: &[NodeId]) -> Result<HashMap<NodeId, NodeId>, String> {
+        let mut remap = HashMap::new();
+        let mut imported = Vec::new();
+        let mut next_id = self.next_free_id();
+
+        for &id in node_ids {
+            if remap.contains_key(&id) { continue; }
+            let mut node = source.node(id).cloned().ok_or(format!("Node {} not found in session '{}'", id, source.name))?;
+            remap.insert(id, next_id);
+            node.id = next_id;
+            imported.push(node);
+            next_id += 1;
+        }
+        self.nodes.extend(imported);
+
+        for c in &source.connections {
+            if let (Some(&from_node), Some(&to_node)) = (remap.get(&c.from_node), remap.get(&c.to_node)) {
+                self.connections.push(Connection { from_node, from_port: c.from_port, to_node, to_port: c.to_port });
+            }
+        }
+
+        for lane in &source.automation {
+            if let Some(&node_id) = remap.get(&lane.node_id) {
+                self.automation.push(LaneState { node_id, ..lane.clone() });
+            }
+        }
+
+        Ok(remap)
+    }
+
+    /// Imports the chain starting at `start` from another session file (a preset), subject to the allowlist.
+    pub fn import_chain_from(&mut self, path: &Path, start: NodeId) -> Result<HashMap<NodeId, NodeId>, String> {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        ALLOWLIST
+            .lock()
+            .map_err(|_| "Allowlist lock poisoned".to_string())?
+            .verify(ArtifactKind::Preset, &name, path)?;
+        let mut source = Session::load(path)?;
+        source.convert_sample_rate(self.sample_rate);
+        let chain = source.downstream_chain(start);
+        if chain.is_empty() {
+            return Err(format!("Node {} not found in session '{}'", start, source.name));
+        }
+        self.import_nodes(&source, &chain)
+    }
+
+    /// Lists everything that changed going from `a` to `b`.
+    pub fn diff(a: &Session, b: &Session) -> Vec<SessionChange> {
+        let mut changes = Vec::new();
+
+        if a.sample_rate != b.sample_rate {
+            changes.push(SessionChange::SampleRateChanged { before: a.sample_rate, after: b.sample_rate });
+        }
+
+        for old in &a.nodes {
+            if b.node(old.id).is_none() {
+                changes.push(SessionChange::NodeRemoved(old.clone()));
+            }
+        }
+
+        for new in &b.nodes {
+            let Some(old) = a.node(new.id) else {
+                changes.push(SessionChange::NodeAdded(new.clone()));
+                continue;
+            };
+
+            if old.plugin != new.plugin || old.assets != new.assets || old.sample_params != new.sample_params {
+                changes.push(SessionChange::NodeReplaced { before: old.clone(), after: new.clone() });
+                continue;
+            }
+
+            let param_ids: std::collections::BTreeSet<ParamId> = old.params.keys().chain(new.params.keys()).copied().collect();
+            for param_id in param_ids {
+                let before = old.params.get(&param_id).copied();
+                let after = new.params.get(&param_id).copied();
+                if before != after {
+                    changes.push(SessionChange::ParamChanged { node_id: new.id, param_id, before, after });
+                }
+            }
+
+            if old.state != new.state {
+                changes.push(SessionChange::StateChanged { node_id: new.id, before: old.state.clone(), after: new.state.clone() });
+            }
+        }
+
+        for c in &a.connections {
+            if !b.connections.contains(c) {
+                changes.push(SessionChange::ConnectionRemoved(*c));
+            }
+        }
+        for c in &b.connections {
+            if !a.connections.contains(c) {
+                changes.push(SessionChange::ConnectionAdded(*c));
+            }
+        }
+
+        for old in &a.automation {
+            if b.lane(old.node_id, old.param_id).is_none() {
+                changes.push(SessionChange::LaneRemoved(old.clone()));
+            }
+        }
+        for new in &b.automation {
+            match a.lane(new.node_id, new.param_id) {
+                None => changes.push(SessionChange::LaneAdded(new.clone())),
+                Some(old) if old != new => changes.push(SessionChange::LaneChanged { before: old.clone(), after: new.clone() }),
+                Some(_) => {}
+            }
+        }
+
+        changes
+    }
+
+    /// Applies a change produced by `diff(_, self)` in reverse, restoring that one aspect of the older session.
+    pub fn revert(&mut self, change: &SessionChange) {
+        match change {
+            SessionChange::SampleRateChanged { before, .. } => self.sample_rate = *before,
+            SessionChange::NodeAdded(node) => {
+                self.nodes.retain(|n| n.id != node.id);
+                self.connections.retain(|c| c.from_node != node.id && c.to_node != node.id);
+            }
+            SessionChange::NodeRemoved(node) => {
+                if self.node(node.id).is_none() {
+                    self.nodes.push(node.clone());
+                }
+            }
+            SessionChange::NodeReplaced { before, .. } => {
+                if let Some(node) = self.node_mut(before.id) {
+                    *node = before.clone();
+                }
+            }
+            SessionChange::ParamChanged { node_id, param_id, before, .. } => {
+                if let Some(node) = self.node_mut(*node_id) {
+                    match before {
+                        Some(value) => { node.params.insert(*param_id, *value); }
+                        None => { node.params.remove(param_id); }
+                    }
+                }
+            }
+            SessionChange::StateChanged { node_id, before, .. } => {
+                if let Some(node) = self.node_mut(*node_id) {
+                    node.state = before.clone();
+                }
+            }
+            SessionChange::ConnectionAdded(c) => self.connections.retain(|x| x != c),
+            SessionChange::ConnectionRemoved(c) => {
+                if !self.connections.contains(c) {
+                    self.connections.push(*c);
+                }
+            }
+            SessionChange::LaneAdded(lane) => self.remove_lane(lane.node_id, lane.param_id),
+            SessionChange::LaneRemoved(lane) => self.set_lane(lane.clone()),
+            SessionChange::LaneChanged { before, .. } => self.set_lane(before.clone()),
+        }
+    }
+
+    /// Applies a change produced by `diff(self, _)`, moving this session towards the newer one.
+    pub fn apply(&mut self, change: &SessionChange) {
+        match change {
+            SessionChange::SampleRateChanged { after, .. } => self.sample_rate = *after,
+            SessionChange::NodeAdded(node) => {
+                if self.node(node.id).is_none() {
+                    self.nodes.push(node.clone());
+                }
+            }
+            SessionChange::NodeRemoved(node) => {
+                self.nodes.retain(|n| n.id != node.id);
+                self.connections.retain(|c| c.from_node != node.id && c.to_node != node.id);
+            }
+            SessionChange::NodeReplaced { after, .. } => {
+                if let Some(node) = self.node_mut(after.id) {
+                    *node = after.clone();
+                }
+            }
+            SessionChange::ParamChanged { node_id, param_id, after, .. } => {
+                if let Some(node) = self.node_mut(*node_id) {
+                    match after {
+                        Some(value) => { node.params.insert(*param_id, *value); }
+                        None => { node.params.remove(param_id); }
+                    }
+                }
+            }
+            SessionChange::StateChanged { node_id, after, .. } => {
+                if let Some(node) = self.node_mut(*node_id) {
+                    node.state = after.clone();
+                }
+            }
+            SessionChange::ConnectionAdded(c) => {
+                if !self.connections.contains(c) {
+                    self.connections.push(*c);
+                }
+            }
+            SessionChange::ConnectionRemoved(c) => self.connections.retain(|x| x != c),
+            SessionChange::LaneAdded(lane) => self.set_lane(lane.clone()),
+            SessionChange::LaneRemoved(lane) => self.remove_lane(lane.node_id, lane.param_id),
+            SessionChange::LaneChanged { after, .. } => self.set_lane(after.clone()),
+        }
+    }
+
+    /// The session content a checkpoint chain starts from.
+    fn checkpoint_base(&self) -> Session {
+        Session::new(&self.name, 0)
+    }
+
+    /// Rebuilds the session as it was at every checkpoint, in order.
+    fn checkpoint_states(&self) -> Vec<Session> {
+        let mut state = self.checkpoint_base();
+        self.checkpoints
+            .iter()
+            .map(|checkpoint| {
+                for change in &checkpoint.changes {
+                    state.apply(change);
+                }
+                state.seed = checkpoint.seed;
+                state.clone()
+            })
+            .collect()
+    }
+
+    /// Records the current state as a named checkpoint. Saved with the session.
+    pub fn add_checkpoint(&mut self, name: &str) {
+        let previous = self.checkpoint_states().pop().unwrap_or_else(|| self.checkpoint_base());
+        let mut current = self.clone();
+        current.checkpoints.clear();
+        let created = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        self.checkpoints.push(Checkpoint {
+            name: name.to_string(),
+            created,
+            seed: self.seed,
+            changes: Session::diff(&previous, &current),
+        });
+    }
+
+    /// The session as it was at checkpoint `name`, without its checkpoint list.
+    pub fn checkpoint_state(&self, name: &str) -> Result<Session, String> {
+        let index = self.checkpoints.iter().rposition(|c| c.name == name).ok_or(format!("No checkpoint named '{}'", name))?;
+        let mut state = self.checkpoint_states().swap_remove(index);
+        state.name = self.name.clone();
+        Ok(state)
+    }
+
+    /// Lists what differs between checkpoint `name` and the current session.
+    pub fn changes_since(&self, name: &str) -> Result<Vec<SessionChange>, String> {
+        Ok(Session::diff(&self.checkpoint_state(name)?, self))
+    }
+
+    /// Returns the session to checkpoint `name`, seed included (`activate` it again if it is playing). Checkpoints
+    /// are kept, so later ones can still be restored.
+    pub fn restore_checkpoint(&mut self, name: &str) -> Result<(), String> {
+        let state = self.checkpoint_state(name)?;
+        self.sample_rate = state.sample_rate;
+        self.seed = state.seed;
+        self.nodes = state.nodes;
+        self.connections = state.connections;
+        self.automation = state.automation;
+        Ok(())
+    }
+
+    /// Deletes a checkpoint, folding its changes into the next one so later checkpoints are unaffected.
+    pub fn remove_checkpoint(&mut self, name: &str) -> Result<(), String> {
+        let index = self.checkpoints.iter().rposition(|c| c.name == name).ok_or(format!("No checkpoint named '{}'", name))?;
+        let states = self.checkpoint_states();
+        let previous = if index == 0 { self.checkpoint_base() } else { states[index - 1].clone() };
+        if let Some(next) = self.checkpoints.get_mut(index + 1) {
+            next.changes = Session::diff(&previous, &states[index + 1]);
+        }
+        self.checkpoints.remove(index);
+        Ok(())
+    }
+}
+
+/// File name for `source` inside `dir` that doesn't clash with an existing file ("kick.wav", "kick_2.wav", ...).
+fn unique_file_name(dir: &Path, source: &Path) -> String {
+    let stem = source.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_else(|| "asset".into());
+    let ext = source.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let mut name = format!("{}{}", stem, ext);
+    let mut n = 2;
+    while dir.join(&name).exists() {
+        name = format!("{}_{}{}", stem, n, ext);
+        n += 1;
+    }
+    name
+}
+
+/// `reference` with `/` separators only.
+fn normalize_separators(reference: &str) -> String {
+    reference.replace('\\', "/")
+}
+
+/// Absolute on any platform: `/...`, `//server/...` or a drive letter, regardless of the OS we run on.
+fn is_absolute_anywhere(reference: &str) -> bool {
+    let bytes = reference.as_bytes();
+    reference.starts_with('/') || (bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && bytes[2] == b'/')
+}
+
+/// Relative `/`-separated form of `reference` if it lies inside `session_dir`, else its `/`-separated absolute form.
+fn portable_reference(reference: &str, session_dir: &Path) -> String {
+    let path = Path::new(reference);
+    let relative = if path.is_absolute() { path.strip_prefix(session_dir).ok() } else { None };
+    normalize_separators(&relative.unwrap_or(path).to_string_lossy())
+}
+
+fn resolve_reference(reference: &str, session_dir: &Path, search: &PathSearch) -> Option<PathBuf> {
+    let normalized = normalize_separators(reference);
+
+    let mut candidates = Vec::new();
+    if is_absolute_anywhere(&normalized) {
+        candidates.push(PathBuf::from(&normalized));
+        for (from, to) in &search.mappings {
+            let from = normalize_separators(from);
+            let from = from.trim_end_matches('/');
+            if normalized.get(..from.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(from)) {
+                candidates.push(PathBuf::from(format!("{}{}", to.trim_end_matches('/'), &normalized[from.len()..])));
+            }
+        }
+    } else {
+        candidates.push(session_dir.join(&normalized));
+    }
+
+    let file_name = normalized.rsplit('/').next().unwrap_or(&normalized);
+    candidates.extend(search.fallback_dirs.iter().map(|dir| dir.join(file_name)));
+    candidates.into_iter().find(|c| c.is_file())
+}
@@ -0,0 +1,278 @@
+// sharedgraph.rs
+
+/* Graph Ownership and Snapshots */
+
+#![allow(warnings)]
+
+use arc_swap::ArcSwap;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::dspapi::NodeId;
+use crate::graph::{AudioGraph, Edge};
+
+/// Longest node name a snapshot taken while rendering keeps, in bytes; longer ones are cut at a character boundary.
+pub const MAX_SNAPSHOT_NAME: usize = 64;
+
+/// A node as of a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeInfo {
+    pub id: NodeId,
+    pub name: String,
+}
+
+/// Immutable description of a graph's structure, for threads that can't touch the graph while it renders.
+#[derive(Debug, Clone, Default)]
+pub struct GraphSnapshot {
+    /// Nodes in processing order.
+    pub nodes: Vec<NodeInfo>,
+    pub edges: Vec<Edge>,
+    pub capacity: usize,
+    pub allocated_bytes: usize,
+    pub compensation_bytes: usize,
+    /// `AudioGraph::revision` the snapshot was taken at.
+    pub revision: u64,
+}
+
+impl GraphSnapshot {
+    pub fn of(graph: &AudioGraph) -> Self {
+        GraphSnapshot {
+            nodes: graph
+                .schedule()
+                .filter_map(|id| graph.node(id).map(|node| NodeInfo { id, name: node.get_name().to_string() }))
+                .collect(),
+            edges: graph.edges().to_vec(),
+            capacity: graph.capacity(),
+            allocated_bytes: graph.allocated_bytes(),
+            compensation_bytes: graph.compensation_bytes(),
+            revision: graph.revision(),
+        }
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&NodeInfo> {
+        self.nodes.iter().find(|n| n.id == id)
+    }
+}
+
+/// A rendering graph's structure as copied out after its last change, for `SharedGraph::snapshot` to build the
+/// next snapshot from. Sized for the graph's capacity up front, so the copy never allocates.
+struct Staged {
+    ids: Vec<NodeId>,
+    /// One name per node slot, each with room for `MAX_SNAPSHOT_NAME` bytes; the first `ids.len()` are in use.
+    names: Vec<String>,
+    edges: Vec<Edge>,
+    allocated_bytes: usize,
+    compensation_bytes: usize,
+    revision: u64,
+}
+
+impl Staged {
+    fn new(graph: &AudioGraph) -> Self {
+        Staged {
+            ids: Vec::with_capacity(graph.capacity()),
+            names: (0..graph.capacity()).map(|_| String::with_capacity(MAX_SNAPSHOT_NAME)).collect(),
+            edges: Vec::with_capacity(graph.edge_capacity()),
+            allocated_bytes: 0,
+            compensation_bytes: 0,
+            revision: graph.revision(),
+        }
+    }
+
+    /// Copies `graph`'s structure in place. Never allocates.
+    fn copy(&mut self, graph: &AudioGraph) {
+        self.ids.clear();
+        for id in graph.schedule() {
+            let (Some(node), Some(name)) = (graph.node(id), self.names.get_mut(self.ids.len())) else { break };
+            let full = node.get_name();
+            let mut end = full.len().min(MAX_SNAPSHOT_NAME);
+            while !full.is_char_boundary(end) { end -= 1; }
+            name.clear();
+            name.push_str(&full[..end]);
+            self.ids.push(id);
+        }
+        self.edges.clear();
+        self.edges.extend(graph.edges().iter().take(self.edges.capacity()));
+        self.allocated_bytes = graph.allocated_bytes();
+        self.compensation_bytes = graph.compensation_bytes();
+        self.revision = graph.revision();
+    }
+
+    fn snapshot(&self) -> GraphSnapshot {
+        GraphSnapshot {
+            nodes: self.ids.iter().zip(&self.names).map(|(&id, name)| NodeInfo { id, name: name.clone() }).collect(),
+            edges: self.edges.clone(),
+            capacity: self.names.len(),
+            allocated_bytes: self.allocated_bytes,
+            compensation_bytes: self.compensation_bytes,
+            revision: self.revision,
+        }
+    }
+}
+
+/// Home of an engine's graph, shared with the audio thread without a lock on the audio path. A rendering stream
+/// checks the graph out for its lifetime and owns it outright, so the callback never waits for another thread
+/// or skips a block because one holds the graph. Meanwhile other threads read `snapshot`, kept up to date with
+/// every structural change (read-copy-update: the renderer copies the structure into preallocated space, and
+/// the reader that next asks builds the new snapshot from it, swaps it in and frees the old one), and change
+/// the graph through engine commands. `lock` gives direct access while nothing renders.
+pub struct SharedGraph {
+    /// The graph while parked; null while a renderer has it.
+    parked: AtomicPtr<AudioGraph>,
+    /// Serializes `lock` and `checkout` among control threads. Never taken on the audio thread.
+    control: Mutex<()>,
+    snapshot: ArcSwap<GraphSnapshot>,
+    /// The renderer's copy of the structure. Only ever `try_lock`ed on the audio thread.
+    staged: Mutex<Staged>,
+    /// Revision of the structure last copied into `staged`, or published directly by `park`.
+    staged_revision: AtomicU64,
+    /// Latency of the graph as of its last block.
+    latency: AtomicUsize,
+}
+
+impl SharedGraph {
+    pub fn new(graph: AudioGraph) -> Self {
+        SharedGraph {
+            snapshot: ArcSwap::from_pointee(GraphSnapshot::of(&graph)),
+            staged: Mutex::new(Staged::new(&graph)),
+            staged_revision: AtomicU64::new(graph.revision()),
+            latency: AtomicUsize::new(graph.latency_samples()),
+            parked: AtomicPtr::new(Box::into_raw(Box::new(graph))),
+            control: Mutex::new(()),
+        }
+    }
+
+    /// Direct access to the graph, e.g. for setup or reconfiguring with the streams closed. Fails while a stream
+    /// renders it; send commands then. The snapshot is republished when the guard is dropped.
+    pub fn lock(&self) -> Result<GraphGuard<'_>, &'static str> {
+        let control = self.control.lock().map_err(|_| "graph control lock poisoned")?;
+        let graph = self.take().ok_or("graph is being rendered")?;
+        Ok(GraphGuard { shared: self, graph: Some(graph), _control: control })
+    }
+
+    /// Hands the graph to a renderer until the returned handle is dropped. Call from the control thread.
+    pub fn checkout(self: &Arc<Self>) -> Result<CheckedOutGraph, &'static str> {
+        let _control = self.control.lock().map_err(|_| "graph control lock poisoned")?;
+        let graph = self.take().ok_or("graph is already being rendered")?;
+        Ok(CheckedOutGraph { shared: Arc::clone(self), graph: Some(graph) })
+    }
+
+    /// Whether a renderer has the graph.
+    pub fn is_rendering(&self) -> bool {
+        self.parked.load(Ordering::Acquire).is_null()
+    }
+
+    /// The graph's structure as of its last change, built here if the renderer changed it since. Node names are
+    /// cut to `MAX_SNAPSHOT_NAME` bytes while a stream renders. Call from a control thread.
+    pub fn snapshot(&self) -> Arc<GraphSnapshot> {
+        if self.staged_revision.load(Ordering::Acquire) != self.snapshot.load().revision {
+            if let Ok(staged) = self.staged.lock() {
+                if staged.revision != self.snapshot.load().revision {
+                    self.snapshot.store(Arc::new(staged.snapshot()));
+                }
+            }
+        }
+        self.snapshot.load_full()
+    }
+
+    /// Latency of the graph as of its last processed block (see `AudioGraph::latency_samples`).
+    pub fn latency_samples(&self) -> usize {
+        self.latency.load(Ordering::Relaxed)
+    }
+
+    fn take(&self) -> Option<Box<AudioGraph>> {
+        let graph = self.parked.swap(ptr::null_mut(), Ordering::AcqRel);
+        (!graph.is_null()).then(|| unsafe { Box::from_raw(graph) })
+    }
+
+    /// Republishes the snapshot and parks `graph`. On the control thread, so the snapshot is built right here.
+    fn park(&self, graph: Box<AudioGraph>) {
+        self.latency.store(graph.latency_samples(), Ordering::Relaxed);
+        // Unconditionally: e.g. `prepare` changes allocations without restructuring.
+        self.snapshot.store(Arc::new(GraphSnapshot::of(&graph)));
+        self.staged_revision.store(graph.revision(), Ordering::Release);
+        self.parked.store(Box::into_raw(graph), Ordering::Release);
+    }
+
+    /// Stores the latency and, if `graph` was restructured since, copies its structure for the next `snapshot`.
+    /// Never allocates or frees, nor waits: if a reader is building a snapshot, the copy is retried next time.
+    fn publish(&self, graph: &AudioGraph) {
+        self.latency.store(graph.latency_samples(), Ordering::Relaxed);
+        if self.staged_revision.load(Ordering::Acquire) == graph.revision() { return; }
+        let Ok(mut staged) = self.staged.try_lock() else { return };
+        staged.copy(graph);
+        self.staged_revision.store(graph.revision(), Ordering::Release);
+    }
+}
+
+impl Drop for SharedGraph {
+    fn drop(&mut self) {
+        drop(self.take());
+    }
+}
+
+/// Direct access to a parked graph, from `SharedGraph::lock`.
+pub struct GraphGuard<'a> {
+    shared: &'a SharedGraph,
+    graph: Option<Box<AudioGraph>>,
+    _control: MutexGuard<'a, ()>,
+}
+
+impl Deref for GraphGuard<'_> {
+    type Target = AudioGraph;
+
+    fn deref(&self) -> &AudioGraph {
+        self.graph.as_ref().expect("graph present until drop")
+    }
+}
+
+impl DerefMut for GraphGuard<'_> {
+    fn deref_mut(&mut self) -> &mut AudioGraph {
+        self.graph.as_mut().expect("graph present until drop")
+    }
+}
+
+impl Drop for GraphGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(graph) = self.graph.take() {
+            self.shared.park(graph);
+        }
+    }
+}
+
+/// The graph while a renderer owns it, from `SharedGraph::checkout`. Parked again when dropped.
+pub struct CheckedOutGraph {
+    shared: Arc<SharedGraph>,
+    graph: Option<Box<AudioGraph>>,
+}
+
+impl CheckedOutGraph {
+    /// Publishes the latency and, after a structural change, the structure for the next snapshot. Call after
+    /// each block; never allocates.
+    pub fn publish(&self) {
+        self.shared.publish(self);
+    }
+}
+
+impl Deref for CheckedOutGraph {
+    type Target = AudioGraph;
+
+    fn deref(&self) -> &AudioGraph {
+        self.graph.as_ref().expect("graph present until drop")
+    }
+}
+
+impl DerefMut for CheckedOutGraph {
+    fn deref_mut(&mut self) -> &mut AudioGraph {
+        self.graph.as_mut().expect("graph present until drop")
+    }
+}
+
+impl Drop for CheckedOutGraph {
+    fn drop(&mut self) {
+        if let Some(graph) = self.graph.take() {
+            self.shared.park(graph);
+        }
+    }
+}
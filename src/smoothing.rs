@@ -0,0 +1,85 @@
+// smoothing.rs
+
+/* Host Parameter Smoothing */
+
+#![allow(warnings)]
+
+use crate::dspapi::ParamId;
+
+/// Default time parameter changes are spread over, in milliseconds.
+pub const DEFAULT_SMOOTHING_MS: f32 = 20.0;
+/// Parameters of one node the smoother follows; changes to further ones are applied directly.
+pub const MAX_SMOOTHED_PARAMS: usize = 32;
+
+#[derive(Debug, Clone, Copy)]
+struct ParamRamp {
+    param_id: ParamId,
+    current: f32,
+    target: f32,
+    /// Change per frame.
+    step: f32,
+    remaining: usize,
+}
+
+/// Moves a node's parameters to new values linearly over a number of frames, so stepwise changes (GUI sliders,
+/// controllers) don't cause zipper noise in nodes that don't smooth internally. Preallocated; never grows.
+pub struct ParamSmoother {
+    ramps: Vec<ParamRamp>,
+}
+
+impl ParamSmoother {
+    pub fn new() -> Self {
+        ParamSmoother { ramps: Vec::with_capacity(MAX_SMOOTHED_PARAMS) }
+    }
+
+    /// Forgets every parameter, e.g. when the node is replaced.
+    pub fn clear(&mut self) {
+        self.ramps.clear();
+    }
+
+    /// Starts moving `param_id` to `value` over `frames`. Returns the value to apply right away: `value` itself
+    /// when the parameter's current value isn't known yet, `frames` is 0, or there is no room to follow it.
+    pub fn set(&mut self, param_id: ParamId, value: f32, frames: usize) -> Option<f32> {
+        if let Some(ramp) = self.ramps.iter_mut().find(|r| r.param_id == param_id) {
+            if frames == 0 {
+                *ramp = ParamRamp { param_id, current: value, target: value, step: 0.0, remaining: 0 };
+                return Some(value);
+            }
+            *ramp = ParamRamp { step: (value - ramp.current) / frames as f32, target: value, remaining: frames, ..*ramp };
+            return None;
+        }
+        if self.ramps.len() == self.ramps.capacity() {
+            // Make room by forgetting a parameter that is at rest.
+            let Some(index) = self.ramps.iter().position(|r| r.remaining == 0) else { return Some(value) };
+            self.ramps.swap_remove(index);
+        }
+        self.ramps.push(ParamRamp { param_id, current: value, target: value, step: 0.0, remaining: 0 });
+        Some(value)
+    }
+
+    /// Whether any parameter is still moving.
+    pub fn is_moving(&self) -> bool {
+        self.ramps.iter().any(|r| r.remaining > 0)
+    }
+
+    /// Advances the moving parameters by a block of `frames`, calling `apply` with each one's value at the end
+    /// of the block.
+    pub fn advance(&mut self, frames: usize, mut apply: impl FnMut(ParamId, f32)) {
+        for ramp in self.ramps.iter_mut().filter(|r| r.remaining > 0) {
+            if frames >= ramp.remaining {
+                ramp.current = ramp.target;
+                ramp.remaining = 0;
+            } else {
+                ramp.current += ramp.step * frames as f32;
+                ramp.remaining -= frames;
+            }
+            apply(ramp.param_id, ramp.current);
+        }
+    }
+}
+
+impl Default for ParamSmoother {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,62 @@
+// strings.rs
+
+/* Localizable Display Strings */
+
+#![allow(warnings)]
+
+use std::sync::{Arc, Mutex};
+use once_cell::sync::Lazy;
+
+use crate::dspapi::ParamId;
+use crate::dspengine::AudioNode;
+
+/// Supplies translated strings. Frontends install one with `set_provider`; the engine itself stays English-only.
+pub trait StringProvider: Send {
+    /// Returns the template for `id`, or `None` to use the English fallback.
+    /// Templates use `{0}`, `{1}`, ... for arguments, like the fallbacks.
+    fn lookup(&self, id: &str) -> Option<String>;
+}
+
+static PROVIDER: Lazy<Arc<Mutex<Option<Box<dyn StringProvider>>>>> = Lazy::new(|| {
+    Arc::new(Mutex::new(None))
+});
+
+pub fn set_provider(provider: Box<dyn StringProvider>) {
+    if let Ok(mut p) = PROVIDER.lock() {
+        *p = Some(provider);
+    }
+}
+
+pub fn clear_provider() {
+    if let Ok(mut p) = PROVIDER.lock() {
+        *p = None;
+    }
+}
+
+/// Resolves a display string: the provider's template for `id` if it has one, else `fallback`, with `{n}` replaced by `args[n]`.
+/// Locks the provider, so call it from control threads only.
+pub fn tr(id: &str, fallback: &str, args: &[&str]) -> String {
+    let template = PROVIDER.lock().ok().and_then(|p| p.as_ref().and_then(|p| p.lookup(id)));
+    let mut text = template.unwrap_or_else(|| fallback.to_string());
+    for (i, arg) in args.iter().enumerate() {
+        text = text.replace(&format!("{{{}}}", i), arg);
+    }
+    text
+}
+
+/// String id prefix for a node type: lowercase name with non-alphanumerics as underscores ("Input Alignment" -> "input_alignment").
+pub fn node_key(node_name: &str) -> String {
+    node_name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect()
+}
+
+/// Display name of a node, id `node.<key>`.
+pub fn node_label(node: &dyn AudioNode) -> String {
+    tr(&format!("node.{}", node_key(node.get_name())), node.get_name(), &[])
+}
+
+/// Display name of a parameter, id `param.<node key>.<param id>`. Falls back to the node's English name, then "Param N".
+pub fn param_label(node: &dyn AudioNode, param_id: ParamId) -> String {
+    let id = format!("param.{}.{}", node_key(node.get_name()), param_id);
+    let fallback = node.param_name(param_id).unwrap_or_else(|| format!("Param {}", param_id));
+    tr(&id, &fallback, &[])
+}
@@ -0,0 +1,234 @@
+// strip.rs
+
+/* Per-Node Host Controls */
+
+#![allow(warnings)]
+
+use crate::dsp::simd;
+use crate::dspapi::ParamId;
+use crate::oversample;
+
+/// Parameter ids from here up address the controls the engine keeps around every node instead of the node
+/// itself. They are set with the usual Set Parameter command and stored with the node's parameters in a session.
+pub const HOST_PARAM_BASE: ParamId = 0xFFFF_FF00;
+/// Bypass (f32, >= 0.5 bypasses). Also set by the Set Bypass command.
+pub const HOST_PARAM_BYPASS: ParamId = HOST_PARAM_BASE;
+/// Wet/dry mix (f32, 0.0 dry to 1.0 fully processed; default 1.0), for using any node in parallel.
+pub const HOST_PARAM_MIX: ParamId = HOST_PARAM_BASE + 1;
+/// Input trim in dB (f32), applied before the node.
+pub const HOST_PARAM_TRIM: ParamId = HOST_PARAM_BASE + 2;
+/// Output gain in dB (f32), applied after the node and the mix.
+pub const HOST_PARAM_GAIN: ParamId = HOST_PARAM_BASE + 3;
+/// Pan (f32, -1.0 left to 1.0 right) of the first two channels. Unity at the centre; panning attenuates the
+/// opposite side along a constant-power curve.
+pub const HOST_PARAM_PAN: ParamId = HOST_PARAM_BASE + 4;
+/// Oversampling factor (f32: 1, 2, 4 or 8, other values round down). The node runs at that multiple of the
+/// engine rate inside an `oversample::Oversampled` wrapper, which adds its filters' latency.
+pub const HOST_PARAM_OVERSAMPLING: ParamId = HOST_PARAM_BASE + 5;
+/// Post-fader send level (f32, linear) to return bus n is `HOST_PARAM_SEND_BASE + n` (see `bus`). Setting one
+/// creates the send; the graph keeps it rather than the strip.
+pub const HOST_PARAM_SEND_BASE: ParamId = HOST_PARAM_BASE + 0x80;
+
+/// Return bus index a send parameter addresses.
+pub fn send_bus(param_id: ParamId) -> Option<u32> {
+    param_id.checked_sub(HOST_PARAM_SEND_BASE).filter(|&index| index < crate::bus::MAX_BUSES)
+}
+
+/// Length of the crossfade when a host control changes.
+const RAMP_SECONDS: f32 = 0.01;
+
+pub fn is_host_param(param_id: ParamId) -> bool {
+    param_id >= HOST_PARAM_BASE
+}
+
+/// A value that follows its target in a straight line, so control changes don't click.
+#[derive(Debug, Clone, Copy)]
+struct Smoothed {
+    current: f32,
+    target: f32,
+    step: f32,
+}
+
+impl Smoothed {
+    fn new(value: f32) -> Self {
+        Smoothed { current: value, target: value, step: 1.0 }
+    }
+
+    /// Heads for `target`, arriving after `ramp_frames` frames.
+    fn set(&mut self, target: f32, ramp_frames: f32) {
+        self.target = target;
+        self.step = ((target - self.current).abs() / ramp_frames).max(f32::EPSILON);
+    }
+
+    fn is_settled(&self) -> bool {
+        self.current == self.target
+    }
+
+    fn next(&mut self) -> f32 {
+        let delta = self.target - self.current;
+        self.current = if delta.abs() <= self.step { self.target } else { self.current + self.step * delta.signum() };
+        self.current
+    }
+}
+
+/// Controls the engine applies around one node's processing.
+#[derive(Debug, Clone)]
+pub struct NodeStrip {
+    ramp_frames: f32,
+    bypass: bool,
+    /// 0.0 plays the processed signal, 1.0 the dry one.
+    bypass_amount: Smoothed,
+    /// Share of the processed signal when not bypassed.
+    mix: Smoothed,
+    /// Linear gains, with the dB values they were set from.
+    trim: Smoothed,
+    trim_db: f32,
+    gain: Smoothed,
+    gain_db: f32,
+    pan: Smoothed,
+    oversampling: usize,
+}
+
+impl Default for NodeStrip {
+    fn default() -> Self {
+        NodeStrip {
+            ramp_frames: 441.0,
+            bypass: false,
+            bypass_amount: Smoothed::new(0.0),
+            mix: Smoothed::new(1.0),
+            trim: Smoothed::new(1.0),
+            trim_db: 0.0,
+            gain: Smoothed::new(1.0),
+            gain_db: 0.0,
+            pan: Smoothed::new(0.0),
+            oversampling: 1,
+        }
+    }
+}
+
+impl NodeStrip {
+    pub fn prepare(&mut self, sample_rate: u32) {
+        self.ramp_frames = (sample_rate as f32 * RAMP_SECONDS).max(1.0);
+    }
+
+    pub fn set_bypass(&mut self, bypass: bool) {
+        self.bypass = bypass;
+        self.bypass_amount.set(if bypass { 1.0 } else { 0.0 }, self.ramp_frames);
+    }
+
+    pub fn is_bypassed(&self) -> bool {
+        self.bypass
+    }
+
+    /// Starts the slot from its dry signal and crossfades to where its controls say, for a node just added.
+    pub fn fade_in(&mut self) {
+        self.bypass_amount = Smoothed::new(1.0);
+        self.set_bypass(self.bypass);
+    }
+
+    /// Factor the node runs oversampled by, 1 for none.
+    pub fn oversampling(&self) -> usize {
+        self.oversampling
+    }
+
+    /// Frames a crossfade of the host controls takes.
+    pub fn ramp_frames(&self) -> f32 {
+        self.ramp_frames
+    }
+
+    /// Sets a host parameter (see `HOST_PARAM_BASE`). Returns false for ids this strip doesn't have.
+    pub fn set_param(&mut self, param_id: ParamId, value: f32) -> bool {
+        match param_id {
+            HOST_PARAM_BYPASS => self.set_bypass(value >= 0.5),
+            HOST_PARAM_MIX => self.mix.set(value.clamp(0.0, 1.0), self.ramp_frames),
+            HOST_PARAM_TRIM => {
+                self.trim_db = value.clamp(-96.0, 24.0);
+                self.trim.set(db_to_gain(self.trim_db), self.ramp_frames);
+            }
+            HOST_PARAM_GAIN => {
+                self.gain_db = value.clamp(-96.0, 24.0);
+                self.gain.set(db_to_gain(self.gain_db), self.ramp_frames);
+            }
+            HOST_PARAM_PAN => self.pan.set(value.clamp(-1.0, 1.0), self.ramp_frames),
+            HOST_PARAM_OVERSAMPLING => self.oversampling = oversample::oversampling_factor(value),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn param(&self, param_id: ParamId) -> Option<f32> {
+        match param_id {
+            HOST_PARAM_BYPASS => Some(if self.bypass { 1.0 } else { 0.0 }),
+            HOST_PARAM_MIX => Some(self.mix.target),
+            HOST_PARAM_TRIM => Some(self.trim_db),
+            HOST_PARAM_GAIN => Some(self.gain_db),
+            HOST_PARAM_PAN => Some(self.pan.target),
+            HOST_PARAM_OVERSAMPLING => Some(self.oversampling as f32),
+            _ => None,
+        }
+    }
+
+
+    /// Fully bypassed: the node doesn't need to run at all.
+    pub fn skips_node(&self) -> bool {
+        self.bypass && self.bypass_amount.is_settled()
+    }
+
+    /// Whether `finish` needs the node's input.
+    pub fn needs_dry(&self) -> bool {
+        !self.bypass_amount.is_settled() || !self.mix.is_settled() || self.mix.target < 1.0
+    }
+
+    /// Applies the input trim to the node's input (interleaved, `channels` wide).
+    pub fn apply_trim(&mut self, buffer: &mut [f32], channels: usize) {
+        if self.trim.is_settled() {
+            if self.trim.target != 1.0 { simd::gain(buffer, self.trim.target); }
+            return;
+        }
+        for frame in buffer.chunks_mut(channels) {
+            let trim = self.trim.next();
+            frame.iter_mut().for_each(|s| *s *= trim);
+        }
+    }
+
+    /// Applies output gain and pan to the slot's output.
+    pub fn apply_output(&mut self, buffer: &mut [f32], channels: usize) {
+        let unity = self.gain.target == 1.0 && self.pan.target == 0.0;
+        if unity && self.gain.is_settled() && self.pan.is_settled() { return; }
+        for frame in buffer.chunks_mut(channels) {
+            let gain = self.gain.next();
+            let (left, right) = pan_gains(self.pan.next());
+            match frame {
+                [l, r, rest @ ..] => {
+                    *l *= gain * left;
+                    *r *= gain * right;
+                    rest.iter_mut().for_each(|s| *s *= gain);
+                }
+                _ => frame.iter_mut().for_each(|s| *s *= gain),
+            }
+        }
+    }
+
+    /// Blends the node's input `dry` into its output `wet` (interleaved, `channels` wide) according to the mix
+    /// and any running bypass crossfade.
+    pub fn finish(&mut self, dry: &[f32], wet: &mut [f32], channels: usize) {
+        if !self.needs_dry() { return; }
+        for (out, input) in wet.chunks_mut(channels).zip(dry.chunks(channels)) {
+            let processed = (1.0 - self.bypass_amount.next()) * self.mix.next();
+            for (o, i) in out.iter_mut().zip(input) {
+                *o = i + (*o - i) * processed;
+            }
+        }
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    if db <= -96.0 { 0.0 } else { 10f32.powf(db / 20.0) }
+}
+
+/// Constant-power pan normalised to unity at the centre, so a centred strip leaves the level alone.
+fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+    let scale = std::f32::consts::SQRT_2;
+    ((angle.cos() * scale).min(1.0), (angle.sin() * scale).min(1.0))
+}
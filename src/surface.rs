@@ -0,0 +1,226 @@
+// surface.rs
+
+/* Mackie Control Universal Surface Protocol */
+
+#![allow(warnings)]
+
+use crate::dspapi::{Command, NodeId, ParamId, StatState};
+use crate::dspengine::{EngineHandle, DSPENGINE};
+use crate::sharedgraph::GraphSnapshot;
+
+/// Channel strips per MCU unit.
+pub const STRIPS: usize = 8;
+/// Characters per strip on each LCD row.
+pub const LCD_CELL: usize = 7;
+
+// MCU note numbers (all on MIDI channel 1).
+const NOTE_REC_ARM: u8 = 0x00;
+const NOTE_SOLO: u8 = 0x08;
+const NOTE_MUTE: u8 = 0x10;
+const NOTE_SELECT: u8 = 0x18;
+const NOTE_BANK_LEFT: u8 = 0x2E;
+const NOTE_BANK_RIGHT: u8 = 0x2F;
+const NOTE_CHANNEL_LEFT: u8 = 0x30;
+const NOTE_CHANNEL_RIGHT: u8 = 0x31;
+const NOTE_STOP: u8 = 0x5D;
+const NOTE_PLAY: u8 = 0x5E;
+const NOTE_RECORD: u8 = 0x5F;
+const NOTE_FADER_TOUCH: u8 = 0x68;
+
+/// Sysex header for the main MCU unit (extenders use device id 0x15).
+const SYSEX_HEADER: [u8; 5] = [0xF0, 0x00, 0x00, 0x66, 0x14];
+
+/// A rack node shown on a surface channel strip. The fader drives `fader_param` over `min..max`.
+#[derive(Debug, Clone)]
+pub struct ChannelStrip {
+    pub node_id: NodeId,
+    pub name: String,
+    pub fader_param: ParamId,
+    pub min: f32,
+    pub max: f32,
+}
+
+/// Strip buttons reported by the surface for the frontend to act on (mute/solo routing lives outside the engine).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StripButton {
+    RecArm,
+    Solo,
+    Mute,
+    Select,
+}
+
+/// Result of one incoming surface message.
+#[derive(Clone)]
+pub enum SurfaceEvent {
+    /// Engine commands to send (fader moves, transport).
+    Commands(Vec<Command>),
+    /// A strip button was pressed on the node shown in that strip.
+    Button { node_id: NodeId, button: StripButton },
+    /// The visible bank changed; the caller should push `refresh()` to the surface.
+    BankChanged,
+}
+
+/// Mackie Control Universal protocol state for one 8-strip unit. Encodes and decodes raw MIDI only,
+/// so it can sit behind any MIDI port. HUI is not supported.
+pub struct McuSurface {
+    pub strips: Vec<ChannelStrip>,
+    /// Index of the first strip shown on the surface.
+    bank_offset: usize,
+    /// Faders the user is holding; motor feedback is suppressed for those so the fader doesn't fight the hand.
+    touched: [bool; STRIPS],
+}
+
+impl McuSurface {
+    pub fn new() -> Self {
+        Self {
+            strips: Vec::new(),
+            bank_offset: 0,
+            touched: [false; STRIPS],
+        }
+    }
+
+    /// Builds one strip per node currently in the default engine's graph, in processing order, labelled with the node name.
+    pub fn strips_from_rack(fader_param: ParamId, min: f32, max: f32) -> Vec<ChannelStrip> {
+        let Ok(handle) = DSPENGINE.lock().map(|engine| engine.handle()) else { return vec![] };
+        Self::strips_from_engine(&handle, fader_param, min, max)
+    }
+
+    /// Like `strips_from_rack`, for a specific engine.
+    pub fn strips_from_engine(engine: &EngineHandle, fader_param: ParamId, min: f32, max: f32) -> Vec<ChannelStrip> {
+        Self::strips_from_snapshot(&engine.graph.snapshot(), fader_param, min, max)
+    }
+
+    fn strips_from_snapshot(graph: &GraphSnapshot, fader_param: ParamId, min: f32, max: f32) -> Vec<ChannelStrip> {
+        graph
+            .nodes
+            .iter()
+            .map(|node| ChannelStrip {
+                node_id: node.id,
+                name: node.name.clone(),
+                fader_param,
+                min,
+                max,
+            })
+            .collect()
+    }
+
+    pub fn set_strips(&mut self, strips: Vec<ChannelStrip>) {
+        self.strips = strips;
+        self.bank_offset = self.bank_offset.min(self.strips.len().saturating_sub(1));
+    }
+
+    pub fn bank_offset(&self) -> usize {
+        self.bank_offset
+    }
+
+    /// Strips currently under the surface's faders.
+    pub fn visible(&self) -> &[ChannelStrip] {
+        let start = self.bank_offset.min(self.strips.len());
+        let end = (start + STRIPS).min(self.strips.len());
+        &self.strips[start..end]
+    }
+
+    fn shift_bank(&mut self, delta: isize) -> bool {
+        let max_offset = self.strips.len().saturating_sub(1) as isize;
+        let next = (self.bank_offset as isize + delta).clamp(0, max_offset.max(0)) as usize;
+        let changed = next != self.bank_offset;
+        self.bank_offset = next;
+        changed
+    }
+
+    /// Decodes a message from the surface.
+    pub fn handle_midi(&mut self, message: &[u8]) -> Option<SurfaceEvent> {
+        let status = *message.first()?;
+        match (status & 0xF0, message.get(1), message.get(2)) {
+            // Faders send 14-bit pitch bend, one MIDI channel per strip.
+            (0xE0, Some(&lsb), Some(&msb)) => {
+                let strip = self.visible().get((status & 0x0F) as usize)?;
+                let value = (((msb as u16) << 7) | lsb as u16) as f32 / 16383.0;
+                let scaled = strip.min + (strip.max - strip.min) * value;
+                Some(SurfaceEvent::Commands(vec![Command::new(
+                    2,
+                    "Set Parameter",
+                    scaled.to_le_bytes().to_vec(),
+                    strip.node_id,
+                    strip.fader_param,
+                    0,
+                    StatState::ACTIVE,
+                )]))
+            }
+            (0x90, Some(&note), Some(&velocity)) => self.handle_button(note, velocity > 0),
+            _ => None,
+        }
+    }
+
+    fn handle_button(&mut self, note: u8, pressed: bool) -> Option<SurfaceEvent> {
+        if (NOTE_FADER_TOUCH..NOTE_FADER_TOUCH + STRIPS as u8).contains(&note) {
+            self.touched[(note - NOTE_FADER_TOUCH) as usize] = pressed;
+            return None;
+        }
+        if !pressed { return None; }
+
+        let strip_button = |base: u8, button: StripButton| {
+            (base..base + STRIPS as u8).contains(&note).then(|| (note - base) as usize).map(|i| (i, button))
+        };
+        let button = strip_button(NOTE_REC_ARM, StripButton::RecArm)
+            .or_else(|| strip_button(NOTE_SOLO, StripButton::Solo))
+            .or_else(|| strip_button(NOTE_MUTE, StripButton::Mute))
+            .or_else(|| strip_button(NOTE_SELECT, StripButton::Select));
+        if let Some((index, button)) = button {
+            let strip = self.visible().get(index)?;
+            return Some(SurfaceEvent::Button { node_id: strip.node_id, button });
+        }
+
+        let command = |id: u32, name: &str| Some(SurfaceEvent::Commands(vec![Command::new(id, name, Vec::new(), 0, 0, 0, StatState::ACTIVE)]));
+        match note {
+            NOTE_PLAY => command(10, "Transport Play"),
+            NOTE_STOP => command(11, "Transport Stop"),
+            NOTE_RECORD => command(12, "Transport Record"),
+            NOTE_BANK_LEFT => self.shift_bank(-(STRIPS as isize)).then_some(SurfaceEvent::BankChanged),
+            NOTE_BANK_RIGHT => self.shift_bank(STRIPS as isize).then_some(SurfaceEvent::BankChanged),
+            NOTE_CHANNEL_LEFT => self.shift_bank(-1).then_some(SurfaceEvent::BankChanged),
+            NOTE_CHANNEL_RIGHT => self.shift_bank(1).then_some(SurfaceEvent::BankChanged),
+            _ => None,
+        }
+    }
+
+    /// Motor fader position for a visible strip. Returns nothing while the user is touching that fader.
+    pub fn fader_message(&self, strip: usize, value: f32) -> Option<Vec<u8>> {
+        if strip >= STRIPS || self.touched[strip] { return None; }
+        let position = (value.clamp(0.0, 1.0) * 16383.0).round() as u16;
+        Some(vec![0xE0 | strip as u8, (position & 0x7F) as u8, (position >> 7) as u8])
+    }
+
+    /// Meter level for a visible strip, as channel pressure. The surface decays the meter by itself.
+    pub fn meter_message(&self, strip: usize, level_db: f32) -> Option<Vec<u8>> {
+        if strip >= STRIPS { return None; }
+        // MCU meters have 13 segments (0x0..0xC) covering roughly -60..0 dBFS; 0xE lights the overload LED.
+        let segment = if level_db >= 0.0 { 0x0E } else { ((level_db + 60.0) / 5.0).clamp(0.0, 12.0) as u8 };
+        Some(vec![0xD0, ((strip as u8) << 4) | segment])
+    }
+
+    /// Writes `text` to the scribble strip of a visible strip, padded/truncated to one LCD cell. Row 0 is top.
+    pub fn lcd_message(&self, strip: usize, row: usize, text: &str) -> Option<Vec<u8>> {
+        if strip >= STRIPS || row > 1 { return None; }
+        let offset = (row * STRIPS * LCD_CELL + strip * LCD_CELL) as u8;
+        let mut message = SYSEX_HEADER.to_vec();
+        message.extend([0x12, offset]);
+        message.extend(text.chars().chain(std::iter::repeat(' ')).take(LCD_CELL).map(|c| if c.is_ascii() && !c.is_ascii_control() { c as u8 } else { b'?' }));
+        message.push(0xF7);
+        Some(message)
+    }
+
+    /// Full redraw of the visible bank: names on the top LCD row, faders at `values` (normalized, per visible strip).
+    /// Unused strips are blanked and their faders parked at the bottom.
+    pub fn refresh(&self, values: &[f32]) -> Vec<Vec<u8>> {
+        let visible = self.visible();
+        let mut out = Vec::new();
+        for i in 0..STRIPS {
+            let name = visible.get(i).map_or("", |s| s.name.as_str());
+            out.extend(self.lcd_message(i, 0, name));
+            let value = if i < visible.len() { values.get(i).copied().unwrap_or(0.0) } else { 0.0 };
+            out.extend(self.fader_message(i, value));
+        }
+        out
+    }
+}
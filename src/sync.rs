@@ -0,0 +1,142 @@
+// sync.rs
+
+/* Multi-Engine Scene Synchronization */
+
+#![allow(warnings)]
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::dspapi::{Command, NodeId, ParamId};
+use crate::dspengine::EngineHandle;
+
+/// Which end of a link a command originated from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EngineSide {
+    Primary,
+    Secondary,
+}
+
+impl EngineSide {
+    fn other(self) -> Self {
+        match self {
+            EngineSide::Primary => EngineSide::Secondary,
+            EngineSide::Secondary => EngineSide::Primary,
+        }
+    }
+}
+
+/// How conflicting changes to the same scene or parameter are resolved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictRule {
+    /// Every change is mirrored; the most recent one wins on both engines.
+    LastWriterWins,
+    /// The primary always mirrors. The secondary only mirrors if the primary hasn't touched the same key within the conflict window.
+    PrimaryWins,
+    /// Changes stay on the engine they were made on.
+    LocalOnly,
+}
+
+/// A named set of parameters mirrored between engines. `None` as param id covers every parameter of the node.
+#[derive(Debug, Clone)]
+pub struct ParamGroup {
+    pub name: String,
+    pub members: Vec<(NodeId, Option<ParamId>)>,
+    pub rule: ConflictRule,
+}
+
+impl ParamGroup {
+    fn contains(&self, node_id: NodeId, param_id: ParamId) -> bool {
+        self.members.iter().any(|&(n, p)| n == node_id && p.is_none_or(|p| p == param_id))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum SyncKey {
+    Scene,
+    Param(NodeId, ParamId),
+}
+
+/// Mirrors scene recalls and selected parameter groups between two engines (e.g. main/cue or FOH/monitor).
+pub struct EngineSync {
+    primary: EngineHandle,
+    secondary: EngineHandle,
+    pub scene_rule: ConflictRule,
+    pub groups: Vec<ParamGroup>,
+    /// Changes from both sides to the same key inside this window are treated as a conflict.
+    pub conflict_window: Duration,
+    last_write: HashMap<SyncKey, (EngineSide, Instant)>,
+}
+
+impl EngineSync {
+    pub fn new(primary: EngineHandle, secondary: EngineHandle) -> Self {
+        Self {
+            primary,
+            secondary,
+            scene_rule: ConflictRule::PrimaryWins,
+            groups: Vec::new(),
+            conflict_window: Duration::from_millis(500),
+            last_write: HashMap::new(),
+        }
+    }
+
+    pub fn add_group(&mut self, group: ParamGroup) {
+        self.groups.retain(|g| g.name != group.name);
+        self.groups.push(group);
+    }
+
+    pub fn remove_group(&mut self, name: &str) {
+        self.groups.retain(|g| g.name != name);
+    }
+
+    /// Delivers a command to the engine on `side` and mirrors it to the other engine when the sync rules allow.
+    /// Returns true if the command was mirrored.
+    pub fn submit(&mut self, side: EngineSide, cmd: Command) -> bool {
+        let mirror = self.mirror_of(side, &cmd);
+        let mirrored = mirror.is_some();
+
+        if let Some(copy) = mirror {
+            self.engine(side.other()).send(copy);
+        }
+        self.engine(side).send(cmd);
+        mirrored
+    }
+
+    fn mirror_of(&mut self, side: EngineSide, cmd: &Command) -> Option<Command> {
+        let (key, rule) = match cmd.command_id {
+            20 => (SyncKey::Scene, self.scene_rule),
+            2 => {
+                let group = self.groups.iter().find(|g| g.contains(cmd.node_id, cmd.param_id))?;
+                (SyncKey::Param(cmd.node_id, cmd.param_id), group.rule)
+            }
+            _ => return None,
+        };
+
+        let now = Instant::now();
+        let previous = self.last_write.insert(key, (side, now));
+        let allowed = match rule {
+            ConflictRule::LocalOnly => false,
+            ConflictRule::LastWriterWins => true,
+            ConflictRule::PrimaryWins => match (side, previous) {
+                (EngineSide::Secondary, Some((EngineSide::Primary, at))) => now.duration_since(at) >= self.conflict_window,
+                _ => true,
+            },
+        };
+
+        if !allowed {
+            // The rejected write must not shadow the primary's claim on the key.
+            if let Some(prev) = previous {
+                self.last_write.insert(key, prev);
+            }
+            return None;
+        }
+        Some(cmd.clone())
+    }
+
+    fn engine(&self, side: EngineSide) -> &EngineHandle {
+        match side {
+            EngineSide::Primary => &self.primary,
+            EngineSide::Secondary => &self.secondary,
+        }
+    }
+}
@@ -0,0 +1,189 @@
+// taps.rs
+
+/* Delay-Compensated Metering Taps */
+
+#![allow(warnings)]
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::dsp::simd;
+use crate::dspapi::{NodeId, PortId};
+use crate::graph::{GRAPH_CAPTURE, GRAPH_INPUT, GRAPH_MIDI_INPUT, GRAPH_MIDI_OUTPUT, GRAPH_OUTPUT};
+use crate::session::Connection;
+
+/// Highest output port a `TapPoint::Connection` can name; it shares the tap slot's word with the node id.
+pub const MAX_TAP_PORT: PortId = 0xFF_FFFF;
+
+/// Where in the rack a tap listens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapPoint {
+    /// The signal entering the rack.
+    Input,
+    /// Live input captured from the input device, as `graph::GRAPH_CAPTURE` feeds it to the rack.
+    Capture,
+    /// A node's output, i.e. the connection from that node to whatever follows it.
+    AfterNode(NodeId),
+    /// The signal leaving output port `from_port` of node `from`, up to `MAX_TAP_PORT`. The graph feeds every
+    /// connection from a node with its output buffer, so this reads what the connection carries.
+    Connection { from: NodeId, from_port: PortId },
+}
+
+impl TapPoint {
+    /// The tap carrying the signal of a session connection. Connections from the other pseudo-nodes carry no
+    /// audio, so `TapSet::add` refuses their taps.
+    pub fn from_connection(connection: &Connection) -> Self {
+        match connection.from_node {
+            GRAPH_INPUT => TapPoint::Input,
+            GRAPH_CAPTURE => TapPoint::Capture,
+            from => TapPoint::Connection { from, from_port: connection.from_port },
+        }
+    }
+
+    /// The node whose output the tap reads, `None` for the rack and captured input.
+    pub fn node(self) -> Option<NodeId> {
+        match self {
+            TapPoint::Input | TapPoint::Capture => None,
+            TapPoint::AfterNode(node) | TapPoint::Connection { from: node, .. } => Some(node),
+        }
+    }
+
+    /// 0 (an empty slot) for a connection port beyond `MAX_TAP_PORT` and for pseudo-nodes other than the inputs,
+    /// which are never measured.
+    fn encode(self) -> u64 {
+        if self.node().is_some_and(|node| matches!(node, GRAPH_INPUT | GRAPH_OUTPUT | GRAPH_CAPTURE | GRAPH_MIDI_INPUT | GRAPH_MIDI_OUTPUT)) {
+            return 0;
+        }
+        match self {
+            TapPoint::Input => 1,
+            TapPoint::Capture => 2,
+            TapPoint::AfterNode(node) => (2 << 32) | node as u64,
+            TapPoint::Connection { from_port, .. } if from_port > MAX_TAP_PORT => 0,
+            TapPoint::Connection { from, from_port } => (3 << 56) | (from_port as u64) << 32 | from as u64,
+        }
+    }
+
+    fn decode(bits: u64) -> Option<Self> {
+        if bits >> 56 == 3 {
+            return Some(TapPoint::Connection { from: bits as u32, from_port: (bits >> 32) as u32 & MAX_TAP_PORT });
+        }
+        match bits >> 32 {
+            0 if bits == 1 => Some(TapPoint::Input),
+            0 if bits == 2 => Some(TapPoint::Capture),
+            2 => Some(TapPoint::AfterNode(bits as u32)),
+            _ => None,
+        }
+    }
+}
+
+/// Latest measurement of one tap.
+#[derive(Debug, Clone, Copy)]
+pub struct MeterReading {
+    pub tap: usize,
+    pub point: TapPoint,
+    pub peak: f32,
+    pub rms: f32,
+    /// Sample position of the measured block with upstream latency subtracted, so readings from paths with
+    /// different latency that share a timestamp describe the same moment of source audio.
+    pub timestamp: u64,
+    /// Latency upstream of the tap when it was measured.
+    pub latency: usize,
+}
+
+#[derive(Default)]
+struct TapSlot {
+    point: AtomicU64,
+    peak: AtomicU32,
+    rms: AtomicU32,
+    timestamp: AtomicU64,
+    latency: AtomicU64,
+}
+
+/// Fixed set of tap slots shared with the audio thread. Adding, removing and reading taps are lock-free.
+pub struct TapSet {
+    slots: Vec<TapSlot>,
+    active: AtomicU32,
+}
+
+impl TapSet {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            slots: (0..capacity).map(|_| TapSlot::default()).collect(),
+            active: AtomicU32::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn allocated_bytes(&self) -> usize {
+        self.slots.len() * std::mem::size_of::<TapSlot>()
+    }
+
+    /// Places a tap. Returns its id, or `None` if every slot is in use or the point can't be tapped.
+    pub fn add(&self, point: TapPoint) -> Option<usize> {
+        let bits = point.encode();
+        if bits == 0 { return None; }
+        let index = self.slots.iter().position(|slot| slot.point.compare_exchange(0, bits, Ordering::AcqRel, Ordering::Relaxed).is_ok())?;
+        self.active.fetch_add(1, Ordering::Relaxed);
+        Some(index)
+    }
+
+    pub fn remove(&self, tap: usize) {
+        if let Some(slot) = self.slots.get(tap) {
+            if slot.point.swap(0, Ordering::AcqRel) != 0 {
+                self.active.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Current readings of every placed tap.
+    pub fn readings(&self) -> Vec<MeterReading> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(tap, slot)| {
+                Some(MeterReading {
+                    tap,
+                    point: TapPoint::decode(slot.point.load(Ordering::Acquire))?,
+                    peak: f32::from_bits(slot.peak.load(Ordering::Relaxed)),
+                    rms: f32::from_bits(slot.rms.load(Ordering::Relaxed)),
+                    timestamp: slot.timestamp.load(Ordering::Relaxed),
+                    latency: slot.latency.load(Ordering::Relaxed) as usize,
+                })
+            })
+            .collect()
+    }
+
+    /// Cheap check so the audio thread can skip metering entirely when no taps are placed.
+    pub fn is_empty(&self) -> bool {
+        self.active.load(Ordering::Relaxed) == 0
+    }
+
+    /// Measures `buffer` for every tap at `point`. Called from the audio thread; never allocates.
+    pub fn measure(&self, point: TapPoint, buffer: &[f32], position: u64, latency: usize) {
+        let bits = point.encode();
+        self.measure_matching(|tapped| tapped == bits, buffer, position, latency);
+    }
+
+    /// Measures `buffer`, node `id`'s output, for every tap after the node or on a connection from it. Called
+    /// from the audio thread; never allocates.
+    pub fn measure_node(&self, id: NodeId, buffer: &[f32], position: u64, latency: usize) {
+        self.measure_matching(|tapped| tapped != 0 && TapPoint::decode(tapped).and_then(TapPoint::node) == Some(id), buffer, position, latency);
+    }
+
+    fn measure_matching(&self, matches: impl Fn(u64) -> bool, buffer: &[f32], position: u64, latency: usize) {
+        let mut measured: Option<(f32, f32)> = None;
+        for slot in self.slots.iter().filter(|slot| matches(slot.point.load(Ordering::Relaxed))) {
+            let (peak, rms) = *measured.get_or_insert_with(|| {
+                let peak = simd::peak(buffer);
+                let power = buffer.iter().map(|s| s * s).sum::<f32>() / buffer.len().max(1) as f32;
+                (peak, power.sqrt())
+            });
+            slot.peak.store(peak.to_bits(), Ordering::Relaxed);
+            slot.rms.store(rms.to_bits(), Ordering::Relaxed);
+            slot.timestamp.store(position.saturating_sub(latency as u64), Ordering::Relaxed);
+            slot.latency.store(latency as u64, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+// threads.rs
+
+/* Engine Thread Introspection */
+
+#![allow(warnings)]
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use once_cell::sync::Lazy;
+
+/// SCHED_FIFO priority the audio thread asks for, lowered to what the system allows.
+pub const RT_PRIORITY: i32 = 80;
+
+static REGISTRY: Lazy<Mutex<Vec<Arc<ThreadSlot>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThreadRole {
+    AudioCallback,
+    Worker,
+    Loader,
+    Recorder,
+    Supervisor,
+    Other,
+}
+
+/// Snapshot of one engine thread, for a frontend "performance" panel or support logs.
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub name: String,
+    pub role: ThreadRole,
+    /// OS scheduling policy and priority, when the platform exposes them.
+    pub policy: Option<&'static str>,
+    pub priority: Option<i32>,
+    /// Smoothed busy time as a fraction of the available time (1.0 = fully loaded).
+    pub utilization: f32,
+    pub peak_utilization: f32,
+}
+
+struct ThreadSlot {
+    name: String,
+    role: ThreadRole,
+    policy: Mutex<Option<&'static str>>,
+    priority: Mutex<Option<i32>>,
+    // f32 bit patterns so the owning thread can update them without locking.
+    utilization: AtomicU32,
+    peak: AtomicU32,
+}
+
+/// Registration held by the thread itself. Dropping it removes the thread from the list.
+pub struct ThreadHandle {
+    slot: Arc<ThreadSlot>,
+}
+
+/// Registers the calling thread. Allocates, so audio callbacks should call it once on their first invocation.
+pub fn register_current(name: &str, role: ThreadRole) -> ThreadHandle {
+    let (policy, priority) = current_scheduling();
+    let slot = Arc::new(ThreadSlot {
+        name: name.to_string(),
+        role,
+        policy: Mutex::new(policy),
+        priority: Mutex::new(priority),
+        utilization: AtomicU32::new(0.0f32.to_bits()),
+        peak: AtomicU32::new(0.0f32.to_bits()),
+    });
+    if let Ok(mut registry) = REGISTRY.lock() {
+        registry.push(Arc::clone(&slot));
+    }
+    ThreadHandle { slot }
+}
+
+/// Lists every registered engine thread.
+pub fn list() -> Vec<ThreadInfo> {
+    let Ok(registry) = REGISTRY.lock() else { return vec![] };
+    registry
+        .iter()
+        .map(|slot| ThreadInfo {
+            name: slot.name.clone(),
+            role: slot.role,
+            policy: slot.policy.lock().ok().and_then(|p| *p),
+            priority: slot.priority.lock().ok().and_then(|p| *p),
+            utilization: f32::from_bits(slot.utilization.load(Ordering::Relaxed)),
+            peak_utilization: f32::from_bits(slot.peak.load(Ordering::Relaxed)),
+        })
+        .collect()
+}
+
+impl ThreadHandle {
+    /// Records one cycle of work: `busy` spent out of `period` available. Lock-free, safe on the audio thread.
+    pub fn record(&self, busy: Duration, period: Duration) {
+        if period.is_zero() { return; }
+        let load = busy.as_secs_f32() / period.as_secs_f32();
+        let previous = f32::from_bits(self.slot.utilization.load(Ordering::Relaxed));
+        // ~50 cycle exponential average: responsive but not jumpy.
+        let smoothed = previous + 0.02 * (load - previous);
+        self.slot.utilization.store(smoothed.to_bits(), Ordering::Relaxed);
+        if load > f32::from_bits(self.slot.peak.load(Ordering::Relaxed)) {
+            self.slot.peak.store(load.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn reset_peak(&self) {
+        self.slot.peak.store(0.0f32.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Re-reads the OS priority, e.g. after the thread has been promoted. Must be called from the registered thread.
+    pub fn refresh_priority(&self) {
+        let (policy, priority) = current_scheduling();
+        if let Ok(mut p) = self.slot.policy.lock() { *p = policy; }
+        if let Ok(mut p) = self.slot.priority.lock() { *p = priority; }
+    }
+}
+
+/// How `promote_current` made the calling thread real-time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RtPromotion {
+    /// SCHED_FIFO at this priority (Linux).
+    Fifo(i32),
+    /// Registered with MMCSS as a "Pro Audio" task (Windows).
+    Mmcss,
+    /// Mach time-constraint policy for the callback period (macOS).
+    TimeConstraint,
+    /// The thread was already real-time. On macOS this is CoreAudio's IO thread, which is also already a
+    /// member of the device's workgroup.
+    AlreadyRealtime,
+}
+
+/// Promotes the calling thread to real-time scheduling for work due every `period`. If the OS refuses (e.g. no
+/// RLIMIT_RTPRIO on Linux) the thread is left as it was and the error says why; audio still runs, with a
+/// higher risk of dropouts under load.
+pub fn promote_current(period: Duration) -> Result<RtPromotion, &'static str> {
+    promote(period)
+}
+
+impl Drop for ThreadHandle {
+    fn drop(&mut self) {
+        if let Ok(mut registry) = REGISTRY.lock() {
+            registry.retain(|slot| !Arc::ptr_eq(slot, &self.slot));
+        }
+    }
+}
+
+#[cfg(unix)]
+fn current_scheduling() -> (Option<&'static str>, Option<i32>) {
+    unsafe {
+        let mut policy = 0;
+        let mut param: libc::sched_param = std::mem::zeroed();
+        if libc::pthread_getschedparam(libc::pthread_self(), &mut policy, &mut param) != 0 {
+            return (None, None);
+        }
+        let name = match policy {
+            libc::SCHED_FIFO => "SCHED_FIFO",
+            libc::SCHED_RR => "SCHED_RR",
+            _ => "SCHED_OTHER",
+        };
+        (Some(name), Some(param.sched_priority))
+    }
+}
+
+#[cfg(not(unix))]
+fn current_scheduling() -> (Option<&'static str>, Option<i32>) {
+    (None, None)
+}
+
+#[cfg(target_os = "linux")]
+fn promote(_period: Duration) -> Result<RtPromotion, &'static str> {
+    unsafe {
+        let (mut policy, mut param) = (0, std::mem::zeroed::<libc::sched_param>());
+        if libc::pthread_getschedparam(libc::pthread_self(), &mut policy, &mut param) == 0 && matches!(policy, libc::SCHED_FIFO | libc::SCHED_RR) {
+            return Ok(RtPromotion::AlreadyRealtime);
+        }
+        let mut priority = RT_PRIORITY.min(libc::sched_get_priority_max(libc::SCHED_FIFO));
+        // Without CAP_SYS_NICE, only priorities up to RLIMIT_RTPRIO are allowed.
+        let mut limit: libc::rlimit = std::mem::zeroed();
+        if libc::getrlimit(libc::RLIMIT_RTPRIO, &mut limit) == 0 && limit.rlim_cur != libc::RLIM_INFINITY && limit.rlim_cur > 0 {
+            priority = priority.min(limit.rlim_cur as i32);
+        }
+        param.sched_priority = priority;
+        match libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) {
+            0 => Ok(RtPromotion::Fifo(priority)),
+            libc::EPERM => Err("not permitted to use real-time scheduling (raise RLIMIT_RTPRIO or grant CAP_SYS_NICE)"),
+            _ => Err("real-time scheduling is unavailable"),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn promote(period: Duration) -> Result<RtPromotion, &'static str> {
+    unsafe {
+        let thread = libc::pthread_mach_thread_np(libc::pthread_self());
+        let mut policy: libc::thread_time_constraint_policy = std::mem::zeroed();
+        let mut count = libc::THREAD_TIME_CONSTRAINT_POLICY_COUNT;
+        let mut get_default = 0;
+        let flavor = libc::THREAD_TIME_CONSTRAINT_POLICY as libc::thread_policy_flavor_t;
+        if libc::thread_policy_get(thread, flavor, &mut policy as *mut _ as libc::thread_policy_t, &mut count, &mut get_default) == libc::KERN_SUCCESS
+            && get_default == 0
+        {
+            return Ok(RtPromotion::AlreadyRealtime);
+        }
+        let mut timebase = libc::mach_timebase_info { numer: 0, denom: 0 };
+        libc::mach_timebase_info(&mut timebase);
+        let ticks = |d: Duration| (d.as_nanos() as u64 * timebase.denom as u64 / timebase.numer.max(1) as u64).min(u32::MAX as u64) as u32;
+        policy = libc::thread_time_constraint_policy { period: ticks(period), computation: ticks(period / 2), constraint: ticks(period), preemptible: 1 };
+        if libc::thread_policy_set(thread, flavor, &mut policy as *mut _ as libc::thread_policy_t, libc::THREAD_TIME_CONSTRAINT_POLICY_COUNT) != libc::KERN_SUCCESS {
+            return Err("time-constraint scheduling was refused");
+        }
+        Ok(RtPromotion::TimeConstraint)
+    }
+}
+
+#[cfg(windows)]
+fn promote(_period: Duration) -> Result<RtPromotion, &'static str> {
+    use windows_sys::Win32::System::Threading::{AvSetMmThreadCharacteristicsW, AvSetMmThreadPriority, AVRT_PRIORITY_HIGH};
+    let task: Vec<u16> = "Pro Audio".encode_utf16().chain(Some(0)).collect();
+    let mut index = 0u32;
+    unsafe {
+        // Kept until the thread exits; the stream's thread ends with the stream.
+        let handle = AvSetMmThreadCharacteristicsW(task.as_ptr(), &mut index);
+        if handle.is_null() { return Err("MMCSS registration failed"); }
+        AvSetMmThreadPriority(handle, AVRT_PRIORITY_HIGH);
+    }
+    Ok(RtPromotion::Mmcss)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn promote(_period: Duration) -> Result<RtPromotion, &'static str> {
+    Err("real-time scheduling is not supported on this platform")
+}
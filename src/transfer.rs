@@ -0,0 +1,204 @@
+// transfer.rs
+
+/* Chunked Payload Transfer */
+
+#![allow(warnings)]
+
+use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+
+use crate::dspapi::{Command, NodeId, ParamId, PortId, StatState};
+use crate::intern::NameId;
+
+pub const TRANSFER_BEGIN: u32 = 30;
+pub const TRANSFER_CHUNK: u32 = 31;
+pub const TRANSFER_END: u32 = 32;
+
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+/// Every chunk but the last carries at least this much, so a transfer's chunk count (and the bookkeeping
+/// allocated for it at `TRANSFER_BEGIN`) is bounded by its size.
+pub const MIN_CHUNK_SIZE: usize = 4 * 1024;
+/// Larger transfers are refused at `TRANSFER_BEGIN`.
+pub const MAX_TRANSFER_BYTES: u64 = 256 * 1024 * 1024;
+/// Transfers one `Reassembler` (one client) may have open at once; further ones are refused at `TRANSFER_BEGIN`.
+pub const MAX_CONCURRENT_TRANSFERS: usize = 4;
+
+// u32 final opcode + u32 final port + u64 total length + u32 chunk count + SHA-256
+const BEGIN_SIZE: usize = 4 + 4 + 8 + 4 + 32;
+// u32 chunk index + u32 chunk checksum
+const CHUNK_HEADER_SIZE: usize = 8;
+
+pub fn is_transfer(command: &Command) -> bool {
+    (TRANSFER_BEGIN..=TRANSFER_END).contains(&command.command_id)
+}
+
+fn chunk_checksum(data: &[u8]) -> u32 {
+    let digest = Sha256::digest(data);
+    u32::from_le_bytes(digest[..4].try_into().unwrap())
+}
+
+/// Splits a command with a large payload (preset blob, IR file) into begin / chunk / end commands, all carrying
+/// `transfer_id` in `port_id`. The receiver's `Reassembler` rebuilds the original command once every chunk arrived
+/// and the whole payload matches its SHA-256. `chunk_size` is raised to `MIN_CHUNK_SIZE` if below it.
+pub fn split(command: &Command, transfer_id: PortId, chunk_size: usize) -> Vec<Command> {
+    let chunk_size = chunk_size.max(MIN_CHUNK_SIZE);
+    let chunks: Vec<&[u8]> = command.payload.chunks(chunk_size).collect();
+
+    let mut begin = Vec::with_capacity(BEGIN_SIZE);
+    begin.extend_from_slice(&command.command_id.to_le_bytes());
+    begin.extend_from_slice(&command.port_id.to_le_bytes());
+    begin.extend_from_slice(&(command.payload.len() as u64).to_le_bytes());
+    begin.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+    begin.extend_from_slice(&Sha256::digest(&command.payload));
+
+    let part = |opcode: u32, payload: Vec<u8>| {
+        Command::with_name_id(opcode, command.description, payload, command.node_id, command.param_id, transfer_id, command.stat)
+    };
+    let mut commands = Vec::with_capacity(chunks.len() + 2);
+    commands.push(part(TRANSFER_BEGIN, begin));
+    for (index, chunk) in chunks.iter().enumerate() {
+        let mut payload = Vec::with_capacity(CHUNK_HEADER_SIZE + chunk.len());
+        payload.extend_from_slice(&(index as u32).to_le_bytes());
+        payload.extend_from_slice(&chunk_checksum(chunk).to_le_bytes());
+        payload.extend_from_slice(chunk);
+        commands.push(part(TRANSFER_CHUNK, payload));
+    }
+    commands.push(part(TRANSFER_END, Vec::new()));
+    commands
+}
+
+/// 107: Transfer Progress (u64 bytes received + u64 total), `port_id` is the transfer id.
+pub fn progress_event(transfer_id: PortId, received: u64, total: u64) -> Command {
+    let mut payload = received.to_le_bytes().to_vec();
+    payload.extend_from_slice(&total.to_le_bytes());
+    Command::new(107, "Transfer Progress", payload, 0, 0, transfer_id, StatState::ACTIVE)
+}
+
+/// 108: Transfer Failed (reason text), `port_id` is the transfer id.
+pub fn failed_event(transfer_id: PortId, reason: &str) -> Command {
+    Command::new(108, "Transfer Failed", reason.as_bytes().to_vec(), 0, 0, transfer_id, StatState::ACTIVE)
+}
+
+pub enum TransferStatus {
+    Progress { transfer_id: PortId, received: u64, total: u64 },
+    /// The original command, payload verified.
+    Complete(Command),
+}
+
+struct Incoming {
+    command_id: u32,
+    port_id: PortId,
+    description: NameId,
+    node_id: NodeId,
+    param_id: ParamId,
+    total: u64,
+    received: u64,
+    sha256: [u8; 32],
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+/// Collects chunked transfers. Chunks may arrive in any order; a failed transfer is dropped and has to be resent.
+/// At most `MAX_CONCURRENT_TRANSFERS` are collected at once.
+pub struct Reassembler {
+    incoming: HashMap<PortId, Incoming>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Reassembler { incoming: HashMap::new() }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.incoming.len()
+    }
+
+    pub fn abort(&mut self, transfer_id: PortId) -> bool {
+        self.incoming.remove(&transfer_id).is_some()
+    }
+
+    /// Feeds one transfer command. Errors drop the transfer.
+    pub fn accept(&mut self, command: &Command) -> Result<TransferStatus, String> {
+        let id = command.port_id;
+        let result = self.accept_inner(command);
+        if result.is_err() {
+            self.incoming.remove(&id);
+        }
+        result
+    }
+
+    fn accept_inner(&mut self, command: &Command) -> Result<TransferStatus, String> {
+        let id = command.port_id;
+        let payload = &command.payload;
+        match command.command_id {
+            TRANSFER_BEGIN => {
+                if payload.len() < BEGIN_SIZE {
+                    return Err(format!("Transfer {}: truncated header", id));
+                }
+                let total = u64::from_le_bytes(payload[8..16].try_into().unwrap());
+                let count = u32::from_le_bytes(payload[16..20].try_into().unwrap()) as usize;
+                if total > MAX_TRANSFER_BYTES {
+                    return Err(format!("Transfer {}: {} bytes exceeds the {} byte limit", id, total, MAX_TRANSFER_BYTES));
+                }
+                if count as u64 > total.div_ceil(MIN_CHUNK_SIZE as u64).max(1) {
+                    return Err(format!("Transfer {}: {} chunks for {} bytes", id, count, total));
+                }
+                if !self.incoming.contains_key(&id) && self.incoming.len() >= MAX_CONCURRENT_TRANSFERS {
+                    return Err(format!("Transfer {}: {} transfers already open", id, MAX_CONCURRENT_TRANSFERS));
+                }
+                self.incoming.insert(id, Incoming {
+                    command_id: u32::from_le_bytes(payload[0..4].try_into().unwrap()),
+                    port_id: u32::from_le_bytes(payload[4..8].try_into().unwrap()),
+                    description: command.description,
+                    node_id: command.node_id,
+                    param_id: command.param_id,
+                    total,
+                    received: 0,
+                    sha256: payload[20..52].try_into().unwrap(),
+                    chunks: vec![None; count],
+                });
+                Ok(TransferStatus::Progress { transfer_id: id, received: 0, total })
+            }
+            TRANSFER_CHUNK => {
+                let transfer = self.incoming.get_mut(&id).ok_or(format!("Transfer {}: chunk without begin", id))?;
+                if payload.len() < CHUNK_HEADER_SIZE {
+                    return Err(format!("Transfer {}: truncated chunk", id));
+                }
+                let index = u32::from_le_bytes(payload[0..4].try_into().unwrap()) as usize;
+                let checksum = u32::from_le_bytes(payload[4..8].try_into().unwrap());
+                let data = &payload[CHUNK_HEADER_SIZE..];
+                if chunk_checksum(data) != checksum {
+                    return Err(format!("Transfer {}: chunk {} failed its checksum", id, index));
+                }
+                let slot = transfer.chunks.get_mut(index).ok_or(format!("Transfer {}: chunk {} out of range", id, index))?;
+                if slot.is_none() {
+                    transfer.received += data.len() as u64;
+                    *slot = Some(data.to_vec());
+                }
+                if transfer.received > transfer.total {
+                    return Err(format!("Transfer {}: more data than announced", id));
+                }
+                Ok(TransferStatus::Progress { transfer_id: id, received: transfer.received, total: transfer.total })
+            }
+            TRANSFER_END => {
+                let transfer = self.incoming.remove(&id).ok_or(format!("Transfer {}: end without begin", id))?;
+                if let Some(missing) = transfer.chunks.iter().position(|c| c.is_none()) {
+                    return Err(format!("Transfer {}: chunk {} missing", id, missing));
+                }
+                let data: Vec<u8> = transfer.chunks.into_iter().flatten().flatten().collect();
+                if data.len() as u64 != transfer.total || Sha256::digest(&data)[..] != transfer.sha256 {
+                    return Err(format!("Transfer {}: payload does not match its checksum", id));
+                }
+                Ok(TransferStatus::Complete(Command::with_name_id(
+                    transfer.command_id,
+                    transfer.description,
+                    data,
+                    transfer.node_id,
+                    transfer.param_id,
+                    transfer.port_id,
+                    command.stat,
+                )))
+            }
+            other => Err(format!("Opcode {} is not part of a transfer", other)),
+        }
+    }
+}
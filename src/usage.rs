@@ -0,0 +1,229 @@
+// usage.rs
+
+/* Per-Node CPU and Energy Accounting */
+
+#![allow(warnings)]
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::dspapi::{Command, NodeId, StatState, RESPONSE_QUEUE};
+use crate::threads::{self, ThreadRole};
+
+/// Rough power draw of one fully busy core, used to turn CPU time into an energy estimate.
+/// Laptop cores sit around 2-8 W under audio loads; frontends can calibrate per machine.
+pub const DEFAULT_WATTS_PER_CORE: f32 = 4.0;
+
+/// Time the running load average follows changes over.
+pub const LOAD_AVERAGE_SECONDS: f32 = 0.5;
+
+const EMPTY_SLOT: u64 = u64::MAX;
+
+struct UsageSlot {
+    node_id: AtomicU64,
+    cpu_nanos: AtomicU64,
+    /// f32 bits of the running average load.
+    load: AtomicU32,
+    /// f32 bits of the highest load since the last `take_loads`.
+    peak: AtomicU32,
+}
+
+/// Cumulative CPU time per rack slot plus the whole callback, written lock-free by the audio thread.
+pub struct UsageMeter {
+    slots: Vec<UsageSlot>,
+    callback_nanos: AtomicU64,
+    since: std::sync::Mutex<Instant>,
+    /// f32 bits of the watts-per-core estimate.
+    watts_per_core: AtomicU32,
+}
+
+/// Cost of one node since the last reset.
+#[derive(Debug, Clone)]
+pub struct NodeUsage {
+    pub node_id: NodeId,
+    pub name: String,
+    pub cpu_time: Duration,
+    /// Fraction of the whole session's processing time.
+    pub share: f32,
+    pub energy_joules: f32,
+}
+
+/// How much of the real-time budget (the duration of the audio it processes) a node takes: 1.0 is a whole
+/// block's worth of time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeLoad {
+    pub node_id: NodeId,
+    /// Running average over about `LOAD_AVERAGE_SECONDS`.
+    pub average: f32,
+    pub peak: f32,
+}
+
+/// Session-wide CPU and energy figures, sorted most expensive node first.
+#[derive(Debug, Clone)]
+pub struct UsageReport {
+    pub nodes: Vec<NodeUsage>,
+    /// Time spent in audio callbacks, including work outside nodes.
+    pub session_cpu_time: Duration,
+    pub session_energy_joules: f32,
+    /// Wall-clock time the figures cover.
+    pub elapsed: Duration,
+    /// Average power attributable to the session.
+    pub average_watts: f32,
+}
+
+impl UsageMeter {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            slots: (0..capacity)
+                .map(|_| UsageSlot { node_id: AtomicU64::new(EMPTY_SLOT), cpu_nanos: AtomicU64::new(0), load: AtomicU32::new(0), peak: AtomicU32::new(0) })
+                .collect(),
+            callback_nanos: AtomicU64::new(0),
+            since: std::sync::Mutex::new(Instant::now()),
+            watts_per_core: AtomicU32::new(DEFAULT_WATTS_PER_CORE.to_bits()),
+        }
+    }
+
+    pub fn allocated_bytes(&self) -> usize {
+        self.slots.len() * std::mem::size_of::<UsageSlot>()
+    }
+
+    pub fn set_watts_per_core(&self, watts: f32) {
+        self.watts_per_core.store(watts.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Adds `busy`, spent processing `period` of audio, to the node in rack slot `index`. A different node in the
+    /// slot restarts its count. Audio thread only.
+    pub fn record_node(&self, index: usize, node_id: NodeId, busy: Duration, period: Duration) {
+        let Some(slot) = self.slots.get(index) else { return };
+        let load = if period.is_zero() { 0.0 } else { busy.as_secs_f32() / period.as_secs_f32() };
+        let mut average = f32::from_bits(slot.load.load(Ordering::Relaxed));
+        if slot.node_id.swap(node_id as u64, Ordering::Relaxed) != node_id as u64 {
+            slot.cpu_nanos.store(0, Ordering::Relaxed);
+            slot.peak.store(0, Ordering::Relaxed);
+            average = load;
+        }
+        slot.cpu_nanos.fetch_add(busy.as_nanos() as u64, Ordering::Relaxed);
+        let weight = period.as_secs_f32() / (period.as_secs_f32() + LOAD_AVERAGE_SECONDS);
+        slot.load.store((average + (load - average) * weight).to_bits(), Ordering::Relaxed);
+        // Non-negative floats order like their bits.
+        slot.peak.fetch_max(load.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Current load of every node, restarting the peaks.
+    pub fn take_loads(&self) -> Vec<NodeLoad> {
+        self.slots
+            .iter()
+            .filter_map(|slot| {
+                let id = slot.node_id.load(Ordering::Relaxed);
+                if id == EMPTY_SLOT { return None; }
+                Some(NodeLoad {
+                    node_id: id as NodeId,
+                    average: f32::from_bits(slot.load.load(Ordering::Relaxed)),
+                    peak: f32::from_bits(slot.peak.swap(0, Ordering::Relaxed)),
+                })
+            })
+            .collect()
+    }
+
+    pub fn record_callback(&self, busy: Duration) {
+        self.callback_nanos.fetch_add(busy.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        for slot in &self.slots {
+            slot.cpu_nanos.store(0, Ordering::Relaxed);
+            slot.load.store(0, Ordering::Relaxed);
+            slot.peak.store(0, Ordering::Relaxed);
+        }
+        self.callback_nanos.store(0, Ordering::Relaxed);
+        if let Ok(mut since) = self.since.lock() {
+            *since = Instant::now();
+        }
+    }
+
+    /// Builds a report. `name_of` resolves node ids to display names.
+    pub fn report(&self, name_of: impl Fn(NodeId) -> Option<String>) -> UsageReport {
+        let watts = f32::from_bits(self.watts_per_core.load(Ordering::Relaxed));
+        let session = Duration::from_nanos(self.callback_nanos.load(Ordering::Relaxed));
+        let elapsed = self.since.lock().map(|s| s.elapsed()).unwrap_or_default();
+
+        let mut nodes: Vec<NodeUsage> = self
+            .slots
+            .iter()
+            .filter_map(|slot| {
+                let id = slot.node_id.load(Ordering::Relaxed);
+                if id == EMPTY_SLOT { return None; }
+                let node_id = id as NodeId;
+                let cpu_time = Duration::from_nanos(slot.cpu_nanos.load(Ordering::Relaxed));
+                Some(NodeUsage {
+                    node_id,
+                    name: name_of(node_id).unwrap_or_default(),
+                    cpu_time,
+                    share: if session.is_zero() { 0.0 } else { cpu_time.as_secs_f32() / session.as_secs_f32() },
+                    energy_joules: cpu_time.as_secs_f32() * watts,
+                })
+            })
+            .collect();
+        nodes.sort_by(|a, b| b.cpu_time.cmp(&a.cpu_time));
+
+        let session_energy_joules = session.as_secs_f32() * watts;
+        UsageReport {
+            nodes,
+            session_cpu_time: session,
+            session_energy_joules,
+            elapsed,
+            average_watts: if elapsed.is_zero() { 0.0 } else { session_energy_joules / elapsed.as_secs_f32() },
+        }
+    }
+}
+
+/// 112: Node Load (per node: u32 node id + f32 average load + f32 peak load, see `NodeLoad`).
+pub fn load_event(loads: &[NodeLoad]) -> Command {
+    let mut payload = Vec::with_capacity(loads.len() * 12);
+    for load in loads {
+        payload.extend_from_slice(&load.node_id.to_le_bytes());
+        payload.extend_from_slice(&load.average.to_le_bytes());
+        payload.extend_from_slice(&load.peak.to_le_bytes());
+    }
+    Command::new(112, "Node Load", payload, 0, 0, 0, StatState::ACTIVE)
+}
+
+/// Background thread publishing node loads on `RESPONSE_QUEUE`, so a GUI can show which plugin eats the CPU.
+pub struct LoadPublisher {
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LoadPublisher {
+    /// Publishes a Node Load response every `interval`; each one carries the peaks since the previous.
+    pub fn spawn(usage: Arc<UsageMeter>, interval: Duration) -> Result<Self, String> {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&shutdown);
+        let thread = thread::Builder::new()
+            .name("opentune-load-publisher".into())
+            .spawn(move || {
+                let _thread = threads::register_current("opentune-load-publisher", ThreadRole::Worker);
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(interval);
+                    let loads = usage.take_loads();
+                    if loads.is_empty() { continue; }
+                    if let Ok(mut queue) = RESPONSE_QUEUE.lock() {
+                        queue.push(load_event(&loads));
+                    }
+                }
+            })
+            .map_err(|e| format!("Failed to spawn load publisher: {}", e))?;
+        Ok(Self { shutdown, thread: Some(thread) })
+    }
+}
+
+impl Drop for LoadPublisher {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
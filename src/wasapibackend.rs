@@ -0,0 +1,391 @@
+// wasapibackend.rs
+
+/* Native WASAPI Exclusive and Low-Latency Output */
+
+#![allow(warnings)]
+
+use std::ffi::OsString;
+use std::os::windows::ffi::OsStringExt;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use windows::core::{Interface, PCSTR};
+use windows::Win32::Devices::Properties;
+use windows::Win32::Foundation::{CloseHandle, HANDLE, S_OK, WAIT_OBJECT_0};
+use windows::Win32::Media::Audio::{self, IAudioClient, IAudioClient3, IAudioRenderClient, IMMDevice, IMMDeviceEnumerator, WAVEFORMATEX, WAVEFORMATEXTENSIBLE};
+use windows::Win32::Media::{KernelStreaming, Multimedia};
+use windows::Win32::System::Com::{self, StructuredStorage, STGM_READ};
+use windows::Win32::System::Threading::{CreateEventA, SetEvent, WaitForMultipleObjects};
+use windows::Win32::System::Variant::VT_LPWSTR;
+
+use crate::sampleformat::FormatConverter;
+
+/// How long the device may go without asking for audio before the stream counts as dead.
+const DEVICE_TIMEOUT_MS: u32 = 2000;
+
+/// How the output stream is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WasapiMode {
+    /// Through cpal, in shared mode with the system mixer's default period (10 ms on most systems).
+    Shared,
+    /// Shared mode through `IAudioClient3` at the smallest engine period the driver allows at or above the
+    /// requested one (Windows 10 and later). Other applications keep playing; the stream runs at the mix format.
+    LowLatencyShared,
+    /// The device to ourselves, bypassing the system mixer: lowest latency, but no other application can play
+    /// on it while the engine runs.
+    Exclusive,
+}
+
+/// WASAPI options of `EngineConfig`, used when the streams are on the WASAPI host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WasapiOptions {
+    pub mode: WasapiMode,
+    /// Period to ask the device for, in frames; `None` asks for the engine buffer size. The device rounds it to
+    /// what it supports; see `WasapiLatency` for what it settled on.
+    pub period_frames: Option<u32>,
+}
+
+impl Default for WasapiOptions {
+    fn default() -> Self {
+        WasapiOptions { mode: WasapiMode::Shared, period_frames: None }
+    }
+}
+
+/// What the device agreed to, reported once the stream is open.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WasapiLatency {
+    pub mode: WasapiMode,
+    pub sample_rate: u32,
+    /// Frames per device period: how often the engine is asked for audio.
+    pub period_frames: u32,
+    /// Size of the device buffer in frames.
+    pub buffer_frames: u32,
+    /// Extra delay the driver reports (`IAudioClient::GetStreamLatency`), in frames.
+    pub stream_latency_frames: u32,
+}
+
+impl WasapiLatency {
+    /// Output latency: one period plus the driver's stream latency.
+    pub fn output_latency(&self) -> Duration {
+        Duration::from_secs_f64((self.period_frames + self.stream_latency_frames) as f64 / self.sample_rate.max(1) as f64)
+    }
+}
+
+/// Renders one interleaved stretch of the outputs.
+pub type RenderFn = Box<dyn FnMut(&mut [f32]) + Send>;
+/// Called when the stream dies, with the reason and whether the device is gone.
+pub type ErrorFn = Box<dyn FnMut(&str, bool) + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SampleKind {
+    F32,
+    I32,
+    I16,
+}
+
+impl SampleKind {
+    fn bytes(self) -> u16 {
+        match self {
+            SampleKind::F32 | SampleKind::I32 => 4,
+            SampleKind::I16 => 2,
+        }
+    }
+}
+
+fn hns(frames: u32, rate: u32) -> i64 {
+    (frames as i64 * 10_000_000 + rate as i64 - 1) / rate.max(1) as i64
+}
+
+fn frames(hns: i64, rate: u32) -> u32 {
+    (hns * rate as i64 / 10_000_000) as u32
+}
+
+/// Speaker mask for the named layouts (WAVE channel order), none for the rest.
+fn channel_mask(channels: u16) -> u32 {
+    match channels {
+        1 => 0x4,   // front centre
+        2 => 0x3,   // front left and right
+        6 => 0x3F,  // 5.1 with rear surrounds
+        8 => 0x63F, // 7.1 with side surrounds
+        _ => 0,
+    }
+}
+
+fn wave_format(kind: SampleKind, channels: u16, rate: u32) -> WAVEFORMATEXTENSIBLE {
+    let bits = kind.bytes() * 8;
+    let block_align = channels * kind.bytes();
+    WAVEFORMATEXTENSIBLE {
+        Format: WAVEFORMATEX {
+            wFormatTag: KernelStreaming::WAVE_FORMAT_EXTENSIBLE as u16,
+            nChannels: channels,
+            nSamplesPerSec: rate,
+            nAvgBytesPerSec: rate * block_align as u32,
+            nBlockAlign: block_align,
+            wBitsPerSample: bits,
+            cbSize: (std::mem::size_of::<WAVEFORMATEXTENSIBLE>() - std::mem::size_of::<WAVEFORMATEX>()) as u16,
+        },
+        Samples: Audio::WAVEFORMATEXTENSIBLE_0 { wValidBitsPerSample: bits },
+        dwChannelMask: channel_mask(channels),
+        SubFormat: if kind == SampleKind::F32 { Multimedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT } else { KernelStreaming::KSDATAFORMAT_SUBTYPE_PCM },
+    }
+}
+
+/// Friendly name of an endpoint, as cpal reports it.
+unsafe fn friendly_name(device: &IMMDevice) -> Option<String> {
+    let store = device.OpenPropertyStore(STGM_READ).ok()?;
+    let mut value = store.GetValue(&Properties::DEVPKEY_Device_FriendlyName as *const _ as *const _).ok()?;
+    let raw = &value.as_raw().Anonymous.Anonymous;
+    let name = if raw.vt == VT_LPWSTR.0 {
+        let wide = *(&raw.Anonymous as *const _ as *const *const u16);
+        let len = (0..).take_while(|&i| *wide.offset(i) != 0).count();
+        Some(OsString::from_wide(std::slice::from_raw_parts(wide, len)).to_string_lossy().into_owned())
+    } else {
+        None
+    };
+    let _ = StructuredStorage::PropVariantClear(&mut value);
+    name
+}
+
+/// Output endpoint `name` (a friendly name, as listed by `audiohost::output_devices`), or the default one.
+unsafe fn find_device(name: Option<&str>) -> Result<IMMDevice, String> {
+    // The engine may already have set the thread's apartment; either kind works for the calls made here.
+    let _ = Com::CoInitializeEx(None, Com::COINIT_MULTITHREADED);
+    let enumerator: IMMDeviceEnumerator = Com::CoCreateInstance(&Audio::MMDeviceEnumerator, None, Com::CLSCTX_ALL).map_err(|e| format!("Failed to list WASAPI devices: {}", e))?;
+    let Some(name) = name else {
+        return enumerator.GetDefaultAudioEndpoint(Audio::eRender, Audio::eConsole).map_err(|e| format!("No output device found: {}", e));
+    };
+    let devices = enumerator.EnumAudioEndpoints(Audio::eRender, Audio::DEVICE_STATE_ACTIVE).map_err(|e| e.to_string())?;
+    for index in 0..devices.GetCount().map_err(|e| e.to_string())? {
+        let Ok(device) = devices.Item(index) else { continue };
+        if friendly_name(&device).as_deref() == Some(name) { return Ok(device); }
+    }
+    Err(format!("Output device '{}' not found", name))
+}
+
+/// An initialized but not yet started WASAPI output, so the engine can see the rate and channels the device
+/// settled on before it builds its render callback.
+pub struct WasapiOutput {
+    client: IAudioClient,
+    render: IAudioRenderClient,
+    event: HANDLE,
+    kind: SampleKind,
+    channels: u16,
+    latency: WasapiLatency,
+    device_name: String,
+}
+
+// The COM objects are only touched by one thread at a time: the engine's until `start`, then the stream's.
+unsafe impl Send for WasapiOutput {}
+
+impl WasapiOutput {
+    /// Opens output `device` (`None` for the default) in `options.mode`, which must not be `Shared`. Exclusive
+    /// mode tries f32, 32-bit and 16-bit integer samples at `rate`, then at the device's mix rate, with
+    /// `channels` channels; low-latency shared mode always runs at the mix format. The period is negotiated
+    /// down to `period_frames` (or `options.period_frames`) as far as the device allows.
+    pub fn open(device: Option<&str>, options: &WasapiOptions, channels: u16, rate: u32, period_frames: u32) -> Result<Self, String> {
+        let requested = options.period_frames.unwrap_or(period_frames).max(16);
+        unsafe {
+            let endpoint = find_device(device)?;
+            let device_name = friendly_name(&endpoint).unwrap_or_default();
+            let activate = || -> Result<IAudioClient, String> {
+                endpoint.Activate::<IAudioClient>(Com::CLSCTX_ALL, None).map_err(|e| format!("Failed to open '{}': {}", device_name, e))
+            };
+            let client = activate()?;
+            let mix = client.GetMixFormat().map_err(|e| e.to_string())?;
+            let (mix_rate, mix_channels) = ((*mix).nSamplesPerSec, (*mix).nChannels);
+
+            let (client, kind, channels, rate, period) = match options.mode {
+                WasapiMode::Shared => {
+                    Com::CoTaskMemFree(Some(mix as *const _));
+                    return Err("Shared mode is opened through cpal".to_string());
+                }
+                WasapiMode::LowLatencyShared => {
+                    let result = (|| -> Result<u32, String> {
+                        let client3 = client.cast::<IAudioClient3>().map_err(|_| "Low-latency shared mode needs Windows 10 or later".to_string())?;
+                        let (mut default, mut fundamental, mut min, mut max) = (0u32, 0u32, 0u32, 0u32);
+                        client3.GetSharedModeEnginePeriod(mix, &mut default, &mut fundamental, &mut min, &mut max).map_err(|e| e.to_string())?;
+                        // The period has to be a multiple of the fundamental one, within the driver's range.
+                        let fundamental = fundamental.max(1);
+                        let period = (requested.div_ceil(fundamental) * fundamental).clamp(min, max.max(min));
+                        client3.InitializeSharedAudioStream(Audio::AUDCLNT_STREAMFLAGS_EVENTCALLBACK, period, mix, None).map_err(|e| format!("Failed to start low-latency shared mode: {}", e))?;
+                        Ok(period)
+                    })();
+                    let float = (*mix).wBitsPerSample == 32;
+                    Com::CoTaskMemFree(Some(mix as *const _));
+                    if !float { return Err("The mix format isn't 32-bit float".to_string()); }
+                    (client, SampleKind::F32, mix_channels, mix_rate, result?)
+                }
+                WasapiMode::Exclusive => {
+                    Com::CoTaskMemFree(Some(mix as *const _));
+                    let rates = if mix_rate == rate { vec![rate] } else { vec![rate, mix_rate] };
+                    let (kind, rate) = rates
+                        .iter()
+                        .flat_map(|&rate| [SampleKind::F32, SampleKind::I32, SampleKind::I16].map(|kind| (kind, rate)))
+                        .find(|&(kind, rate)| client.IsFormatSupported(Audio::AUDCLNT_SHAREMODE_EXCLUSIVE, &wave_format(kind, channels, rate).Format, None) == S_OK)
+                        .ok_or(format!("'{}' can't be opened exclusively with {} channels", device_name, channels))?;
+                    let format = wave_format(kind, channels, rate);
+                    let (mut default, mut minimum) = (0i64, 0i64);
+                    client.GetDevicePeriod(Some(&mut default), Some(&mut minimum)).map_err(|e| e.to_string())?;
+                    let mut duration = hns(requested, rate).max(minimum);
+                    let mut client = client;
+                    if let Err(e) = client.Initialize(Audio::AUDCLNT_SHAREMODE_EXCLUSIVE, Audio::AUDCLNT_STREAMFLAGS_EVENTCALLBACK, duration, duration, &format.Format, None) {
+                        if e.code() != Audio::AUDCLNT_E_BUFFER_SIZE_NOT_ALIGNED {
+                            return Err(format!("Failed to open '{}' exclusively: {}", device_name, e));
+                        }
+                        // The driver wants a period it can align: ask again with the buffer size it offered.
+                        let aligned = client.GetBufferSize().map_err(|e| e.to_string())?;
+                        duration = hns(aligned, rate);
+                        client = activate()?;
+                        client.Initialize(Audio::AUDCLNT_SHAREMODE_EXCLUSIVE, Audio::AUDCLNT_STREAMFLAGS_EVENTCALLBACK, duration, duration, &format.Format, None).map_err(|e| format!("Failed to open '{}' exclusively: {}", device_name, e))?;
+                    }
+                    (client, kind, channels, rate, frames(duration, rate))
+                }
+            };
+
+            let event = CreateEventA(None, false, false, PCSTR(ptr::null())).map_err(|e| e.to_string())?;
+            let opened = (|| -> Result<_, String> {
+                client.SetEventHandle(event).map_err(|e| e.to_string())?;
+                let buffer_frames = client.GetBufferSize().map_err(|e| e.to_string())?;
+                let stream_latency = client.GetStreamLatency().map(|l| frames(l, rate)).unwrap_or(0);
+                let render = client.GetService::<IAudioRenderClient>().map_err(|e| e.to_string())?;
+                Ok((render, buffer_frames, stream_latency))
+            })();
+            let (render, buffer_frames, stream_latency_frames) = match opened {
+                Ok(opened) => opened,
+                Err(e) => {
+                    let _ = CloseHandle(event);
+                    return Err(e);
+                }
+            };
+            let latency = WasapiLatency { mode: options.mode, sample_rate: rate, period_frames: period.min(buffer_frames), buffer_frames, stream_latency_frames };
+            Ok(WasapiOutput { client, render, event, kind, channels, latency, device_name })
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.latency.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn latency(&self) -> WasapiLatency {
+        self.latency
+    }
+
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+}
+
+/// A running WASAPI output, fed from a thread of its own woken by the device. Dropping it stops the device.
+pub struct WasapiStream {
+    stop: HANDLE,
+    thread: Option<JoinHandle<()>>,
+    latency: WasapiLatency,
+    device_name: String,
+}
+
+unsafe impl Send for WasapiStream {}
+
+/// Handles moved to the stream thread.
+struct Handles(WasapiOutput, HANDLE);
+unsafe impl Send for Handles {}
+
+impl WasapiStream {
+    /// Starts the output, calling `render` for every period. Integer devices are fed through a
+    /// `FormatConverter`, with dither when `dither` is set.
+    pub fn start(output: WasapiOutput, mut render: RenderFn, dither: bool, mut on_error: ErrorFn) -> Result<Self, String> {
+        let stop = unsafe { CreateEventA(None, true, false, PCSTR(ptr::null())) }.map_err(|e| e.to_string())?;
+        let (latency, device_name) = (output.latency, output.device_name.clone());
+        let handles = Handles(output, stop);
+        let thread = thread::Builder::new()
+            .name("opentune-wasapi".into())
+            .spawn(move || unsafe {
+                let Handles(output, stop) = handles;
+                let _ = Com::CoInitializeEx(None, Com::COINIT_MULTITHREADED);
+                let channels = output.channels as usize;
+                let buffer_frames = output.latency.buffer_frames;
+                let exclusive = output.latency.mode == WasapiMode::Exclusive;
+                let mut converter = FormatConverter::new(buffer_frames as usize, channels, dither);
+
+                // Start from silence so the first period doesn't play whatever the buffer held.
+                if let Ok(_) = output.render.GetBuffer(buffer_frames) {
+                    let _ = output.render.ReleaseBuffer(buffer_frames, Audio::AUDCLNT_BUFFERFLAGS_SILENT.0 as u32);
+                }
+                if let Err(e) = output.client.Start() {
+                    on_error(&format!("Failed to start the WASAPI stream: {}", e), false);
+                    return;
+                }
+                loop {
+                    let woken = WaitForMultipleObjects(&[output.event, stop], false, DEVICE_TIMEOUT_MS);
+                    if woken.0 == WAIT_OBJECT_0.0 + 1 { break; }
+                    if woken != WAIT_OBJECT_0 {
+                        on_error("The WASAPI device stopped asking for audio", false);
+                        break;
+                    }
+                    // Exclusive mode hands over the whole buffer every period; shared mode only what has played.
+                    let frames = if exclusive {
+                        buffer_frames
+                    } else {
+                        match output.client.GetCurrentPadding() {
+                            Ok(padding) => buffer_frames.saturating_sub(padding),
+                            Err(e) => {
+                                on_error(&e.to_string(), e.code() == Audio::AUDCLNT_E_DEVICE_INVALIDATED);
+                                break;
+                            }
+                        }
+                    };
+                    if frames == 0 { continue; }
+                    let data = match output.render.GetBuffer(frames) {
+                        Ok(data) => data,
+                        Err(e) => {
+                            on_error(&e.to_string(), e.code() == Audio::AUDCLNT_E_DEVICE_INVALIDATED);
+                            break;
+                        }
+                    };
+                    let samples = frames as usize * channels;
+                    match output.kind {
+                        SampleKind::F32 => render(std::slice::from_raw_parts_mut(data as *mut f32, samples)),
+                        SampleKind::I32 => converter.render(std::slice::from_raw_parts_mut(data as *mut i32, samples), &mut render),
+                        SampleKind::I16 => converter.render(std::slice::from_raw_parts_mut(data as *mut i16, samples), &mut render),
+                    }
+                    if let Err(e) = output.render.ReleaseBuffer(frames, 0) {
+                        on_error(&e.to_string(), e.code() == Audio::AUDCLNT_E_DEVICE_INVALIDATED);
+                        break;
+                    }
+                }
+                let _ = output.client.Stop();
+            })
+            .map_err(|e| format!("Failed to spawn the WASAPI thread: {}", e))?;
+        Ok(WasapiStream { stop, thread: Some(thread), latency, device_name })
+    }
+
+    pub fn latency(&self) -> WasapiLatency {
+        self.latency
+    }
+
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+}
+
+impl Drop for WasapiOutput {
+    fn drop(&mut self) {
+        unsafe { let _ = CloseHandle(self.event); }
+    }
+}
+
+impl Drop for WasapiStream {
+    fn drop(&mut self) {
+        unsafe { let _ = SetEvent(self.stop); }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        unsafe { let _ = CloseHandle(self.stop); }
+    }
+}
@@ -0,0 +1,36 @@
+// wav.rs
+
+/* Minimal WAV Writer */
+
+#![allow(warnings)]
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Writes interleaved samples as a 32-bit float WAV file (WAVE_FORMAT_IEEE_FLOAT).
+pub fn write_f32(path: &Path, samples: &[f32], channels: u16, sample_rate: u32) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let data_len = (samples.len() * 4) as u32;
+    let block_align = channels * 4;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&3u16.to_le_bytes())?;
+    out.write_all(&channels.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&32u16.to_le_bytes())?;
+
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for s in samples {
+        out.write_all(&s.to_le_bytes())?;
+    }
+    out.flush()
+}
@@ -0,0 +1,136 @@
+// xrun.rs
+
+/* Xrun Detection */
+
+#![allow(warnings)]
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::dspapi::{Command, StatState, RESPONSE_QUEUE};
+use crate::threads::{self, ThreadRole};
+
+/// How often new xruns are looked for and reported.
+pub const XRUN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Glitch counters of one engine, bumped lock-free from the audio callbacks and `push_samples`.
+#[derive(Default)]
+pub struct XrunCounters {
+    underruns: AtomicU64,
+    overruns: AtomicU64,
+    capture_overruns: AtomicU64,
+    deadline_misses: AtomicU64,
+}
+
+/// Xrun totals since the engine was created, or counts between two reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct XrunCounts {
+    /// Pushed input ran out mid-stream; the rest of the block was silence.
+    pub underruns: u64,
+    /// `push_samples` was refused because the playback ring was full.
+    pub overruns: u64,
+    /// Captured input was dropped because nobody read the capture rings.
+    pub capture_overruns: u64,
+    /// Output callbacks that took longer than the audio they produced.
+    pub deadline_misses: u64,
+}
+
+impl XrunCounts {
+    pub fn total(&self) -> u64 {
+        self.underruns + self.overruns + self.capture_overruns + self.deadline_misses
+    }
+
+    /// Counts since `earlier`.
+    pub fn since(&self, earlier: &XrunCounts) -> XrunCounts {
+        XrunCounts {
+            underruns: self.underruns - earlier.underruns,
+            overruns: self.overruns - earlier.overruns,
+            capture_overruns: self.capture_overruns - earlier.capture_overruns,
+            deadline_misses: self.deadline_misses - earlier.deadline_misses,
+        }
+    }
+}
+
+impl XrunCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn overrun(&self) {
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn capture_overrun(&self) {
+        self.capture_overruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn deadline_miss(&self) {
+        self.deadline_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counts(&self) -> XrunCounts {
+        XrunCounts {
+            underruns: self.underruns.load(Ordering::Relaxed),
+            overruns: self.overruns.load(Ordering::Relaxed),
+            capture_overruns: self.capture_overruns.load(Ordering::Relaxed),
+            deadline_misses: self.deadline_misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 113: Xrun (u32 underruns + u32 overruns + u32 capture overruns + u32 deadline misses since the previous
+/// report), `node_id` is the engine.
+pub fn xrun_event(engine_id: u32, counts: &XrunCounts) -> Command {
+    let mut payload = Vec::with_capacity(16);
+    for count in [counts.underruns, counts.overruns, counts.capture_overruns, counts.deadline_misses] {
+        payload.extend_from_slice(&(count.min(u32::MAX as u64) as u32).to_le_bytes());
+    }
+    Command::new(113, "Xrun", payload, engine_id, 0, 0, StatState::ACTIVE)
+}
+
+/// Background thread reporting new xruns on `RESPONSE_QUEUE`, so the application can warn the user.
+pub struct XrunMonitor {
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl XrunMonitor {
+    /// Checks `counters` every `interval` and publishes an Xrun response whenever they went up.
+    pub fn spawn(counters: Arc<XrunCounters>, engine_id: u32, interval: Duration) -> Result<Self, String> {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&shutdown);
+        let mut reported = counters.counts();
+        let thread = thread::Builder::new()
+            .name("opentune-xrun-monitor".into())
+            .spawn(move || {
+                let _thread = threads::register_current("opentune-xrun-monitor", ThreadRole::Worker);
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(interval);
+                    let counts = counters.counts();
+                    let new = counts.since(&reported);
+                    if new.total() == 0 { continue; }
+                    if let Ok(mut queue) = RESPONSE_QUEUE.lock() {
+                        queue.push(xrun_event(engine_id, &new));
+                        reported = counts;
+                    }
+                }
+            })
+            .map_err(|e| format!("Failed to spawn xrun monitor: {}", e))?;
+        Ok(Self { shutdown, thread: Some(thread) })
+    }
+}
+
+impl Drop for XrunMonitor {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
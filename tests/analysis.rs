@@ -0,0 +1,48 @@
+// analysis.rs
+
+/* Offline Node Analysis */
+
+use opentune::analysis::{log_frequencies, probe_response};
+use opentune::dspengine::AudioNode;
+
+/// A pure delay of one millisecond, so its latency is only known once prepared.
+struct Lookahead {
+    channels: usize,
+    delay: usize,
+    line: Vec<f32>,
+    at: usize,
+}
+
+impl AudioNode for Lookahead {
+    fn prepare(&mut self, sample_rate: u32, _max_block_size: usize) {
+        self.delay = sample_rate as usize / 1000;
+        self.line = vec![0.0; self.delay * self.channels];
+        self.at = 0;
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        if self.line.is_empty() { return; }
+        for sample in buffer.iter_mut() {
+            std::mem::swap(sample, &mut self.line[self.at]);
+            self.at = (self.at + 1) % self.line.len();
+        }
+    }
+
+    fn set_param(&mut self, _param_id: u32, _payload: &[u8]) {}
+
+    fn get_id(&self) -> u32 { 1 }
+
+    fn get_name(&self) -> &str { "Lookahead" }
+
+    fn latency_samples(&self) -> usize { self.delay }
+}
+
+#[test]
+fn latency_set_in_prepare_is_removed_from_the_phase() {
+    let mut node = Lookahead { channels: 2, delay: 0, line: Vec::new(), at: 0 };
+    let response = probe_response(&mut node, 48000, 2, &log_frequencies(16, 20.0, 20000.0));
+    for (f, phase) in response.freqs.iter().zip(&response.phase_deg) {
+        assert!(phase.abs() < 0.5, "{} Hz is shifted by {} degrees", f, phase);
+    }
+    assert!(response.magnitude_db.iter().all(|db| db.abs() < 0.01));
+}
@@ -0,0 +1,57 @@
+// audit_replay.rs
+
+/* Replaying Audit Logs into a Session */
+
+use opentune::audit::{self, AuditEntry};
+use opentune::graph::GRAPH_OUTPUT;
+use opentune::session::{Connection, Session};
+
+fn entry(seq: u64, command_id: u32, description: &str, node_id: u32, port_id: u32, payload: Vec<u8>) -> AuditEntry {
+    AuditEntry { seq, unix_ms: 0, offset_ms: seq, engine_id: 1, command_id, description: description.into(), node_id, param_id: 0, port_id, payload }
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+#[test]
+fn routing_and_moves_are_replayed() {
+    let entries = [
+        entry(0, 0, "Gain", 1, 0, Vec::new()),
+        entry(1, 0, "Delay", 2, 0, Vec::new()),
+        entry(2, 0, "Reverb", 3, 0, Vec::new()),
+        entry(3, 3, "Connect", 1, 0, words(&[3, 1])),
+        entry(4, 3, "Connect", 2, 0, words(&[3, 1])),
+        entry(5, 4, "Disconnect", 1, 0, words(&[3, 1])),
+        entry(6, 7, "Move Node", 3, 0, words(&[1])),
+        entry(7, 7, "Move Node", 1, 0, words(&[GRAPH_OUTPUT])),
+    ];
+    let mut session = Session::new("show", 48000);
+    assert_eq!(audit::replay_into_session(&mut session, &entries, None), entries.len());
+    assert_eq!(session.connections, [Connection { from_node: 2, from_port: 0, to_node: 3, to_port: 1 }]);
+    assert_eq!(session.nodes.iter().map(|n| n.id).collect::<Vec<_>>(), [3, 2, 1]);
+}
+
+#[test]
+fn replay_keeps_to_the_engine_a_command_was_sent_to() {
+    // A parameter change mirrored from the main engine (1) to the cue engine (2) is logged once for each.
+    let mirrored = |seq: u64, engine_id: u32| AuditEntry { engine_id, ..entry(seq, 2, "Set Parameter", 1, 0, 0.25f32.to_le_bytes().to_vec()) };
+    let entries = [
+        entry(0, 0, "Gain", 1, 0, Vec::new()),
+        AuditEntry { engine_id: 2, ..entry(1, 0, "Gain", 1, 0, Vec::new()) },
+        mirrored(2, 1),
+        mirrored(3, 2),
+        AuditEntry { engine_id: 2, ..entry(4, 0, "Delay", 2, 0, Vec::new()) },
+    ];
+
+    let mut main = Session::new("main", 48000);
+    assert_eq!(audit::replay_into_session(&mut main, &entries, Some(1)), 2);
+    assert_eq!(main.nodes.iter().map(|n| n.id).collect::<Vec<_>>(), [1]);
+    let mut cue = Session::new("cue", 48000);
+    assert_eq!(audit::replay_into_session(&mut cue, &entries, Some(2)), 3);
+    assert_eq!(cue.nodes.iter().map(|n| n.id).collect::<Vec<_>>(), [1, 2]);
+
+    let mut sent = Vec::new();
+    assert_eq!(audit::replay(&entries, Some(2), 0.0, |command| { sent.push(command.node_id); true }), 3);
+    assert_eq!(sent, [1, 1, 2]);
+}
@@ -0,0 +1,56 @@
+// automation.rs
+
+/* Parameter Automation Implementation */
+
+use opentune::automation::{AutomationLane, CurveShape};
+
+const SHAPES: [CurveShape; 6] = [
+    CurveShape::Linear,
+    CurveShape::Exponential(4.0),
+    CurveShape::Exponential(-3.0),
+    CurveShape::Exponential(0.0),
+    CurveShape::Bezier { c1: 0.9, c2: 0.1 },
+    CurveShape::Step,
+];
+
+/// Three segments of `shape` with an up, a down and a short one, so blocks cross every boundary.
+fn lane(shape: CurveShape) -> AutomationLane {
+    let mut lane = AutomationLane::new(1, 0);
+    lane.add_point(100, 0.2, shape);
+    lane.add_point(1100, 0.9, shape);
+    lane.add_point(1650, -0.4, shape);
+    lane.add_point(1663, 0.5, shape);
+    lane
+}
+
+#[test]
+fn rendered_blocks_follow_the_curve_sample_by_sample() {
+    for shape in SHAPES {
+        let mut rendered = lane(shape);
+        let mut reference = lane(shape);
+        // Odd block sizes, and a jump back to exercise the cursor seeking backwards.
+        let blocks = [(0u64, 97usize), (97, 1003), (1100, 511), (1611, 128), (40, 700), (1500, 400)];
+        for (start, len) in blocks {
+            let mut out = vec![f32::NAN; len];
+            assert!(rendered.render_block(start, &mut out));
+            for (i, &value) in out.iter().enumerate() {
+                let position = start + i as u64;
+                let expected = reference.value_at(position).unwrap();
+                assert!((value - expected).abs() < 1.0e-3, "{:?} at {}: rendered {}, expected {}", shape, position, value, expected);
+            }
+        }
+    }
+}
+
+#[test]
+fn stepped_lanes_hold_each_value_until_the_next_point() {
+    let mut lane = lane(CurveShape::Linear);
+    lane.stepped = true;
+    let mut out = vec![0.0f32; 1700];
+    assert!(lane.render_block(0, &mut out));
+    assert!(out[..1100].iter().all(|&v| v == 0.2));
+    assert!(out[1100..1650].iter().all(|&v| v == 0.9));
+    assert!(out[1650..1663].iter().all(|&v| v == -0.4));
+    assert!(out[1663..].iter().all(|&v| v == 0.5));
+    assert!(!AutomationLane::new(1, 0).render_block(0, &mut out));
+}
@@ -0,0 +1,49 @@
+// block_adapter.rs
+
+/* Fixed Block Size Adapter */
+
+use opentune::blockadapter::FixedBlockAdapter;
+use opentune::dspengine::AudioNode;
+
+/// Outputs its sidechain; checks that it is only ever run on whole blocks of 64 frames.
+struct Sidechained {
+    sidechain: Vec<f32>,
+}
+
+impl AudioNode for Sidechained {
+    fn process(&mut self, buffer: &mut [f32]) {
+        assert_eq!(buffer.len(), 64);
+        buffer.copy_from_slice(&self.sidechain);
+    }
+
+    fn set_param(&mut self, _param_id: u32, _payload: &[u8]) {}
+
+    fn get_id(&self) -> u32 { 1 }
+
+    fn get_name(&self) -> &str { "Sidechained" }
+
+    fn input_ports(&self) -> usize { 2 }
+
+    fn set_sidechain_input(&mut self, _port: u32, input: &[f32]) {
+        self.sidechain.copy_from_slice(input);
+    }
+}
+
+#[test]
+fn sidechains_are_buffered_with_their_block() {
+    let mut adapter = FixedBlockAdapter::new(Box::new(Sidechained { sidechain: vec![0.0; 64] }), 64, 1);
+    adapter.prepare(48000, 48);
+    assert_eq!(adapter.input_ports(), 2);
+
+    let sidechain: Vec<f32> = (0..48 * 8).map(|i| i as f32 + 1.0).collect();
+    let mut output = Vec::new();
+    for call in sidechain.chunks(48) {
+        adapter.set_sidechain_input(1, call);
+        let mut buffer = [0.0f32; 48];
+        adapter.process(&mut buffer);
+        output.extend_from_slice(&buffer);
+    }
+    // One block late, as the main signal.
+    assert!(output[..64].iter().all(|&s| s == 0.0));
+    assert_eq!(output[64..], sidechain[..sidechain.len() - 64]);
+}
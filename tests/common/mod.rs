@@ -0,0 +1,28 @@
+// mod.rs
+
+/* Shared Test Fixtures */
+
+use opentune::dspengine::AudioNode;
+
+/// Scales its input by parameter 0.
+pub struct Gain {
+    pub gain: f32,
+}
+
+impl AudioNode for Gain {
+    fn prepare(&mut self, _sample_rate: u32, _max_block_size: usize) {}
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        buffer.iter_mut().for_each(|s| *s *= self.gain);
+    }
+
+    fn set_param(&mut self, _param_id: u32, payload: &[u8]) {
+        if let Ok(bytes) = payload.try_into() { self.gain = f32::from_le_bytes(bytes); }
+    }
+
+    fn host_smoothing(&self, _param_id: u32) -> bool { false }
+
+    fn get_id(&self) -> u32 { 1 }
+
+    fn get_name(&self) -> &str { "Gain" }
+}
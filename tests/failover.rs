@@ -0,0 +1,48 @@
+// failover.rs
+
+/* Redundant Engine Failover */
+
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use opentune::audiohost;
+use opentune::dspapi::{StatState, RESPONSE_QUEUE};
+use opentune::dspengine::{DspEngine, EngineConfig};
+use opentune::failover::FailoverSupervisor;
+use opentune::nullbackend::NullOptions;
+
+fn null_engine(id: u32, max_frames: Option<u64>) -> Arc<Mutex<DspEngine>> {
+    let config = EngineConfig {
+        audio_host: Some(audiohost::NULL.to_string()),
+        null: NullOptions { free_run: false, max_frames },
+        ..EngineConfig::new(48000, 256)
+    };
+    Arc::new(Mutex::new(DspEngine::with_config(id, "failover", config)))
+}
+
+#[test]
+fn backup_runs_in_standby_and_takes_over_when_the_primary_stalls() {
+    // The null backend stops calling a primary with a frame limit, like a hung driver.
+    let primary = null_engine(1, Some(20 * 256));
+    let backup = null_engine(2, None);
+    primary.lock().unwrap().start().unwrap();
+
+    let supervisor = FailoverSupervisor::spawn(Arc::clone(&primary), Arc::clone(&backup), 4).unwrap();
+    {
+        let backup = backup.lock().unwrap();
+        assert!(backup.is_running());
+        assert!(backup.standby.load(Ordering::Relaxed));
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !supervisor.has_failed_over() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert!(supervisor.has_failed_over());
+    assert!(!backup.lock().unwrap().standby.load(Ordering::Relaxed));
+    assert!(primary.lock().unwrap().standby.load(Ordering::Relaxed));
+    let engaged = RESPONSE_QUEUE.lock().unwrap().iter().any(|r| r.command_id == 100 && r.node_id == 2 && matches!(r.stat, StatState::ACTIVE));
+    assert!(engaged);
+    backup.lock().unwrap().stop();
+}
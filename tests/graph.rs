@@ -0,0 +1,288 @@
+// graph.rs
+
+/* Routable Audio Graph */
+
+use opentune::bus::{self, ReturnBus};
+use opentune::dspengine::AudioNode;
+use opentune::graph::{AudioGraph, Edge, GRAPH_INPUT, GRAPH_OUTPUT};
+use opentune::strip::HOST_PARAM_GAIN;
+
+/// Scales its input by a fixed gain.
+struct Scale {
+    id: u32,
+    gain: f32,
+}
+
+impl AudioNode for Scale {
+    fn process(&mut self, buffer: &mut [f32]) {
+        buffer.iter_mut().for_each(|s| *s *= self.gain);
+    }
+
+    fn set_param(&mut self, _param_id: u32, _payload: &[u8]) {}
+
+    fn get_id(&self) -> u32 { self.id }
+
+    fn get_name(&self) -> &str { "Scale" }
+}
+
+fn scale(id: u32, gain: f32) -> Box<dyn AudioNode> {
+    Box::new(Scale { id, gain })
+}
+
+fn edge(from: u32, to: u32) -> Edge {
+    Edge { from, from_port: 0, to, to_port: 0 }
+}
+
+/// One stereo block of 0.5 through the graph.
+fn run(graph: &mut AudioGraph) -> Vec<f32> {
+    let mut io = vec![0.5f32; 2 * 64];
+    graph.process(&mut io, &[], &[], &[], 0, None, None, None, None);
+    io
+}
+
+fn sorted(edges: &[Edge]) -> Vec<(u32, u32)> {
+    let mut pairs: Vec<_> = edges.iter().map(|e| (e.from, e.to)).collect();
+    pairs.sort();
+    pairs
+}
+
+#[test]
+fn appended_nodes_run_as_a_sequential_rack() {
+    let mut graph = AudioGraph::new(4, 8, 2, 64);
+    graph.prepare(48000, 64);
+    assert_eq!(graph.edges(), &[edge(GRAPH_INPUT, GRAPH_OUTPUT)]);
+    assert!(run(&mut graph).iter().all(|&s| s == 0.5));
+
+    graph.append_node(scale(1, 2.0)).unwrap();
+    graph.append_node(scale(2, 3.0)).unwrap();
+    assert_eq!(graph.edges(), &[edge(GRAPH_INPUT, 1), edge(1, 2), edge(2, GRAPH_OUTPUT)]);
+    assert_eq!(graph.schedule().collect::<Vec<_>>(), [1, 2]);
+    assert!(run(&mut graph).iter().all(|&s| s == 3.0));
+}
+
+#[test]
+fn removing_a_node_bridges_the_gap() {
+    let mut graph = AudioGraph::new(4, 8, 2, 64);
+    graph.prepare(48000, 64);
+    for (id, gain) in [(1, 2.0), (2, 3.0), (3, 5.0)] {
+        graph.append_node(scale(id, gain)).unwrap();
+    }
+
+    assert_eq!(graph.remove_node(2).unwrap().get_id(), 2);
+    assert_eq!(sorted(graph.edges()), [(1, 3), (3, GRAPH_OUTPUT), (GRAPH_INPUT, 1)]);
+    assert!(run(&mut graph).iter().all(|&s| s == 5.0));
+
+    graph.remove_node(1).unwrap();
+    graph.remove_node(3).unwrap();
+    assert_eq!(graph.edges(), &[edge(GRAPH_INPUT, GRAPH_OUTPUT)]);
+    assert!(run(&mut graph).iter().all(|&s| s == 0.5));
+    assert_eq!(graph.remove_node(3).err(), Some("no such node"));
+}
+
+#[test]
+fn split_outputs_feed_each_edge_and_merged_inputs_sum() {
+    let mut graph = AudioGraph::new(4, 8, 2, 64);
+    graph.prepare(48000, 64);
+    graph.add_node(scale(1, 2.0)).unwrap();
+    graph.add_node(scale(2, 3.0)).unwrap();
+    graph.disconnect(GRAPH_INPUT, 0, GRAPH_OUTPUT, 0).unwrap();
+    assert!(run(&mut graph).iter().all(|&s| s == 0.0));
+
+    // The input splits to both nodes, whose outputs merge into the graph output.
+    for (from, to) in [(GRAPH_INPUT, 1), (GRAPH_INPUT, 2), (1, GRAPH_OUTPUT), (2, GRAPH_OUTPUT)] {
+        graph.connect(from, 0, to, 0).unwrap();
+    }
+    assert!(run(&mut graph).iter().all(|&s| s == 2.5));
+
+    // A merge into a node sums before it processes.
+    graph.disconnect(2, 0, GRAPH_OUTPUT, 0).unwrap();
+    graph.disconnect(GRAPH_INPUT, 0, 1, 0).unwrap();
+    graph.connect(2, 0, 1, 0).unwrap();
+    graph.connect(GRAPH_INPUT, 0, 1, 0).unwrap();
+    assert_eq!(graph.schedule().collect::<Vec<_>>(), [2, 1]);
+    assert!(run(&mut graph).iter().all(|&s| s == (1.5 + 0.5) * 2.0));
+}
+
+#[test]
+fn cycles_are_refused_and_leave_the_edges_alone() {
+    let mut graph = AudioGraph::new(4, 8, 2, 64);
+    graph.prepare(48000, 64);
+    for (id, gain) in [(1, 2.0), (2, 3.0), (3, 5.0)] {
+        graph.append_node(scale(id, gain)).unwrap();
+    }
+    let edges = graph.edges().to_vec();
+    let schedule: Vec<_> = graph.schedule().collect();
+
+    assert_eq!(graph.connect(3, 0, 1, 0), Err("connection would create a cycle"));
+    assert_eq!(graph.connect(2, 0, 2, 0), Err("connection would create a cycle"));
+    assert_eq!(graph.edges(), &edges[..]);
+    assert_eq!(graph.schedule().collect::<Vec<_>>(), schedule);
+    assert!(run(&mut graph).iter().all(|&s| s == 15.0));
+
+    assert_eq!(graph.connect(1, 0, 2, 0), Err("already connected"));
+    assert_eq!(graph.connect(1, 0, 9, 0), Err("no such node"));
+    assert_eq!(graph.connect(1, 1, 2, 0), Err("no such port"));
+    assert_eq!(graph.connect(GRAPH_OUTPUT, 0, 1, 0), Err("the graph output has no outputs and the graph inputs no inputs"));
+}
+
+#[test]
+fn changes_past_capacity_are_refused() {
+    let mut graph = AudioGraph::new(2, 3, 2, 64);
+    graph.prepare(48000, 64);
+    assert_eq!(graph.capacity(), 2);
+    assert_eq!(graph.edge_capacity(), 3);
+
+    graph.append_node(scale(1, 2.0)).unwrap();
+    graph.add_node(scale(2, 3.0)).unwrap();
+    assert!(!graph.has_room());
+    assert_eq!(graph.add_node(scale(3, 5.0)), Err("graph is full"));
+    assert_eq!(graph.add_node(scale(1, 5.0)), Err("a node with this id is already in the graph"));
+
+    graph.connect(GRAPH_INPUT, 0, 2, 0).unwrap();
+    let edges = graph.edges().to_vec();
+    assert_eq!(graph.connect(2, 0, GRAPH_OUTPUT, 0), Err("no free connection slots"));
+    assert_eq!(graph.edges(), &edges[..]);
+    assert!(run(&mut graph).iter().all(|&s| s == 1.0));
+
+    // Freed capacity is usable again.
+    graph.remove_node(2).unwrap();
+    graph.add_node(scale(3, 5.0)).unwrap();
+    graph.connect(1, 0, 3, 0).unwrap();
+    assert_eq!(graph.len(), 2);
+}
+
+/// First-channel samples of `blocks` blocks of 0.5 through the graph.
+fn run_blocks(graph: &mut AudioGraph, blocks: usize) -> Vec<f32> {
+    (0..blocks).flat_map(|_| run(graph).into_iter().step_by(2)).collect()
+}
+
+#[test]
+fn crossfaded_nodes_ramp_from_the_old_output_to_the_new() {
+    let mut graph = AudioGraph::new(4, 8, 2, 64);
+    graph.prepare(48000, 64);
+    graph.append_node(scale(1, 1.0)).unwrap();
+    assert!(run(&mut graph).iter().all(|&s| s == 0.5));
+
+    assert!(graph.crossfade_node(1, scale(1, 3.0)).is_ok());
+    let ramp = graph.strip(1).unwrap().ramp_frames() as usize;
+    let mut output: Vec<f32> = Vec::new();
+    while graph.take_retired().is_none() {
+        assert!(output.len() <= ramp, "old node never retired");
+        output.extend(run_blocks(&mut graph, 1));
+    }
+
+    assert!(output.len() >= ramp);
+    assert!(output.windows(2).all(|w| w[0] <= w[1]), "ramp isn't monotonic");
+    assert!(output.iter().all(|&s| (0.5..=1.5).contains(&s)));
+    assert!(output[0] < 0.6);
+    assert_eq!(output[ramp - 1], 1.5);
+    assert!(run(&mut graph).iter().all(|&s| s == 1.5));
+}
+
+#[test]
+fn faded_out_nodes_retire_once_at_their_dry_signal() {
+    let mut graph = AudioGraph::new(4, 8, 2, 64);
+    graph.prepare(48000, 64);
+    graph.append_node(scale(1, 1.0)).unwrap();
+    graph.append_node(scale(2, 4.0)).unwrap();
+    assert!(run(&mut graph).iter().all(|&s| s == 2.0));
+
+    graph.fade_out_node(2).unwrap();
+    let ramp = graph.strip(2).unwrap().ramp_frames() as usize;
+    let mut output: Vec<f32> = Vec::new();
+    let retired = loop {
+        assert!(output.len() <= ramp, "node never retired");
+        let block = run_blocks(&mut graph, 1);
+        output.extend_from_slice(&block);
+        match graph.take_retired() {
+            Some(node) => break node,
+            // Still running, so still audible.
+            None => {
+                assert!(graph.node(2).is_some());
+                assert!(*block.last().unwrap() > 0.5);
+            }
+        }
+    };
+
+    assert_eq!(retired.get_id(), 2);
+    assert!(graph.node(2).is_none());
+    assert!(output.windows(2).all(|w| w[0] >= w[1]), "fade isn't monotonic");
+    assert_eq!(*output.last().unwrap(), 0.5);
+    assert_eq!(graph.edges(), &[edge(GRAPH_INPUT, 1), edge(1, GRAPH_OUTPUT)]);
+    assert!(run(&mut graph).iter().all(|&s| s == 0.5));
+}
+
+#[test]
+fn adding_the_same_id_during_a_fade_out_removes_the_old_node_at_once() {
+    let mut graph = AudioGraph::new(2, 8, 2, 64);
+    graph.prepare(48000, 64);
+    graph.append_node(scale(1, 1.0)).unwrap();
+    graph.append_node(scale(2, 4.0)).unwrap();
+    graph.fade_out_node(2).unwrap();
+    run(&mut graph);
+    assert!(graph.take_retired().is_none());
+    assert_eq!(graph.set_bypass(2, false), Err("node is being removed"));
+
+    // The graph is full, but the fading node gives up its slot.
+    assert!(graph.has_room());
+    graph.append_node(scale(2, 5.0)).unwrap();
+    let mut retired = graph.take_retired().expect("old node retired at once");
+    let mut probe = [1.0f32];
+    retired.process(&mut probe);
+    assert_eq!(probe, [4.0]);
+    assert!(graph.take_retired().is_none());
+
+    assert_eq!(graph.len(), 2);
+    assert_eq!(graph.edges(), &[edge(GRAPH_INPUT, 1), edge(1, 2), edge(2, GRAPH_OUTPUT)]);
+    assert!(run(&mut graph).iter().all(|&s| s == 2.5));
+}
+
+#[test]
+fn sends_reach_their_bus_after_the_fader_and_ramp_level_changes() {
+    let bus = bus::bus_id(0).unwrap();
+    let mut graph = AudioGraph::new(4, 8, 2, 64);
+    graph.prepare(48000, 64);
+    graph.append_node(scale(1, 2.0)).unwrap();
+    graph.add_return_bus(Box::new(ReturnBus::new(bus))).unwrap();
+    assert_eq!(graph.add_return_bus(scale(2, 1.0)), Err("return bus ids start at bus::BUS_ID_BASE"));
+    // Only the bus reaches the output.
+    graph.disconnect(1, 0, GRAPH_OUTPUT, 0).unwrap();
+    graph.set_param(1, HOST_PARAM_GAIN, &(-6.0f32).to_le_bytes()).unwrap();
+    assert!(run_blocks(&mut graph, 10).iter().all(|&s| s == 0.0));
+
+    assert_eq!(graph.set_send(1, 2, 0.5), Err("no such return bus"));
+    graph.set_send(1, bus, 0.5).unwrap();
+    assert_eq!(graph.send_level(1, bus), Some(0.5));
+    let post_fader = 0.5 * 2.0 * 10f32.powf(-6.0 / 20.0);
+    let level = |block: &[f32]| *block.last().unwrap() / post_fader;
+
+    // A new send ramps up from silence over its first block.
+    let block = run_blocks(&mut graph, 1);
+    assert!(block.windows(2).all(|w| w[0] < w[1]));
+    assert!(block[0] < 0.5 * post_fader / 32.0);
+    assert!((level(&block) - 0.5).abs() < 1e-5);
+    assert!(run_blocks(&mut graph, 1).iter().all(|&s| (s / post_fader - 0.5).abs() < 1e-5));
+
+    graph.set_send(1, bus, 0.25).unwrap();
+    let block = run_blocks(&mut graph, 1);
+    assert!(block.windows(2).all(|w| w[0] > w[1]));
+    assert!(block[0] > 0.49 * post_fader);
+    assert!((level(&block) - 0.25).abs() < 1e-5);
+
+    // Removing the bus removes the send.
+    graph.remove_node(bus).unwrap();
+    assert_eq!(graph.send_level(1, bus), None);
+    assert!(graph.edges().iter().all(|e| e.to != bus && e.from != bus));
+    assert_eq!(graph.set_send(1, bus, 0.5), Err("no such return bus"));
+
+    // And so does removing the sending node.
+    graph.add_return_bus(Box::new(ReturnBus::new(bus))).unwrap();
+    assert_eq!(graph.send_level(1, bus), None);
+    graph.set_send(1, bus, 0.5).unwrap();
+    run_blocks(&mut graph, 2);
+    graph.remove_node(1).unwrap();
+    assert_eq!(graph.send_level(1, bus), None);
+    assert_eq!(graph.edges(), &[edge(bus, GRAPH_OUTPUT)]);
+    assert!(run_blocks(&mut graph, 1).iter().all(|&s| s == 0.0));
+    assert_eq!(graph.set_send(1, bus, 0.5), Err("no such node"));
+}
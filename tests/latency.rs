@@ -0,0 +1,59 @@
+// latency.rs
+
+/* End-to-End Output Latency */
+
+use std::time::{Duration, Instant};
+
+use opentune::audiohost;
+use opentune::dspengine::{AudioNode, DspEngine, EngineConfig};
+use opentune::nullbackend::NullOptions;
+
+/// Passes audio through, reporting a fixed lookahead like a limiter would.
+struct Lookahead;
+
+impl AudioNode for Lookahead {
+    fn prepare(&mut self, _sample_rate: u32, _max_block_size: usize) {}
+
+    fn process(&mut self, _buffer: &mut [f32]) {}
+
+    fn set_param(&mut self, _param_id: u32, _payload: &[u8]) {}
+
+    fn get_id(&self) -> u32 { 1 }
+
+    fn get_name(&self) -> &str { "Lookahead" }
+
+    fn latency_samples(&self) -> usize { 64 }
+}
+
+#[test]
+fn latency_adds_queued_audio_stream_and_plugin_delay() {
+    let config = EngineConfig {
+        audio_host: Some(audiohost::NULL.to_string()),
+        null: NullOptions { free_run: true, max_frames: Some(4 * 256) },
+        ring_buffer_capacity: 1 << 14,
+        ..EngineConfig::new(48000, 256)
+    };
+    let mut engine = DspEngine::with_config(1, "latency", config);
+    engine.graph.lock().unwrap().append_node(Box::new(Lookahead)).unwrap();
+    assert_eq!(engine.push_samples(&vec![0.25f32; 2 * 10 * 256]), 2 * 10 * 256);
+
+    let stopped = engine.latency();
+    assert_eq!((stopped.queued_frames, stopped.stream_frames), (10 * 256, 0));
+
+    engine.start().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while engine.null_frames() != Some(4 * 256) && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    let report = engine.latency();
+    assert_eq!(report.sample_rate, 48000);
+    assert_eq!(report.queued_frames, 6 * 256);
+    // Whole blocks with nothing held back, and the null backend plays nothing.
+    assert_eq!(report.stream_frames, 0);
+    assert_eq!(report.plugin_frames, 64);
+    assert_eq!(report.total_frames(), 6 * 256 + 64);
+    assert_eq!(engine.latency.current(report.plugin_frames), report);
+    engine.stop();
+    assert_eq!(engine.latency().stream_frames, 0);
+}
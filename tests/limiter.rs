@@ -0,0 +1,58 @@
+// limiter.rs
+
+/* Output Protection Limiter */
+
+use opentune::dsp::limiter::{OutputLimiter, DEFAULT_CEILING_DB, DEFAULT_RELEASE_MS};
+
+const RATE: u32 = 48000;
+
+#[test]
+fn hot_and_non_finite_input_never_leaves_above_the_ceiling() {
+    let mut limiter = OutputLimiter::new(RATE, 2);
+    let ceiling = 10f32.powf(DEFAULT_CEILING_DB / 20.0);
+    assert!((limiter.ceiling_db() - DEFAULT_CEILING_DB).abs() < 1e-4);
+
+    // +60 dB, with a change of sign every frame and non-finite samples mixed in.
+    let mut io: Vec<f32> = (0..2 * 4096).map(|i| if i % 4 < 2 { 1000.0 } else { -1000.0 }).collect();
+    for (i, bad) in [(7, f32::NAN), (100, f32::INFINITY), (101, f32::NEG_INFINITY), (4001, f32::NAN)] {
+        io[i] = bad;
+    }
+    assert!(limiter.process(&mut io));
+    assert!(io.iter().all(|s| s.is_finite() && s.abs() <= ceiling));
+    assert_eq!([io[7], io[100], io[101], io[4001]], [0.0; 4]);
+    // Channels are linked: the other side of a replaced sample is still limited, not silenced.
+    assert!(io[6].abs() > 0.5 * ceiling);
+    assert!(limiter.gain_reduction_db() > 59.0);
+
+    // Non-finite samples alone engage it, without reducing anything else.
+    let mut limiter = OutputLimiter::new(RATE, 2);
+    let mut io = [0.25, f32::NAN, f32::INFINITY, -0.25];
+    assert!(limiter.process(&mut io));
+    assert_eq!(io, [0.25, 0.0, 0.0, -0.25]);
+    assert_eq!(limiter.gain_reduction_db(), 0.0);
+    assert!(!limiter.process(&mut [0.5; 64]));
+}
+
+#[test]
+fn gain_recovers_over_the_release_time() {
+    let mut limiter = OutputLimiter::new(RATE, 2);
+    limiter.process(&mut [1000.0; 2 * 64]);
+    let held = limiter.gain_reduction_db();
+    assert!(held > 59.0);
+
+    // Quiet input: the reduction decays, each frame at most as reduced as the one before.
+    let release = (DEFAULT_RELEASE_MS * 0.001 * RATE as f32) as usize;
+    let mut io = vec![0.5f32; 2 * release];
+    assert!(limiter.process(&mut io));
+    assert!(io.windows(2).all(|w| w[0] <= w[1]));
+    // Most of the way (1 - 1/e) back to unity after one release time.
+    let gain = 1.0 - (1.0 - 10f32.powf(-held / 20.0)) / std::f32::consts::E;
+    let recovered = io[io.len() - 1] / 0.5;
+    assert!((recovered - gain).abs() < 1e-3, "gain {} after the release time, expected {}", recovered, gain);
+
+    let mut io = vec![0.5f32; 2 * 20 * release];
+    limiter.process(&mut io);
+    assert_eq!(limiter.gain_reduction_db(), 0.0);
+    assert!(io[io.len() - 2..].iter().all(|&s| s == 0.5));
+    assert!(!limiter.process(&mut [0.5; 64]));
+}
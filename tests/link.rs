@@ -0,0 +1,58 @@
+// link.rs
+
+/* Ableton Link Sync */
+
+#![cfg(feature = "link")]
+
+use std::sync::{Arc, Mutex};
+
+use opentune::dspengine::Transport;
+use opentune::link::{LinkSession, LinkSync};
+
+/// A session whose peers are played by the test; records the tempos proposed to it.
+#[derive(Clone, Default)]
+struct FakeSession {
+    tempo: Arc<Mutex<f64>>,
+    proposed: Arc<Mutex<Vec<f64>>>,
+}
+
+impl LinkSession for FakeSession {
+    fn clock_micros(&self) -> i64 { 0 }
+
+    fn tempo(&self) -> f64 { *self.tempo.lock().unwrap() }
+
+    fn set_tempo(&mut self, bpm: f64, _at_micros: i64) {
+        self.proposed.lock().unwrap().push(bpm);
+        *self.tempo.lock().unwrap() = bpm;
+    }
+
+    fn beat_at(&self, _at_micros: i64, _quantum: f64) -> f64 { 0.0 }
+
+    fn is_playing(&self) -> bool { false }
+
+    fn set_playing(&mut self, _playing: bool, _at_micros: i64) {}
+
+    fn num_peers(&self) -> usize { 1 }
+}
+
+#[test]
+fn fractional_peer_tempos_are_followed_without_being_proposed_back() {
+    let transport = Arc::new(Transport::new());
+    let session = FakeSession { tempo: Arc::new(Mutex::new(transport.info().tempo as f64)), ..Default::default() };
+    let mut sync = LinkSync::new(Box::new(session.clone()), Arc::clone(&transport), 48000, 4.0);
+
+    for peer_tempo in [120.37, 97.123, 133.3333] {
+        *session.tempo.lock().unwrap() = peer_tempo;
+        for _ in 0..3 {
+            sync.sync();
+        }
+        assert_eq!(transport.info().tempo, peer_tempo as f32);
+    }
+    assert!(session.proposed.lock().unwrap().is_empty());
+
+    // A local edit is still shared.
+    transport.set_tempo(140.5).unwrap();
+    sync.sync();
+    sync.sync();
+    assert_eq!(*session.proposed.lock().unwrap(), [140.5]);
+}
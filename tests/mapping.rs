@@ -0,0 +1,141 @@
+// mapping.rs
+
+/* MIDI-Learn / OSC Mapping Layer */
+
+use opentune::dspapi::RESPONSE_QUEUE;
+use opentune::dspengine::{DspEngine, EngineConfig};
+use opentune::mapping::{CcEncoding, MappingSource, MappingTable, MappingTarget, TransportAction};
+
+#[test]
+fn held_faders_fire_transport_and_scenes_once() {
+    let mut table = MappingTable::new();
+    table.map(MappingSource::MidiCc { channel: 0, cc: 1 }, MappingTarget::Transport(TransportAction::Play));
+    table.map(MappingSource::MidiCc { channel: 0, cc: 2 }, MappingTarget::SceneRecall(3));
+
+    for cc in [1u8, 2] {
+        let fired: usize = [20u8, 70, 90, 127, 100, 30, 80].iter().map(|&value| table.handle_midi(&[0xB0, cc, value]).len()).sum();
+        assert_eq!(fired, 2, "cc {} should fire on each of its two rises", cc);
+    }
+
+    let commands = table.handle_midi(&[0xB0, 2, 10]);
+    assert!(commands.is_empty());
+    let commands = table.handle_midi(&[0xB0, 2, 127]);
+    assert_eq!(commands.len(), 1);
+    assert_eq!(commands[0].command_id, 20);
+}
+
+#[test]
+fn mapped_scene_buttons_recall_the_scene_on_the_engine() {
+    let mut engine = DspEngine::with_config(280, "scenes", EngineConfig::new(48000, 64));
+    let handle = engine.handle();
+    let mut table = MappingTable::new();
+    table.map(MappingSource::MidiNote { channel: 0, note: 36 }, MappingTarget::SceneRecall(3));
+    assert_eq!(handle.active_scene(), None);
+
+    for command in table.handle_midi(&[0x90, 36, 127]) {
+        assert!(handle.send(command));
+    }
+    let mut block = [0.0f32; 2 * 64];
+    engine.process_block(&mut block).unwrap();
+
+    assert_eq!(handle.active_scene(), Some(3));
+    let recalled: Vec<u32> = RESPONSE_QUEUE.lock().unwrap().iter()
+        .filter(|r| r.command_id == 117 && r.node_id == 280)
+        .map(|r| u32::from_le_bytes(r.payload[..4].try_into().unwrap()))
+        .collect();
+    assert_eq!(recalled, vec![3]);
+}
+
+/// Turns the encoder on CC 10 by `value` and returns the parameter value it sets, if any.
+fn turn(table: &mut MappingTable, value: u8) -> Option<f32> {
+    let commands = table.handle_midi(&[0xB0, 10, value]);
+    assert!(commands.len() <= 1);
+    commands.first().map(|c| f32::from_le_bytes(c.payload[..4].try_into().unwrap()))
+}
+
+fn encoder(encoding: CcEncoding, sensitivity: f32, acceleration: f32) -> MappingTable {
+    let source = MappingSource::MidiCc { channel: 0, cc: 10 };
+    let mut table = MappingTable::new();
+    table.map(source.clone(), MappingTarget::NodeParam { node_id: 4, param_id: 1 });
+    assert!(table.set_encoding(&source, encoding, sensitivity, acceleration));
+    table
+}
+
+fn assert_near(actual: Option<f32>, expected: f32) {
+    let actual = actual.expect("the encoder should have moved the parameter");
+    assert!((actual - expected).abs() < 1.0e-5, "got {}, expected {}", actual, expected);
+}
+
+#[test]
+fn twos_complement_encoders_step_from_the_seeded_value() {
+    let mut table = encoder(CcEncoding::TwosComplement, 0.01, 0.0);
+    // Unseeded, the parameter is taken to be at the bottom.
+    assert_near(turn(&mut table, 1), 0.01);
+    table.set_param_value(4, 1, 0.5);
+    assert_near(turn(&mut table, 1), 0.51);
+    assert_near(turn(&mut table, 127), 0.50);
+    assert_near(turn(&mut table, 126), 0.48);
+    assert_eq!(turn(&mut table, 0), None);
+}
+
+#[test]
+fn bin_offset_encoders_center_on_64() {
+    let mut table = encoder(CcEncoding::BinOffset, 0.01, 0.0);
+    table.set_param_value(4, 1, 0.5);
+    assert_near(turn(&mut table, 65), 0.51);
+    assert_near(turn(&mut table, 63), 0.50);
+    assert_near(turn(&mut table, 61), 0.47);
+    assert_eq!(turn(&mut table, 64), None);
+}
+
+#[test]
+fn encoder_acceleration_scales_the_ticks_after_the_first() {
+    let mut table = encoder(CcEncoding::TwosComplement, 0.01, 1.0);
+    table.set_param_value(4, 1, 0.5);
+    // 3 ticks: 1 + 2 * (1 + 1.0).
+    assert_near(turn(&mut table, 3), 0.55);
+    assert_near(turn(&mut table, 1), 0.56);
+    assert_near(turn(&mut table, 125), 0.51);
+}
+
+#[test]
+fn encoders_clamp_to_the_parameter_range() {
+    let mut table = encoder(CcEncoding::BinOffset, 0.01, 0.0);
+    table.set_param_value(4, 1, 0.995);
+    assert_near(turn(&mut table, 65), 1.0);
+    assert_eq!(turn(&mut table, 70), None);
+    table.set_param_value(4, 1, 0.005);
+    assert_near(turn(&mut table, 63), 0.0);
+    assert_eq!(turn(&mut table, 60), None);
+
+    // Seeded in the mapping's output range, stepping in its normalized range.
+    table.mappings[0].max = 10.0;
+    table.set_param_value(4, 1, 5.0);
+    assert_near(turn(&mut table, 65), 5.1);
+    table.set_param_value(4, 1, 12.0);
+    assert_eq!(turn(&mut table, 65), None);
+}
+
+#[test]
+fn encoders_on_transport_targets_fire_once_per_rise() {
+    let source = MappingSource::MidiCc { channel: 0, cc: 11 };
+    let mut table = MappingTable::new();
+    table.map(source.clone(), MappingTarget::Transport(TransportAction::Play));
+    assert!(table.set_encoding(&source, CcEncoding::BinOffset, 0.25, 0.0));
+    let mut fired = |value: u8| table.handle_midi(&[0xB0, 11, value]).iter().map(|c| c.command_id).collect::<Vec<_>>();
+
+    // Up to 0.25, then past 0.5 and on to the top: Play fires once.
+    assert!(fired(65).is_empty());
+    assert_eq!(fired(65), [10]);
+    assert!(fired(65).is_empty());
+    assert!(fired(65).is_empty());
+    // Back below 0.5 and up again fires once more.
+    assert!(fired(61).is_empty());
+    assert_eq!(fired(66), [10]);
+
+    // Only CC mappings take an encoding.
+    let note = MappingSource::MidiNote { channel: 0, note: 36 };
+    table.map(note.clone(), MappingTarget::SceneRecall(1));
+    assert!(!table.set_encoding(&note, CcEncoding::TwosComplement, 0.1, 0.0));
+    assert!(!table.set_encoding(&MappingSource::MidiCc { channel: 1, cc: 11 }, CcEncoding::TwosComplement, 0.1, 0.0));
+}
@@ -0,0 +1,60 @@
+// null_backend.rs
+
+/* Null Audio Backend */
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use opentune::aggregate::OutputSource;
+use opentune::audiohost;
+use opentune::dspapi::{Command, StatState};
+use opentune::dspengine::{AudioNode, DspEngine, EngineConfig};
+use opentune::nullbackend::NullOptions;
+
+/// Halves its input.
+struct Half;
+
+impl AudioNode for Half {
+    fn prepare(&mut self, _sample_rate: u32, _max_block_size: usize) {}
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        buffer.iter_mut().for_each(|s| *s *= 0.5);
+    }
+
+    fn set_param(&mut self, _param_id: u32, _payload: &[u8]) {}
+
+    fn get_id(&self) -> u32 { 1 }
+
+    fn get_name(&self) -> &str { "Half" }
+}
+
+#[test]
+fn null_backend_renders_an_exact_amount_without_hardware() {
+    let config = EngineConfig {
+        audio_host: Some(audiohost::NULL.to_string()),
+        null: NullOptions { free_run: true, max_frames: Some(20 * 256) },
+        ring_buffer_capacity: 1 << 14,
+        output_protection: false,
+        ..EngineConfig::new(48000, 256)
+    };
+    let mut engine = DspEngine::with_config(1, "null", config);
+    engine.graph.lock().unwrap().append_node(Box::new(Half)).unwrap();
+    let feeds = Arc::clone(&engine.output_feeds);
+    let feed = feeds.claim(OutputSource::Master).unwrap();
+    engine.handle().send(Command::new(10, "Transport Play", Vec::new(), 0, 0, 0, StatState::ACTIVE));
+    assert_eq!(engine.push_samples(&vec![1.0f32; 2 * 20 * 256]), 2 * 20 * 256);
+
+    engine.start().unwrap();
+    assert_eq!(engine.device_name(), Some(audiohost::NULL));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while engine.null_frames() != Some(20 * 256) && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    assert_eq!(engine.null_frames(), Some(20 * 256));
+    assert_eq!(engine.transport.info().position, 20 * 256);
+    let rendered = feeds.ring(feed).unwrap().read_slice();
+    assert_eq!(rendered.len(), 2 * 20 * 256);
+    assert!(rendered.iter().all(|&s| s == 0.5));
+    engine.stop();
+}
@@ -0,0 +1,59 @@
+// offline_render.rs
+
+/* Offline Rendering */
+
+use std::time::Duration;
+
+use opentune::dspapi::{Command, StatState};
+use opentune::dspengine::DspEngine;
+
+mod common;
+
+use common::Gain;
+
+#[test]
+fn offline_render_runs_the_rack_and_applies_queued_commands() {
+    let mut engine = DspEngine::new(1, "offline", 48000, 256);
+    engine.graph.lock().unwrap().append_node(Box::new(Gain { gain: 0.5 })).unwrap();
+    engine.handle().send(Command::new(10, "Transport Play", Vec::new(), 0, 0, 0, StatState::ACTIVE));
+
+    let input = vec![1.0f32; 2 * 1000];
+    let output = engine.render_offline(&input, Duration::from_millis(50)).unwrap();
+
+    // 50 ms at 48 kHz, stereo; input runs out after 1000 frames.
+    assert_eq!(output.len(), 2 * 2400);
+    assert!(output[..2000].iter().all(|&s| s == 0.5));
+    assert!(output[2000..].iter().all(|&s| s == 0.0));
+    assert_eq!(engine.transport.info().position, 2400);
+
+    engine.handle().send(Command::new(2, "Set Parameter", 2.0f32.to_le_bytes().to_vec(), 1, 0, 0, StatState::ACTIVE));
+    let output = engine.render_offline(&input, Duration::from_millis(10)).unwrap();
+    assert!(output.iter().all(|&s| s == 2.0));
+}
+
+#[test]
+fn frozen_node_plays_back_its_recording() {
+    let mut engine = DspEngine::new(1, "freeze", 48000, 256);
+    engine.graph.lock().unwrap().append_node(Box::new(Gain { gain: 0.5 })).unwrap();
+    let handle = engine.handle();
+    let mut range = 0u64.to_le_bytes().to_vec();
+    range.extend_from_slice(&1000u64.to_le_bytes());
+    handle.send(Command::new(26, "Freeze Node", range, 1, 0, 0, StatState::ACTIVE));
+    handle.send(Command::new(10, "Transport Play", Vec::new(), 0, 0, 0, StatState::ACTIVE));
+
+    let input = vec![1.0f32; 2 * 2000];
+    engine.render_offline(&input, Duration::from_millis(25)).unwrap();
+    assert_eq!(engine.graph.lock().unwrap().freeze_progress(1), Some(1.0));
+
+    // The node no longer runs: the new gain isn't heard inside the frozen range, and nothing plays after it.
+    handle.send(Command::new(2, "Set Parameter", 2.0f32.to_le_bytes().to_vec(), 1, 0, 0, StatState::ACTIVE));
+    handle.send(Command::new(14, "Locate", 0u64.to_le_bytes().to_vec(), 0, 0, 0, StatState::ACTIVE));
+    let output = engine.render_offline(&input, Duration::from_millis(25)).unwrap();
+    assert!(output[..2000].iter().all(|&s| s == 0.5));
+    assert!(output[2000..].iter().all(|&s| s == 0.0));
+
+    handle.send(Command::new(27, "Unfreeze Node", Vec::new(), 1, 0, 0, StatState::ACTIVE));
+    let output = engine.render_offline(&input, Duration::from_millis(10)).unwrap();
+    assert!(output.iter().all(|&s| s == 2.0));
+    assert_eq!(engine.graph.lock().unwrap().freeze_progress(1), None);
+}
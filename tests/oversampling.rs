@@ -0,0 +1,73 @@
+// oversampling.rs
+
+/* Per-Node Oversampling */
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use opentune::dspapi::{Command, StatState};
+use opentune::dspengine::{AudioNode, DspEngine, EngineConfig};
+use opentune::strip::HOST_PARAM_OVERSAMPLING;
+
+/// Hard clipper at 0.5, recording the rate it was prepared at.
+struct Clipper {
+    rate: Arc<AtomicU32>,
+}
+
+impl AudioNode for Clipper {
+    fn prepare(&mut self, sample_rate: u32, _max_block_size: usize) {
+        self.rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    fn process(&mut self, buffer: &mut [f32]) {
+        buffer.iter_mut().for_each(|s| *s = s.clamp(-0.5, 0.5));
+    }
+
+    fn set_param(&mut self, _param_id: u32, _payload: &[u8]) {}
+
+    fn get_id(&self) -> u32 { 1 }
+
+    fn get_name(&self) -> &str { "Clipper" }
+}
+
+fn set_oversampling(engine: &DspEngine, factor: f32) {
+    engine.handle().send(Command::new(2, "Set Parameter", factor.to_le_bytes().to_vec(), 1, HOST_PARAM_OVERSAMPLING, 0, StatState::ACTIVE));
+}
+
+/// Pushes `blocks` of a quiet 1 kHz stereo sine and pumps them through, returning the left channel in and out.
+fn render_sine(engine: &mut DspEngine, blocks: usize) -> (Vec<f32>, Vec<f32>) {
+    let input: Vec<f32> = (0..blocks * 256).map(|n| 0.25 * (2.0 * std::f32::consts::PI * 1000.0 * n as f32 / 48000.0).sin()).collect();
+    let interleaved: Vec<f32> = input.iter().flat_map(|&s| [s, s]).collect();
+    assert_eq!(engine.push_samples(&interleaved), interleaved.len());
+    let mut output = Vec::new();
+    let mut block = [0.0f32; 2 * 256];
+    for _ in 0..blocks {
+        engine.process_block(&mut block).unwrap();
+        output.extend(block.chunks(2).map(|frame| frame[0]));
+    }
+    (input, output)
+}
+
+#[test]
+fn oversampled_node_runs_at_a_multiple_of_the_engine_rate_with_compensated_latency() {
+    let config = EngineConfig { ring_buffer_capacity: 1 << 16, output_protection: false, ..EngineConfig::new(48000, 256) };
+    let mut engine = DspEngine::with_config(1, "oversampling", config);
+    let rate = Arc::new(AtomicU32::new(0));
+    engine.graph.lock().unwrap().append_node(Box::new(Clipper { rate: Arc::clone(&rate) })).unwrap();
+
+    set_oversampling(&engine, 4.0);
+    let (input, output) = render_sine(&mut engine, 8);
+    assert_eq!(rate.load(Ordering::Relaxed), 4 * 48000);
+    let latency = engine.latency_samples();
+    assert_eq!(latency, 32);
+    // Below the clipping level the filters pass the sine through, only delayed.
+    for (n, sample) in output.iter().enumerate().skip(256) {
+        assert!((sample - input[n - latency]).abs() < 1e-3, "frame {}: {} vs {}", n, sample, input[n - latency]);
+    }
+
+    set_oversampling(&engine, 1.0);
+    let (input, output) = render_sine(&mut engine, 2);
+    assert_eq!(rate.load(Ordering::Relaxed), 48000);
+    assert_eq!(engine.latency_samples(), 0);
+    assert_eq!(output[256..], input[256..]);
+}
@@ -0,0 +1,37 @@
+// plugin_manager.rs
+
+/* Plugin Manager Implementation */
+
+use opentune::dspapi::{Command, StatState};
+use opentune::dspengine::{AudioNode, DspEngine, EngineConfig};
+use opentune::pmanager::PMANAGER;
+
+struct Silence;
+
+impl AudioNode for Silence {
+    fn process(&mut self, buffer: &mut [f32]) {
+        buffer.fill(0.0);
+    }
+
+    fn set_param(&mut self, _param_id: u32, _payload: &[u8]) {}
+
+    fn get_id(&self) -> u32 { 7 }
+
+    fn get_name(&self) -> &str { "Silence" }
+}
+
+#[test]
+fn added_nodes_wait_while_the_plugin_manager_is_held() {
+    PMANAGER.lock().unwrap().register("Silence", || Box::new(Silence));
+    let mut engine = DspEngine::with_config(1, "pump", EngineConfig::new(48000, 64));
+    let mut block = [0.0f32; 2 * 64];
+
+    // A scan or verification holding the manager doesn't stall the block; the node arrives in the next one.
+    let plugins = PMANAGER.lock().unwrap();
+    engine.handle().send(Command::new(0, "Silence", Vec::new(), 7, 0, 0, StatState::ACTIVE));
+    engine.process_block(&mut block).unwrap();
+    assert!(engine.graph.snapshot().nodes.is_empty());
+    drop(plugins);
+    engine.process_block(&mut block).unwrap();
+    assert_eq!(engine.graph.snapshot().nodes.iter().map(|n| n.name.as_str()).collect::<Vec<_>>(), ["Silence"]);
+}
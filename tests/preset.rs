@@ -0,0 +1,55 @@
+// preset.rs
+
+/* Node Presets */
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use opentune::preset::{export_vstpreset, import_vstpreset, NodePreset};
+
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("opentune-{}-{}.vstpreset", name, std::process::id()))
+}
+
+#[test]
+fn corrupt_vst3_presets_are_rejected_without_panicking() {
+    let preset = NodePreset { name: "Warm".into(), plugin: "Gain".into(), params: BTreeMap::from([(0, 0.5)]), state: Vec::new() };
+    let path = temp_file("corrupt");
+    export_vstpreset(&preset, &path).unwrap();
+    assert_eq!(import_vstpreset(&path).unwrap(), preset);
+    let good = std::fs::read(&path).unwrap();
+    let list = i64::from_le_bytes(good[40..48].try_into().unwrap()) as usize;
+
+    let mut corrupt = Vec::new();
+    // Truncated inside the chunk list, and right after the header.
+    corrupt.push(good[..list + 10].to_vec());
+    corrupt.push(good[..48].to_vec());
+    // List offset near the end of the address space, and negative.
+    for offset in [i64::MAX - 2, -1] {
+        let mut data = good.clone();
+        data[40..48].copy_from_slice(&offset.to_le_bytes());
+        corrupt.push(data);
+    }
+    // A huge chunk count with nothing behind it, and a negative one.
+    for count in [i32::MAX, -5] {
+        let mut data = good.clone();
+        data[list + 4..list + 8].copy_from_slice(&count.to_le_bytes());
+        data.truncate(list + 8);
+        corrupt.push(data);
+    }
+    // Component offset and size overflowing when added.
+    let mut data = good.clone();
+    data[list + 12..list + 20].copy_from_slice(&(i64::MAX - 4).to_le_bytes());
+    data[list + 20..list + 28].copy_from_slice(&i64::MAX.to_le_bytes());
+    corrupt.push(data);
+    // Garbage after a valid header.
+    let mut data = good[..48].to_vec();
+    data.extend((0..200u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8));
+    corrupt.push(data);
+
+    for (i, data) in corrupt.into_iter().enumerate() {
+        std::fs::write(&path, data).unwrap();
+        assert!(import_vstpreset(&path).is_err(), "corrupt preset {} was accepted", i);
+    }
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,168 @@
+// process_block.rs
+
+/* Manual Block Pumping */
+
+use std::sync::{Arc, Mutex};
+
+use opentune::dspapi::{Command, StatState, RESPONSE_QUEUE};
+use opentune::dspengine::{AudioNode, DspEngine, EngineConfig, PANIC_FADE_MS};
+use opentune::midi::{MidiEvent, ALL_NOTES_OFF};
+
+mod common;
+
+use common::Gain;
+
+/// A stuck voice: adds 1.0 to its input until reset. Records its resets and the MIDI it is handed.
+struct Voice {
+    sounding: bool,
+    log: Arc<Mutex<VoiceLog>>,
+}
+
+#[derive(Default)]
+struct VoiceLog {
+    resets: usize,
+    /// MIDI messages per block.
+    midi: Vec<Vec<Vec<u8>>>,
+}
+
+impl AudioNode for Voice {
+    fn process(&mut self, buffer: &mut [f32]) {
+        if self.sounding { buffer.iter_mut().for_each(|s| *s += 1.0); }
+    }
+
+    fn set_param(&mut self, _param_id: u32, _payload: &[u8]) {}
+
+    fn get_id(&self) -> u32 { 2 }
+
+    fn get_name(&self) -> &str { "Voice" }
+
+    fn accepts_midi(&self) -> bool { true }
+
+    fn set_midi_input(&mut self, events: &[MidiEvent]) {
+        self.log.lock().unwrap().midi.push(events.iter().map(|e| e.bytes().to_vec()).collect());
+    }
+
+    fn reset(&mut self) {
+        self.sounding = false;
+        self.log.lock().unwrap().resets += 1;
+    }
+}
+
+#[test]
+fn pumped_blocks_take_pushed_samples_and_queued_commands() {
+    let config = EngineConfig { ring_buffer_capacity: 1 << 12, ..EngineConfig::new(48000, 64) };
+    let mut engine = DspEngine::with_config(1, "pump", config);
+    engine.graph.lock().unwrap().append_node(Box::new(Gain { gain: 0.5 })).unwrap();
+    let handle = engine.handle();
+    handle.send(Command::new(10, "Transport Play", Vec::new(), 0, 0, 0, StatState::ACTIVE));
+    assert_eq!(engine.push_samples(&[1.0f32; 2 * 64 * 3]), 2 * 64 * 3);
+
+    let mut block = [0.0f32; 2 * 64];
+    engine.process_block(&mut block).unwrap();
+    assert!(block.iter().all(|&s| s == 0.5));
+
+    handle.send(Command::new(2, "Set Parameter", 2.0f32.to_le_bytes().to_vec(), 1, 0, 0, StatState::ACTIVE));
+    engine.process_block(&mut block).unwrap();
+    assert!(block.iter().all(|&s| s == 2.0));
+
+    // Two blocks at once use up what was pushed; the next one is silent.
+    let mut blocks = [0.0f32; 2 * 64 * 2];
+    engine.process_block(&mut blocks).unwrap();
+    assert!(blocks[..2 * 64].iter().all(|&s| s == 2.0));
+    assert!(blocks[2 * 64..].iter().all(|&s| s == 0.0));
+    assert_eq!(engine.transport.info().position, 4 * 64);
+}
+
+#[test]
+fn parameter_changes_wait_while_the_modulation_matrix_is_held() {
+    let config = EngineConfig { ring_buffer_capacity: 1 << 12, ..EngineConfig::new(48000, 64) };
+    let mut engine = DspEngine::with_config(1, "pump", config);
+    engine.graph.lock().unwrap().append_node(Box::new(Gain { gain: 0.5 })).unwrap();
+    let handle = engine.handle();
+    assert_eq!(engine.push_samples(&[1.0f32; 2 * 64 * 3]), 2 * 64 * 3);
+    let mut block = [0.0f32; 2 * 64];
+    engine.process_block(&mut block).unwrap();
+
+    // A GUI editing the matrix doesn't stall the block; the change lands in the next one.
+    let modulation = std::sync::Arc::clone(&engine.modulation);
+    let matrix = modulation.lock().unwrap();
+    handle.send(Command::new(2, "Set Parameter", 2.0f32.to_le_bytes().to_vec(), 1, 0, 0, StatState::ACTIVE));
+    engine.process_block(&mut block).unwrap();
+    assert!(block.iter().all(|&s| s == 0.5));
+    drop(matrix);
+    engine.process_block(&mut block).unwrap();
+    assert!(block.iter().all(|&s| s == 2.0));
+}
+
+#[test]
+fn snapshots_follow_changes_made_while_pumping() {
+    let mut engine = DspEngine::with_config(1, "pump", EngineConfig::new(48000, 64));
+    engine.graph.lock().unwrap().append_node(Box::new(Gain { gain: 0.5 })).unwrap();
+    let mut block = [0.0f32; 2 * 64];
+    engine.process_block(&mut block).unwrap();
+    assert_eq!(engine.graph.snapshot().nodes.iter().map(|n| n.name.as_str()).collect::<Vec<_>>(), ["Gain"]);
+
+    engine.handle().send(Command::new(6, "Clear Rack", Vec::new(), 0, 0, 0, StatState::ACTIVE));
+    engine.process_block(&mut block).unwrap();
+    assert!(engine.graph.is_rendering());
+    assert!(engine.graph.snapshot().nodes.is_empty());
+}
+
+#[test]
+fn adding_to_a_full_rack_is_always_rejected() {
+    let config = EngineConfig { max_nodes: 1, ..EngineConfig::new(48000, 64) };
+    let mut engine = DspEngine::with_config(1, "pump", config);
+    engine.graph.lock().unwrap().append_node(Box::new(Gain { gain: 0.5 })).unwrap();
+
+    // Holding the response queue only delays the rejection to a later block.
+    let responses = RESPONSE_QUEUE.lock().unwrap();
+    engine.handle().send(Command::new(0, "Gain", Vec::new(), 0x5EE5, 0, 0, StatState::ACTIVE));
+    let mut block = [0.0f32; 2 * 64];
+    engine.process_block(&mut block).unwrap();
+    drop(responses);
+    engine.process_block(&mut block).unwrap();
+
+    let rejected = RESPONSE_QUEUE.lock().unwrap().iter().filter(|r| r.command_id == 105 && r.node_id == 0x5EE5).count();
+    assert_eq!(rejected, 1);
+    assert_eq!(engine.graph.snapshot().nodes.len(), 1);
+}
+
+#[test]
+fn panic_drops_queued_audio_fades_out_and_resets_the_rack() {
+    let config = EngineConfig { ring_buffer_capacity: 1 << 14, ..EngineConfig::new(48000, 512) };
+    let mut engine = DspEngine::with_config(1, "pump", config);
+    let log = Arc::new(Mutex::new(VoiceLog::default()));
+    engine.graph.lock().unwrap().append_node(Box::new(Gain { gain: 0.5 })).unwrap();
+    engine.graph.lock().unwrap().append_node(Box::new(Voice { sounding: true, log: Arc::clone(&log) })).unwrap();
+    assert_eq!(engine.push_samples(&[1.0f32; 2 * 512 * 4]), 2 * 512 * 4);
+
+    let mut block = [0.0f32; 2 * 512];
+    engine.process_block(&mut block).unwrap();
+    assert!(block.iter().all(|&s| s == 1.5));
+
+    assert!(engine.send_midi(&[0x90, 60, 100]));
+    engine.handle().send(Command::new(43, "Panic", Vec::new(), 0, 0, 0, StatState::ACTIVE));
+    engine.process_block(&mut block).unwrap();
+    assert_eq!(engine.latency().queued_frames, 0);
+    // Only the stuck voice is left to fade, from full level to silence within the fade time.
+    let fade = (PANIC_FADE_MS * 0.001 * 48000.0) as usize;
+    let left: Vec<f32> = block.iter().step_by(2).copied().collect();
+    assert!(left[0] > 0.99);
+    assert!(left.windows(2).all(|w| w[0] >= w[1]), "fade isn't monotonic");
+    assert!(left[fade..].iter().all(|&s| s == 0.0));
+    assert!(block.chunks(2).all(|frame| frame[0] == frame[1]));
+    {
+        let log = log.lock().unwrap();
+        assert_eq!(log.resets, 1);
+        assert_eq!(log.midi.len(), 2);
+        assert!(log.midi[1].is_empty(), "queued MIDI wasn't dropped");
+    }
+
+    engine.process_block(&mut block).unwrap();
+    assert!(block.iter().all(|&s| s.abs() < 1e-6));
+    let log = log.lock().unwrap();
+    assert_eq!(log.resets, 1);
+    let notes_off: Vec<_> = log.midi[2].iter().filter(|m| m[1] == ALL_NOTES_OFF).map(|m| m[0]).collect();
+    assert_eq!(notes_off, (0xB0..=0xBF).collect::<Vec<u8>>());
+    assert!(log.midi[2].iter().all(|m| m[0] & 0xF0 == 0xB0));
+}
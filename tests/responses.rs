@@ -0,0 +1,31 @@
+// responses.rs
+
+/* Engine Response Queue */
+
+use opentune::dspapi::{Command, ResponseQueue, StatState, RESPONSE_TELEMETRY_CAPACITY};
+
+fn node_load(sequence: u32) -> Command {
+    Command::new(112, "Node Load", Vec::new(), sequence, 0, 0, StatState::ACTIVE)
+}
+
+#[test]
+fn unreceived_telemetry_keeps_only_the_newest_frames() {
+    let mut queue = ResponseQueue::new();
+    queue.push(Command::new(105, "Node Rejected", b"graph is full".to_vec(), 9, 0, 0, StatState::INACTIVE));
+    let frames = RESPONSE_TELEMETRY_CAPACITY as u32 * 4;
+    for sequence in 0..frames {
+        queue.push(node_load(sequence));
+    }
+
+    assert_eq!(queue.len(), RESPONSE_TELEMETRY_CAPACITY + 1);
+    assert_eq!(queue.dropped(), (frames as usize - RESPONSE_TELEMETRY_CAPACITY) as u64);
+    let received: Vec<Command> = queue.drain().collect();
+    assert_eq!(received[0].command_id, 105);
+    assert_eq!(received[1].node_id, frames - RESPONSE_TELEMETRY_CAPACITY as u32);
+    assert_eq!(received.last().unwrap().node_id, frames - 1);
+
+    // Receiving makes room again.
+    queue.push(node_load(0));
+    assert_eq!(queue.dropped(), (frames as usize - RESPONSE_TELEMETRY_CAPACITY) as u64);
+    assert_eq!(queue.len(), 1);
+}
@@ -0,0 +1,40 @@
+// sample_rate_conformance.rs
+
+/* Internal Node Sample-Rate Conformance */
+
+use opentune::alignment::InputAlignment;
+use opentune::dspengine::AudioNode;
+use opentune::dsptest::assert_sample_rate_agnostic;
+
+// Fractional delays roll off close to Nyquist at 44.1 kHz, so the probes stop at 10 kHz.
+const PROBE_FREQS: [f32; 4] = [100.0, 1000.0, 5000.0, 10000.0];
+
+#[test]
+fn input_alignment_is_sample_rate_agnostic() {
+    assert_sample_rate_agnostic(
+        || {
+            let mut node = InputAlignment::new(1, 2);
+            node.prepare(48000, 256);
+            node.set_delay_seconds(0, 0.0025);
+            node.set_polarity(1, true);
+            Box::new(node)
+        },
+        &PROBE_FREQS,
+        0.5,
+    );
+}
+
+#[test]
+fn delays_clamped_at_a_high_rate_recover_at_a_lower_one() {
+    let mut node = InputAlignment::new(1, 2);
+    node.prepare(48000, 4096);
+    node.set_delay_seconds(0, 0.05);
+    node.prepare(192000, 4096);
+    node.prepare(44100, 4096);
+
+    let mut buffer = vec![0.0f32; 2 * 4096];
+    buffer[0] = 1.0;
+    node.process(&mut buffer);
+    let peak = (0..4096).max_by(|&a, &b| buffer[2 * a].abs().total_cmp(&buffer[2 * b].abs())).unwrap();
+    assert_eq!(peak, 2205);
+}
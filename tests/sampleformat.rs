@@ -0,0 +1,76 @@
+// sampleformat.rs
+
+/* Output Sample Formats */
+
+use cpal::SampleFormat;
+
+use opentune::sampleformat::{best_output_format, FormatConverter, OutputSample};
+
+#[test]
+fn full_scale_clips_and_u16_is_offset() {
+    for (x, expected) in [(0.0, 0), (0.5, 16384), (-0.5, -16384), (1.0, i16::MAX), (-1.0, i16::MIN), (4.0, i16::MAX), (-4.0, i16::MIN)] {
+        assert_eq!(i16::quantize(x, 0.0), expected, "{}", x);
+    }
+    for (x, expected) in [(0.0, 32768), (0.5, 49152), (-0.5, 16384), (1.0, u16::MAX), (-1.0, 0), (4.0, u16::MAX), (-4.0, 0)] {
+        assert_eq!(u16::quantize(x, 0.0), expected, "{}", x);
+    }
+    for (x, expected) in [(0.0, 0), (0.5, 1 << 30), (-0.5, -(1 << 30)), (1.0, i32::MAX), (-1.0, i32::MIN), (4.0, i32::MAX), (-4.0, i32::MIN)] {
+        assert_eq!(i32::quantize(x, 0.0), expected, "{}", x);
+    }
+
+    // Dither is in LSBs, added before rounding and clipping.
+    assert_eq!(i16::quantize(0.0, 0.6), 1);
+    assert_eq!(i16::quantize(0.0, -0.6), -1);
+    assert_eq!(u16::quantize(0.0, -0.6), 32767);
+    assert_eq!(i16::quantize(1.0, -0.6), i16::MAX);
+    assert_eq!(i32::quantize(0.0, 0.6), 1);
+}
+
+#[test]
+fn dither_is_only_added_at_24_bits_or_fewer() {
+    let silence = |out: &mut [f32]| out.fill(0.0);
+
+    let mut converter = FormatConverter::new(64, 2, true);
+    let mut output = [0i16; 2 * 256];
+    converter.render(&mut output, silence);
+    assert!(output.iter().all(|&s| (-1..=1).contains(&s)));
+    assert!(output.iter().any(|&s| s != 0), "16-bit output wasn't dithered");
+    let mut output = [0u16; 2 * 256];
+    converter.render(&mut output, silence);
+    assert!(output.iter().all(|&s| (32767..=32769).contains(&s)));
+    assert!(output.iter().any(|&s| s != 32768));
+
+    let mut output = [1i32; 2 * 256];
+    converter.render(&mut output, silence);
+    assert!(output.iter().all(|&s| s == 0), "32-bit output was dithered");
+
+    let mut converter = FormatConverter::new(64, 2, false);
+    let mut output = [1i16; 2 * 256];
+    converter.render(&mut output, silence);
+    assert!(output.iter().all(|&s| s == 0));
+}
+
+#[test]
+fn long_callbacks_are_rendered_in_pieces() {
+    let mut converter = FormatConverter::new(4, 2, false);
+    let mut next = 0;
+    let mut pieces = 0;
+    let mut output = [0i16; 2 * 13];
+    converter.render(&mut output, |out| {
+        pieces += 1;
+        assert!(out.len() <= 2 * 4);
+        for s in out.iter_mut() {
+            *s = next as f32 / 32768.0;
+            next += 1;
+        }
+    });
+    assert_eq!(pieces, 4);
+    assert_eq!(output.to_vec(), (0..2 * 13).collect::<Vec<i16>>());
+}
+
+#[test]
+fn formats_are_picked_best_first() {
+    assert_eq!(best_output_format([SampleFormat::U16, SampleFormat::I16]), Some(SampleFormat::I16));
+    assert_eq!(best_output_format([SampleFormat::I16, SampleFormat::I32, SampleFormat::F32]), Some(SampleFormat::F32));
+    assert_eq!(best_output_format([SampleFormat::F64, SampleFormat::U8]), None);
+}
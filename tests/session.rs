@@ -0,0 +1,56 @@
+// session.rs
+
+/* Session Model */
+
+use std::collections::BTreeMap;
+
+use opentune::automation::{Breakpoint, CurveShape};
+use opentune::session::{LaneState, NodeState, Session, SessionChange};
+
+fn node(id: u32) -> NodeState {
+    NodeState { id, plugin: "Gain".to_string(), params: BTreeMap::new(), state: Vec::new(), sample_params: Vec::new(), assets: BTreeMap::new() }
+}
+
+fn lane(node_id: u32, param_id: u32, values: &[f32]) -> LaneState {
+    let points = values.iter().enumerate().map(|(i, &value)| Breakpoint { position: i as u64 * 4800, value, shape: CurveShape::Linear }).collect();
+    LaneState { node_id, param_id, stepped: false, points }
+}
+
+#[test]
+fn automation_edits_are_undone_lane_by_lane() {
+    let mut before = Session::new("show", 48000);
+    before.nodes = vec![node(1), node(2)];
+    before.automation = vec![lane(1, 0, &[0.0, 1.0]), lane(2, 0, &[0.5])];
+
+    let mut after = before.clone();
+    after.set_lane(lane(1, 0, &[0.0, 0.25]));
+    after.remove_lane(2, 0);
+    after.set_lane(lane(2, 3, &[1.0]));
+
+    let changes = Session::diff(&before, &after);
+    assert_eq!(changes.len(), 3);
+    assert!(changes.iter().any(|c| matches!(c, SessionChange::LaneChanged { after, .. } if after.node_id == 1)));
+    assert!(changes.iter().any(|c| matches!(c, SessionChange::LaneRemoved(l) if (l.node_id, l.param_id) == (2, 0))));
+    assert!(changes.iter().any(|c| matches!(c, SessionChange::LaneAdded(l) if (l.node_id, l.param_id) == (2, 3))));
+
+    let mut undone = after.clone();
+    for change in changes.iter().rev() {
+        undone.revert(change);
+    }
+    assert_eq!(Session::diff(&before, &undone), Vec::new());
+}
+
+#[test]
+fn imported_chains_bring_their_automation() {
+    let mut preset = Session::new("preset", 48000);
+    preset.nodes = vec![node(1), node(2)];
+    preset.automation = vec![lane(1, 0, &[0.0, 1.0]), lane(2, 5, &[0.5])];
+
+    let mut show = Session::new("show", 48000);
+    show.nodes = vec![node(1)];
+    let remap = show.import_nodes(&preset, &[1]).unwrap();
+
+    assert_eq!(show.automation.len(), 1);
+    assert_eq!(show.automation[0].node_id, remap[&1]);
+    assert_eq!(show.automation[0].points, preset.automation[0].points);
+}
@@ -0,0 +1,103 @@
+// simd.rs
+
+/* SIMD Buffer Utilities */
+
+// Forces the global level, so it lives in its own test binary.
+
+use opentune::dsp::simd::{self, SimdLevel};
+
+const LENGTHS: [usize; 8] = [0, 1, 3, 7, 8, 13, 37, 101];
+
+/// Deterministic samples with NaN and both infinities mixed in at odd positions.
+fn signal(len: usize, seed: u32) -> Vec<f32> {
+    let mut state = seed.wrapping_mul(2_654_435_761).wrapping_add(1);
+    (0..len)
+        .map(|i| match (i + seed as usize) % 11 {
+            3 => f32::NAN,
+            7 => f32::INFINITY,
+            9 => f32::NEG_INFINITY,
+            _ => {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state as f32 / u32::MAX as f32) * 4.0 - 2.0
+            }
+        })
+        .collect()
+}
+
+/// Every operation at the current level, over each length.
+fn run() -> Vec<Vec<f32>> {
+    let mut results = Vec::new();
+    for len in LENGTHS {
+        let src = signal(len, 1);
+
+        let mut out = signal(len, 2);
+        simd::gain(&mut out, 0.7);
+        results.push(out);
+
+        let mut out = signal(len, 3);
+        simd::mix(&mut out, &src);
+        results.push(out);
+
+        let mut out = signal(len, 4);
+        simd::mix_gain(&mut out, &src, -1.3);
+        results.push(out);
+
+        results.push(vec![simd::peak(&src)]);
+        results.push(vec![simd::peak(&src[..len / 2])]);
+
+        for channels in [1, 2, 3] {
+            let stride = len + 2;
+            let planes = signal(stride * channels, 5);
+            let mut out = vec![0.0; len * channels];
+            simd::interleave(&planes, stride, channels, &mut out);
+            results.push(out);
+
+            let input = signal(len * channels, 6);
+            let mut planes = vec![0.0; stride * channels];
+            simd::deinterleave(&input, channels, &mut planes, stride);
+            results.push(planes);
+        }
+    }
+    results
+}
+
+fn same(a: f32, b: f32) -> bool {
+    a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan())
+}
+
+/// Peak of a buffer with NaN and infinities in it at the current level.
+fn check_peak(level: SimdLevel) {
+    let mut buffer = vec![0.25; 19];
+    buffer[0] = f32::NAN;
+    buffer[9] = -0.75;
+    buffer[17] = f32::NAN;
+    assert_eq!(simd::peak(&buffer), 0.75, "{:?}", level);
+    buffer[12] = f32::NEG_INFINITY;
+    assert_eq!(simd::peak(&buffer), f32::INFINITY, "{:?}", level);
+    assert_eq!(simd::peak(&[f32::NAN; 16]), 0.0, "{:?}", level);
+}
+
+#[test]
+fn every_level_matches_scalar() {
+    let best = simd::level();
+    simd::set_level(SimdLevel::Scalar).unwrap();
+    check_peak(SimdLevel::Scalar);
+    let expected = run();
+
+    for level in [SimdLevel::Sse, SimdLevel::Avx, SimdLevel::Neon] {
+        if simd::set_level(level).is_err() {
+            continue;
+        }
+        check_peak(level);
+        let actual = run();
+        for (case, (want, got)) in expected.iter().zip(&actual).enumerate() {
+            assert_eq!(want.len(), got.len(), "{:?} case {}", level, case);
+            for (i, (&w, &g)) in want.iter().zip(got).enumerate() {
+                assert!(same(w, g), "{:?} case {} sample {}: {} vs {}", level, case, i, g, w);
+            }
+        }
+    }
+    simd::set_level(best).unwrap();
+}
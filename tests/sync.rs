@@ -0,0 +1,32 @@
+// sync.rs
+
+/* Multi-Engine Scene Synchronization */
+
+use opentune::dspapi::{Command, StatState};
+use opentune::dspengine::{DspEngine, EngineConfig};
+use opentune::sync::{EngineSide, EngineSync};
+
+fn recall(scene: u32) -> Command {
+    Command::new(20, "Scene Recall", scene.to_le_bytes().to_vec(), 0, 0, 0, StatState::ACTIVE)
+}
+
+#[test]
+fn primary_scene_recalls_reach_the_secondary_engine() {
+    let mut primary = DspEngine::with_config(1, "main", EngineConfig::new(48000, 64));
+    let mut secondary = DspEngine::with_config(2, "cue", EngineConfig::new(48000, 64));
+    let mut sync = EngineSync::new(primary.handle(), secondary.handle());
+    let mut block = [0.0f32; 2 * 64];
+
+    assert!(sync.submit(EngineSide::Primary, recall(5)));
+    primary.process_block(&mut block).unwrap();
+    secondary.process_block(&mut block).unwrap();
+    assert_eq!(primary.active_scene(), Some(5));
+    assert_eq!(secondary.active_scene(), Some(5));
+
+    // Inside the conflict window the primary keeps its scene; the secondary's recall stays local.
+    assert!(!sync.submit(EngineSide::Secondary, recall(6)));
+    primary.process_block(&mut block).unwrap();
+    secondary.process_block(&mut block).unwrap();
+    assert_eq!(primary.active_scene(), Some(5));
+    assert_eq!(secondary.active_scene(), Some(6));
+}
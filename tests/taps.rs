@@ -0,0 +1,51 @@
+// taps.rs
+
+/* Delay-Compensated Metering Taps */
+
+use opentune::graph::{AudioGraph, GRAPH_CAPTURE, GRAPH_MIDI_INPUT, GRAPH_OUTPUT, MIDI_PORT};
+use opentune::session::Connection;
+use opentune::taps::{TapPoint, TapSet, MAX_TAP_PORT};
+
+#[test]
+fn connection_taps_read_the_source_node_output() {
+    let taps = TapSet::new(4);
+    let connection = Connection { from_node: 7, from_port: 2, to_node: 9, to_port: 1 };
+    let point = TapPoint::from_connection(&connection);
+    assert_eq!(point, TapPoint::Connection { from: 7, from_port: 2 });
+    let on_connection = taps.add(point).unwrap();
+    let after_node = taps.add(TapPoint::AfterNode(7)).unwrap();
+    let elsewhere = taps.add(TapPoint::AfterNode(8)).unwrap();
+    assert!(taps.add(TapPoint::Connection { from: 7, from_port: MAX_TAP_PORT + 1 }).is_none());
+
+    taps.measure_node(7, &[0.5, -0.5, 0.5, -0.5], 1000, 64);
+
+    let readings = taps.readings();
+    let reading = |tap: usize| readings.iter().find(|r| r.tap == tap).unwrap();
+    assert_eq!(reading(on_connection).point, point);
+    assert_eq!(reading(on_connection).peak, 0.5);
+    assert_eq!(reading(on_connection).timestamp, 1000 - 64);
+    assert_eq!(reading(after_node).peak, 0.5);
+    assert_eq!(reading(elsewhere).peak, 0.0);
+}
+
+#[test]
+fn connections_from_the_live_input_read_the_captured_signal() {
+    let taps = TapSet::new(4);
+    let from_capture = TapPoint::from_connection(&Connection { from_node: GRAPH_CAPTURE, from_port: 0, to_node: 7, to_port: 0 });
+    assert_eq!(from_capture, TapPoint::Capture);
+    let on_capture = taps.add(from_capture).unwrap();
+    // Pseudo-nodes that carry no audio would never be measured, so their taps are refused.
+    let from_midi = Connection { from_node: GRAPH_MIDI_INPUT, from_port: MIDI_PORT, to_node: 7, to_port: MIDI_PORT };
+    assert!(taps.add(TapPoint::from_connection(&from_midi)).is_none());
+    assert!(taps.add(TapPoint::Connection { from: GRAPH_MIDI_INPUT, from_port: 0 }).is_none());
+    assert!(taps.add(TapPoint::AfterNode(GRAPH_OUTPUT)).is_none());
+
+    let mut graph = AudioGraph::new(4, 8, 2, 64);
+    let mut io = [0.0f32; 2 * 64];
+    let capture = [0.25f32; 2 * 64];
+    graph.process(&mut io, &capture, &[], &[], 640, Some(&taps), None, None, None);
+
+    let reading = taps.readings().into_iter().find(|r| r.tap == on_capture).unwrap();
+    assert_eq!(reading.peak, 0.25);
+    assert_eq!(reading.timestamp, 640);
+}
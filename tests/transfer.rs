@@ -0,0 +1,59 @@
+// transfer.rs
+
+/* Chunked Payload Transfer */
+
+use opentune::dspapi::{Command, StatState};
+use opentune::transfer::{self, Reassembler, TransferStatus, MAX_CONCURRENT_TRANSFERS, MIN_CHUNK_SIZE};
+
+fn blob(len: usize) -> Command {
+    let payload = (0..len).map(|i| (i * 7 % 251) as u8).collect();
+    Command::new(5, "Load IR", payload, 3, 0, 9, StatState::ACTIVE)
+}
+
+#[test]
+fn chunks_arriving_out_of_order_are_reassembled() {
+    let original = blob(5 * MIN_CHUNK_SIZE + 123);
+    let mut parts = transfer::split(&original, 42, MIN_CHUNK_SIZE);
+    let end = parts.pop().unwrap();
+    parts[1..].reverse();
+
+    let mut reassembler = Reassembler::new();
+    for part in &parts {
+        assert!(matches!(reassembler.accept(part), Ok(TransferStatus::Progress { transfer_id: 42, .. })));
+    }
+    let Ok(TransferStatus::Complete(command)) = reassembler.accept(&end) else { panic!("transfer did not complete") };
+    assert_eq!(command.command_id, 5);
+    assert_eq!(command.port_id, 9);
+    assert_eq!(command.payload, original.payload);
+    assert_eq!(reassembler.pending(), 0);
+}
+
+#[test]
+fn corrupt_chunks_drop_the_transfer() {
+    let mut parts = transfer::split(&blob(3 * MIN_CHUNK_SIZE), 7, MIN_CHUNK_SIZE);
+    let last = parts.len() - 2;
+    parts[2].payload[20] ^= 0xFF;
+
+    let mut reassembler = Reassembler::new();
+    reassembler.accept(&parts[0]).unwrap();
+    reassembler.accept(&parts[1]).unwrap();
+    assert!(reassembler.accept(&parts[2]).is_err());
+    assert_eq!(reassembler.pending(), 0);
+    assert!(reassembler.accept(&parts[last]).is_err());
+}
+
+#[test]
+fn begin_commands_cannot_claim_more_chunks_or_transfers_than_allowed() {
+    let mut reassembler = Reassembler::new();
+    let mut begin = transfer::split(&blob(MIN_CHUNK_SIZE), 1, MIN_CHUNK_SIZE).remove(0);
+    begin.payload[16..20].copy_from_slice(&(MIN_CHUNK_SIZE as u32).to_le_bytes());
+    assert!(reassembler.accept(&begin).is_err());
+
+    for id in 0..MAX_CONCURRENT_TRANSFERS as u32 {
+        reassembler.accept(&transfer::split(&blob(10), id, MIN_CHUNK_SIZE).remove(0)).unwrap();
+    }
+    let extra = transfer::split(&blob(10), 99, MIN_CHUNK_SIZE).remove(0);
+    assert!(reassembler.accept(&extra).is_err());
+    assert!(reassembler.abort(0));
+    assert!(reassembler.accept(&extra).is_ok());
+}